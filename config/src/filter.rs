@@ -0,0 +1,326 @@
+//! The tcpdump-like expressions of `capture.bpf`.
+//!
+//! Only the subset needed to narrow a capture down to database traffic is
+//! understood: `ip`, `ip6`, `tcp`, `udp`, `icmp`, `proto`, `port`, `host`
+//! and `net` with optional `src`/`dst`, combined with `and`, `or`, `not`
+//! and parentheses.
+
+use ipnetwork::IpNetwork;
+use std::fmt;
+use std::net::IpAddr;
+
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+pub const IPPROTO_GRE: u8 = 47;
+pub const IPPROTO_SCTP: u8 = 132;
+
+/// Which address or port of a packet a primitive looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dir {
+    Src,
+    Dst,
+    Either,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Ip4,
+    Ip6,
+    // IPv4 protocol or IPv6 next header
+    Proto(u8),
+    // `proto` of None is any of TCP, UDP and SCTP
+    Port {
+        proto: Option<u8>,
+        dir: Dir,
+        port: u16,
+    },
+    Host {
+        dir: Dir,
+        addr: IpAddr,
+    },
+    Net {
+        dir: Dir,
+        net: IpNetwork,
+    },
+}
+
+impl Filter {
+    /// Parse `expr`, an empty expression is None and lets every packet
+    /// through.
+    pub fn parse(expr: &str) -> Result<Option<Filter>, String> {
+        let tokens = tokenize(expr)?;
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let filter = parser.or()?;
+        match parser.peek() {
+            None => Ok(Some(filter)),
+            Some(token) => Err(format!("unexpected `{}`", token)),
+        }
+    }
+}
+
+impl fmt::Display for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dir::Src => write!(f, "src "),
+            Dir::Dst => write!(f, "dst "),
+            Dir::Either => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::And(a, b) => write!(f, "({} and {})", a, b),
+            Filter::Or(a, b) => write!(f, "({} or {})", a, b),
+            Filter::Not(a) => write!(f, "not {}", a),
+            Filter::Ip4 => write!(f, "ip"),
+            Filter::Ip6 => write!(f, "ip6"),
+            Filter::Proto(proto) => write!(f, "proto {}", proto),
+            Filter::Port { proto, dir, port } => match proto.and_then(proto_name) {
+                Some(name) => write!(f, "{} {}port {}", name, dir, port),
+                None => write!(f, "{}port {}", dir, port),
+            },
+            Filter::Host { dir, addr } => write!(f, "{}host {}", dir, addr),
+            Filter::Net { dir, net } => write!(f, "{}net {}", dir, net),
+        }
+    }
+}
+
+fn proto_name(proto: u8) -> Option<&'static str> {
+    match proto {
+        IPPROTO_TCP => Some("tcp"),
+        IPPROTO_UDP => Some("udp"),
+        IPPROTO_SCTP => Some("sctp"),
+        _ => None,
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' | ')' | '!' => tokens.push(c.to_string()),
+            '&' | '|' => {
+                if chars.next() != Some(c) {
+                    return Err(format!("`{}` is not an operator, use `{}{}`", c, c, c));
+                }
+                tokens.push(if c == '&' { "and" } else { "or" }.to_string());
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()!&|".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(word);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<&str, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| "unexpected end of filter".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while self.eat("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.unary()?;
+        while self.eat("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.eat("not") || self.eat("!") {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let filter = self.or()?;
+            if !self.eat(")") {
+                return Err("missing `)`".to_string());
+            }
+            return Ok(filter);
+        }
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Filter, String> {
+        let token = self.next()?.to_string();
+        let proto = match token.as_str() {
+            "ip" => return Ok(Filter::Ip4),
+            "ip6" => return Ok(Filter::Ip6),
+            "icmp" => return Ok(Filter::Proto(IPPROTO_ICMP)),
+            "proto" => {
+                let value = self.next()?;
+                return match value {
+                    "tcp" => Ok(Filter::Proto(IPPROTO_TCP)),
+                    "udp" => Ok(Filter::Proto(IPPROTO_UDP)),
+                    "icmp" => Ok(Filter::Proto(IPPROTO_ICMP)),
+                    "gre" => Ok(Filter::Proto(IPPROTO_GRE)),
+                    "sctp" => Ok(Filter::Proto(IPPROTO_SCTP)),
+                    _ => value
+                        .parse()
+                        .map(Filter::Proto)
+                        .map_err(|_| format!("`{}` is not a protocol", value)),
+                };
+            }
+            "tcp" => Some(IPPROTO_TCP),
+            "udp" => Some(IPPROTO_UDP),
+            "sctp" => Some(IPPROTO_SCTP),
+            _ => None,
+        };
+        match proto {
+            // `tcp port 3306` and friends, `tcp` alone otherwise
+            Some(proto) => match self.peek() {
+                Some("src" | "dst" | "port") => {
+                    let dir = self.dir();
+                    self.port(Some(proto), dir)
+                }
+                _ => Ok(Filter::Proto(proto)),
+            },
+            None => {
+                self.pos -= 1;
+                let dir = self.dir();
+                match self.next()? {
+                    "port" => {
+                        self.pos -= 1;
+                        self.port(None, dir)
+                    }
+                    "host" => {
+                        let value = self.next()?;
+                        let addr = value
+                            .parse()
+                            .map_err(|_| format!("`{}` is not an IP address", value))?;
+                        Ok(Filter::Host { dir, addr })
+                    }
+                    "net" => {
+                        let value = self.next()?;
+                        let net = value
+                            .parse()
+                            .map_err(|e| format!("`{}` is not a network: {}", value, e))?;
+                        Ok(Filter::Net { dir, net })
+                    }
+                    other => Err(format!("unknown primitive `{}`", other)),
+                }
+            }
+        }
+    }
+
+    fn dir(&mut self) -> Dir {
+        if self.eat("src") {
+            Dir::Src
+        } else if self.eat("dst") {
+            Dir::Dst
+        } else {
+            Dir::Either
+        }
+    }
+
+    fn port(&mut self, proto: Option<u8>, dir: Dir) -> Result<Filter, String> {
+        if !self.eat("port") {
+            return Err(format!(
+                "expected `port` after `{}`",
+                dir.to_string().trim()
+            ));
+        }
+        let value = self.next()?;
+        let port = value
+            .parse()
+            .map_err(|_| format!("`{}` is not a port", value))?;
+        Ok(Filter::Port { proto, dir, port })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(expr: &str) -> String {
+        Filter::parse(expr).unwrap().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Filter::parse("  ").unwrap(), None);
+        assert_eq!(parse("tcp"), "proto 6");
+        assert_eq!(parse("tcp and port 3306"), "(proto 6 and port 3306)");
+        assert_eq!(
+            parse("tcp or udp port 4789 or proto gre"),
+            "((proto 6 or udp port 4789) or proto 47)"
+        );
+        // and binds tighter than or
+        assert_eq!(
+            parse("ip6 or src host 10.0.0.1 && !dst port 3306"),
+            "(ip6 or (src host 10.0.0.1 and not dst port 3306))"
+        );
+        assert_eq!(
+            parse("not (net 10.0.0.0/8 or dst net fd00::/8)"),
+            "not (net 10.0.0.0/8 or dst net fd00::/8)"
+        );
+        assert_eq!(parse("tcp src port 50000"), "tcp src port 50000");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (expr, error) in [
+            ("tcp and", "unexpected end of filter"),
+            ("tcp port", "unexpected end of filter"),
+            ("port 70000", "`70000` is not a port"),
+            ("host db1", "`db1` is not an IP address"),
+            ("(tcp", "missing `)`"),
+            ("tcp udp", "unexpected `udp`"),
+            ("tcp & udp", "`&` is not an operator, use `&&`"),
+            ("src tcp", "unknown primitive `tcp`"),
+            ("proto http", "`http` is not a protocol"),
+            ("vlan", "unknown primitive `vlan`"),
+        ] {
+            assert_eq!(Filter::parse(expr), Err(error.to_string()), "{}", expr);
+        }
+    }
+}
@@ -1,8 +1,1096 @@
+pub use ipnetwork::IpNetwork;
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
-pub struct Config {
+pub mod filter;
+
+/// Prefix of the environment variables that override configuration keys,
+/// e.g. `RSHARK_CAPTURE_DEVICES` overrides `capture.devices`.
+pub const ENV_PREFIX: &str = "RSHARK_";
+
+/// Database types a port can be mapped to in the `[protocols]` section.
+pub const SUPPORTED_DB: &[&str] = &["mysql"];
+
+/// Largest snaplen accepted, same upper bound as tcpdump.
+pub const MAX_SNAPLEN: u32 = 262144;
+
+/// `capture.ring_block_size` must be a multiple of the page size.
+pub const RING_PAGE_SIZE: u32 = 4096;
+
+/// Upper bound of `capture.fanout_threads`.
+pub const MAX_FANOUT_THREADS: usize = 64;
+
+/// `capture.devices` entry that captures on every interface that is up.
+pub const ANY_DEVICE: &str = "any";
+
+/// Upper bound of `pipeline.shards`.
+pub const MAX_SHARDS: usize = 256;
+
+/// Keys that can be overridden from the environment or the command line.
+pub const OVERRIDABLE_KEYS: &[&str] = &[
+    "capture.devices",
+    "capture.file",
+    "capture.snaplen",
+    "capture.promisc",
+    "capture.bpf",
+    "capture.backend",
+    "capture.ring_block_size",
+    "capture.ring_block_count",
+    "capture.ring_block_timeout_ms",
+    "capture.fanout_threads",
+    "capture.dedup_window_ms",
+    "protocols.mysql",
+    "session.idle_timeout_secs",
+    "session.handshake_timeout_secs",
+    "session.max_sessions",
+    "metrics.enabled",
+    "metrics.listen",
+    "metrics.interval_secs",
+    "pipeline.frame_queue",
+    "pipeline.packet_queue",
+    "pipeline.event_queue",
+    "pipeline.overflow",
+    "pipeline.shards",
+    "ip.fragment_timeout_secs",
+    "ip.max_fragment_bytes",
+    "ip.max_fragmented_datagrams",
+    "tunnel.enabled",
+    "tunnel.vxlan_ports",
+    "tunnel.geneve_ports",
+    "tunnel.gre",
+    "tunnel.max_depth",
+    "discovery.enabled",
+    "discovery.max_servers",
+    "export.enabled",
+    "export.dir",
+    "export.ring_frames",
+    "export.ring_bytes",
+    "export.max_file_bytes",
+    "export.max_sessions",
+    "export.flows",
+    "export.users",
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Invalid {
+        key: String,
+        reason: String,
+    },
+    Override {
+        origin: String,
+        key: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(
+                    f,
+                    "failed to read config file {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::Parse { path, source } => {
+                write!(
+                    f,
+                    "failed to parse config file {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::Invalid { key, reason } => {
+                write!(f, "invalid value for `{}`: {}", key, reason)
+            }
+            ConfigError::Override {
+                origin,
+                key,
+                reason,
+            } => write!(
+                f,
+                "invalid override of `{}` from {}: {}",
+                key, origin, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// How frames are read from a live interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureBackend {
+    // one recv per frame through pnet, portable
+    #[default]
+    Pnet,
+    // memory-mapped AF_PACKET TPACKET_V3 ring, Linux only
+    Ring,
+}
+
+impl std::str::FromStr for CaptureBackend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pnet" => Ok(CaptureBackend::Pnet),
+            "ring" => Ok(CaptureBackend::Ring),
+            _ => Err(format!(
+                "unknown capture backend `{}`, expected pnet or ring",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    // interface names, or just "any"
+    pub devices: Vec<String>,
+    pub file: Option<PathBuf>,
+    pub snaplen: u32,
+    pub promisc: bool,
     pub bpf: String,
-    pub device: String,
-    pub support_db: HashMap<String, String>,
+    pub backend: CaptureBackend,
+    // ring backend: bytes per block, a multiple of the page size
+    pub ring_block_size: u32,
+    pub ring_block_count: u32,
+    // a partially filled block is handed over after this long
+    pub ring_block_timeout_ms: u32,
+    // ring backend: sockets in one PACKET_FANOUT group, each on its own thread
+    pub fanout_threads: usize,
+    // a frame seen again on another interface within this window is
+    // dropped, 0 keeps every copy
+    pub dedup_window_ms: u64,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            devices: Vec::new(),
+            file: None,
+            snaplen: 65535,
+            promisc: true,
+            bpf: "tcp".to_string(),
+            backend: CaptureBackend::Pnet,
+            ring_block_size: 1 << 20,
+            ring_block_count: 64,
+            ring_block_timeout_ms: 64,
+            fanout_threads: 1,
+            dedup_window_ms: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub idle_timeout_secs: u64,
+    pub handshake_timeout_secs: u64,
+    pub max_sessions: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            idle_timeout_secs: 300,
+            handshake_timeout_secs: 30,
+            max_sessions: 65536,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    #[default]
+    Stdout,
+    File,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "unknown output format `{}`, expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    pub kind: OutputKind,
+    #[serde(default)]
+    pub format: OutputFormat,
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub interval_secs: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            listen: "127.0.0.1:9898".to_string(),
+            interval_secs: 10,
+        }
+    }
+}
+
+/// What a pipeline stage does when the queue to the next one is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    // wait for room, live capture then loses frames in the kernel instead
+    #[default]
+    Block,
+    // discard the item and count it
+    Drop,
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop" => Ok(OverflowPolicy::Drop),
+            _ => Err(format!(
+                "unknown overflow policy `{}`, expected block or drop",
+                s
+            )),
+        }
+    }
+}
+
+/// Queues between capture, decoding and the session manager.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    // raw frames waiting to be decoded
+    pub frame_queue: usize,
+    // decoded packets waiting for the session manager
+    pub packet_queue: usize,
+    // session events waiting for the outputs of `rshark live`
+    pub event_queue: usize,
+    pub overflow: OverflowPolicy,
+    // session managers, each owning the sessions whose flow hashes to it
+    pub shards: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            frame_queue: 8192,
+            packet_queue: 8192,
+            event_queue: 8192,
+            overflow: OverflowPolicy::Block,
+            shards: 4,
+        }
+    }
+}
+
+/// Limits of IPv4/IPv6 fragment reassembly.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpConfig {
+    pub fragment_timeout_secs: u64,
+    // fragment payload bytes buffered across all datagrams
+    pub max_fragment_bytes: usize,
+    pub max_fragmented_datagrams: usize,
+}
+
+impl Default for IpConfig {
+    fn default() -> Self {
+        IpConfig {
+            fragment_timeout_secs: 30,
+            max_fragment_bytes: 4 * 1024 * 1024,
+            max_fragmented_datagrams: 1024,
+        }
+    }
+}
+
+/// Decapsulation of mirrored traffic arriving in VXLAN, GENEVE or GRE.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TunnelConfig {
+    pub enabled: bool,
+    // UDP destination ports carrying VXLAN
+    pub vxlan_ports: Vec<u16>,
+    // UDP destination ports carrying GENEVE
+    pub geneve_ports: Vec<u16>,
+    // GRE, including ERSPAN type I, II and III
+    pub gre: bool,
+    // nested tunnels peeled off before giving up
+    pub max_depth: usize,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        TunnelConfig {
+            enabled: false,
+            vxlan_ports: vec![4789],
+            geneve_ports: vec![6081],
+            gre: true,
+            max_depth: 2,
+        }
+    }
+}
+
+/// Finds servers on ports not listed in `[protocols]` by their greeting.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    // networks a discovered server must be in, empty allows any
+    pub allow: Vec<String>,
+    // networks that are never registered, checked before `allow`
+    pub deny: Vec<String>,
+    pub max_servers: usize,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            enabled: false,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            max_servers: 1024,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn allow_networks(&self) -> Vec<IpNetwork> {
+        self.allow.iter().filter_map(|n| n.parse().ok()).collect()
+    }
+
+    pub fn deny_networks(&self) -> Vec<IpNetwork> {
+        self.deny.iter().filter_map(|n| n.parse().ok()).collect()
+    }
+}
+
+/// Writes the frames of selected sessions to one pcap file each.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    // recent frames kept per session until it is selected, whichever
+    // limit is hit first
+    pub ring_frames: usize,
+    pub ring_bytes: usize,
+    // an export stops growing past this size
+    pub max_file_bytes: u64,
+    // sessions exported per run
+    pub max_sessions: usize,
+    // selectors, a session matching any of them is exported
+    // flow keys as `client -> server`, e.g. "10.0.0.1:50000 -> 10.0.0.2:3306"
+    pub flows: Vec<String>,
+    pub users: Vec<String>,
+    pub rules: Vec<ExportRule>,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            enabled: false,
+            dir: PathBuf::from("export"),
+            ring_frames: 64,
+            ring_bytes: 256 * 1024,
+            max_file_bytes: 64 * 1024 * 1024,
+            max_sessions: 100,
+            flows: Vec::new(),
+            users: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn flow_keys(&self) -> Vec<(SocketAddr, SocketAddr)> {
+        self.flows.iter().filter_map(|f| parse_flow(f).ok()).collect()
+    }
+}
+
+/// A session matches when every field that is set matches.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportRule {
+    // server address, with or without the port
+    pub server: Option<String>,
+    pub user: Option<String>,
+    pub database: Option<String>,
+    // `_client_name` connection attribute
+    pub client_name: Option<String>,
+}
+
+impl ExportRule {
+    /// Whether `addr` matches `server`, an unset server matches any.
+    pub fn matches_server(&self, addr: SocketAddr) -> bool {
+        match &self.server {
+            None => true,
+            Some(server) => match server.parse::<SocketAddr>() {
+                Ok(server) => server == addr,
+                Err(_) => server.parse::<IpAddr>().is_ok_and(|ip| ip == addr.ip()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub capture: CaptureConfig,
+    // database type -> server ports
+    pub protocols: HashMap<String, Vec<u16>>,
+    pub session: SessionConfig,
+    pub outputs: Vec<OutputConfig>,
+    pub metrics: MetricsConfig,
+    pub pipeline: PipelineConfig,
+    pub ip: IpConfig,
+    pub tunnel: TunnelConfig,
+    pub discovery: DiscoveryConfig,
+    pub export: ExportConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut protocols = HashMap::new();
+        protocols.insert("mysql".to_string(), vec![3306]);
+        Config {
+            capture: CaptureConfig::default(),
+            protocols,
+            session: SessionConfig::default(),
+            outputs: vec![OutputConfig::default()],
+            metrics: MetricsConfig::default(),
+            pipeline: PipelineConfig::default(),
+            ip: IpConfig::default(),
+            tunnel: TunnelConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            export: ExportConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse a configuration from TOML text, `path` is only used in errors.
+    pub fn from_toml(path: &Path, content: &str) -> Result<Config, ConfigError> {
+        toml::from_str(content).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Config::from_toml(path, &content)
+    }
+
+    /// Overlay `RSHARK_*` variables found in `vars` on top of the current values.
+    pub fn apply_env<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        for key in OVERRIDABLE_KEYS {
+            let name = env_var_name(key);
+            if let Some(value) = vars.get(&name) {
+                self.set(key, value)
+                    .map_err(|reason| ConfigError::Override {
+                        origin: format!("environment variable {}", name),
+                        key: key.to_string(),
+                        reason,
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Overlay a `key=value` pair given on the command line.
+    pub fn apply_arg(&mut self, arg: &str) -> Result<(), ConfigError> {
+        let (key, value) = match arg.split_once('=') {
+            Some(kv) => kv,
+            None => {
+                return Err(ConfigError::Override {
+                    origin: "command line".to_string(),
+                    key: arg.to_string(),
+                    reason: "expected key=value".to_string(),
+                })
+            }
+        };
+        self.set(key.trim(), value.trim())
+            .map_err(|reason| ConfigError::Override {
+                origin: "command line".to_string(),
+                key: key.trim().to_string(),
+                reason,
+            })
+    }
+
+    /// Set a single dotted key from its string form.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "capture.devices" => self.capture.devices = parse_list(value),
+            "capture.file" => self.capture.file = non_empty(value).map(PathBuf::from),
+            "capture.snaplen" => self.capture.snaplen = parse_value(value)?,
+            "capture.promisc" => self.capture.promisc = parse_value(value)?,
+            "capture.bpf" => self.capture.bpf = value.to_string(),
+            "capture.backend" => self.capture.backend = parse_value(value)?,
+            "capture.ring_block_size" => self.capture.ring_block_size = parse_value(value)?,
+            "capture.ring_block_count" => self.capture.ring_block_count = parse_value(value)?,
+            "capture.ring_block_timeout_ms" => {
+                self.capture.ring_block_timeout_ms = parse_value(value)?
+            }
+            "capture.fanout_threads" => self.capture.fanout_threads = parse_value(value)?,
+            "capture.dedup_window_ms" => self.capture.dedup_window_ms = parse_value(value)?,
+            "session.idle_timeout_secs" => self.session.idle_timeout_secs = parse_value(value)?,
+            "session.handshake_timeout_secs" => {
+                self.session.handshake_timeout_secs = parse_value(value)?
+            }
+            "session.max_sessions" => self.session.max_sessions = parse_value(value)?,
+            "metrics.enabled" => self.metrics.enabled = parse_value(value)?,
+            "metrics.listen" => self.metrics.listen = value.to_string(),
+            "metrics.interval_secs" => self.metrics.interval_secs = parse_value(value)?,
+            "pipeline.frame_queue" => self.pipeline.frame_queue = parse_value(value)?,
+            "pipeline.packet_queue" => self.pipeline.packet_queue = parse_value(value)?,
+            "pipeline.event_queue" => self.pipeline.event_queue = parse_value(value)?,
+            "pipeline.overflow" => self.pipeline.overflow = parse_value(value)?,
+            "pipeline.shards" => self.pipeline.shards = parse_value(value)?,
+            "ip.fragment_timeout_secs" => self.ip.fragment_timeout_secs = parse_value(value)?,
+            "ip.max_fragment_bytes" => self.ip.max_fragment_bytes = parse_value(value)?,
+            "ip.max_fragmented_datagrams" => self.ip.max_fragmented_datagrams = parse_value(value)?,
+            "tunnel.enabled" => self.tunnel.enabled = parse_value(value)?,
+            "tunnel.vxlan_ports" => self.tunnel.vxlan_ports = parse_ports(value)?,
+            "tunnel.geneve_ports" => self.tunnel.geneve_ports = parse_ports(value)?,
+            "tunnel.gre" => self.tunnel.gre = parse_value(value)?,
+            "tunnel.max_depth" => self.tunnel.max_depth = parse_value(value)?,
+            "discovery.enabled" => self.discovery.enabled = parse_value(value)?,
+            "discovery.max_servers" => self.discovery.max_servers = parse_value(value)?,
+            "export.enabled" => self.export.enabled = parse_value(value)?,
+            "export.dir" => self.export.dir = PathBuf::from(value),
+            "export.ring_frames" => self.export.ring_frames = parse_value(value)?,
+            "export.ring_bytes" => self.export.ring_bytes = parse_value(value)?,
+            "export.max_file_bytes" => self.export.max_file_bytes = parse_value(value)?,
+            "export.max_sessions" => self.export.max_sessions = parse_value(value)?,
+            "export.flows" => self.export.flows = parse_list(value),
+            "export.users" => self.export.users = parse_list(value),
+            _ => match key.strip_prefix("protocols.") {
+                Some(db) => {
+                    self.protocols.insert(db.to_string(), parse_ports(value)?);
+                }
+                None => return Err(format!("unknown key `{}`", key)),
+            },
+        }
+        Ok(())
+    }
+
+    /// Check the values once every overlay has been applied.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let capture = &self.capture;
+        match (capture.devices.is_empty(), &capture.file) {
+            (true, None) => {
+                return Err(invalid(
+                    "capture.devices",
+                    "one of `capture.devices` or `capture.file` must be set",
+                ))
+            }
+            (false, Some(_)) => {
+                return Err(invalid(
+                    "capture.file",
+                    "`capture.devices` and `capture.file` are mutually exclusive",
+                ))
+            }
+            _ => {}
+        }
+        if capture.devices.len() > 1 && capture.devices.iter().any(|d| d == ANY_DEVICE) {
+            return Err(invalid(
+                "capture.devices",
+                &format!("`{}` cannot be combined with other interfaces", ANY_DEVICE),
+            ));
+        }
+        if let Some(dup) = capture
+            .devices
+            .iter()
+            .enumerate()
+            .find(|(i, d)| capture.devices[..*i].contains(d))
+            .map(|(_, d)| d)
+        {
+            return Err(invalid(
+                "capture.devices",
+                &format!("interface `{}` is listed twice", dup),
+            ));
+        }
+        if capture.snaplen == 0 || capture.snaplen > MAX_SNAPLEN {
+            return Err(invalid(
+                "capture.snaplen",
+                &format!(
+                    "must be between 1 and {}, got {}",
+                    MAX_SNAPLEN, capture.snaplen
+                ),
+            ));
+        }
+        if let Err(e) = filter::Filter::parse(&capture.bpf) {
+            return Err(invalid(
+                "capture.bpf",
+                &format!("`{}` is not a valid filter: {}", capture.bpf, e),
+            ));
+        }
+        if capture.backend == CaptureBackend::Ring {
+            if capture.ring_block_size == 0 || !capture.ring_block_size.is_multiple_of(RING_PAGE_SIZE) {
+                return Err(invalid(
+                    "capture.ring_block_size",
+                    &format!("must be a multiple of {}", RING_PAGE_SIZE),
+                ));
+            }
+            if capture.ring_block_size < capture.snaplen {
+                return Err(invalid(
+                    "capture.ring_block_size",
+                    "must hold at least one frame of `capture.snaplen` bytes",
+                ));
+            }
+            if capture.ring_block_count == 0 {
+                return Err(invalid(
+                    "capture.ring_block_count",
+                    "must be greater than 0",
+                ));
+            }
+            if capture.fanout_threads == 0 || capture.fanout_threads > MAX_FANOUT_THREADS {
+                return Err(invalid(
+                    "capture.fanout_threads",
+                    &format!("must be between 1 and {}", MAX_FANOUT_THREADS),
+                ));
+            }
+        }
+
+        if self.protocols.is_empty() {
+            return Err(invalid(
+                "protocols",
+                "at least one database type is required",
+            ));
+        }
+        let mut seen: HashMap<u16, &str> = HashMap::new();
+        for (db, ports) in &self.protocols {
+            let key = format!("protocols.{}", db);
+            if !SUPPORTED_DB.contains(&db.as_str()) {
+                return Err(invalid(
+                    &key,
+                    &format!(
+                        "unsupported database type, expected one of: {}",
+                        SUPPORTED_DB.join(", ")
+                    ),
+                ));
+            }
+            if ports.is_empty() {
+                return Err(invalid(&key, "at least one port is required"));
+            }
+            for port in ports {
+                if *port == 0 {
+                    return Err(invalid(&key, "port 0 is not a valid server port"));
+                }
+                if let Some(other) = seen.insert(*port, db) {
+                    return Err(invalid(
+                        &key,
+                        &format!("port {} is already assigned to `protocols.{}`", port, other),
+                    ));
+                }
+            }
+        }
+
+        if self.session.idle_timeout_secs == 0 {
+            return Err(invalid(
+                "session.idle_timeout_secs",
+                "must be greater than 0",
+            ));
+        }
+        if self.session.handshake_timeout_secs == 0 {
+            return Err(invalid(
+                "session.handshake_timeout_secs",
+                "must be greater than 0",
+            ));
+        }
+        if self.session.max_sessions == 0 {
+            return Err(invalid("session.max_sessions", "must be greater than 0"));
+        }
+
+        for (i, output) in self.outputs.iter().enumerate() {
+            if output.kind == OutputKind::File && output.path.is_none() {
+                return Err(invalid(
+                    &format!("outputs[{}].path", i),
+                    "required when kind is \"file\"",
+                ));
+            }
+        }
+
+        if self.metrics.enabled {
+            if let Err(e) = self.metrics.listen.parse::<SocketAddr>() {
+                return Err(invalid(
+                    "metrics.listen",
+                    &format!("`{}` is not a socket address: {}", self.metrics.listen, e),
+                ));
+            }
+            if self.metrics.interval_secs == 0 {
+                return Err(invalid("metrics.interval_secs", "must be greater than 0"));
+            }
+        }
+
+        if self.pipeline.frame_queue == 0 {
+            return Err(invalid("pipeline.frame_queue", "must be greater than 0"));
+        }
+        if self.pipeline.packet_queue == 0 {
+            return Err(invalid("pipeline.packet_queue", "must be greater than 0"));
+        }
+        if self.pipeline.event_queue == 0 {
+            return Err(invalid("pipeline.event_queue", "must be greater than 0"));
+        }
+        if self.pipeline.shards == 0 || self.pipeline.shards > MAX_SHARDS {
+            return Err(invalid(
+                "pipeline.shards",
+                &format!("must be between 1 and {}", MAX_SHARDS),
+            ));
+        }
+
+        if self.ip.fragment_timeout_secs == 0 {
+            return Err(invalid(
+                "ip.fragment_timeout_secs",
+                "must be greater than 0",
+            ));
+        }
+
+        if self.tunnel.enabled {
+            for (key, ports) in [
+                ("tunnel.vxlan_ports", &self.tunnel.vxlan_ports),
+                ("tunnel.geneve_ports", &self.tunnel.geneve_ports),
+            ] {
+                if ports.contains(&0) {
+                    return Err(invalid(key, "port 0 is not a valid tunnel port"));
+                }
+            }
+            if let Some(port) = self
+                .tunnel
+                .vxlan_ports
+                .iter()
+                .find(|p| self.tunnel.geneve_ports.contains(p))
+            {
+                return Err(invalid(
+                    "tunnel.geneve_ports",
+                    &format!("port {} is already assigned to `tunnel.vxlan_ports`", port),
+                ));
+            }
+            if self.tunnel.max_depth == 0 {
+                return Err(invalid("tunnel.max_depth", "must be greater than 0"));
+            }
+        }
+
+        for (name, networks) in [
+            ("allow", &self.discovery.allow),
+            ("deny", &self.discovery.deny),
+        ] {
+            for (i, network) in networks.iter().enumerate() {
+                if let Err(e) = network.parse::<IpNetwork>() {
+                    return Err(invalid(
+                        &format!("discovery.{}[{}]", name, i),
+                        &format!("`{}` is not a network: {}", network, e),
+                    ));
+                }
+            }
+        }
+        if self.discovery.enabled && self.discovery.max_servers == 0 {
+            return Err(invalid("discovery.max_servers", "must be greater than 0"));
+        }
+
+        let export = &self.export;
+        for (i, flow) in export.flows.iter().enumerate() {
+            if let Err(e) = parse_flow(flow) {
+                return Err(invalid(&format!("export.flows[{}]", i), &e));
+            }
+        }
+        for (i, rule) in export.rules.iter().enumerate() {
+            let key = format!("export.rules[{}]", i);
+            if rule.server.is_none()
+                && rule.user.is_none()
+                && rule.database.is_none()
+                && rule.client_name.is_none()
+            {
+                return Err(invalid(&key, "a rule needs at least one field"));
+            }
+            if let Some(server) = &rule.server {
+                if server.parse::<SocketAddr>().is_err() && server.parse::<IpAddr>().is_err() {
+                    return Err(invalid(
+                        &format!("{}.server", key),
+                        &format!("`{}` is not an address", server),
+                    ));
+                }
+            }
+        }
+        if export.enabled {
+            if export.flows.is_empty() && export.users.is_empty() && export.rules.is_empty() {
+                return Err(invalid(
+                    "export.enabled",
+                    "set at least one of `export.flows`, `export.users` or `export.rules`",
+                ));
+            }
+            if export.max_sessions == 0 {
+                return Err(invalid("export.max_sessions", "must be greater than 0"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Database type served on `port`, if any.
+    pub fn db_type(&self, port: u16) -> Option<&str> {
+        self.protocols
+            .iter()
+            .find(|(_, ports)| ports.contains(&port))
+            .map(|(db, _)| db.as_str())
+    }
+}
+
+pub fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+fn invalid(key: &str, reason: &str) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn parse_value<T>(value: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse::<T>()
+        .map_err(|e| format!("cannot parse `{}`: {}", value, e))
+}
+
+/// Flow key as `client -> server`.
+fn parse_flow(value: &str) -> Result<(SocketAddr, SocketAddr), String> {
+    let (client, server) = value
+        .split_once("->")
+        .ok_or_else(|| format!("`{}` is not a `client -> server` flow", value))?;
+    Ok((parse_value(client.trim())?, parse_value(server.trim())?))
+}
+
+/// Comma separated list of names, e.g. `eth0,eth1`.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Comma separated port list, e.g. `3306,3307`.
+fn parse_ports(value: &str) -> Result<Vec<u16>, String> {
+    value
+        .split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| parse_value(p.trim()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+[capture]
+devices = ["eth0"]
+snaplen = 1500
+promisc = false
+bpf = "tcp and port 3306"
+
+[protocols]
+mysql = [3306, 6033]
+
+[session]
+idle_timeout_secs = 60
+
+[[outputs]]
+kind = "file"
+format = "json"
+path = "/var/log/rshark.json"
+
+[metrics]
+enabled = true
+listen = "0.0.0.0:9898"
+"#;
+
+    #[test]
+    fn test_load_sample() {
+        let conf = Config::from_toml(Path::new("sample.toml"), SAMPLE).unwrap();
+        conf.validate().unwrap();
+        assert_eq!(conf.capture.devices, ["eth0"]);
+        assert_eq!(conf.capture.snaplen, 1500);
+        assert!(!conf.capture.promisc);
+        assert_eq!(conf.db_type(6033), Some("mysql"));
+        assert_eq!(conf.db_type(5432), None);
+        assert_eq!(conf.session.idle_timeout_secs, 60);
+        assert_eq!(conf.session.handshake_timeout_secs, 30);
+        assert_eq!(conf.outputs[0].format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let err = Config::from_toml(Path::new("bad.toml"), "[capture]\ndevcie = \"eth0\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("devcie"), "{}", err);
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut conf = Config::from_toml(Path::new("sample.toml"), SAMPLE).unwrap();
+        conf.apply_env(vec![
+            ("RSHARK_CAPTURE_DEVICES".to_string(), "lo, eth1".to_string()),
+            (
+                "RSHARK_PROTOCOLS_MYSQL".to_string(),
+                "3307,3308".to_string(),
+            ),
+            ("HOME".to_string(), "/root".to_string()),
+        ])
+        .unwrap();
+        conf.apply_arg("capture.snaplen=9000").unwrap();
+        conf.apply_arg("pipeline.overflow=drop").unwrap();
+        conf.apply_arg("capture.backend=ring").unwrap();
+        assert_eq!(conf.capture.devices, ["lo", "eth1"]);
+        assert_eq!(conf.pipeline.overflow, OverflowPolicy::Drop);
+        assert_eq!(conf.capture.backend, CaptureBackend::Ring);
+        assert_eq!(conf.capture.snaplen, 9000);
+        assert_eq!(conf.db_type(3308), Some("mysql"));
+        assert_eq!(conf.db_type(3306), None);
+
+        let err = conf
+            .apply_env(vec![(
+                "RSHARK_SESSION_MAX_SESSIONS".to_string(),
+                "many".to_string(),
+            )])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("session.max_sessions"), "{}", err);
+        assert!(err.contains("RSHARK_SESSION_MAX_SESSIONS"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut conf = Config::default();
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("capture.devices"), "{}", err);
+
+        conf.capture.devices = vec!["eth0".to_string(), "any".to_string()];
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("any"), "{}", err);
+
+        conf.capture.devices = vec!["eth0".to_string(), "eth0".to_string()];
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("listed twice"), "{}", err);
+
+        conf.capture.devices = vec!["eth0".to_string()];
+        conf.capture.bpf = "tcp and port".to_string();
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("capture.bpf"), "{}", err);
+
+        conf.capture.bpf = "tcp and port 3306".to_string();
+        conf.protocols.insert("postgres".to_string(), vec![5432]);
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("protocols.postgres"), "{}", err);
+
+        conf.protocols.remove("postgres");
+        conf.outputs.push(OutputConfig {
+            kind: OutputKind::File,
+            format: OutputFormat::Text,
+            path: None,
+        });
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("outputs[1].path"), "{}", err);
+
+        conf.outputs.pop();
+        conf.discovery.deny = vec!["10.0.0.0/8".to_string(), "10.0.0.0/33".to_string()];
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("discovery.deny[1]"), "{}", err);
+
+        conf.discovery.deny.clear();
+        conf.export.enabled = true;
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("export.enabled"), "{}", err);
+
+        conf.export.flows = vec!["10.0.0.1:50000 -> 10.0.0.2".to_string()];
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("export.flows[0]"), "{}", err);
+
+        conf.export.flows = vec!["10.0.0.1:50000 -> 10.0.0.2:3306".to_string()];
+        conf.export.rules.push(ExportRule::default());
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("export.rules[0]"), "{}", err);
+
+        conf.export.rules[0].server = Some("10.0.0.2".to_string());
+        conf.validate().unwrap();
+        assert_eq!(conf.export.flow_keys().len(), 1);
+        assert!(conf.export.rules[0].matches_server("10.0.0.2:3306".parse().unwrap()));
+        assert!(!conf.export.rules[0].matches_server("10.0.0.3:3306".parse().unwrap()));
+        conf.export.enabled = false;
+
+        conf.apply_arg("tunnel.enabled=true").unwrap();
+        conf.apply_arg("tunnel.geneve_ports=6081,4789").unwrap();
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("tunnel.geneve_ports"), "{}", err);
+    }
 }
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::binlog::{BinlogDecoder, EventHeader};
+use std::io::Cursor;
+
+// events one after the other, so that rows events find their table maps
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = BinlogDecoder::default();
+    let mut rest = data;
+    while let Some(header) = EventHeader::new(&mut Cursor::new(rest)) {
+        let _ = decoder.decode(rest);
+        rest = rest
+            .get((header.event_size as usize).max(1)..)
+            .unwrap_or_default();
+    }
+});
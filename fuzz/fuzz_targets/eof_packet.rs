@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::server::eof::EOFPacket;
+use std::io::Cursor;
+
+// the first 4 bytes are the client capabilities, the rest the packet
+fuzz_target!(|data: &[u8]| {
+    if let Some((caps, payload)) = data.split_first_chunk::<4>() {
+        let _ = EOFPacket::new(u32::from_le_bytes(*caps), &mut Cursor::new(payload));
+    }
+});
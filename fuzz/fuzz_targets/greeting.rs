@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::server::greeting::Greeting;
+
+fuzz_target!(|data: &[u8]| {
+    // the probe runs on every server segment of an unknown port
+    let probed = Greeting::probe(data);
+    let greeting = Greeting::new(data.to_vec());
+    if probed {
+        assert!(greeting.is_some(), "probed greeting failed to parse");
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::common;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = Cursor::new(data);
+    while let Some((_, len)) = common::read_len_enc_int(&mut reader) {
+        assert!(reader.position() as usize <= data.len() && len <= 9);
+    }
+
+    // a string never claims more bytes than the input has
+    let mut reader = Cursor::new(data);
+    let mut read = 0;
+    while let Some((_, len)) = common::read_len_enc_str(&mut reader) {
+        read += len;
+        assert_eq!(read, reader.position() as usize);
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::client::login::Login;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Login::new(data.to_vec());
+});
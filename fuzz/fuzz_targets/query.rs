@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::client::query::QueryPacket;
+
+// the first 4 bytes are the client capabilities, the rest the packet
+fuzz_target!(|data: &[u8]| {
+    if let Some((caps, payload)) = data.split_first_chunk::<4>() {
+        let _ = QueryPacket::new(u32::from_le_bytes(*caps), payload.to_vec());
+    }
+});
@@ -0,0 +1,61 @@
+#![no_main]
+
+//! The input is a list of records, a flags byte, a 2 byte little endian
+//! length and that many bytes. Bit 0 of the flags sends the record from the
+//! client, bit 1 passes it as a whole Ethernet frame instead of a TCP payload
+//! between 10.0.0.1:50000 and 10.0.0.2:3306.
+
+use bytes::Bytes;
+use config::Config;
+use libfuzzer_sys::fuzz_target;
+use session::testing::tcp_frame;
+use session::{CapturedFrame, Decoder, LinkType, SessionManager, SessionPacket, Timestamp};
+use std::sync::mpsc;
+
+const FROM_CLIENT: u8 = 1;
+const RAW_FRAME: u8 = 2;
+
+fuzz_target!(|data: &[u8]| {
+    let config = Config::default();
+    let mut decoder = Decoder::new(&config);
+    let mut packets = Vec::new();
+    let (mut rest, mut seq) = (data, [0u32; 2]);
+    while let Some((&[flags, lo, hi], tail)) = rest.split_first_chunk::<3>() {
+        let len = (u16::from_le_bytes([lo, hi]) as usize).min(tail.len());
+        let (record, tail) = tail.split_at(len);
+        rest = tail;
+
+        let from_client = flags & FROM_CLIENT > 0;
+        let data = if flags & RAW_FRAME > 0 {
+            record.to_vec()
+        } else {
+            let seq = &mut seq[from_client as usize];
+            *seq = seq.wrapping_add(len as u32);
+            tcp_frame(from_client, *seq, record)
+        };
+        let frame = CapturedFrame {
+            timestamp: Timestamp::new(packets.len() as u64, 0),
+            interface: None,
+            link_type: LinkType::Ethernet,
+            orig_len: data.len() as u32,
+            data: Bytes::from(data),
+        };
+        packets.extend(SessionPacket::new(&config, &mut decoder, frame));
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let (tx, rx) = tokio::sync::mpsc::channel(packets.len().max(1));
+    for pkt in packets {
+        tx.try_send(pkt).unwrap();
+    }
+    drop(tx);
+    let (events, received) = mpsc::channel();
+    let mut manager = SessionManager::new(config, rx, Default::default());
+    manager.set_sink(Box::new(events));
+    runtime.block_on(manager.run());
+    for event in received.try_iter() {
+        let _ = event.kind.to_string();
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packets::mysql::server::tabluar::{Tabular, TextResult};
+use std::io::Cursor;
+
+// the first 4 bytes are the client capabilities, the rest the packet
+fuzz_target!(|data: &[u8]| {
+    if let Some((caps, payload)) = data.split_first_chunk::<4>() {
+        let caps = u32::from_le_bytes(*caps);
+        let _ = Tabular::new(caps, Cursor::new(payload));
+        let _ = TextResult::new(caps, Cursor::new(payload));
+    }
+});
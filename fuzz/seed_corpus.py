@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+"""Writes the seed corpus of the fuzz targets into fuzz/seeds.
+
+The seeds are the byte arrays of the packet parser unit tests, read from the
+sources so they follow the tests. Targets that take client capabilities get
+the ones the test passes in front of the packet, and the session target gets
+a handshake and a query made of the same arrays. Rerun after changing the
+arrays and commit the seeds.
+"""
+
+import os
+import re
+import shutil
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+MYSQL = os.path.join(HERE, "..", "packets", "src", "mysql")
+
+PROTOCOL_41 = 0x200
+# the capabilities of the OK packet tests, a MySQL 8.0 client
+CLIENT_CAPS = 16754309
+# the capabilities of the query attribute tests
+QUERY_ATTRIBUTES = 1 << 27
+
+# target, source file and the capabilities its tests use, if the parser takes them
+TARGETS = [
+    ("greeting", "server/greeting.rs", None),
+    ("login", "client/login.rs", None),
+    ("ok_packet", "server/ok.rs", CLIENT_CAPS),
+    ("err_packet", "server/err.rs", PROTOCOL_41),
+    ("eof_packet", "server/eof.rs", PROTOCOL_41),
+    ("tabular", "server/tabluar.rs", CLIENT_CAPS),
+    ("query", "client/query.rs", QUERY_ATTRIBUTES),
+    ("binlog", "binlog.rs", None),
+]
+
+BYTE = r"(?:0x[0-9a-fA-F]{2}|\d+|b'.')"
+ARRAY = r"\[\s*(" + BYTE + r"(?:\s*,\s*" + BYTE + r")*)\s*,?\s*\]"
+# `let payload = [..]`, `let packet_bytes: &[u8] = &[..]` or `vec![..]`
+LET_ARRAY = re.compile(r"let (?:mut )?\w+(?:\s*:[^=]*)?\s*=\s*&?(?:vec!)?" + ARRAY)
+# the `(&[..], value, len)` cases of the lenenc tests
+CASE_ARRAY = re.compile(r"\(&" + ARRAY)
+TEST_FN = re.compile(r"fn (\w+)\(\)")
+
+
+def parse(elements):
+    out = bytearray()
+    for e in re.split(r"\s*,\s*", elements.strip().rstrip(",")):
+        out.append(ord(e[2]) if e.startswith("b'") else int(e, 0))
+    return bytes(out)
+
+
+def arrays(path, pattern=LET_ARRAY, min_len=4):
+    """The arrays of the test module of `path`, named after their test."""
+    with open(path) as f:
+        source = f.read()
+    tests = source[source.index("#[cfg(test)]"):]
+    fns = [(m.start(), m.group(1)) for m in TEST_FN.finditer(tests)]
+    seen = set()
+    for m in pattern.finditer(tests):
+        data = parse(m.group(1))
+        if len(data) < min_len or data in seen:
+            continue
+        seen.add(data)
+        name = [name for start, name in fns if start < m.start()][-1]
+        yield name, data
+
+
+def mysql(seq, payload):
+    return struct.pack("<I", len(payload))[:3] + bytes([seq]) + payload
+
+
+def record(from_client, payload):
+    return struct.pack("<BH", 1 if from_client else 0, len(payload)) + payload
+
+
+def write(target, name, data):
+    path = os.path.join(HERE, "seeds", target)
+    os.makedirs(path, exist_ok=True)
+    with open(os.path.join(path, name), "wb") as f:
+        f.write(data)
+
+
+def main():
+    shutil.rmtree(os.path.join(HERE, "seeds"), ignore_errors=True)
+    found = {}
+    for target, source, caps in TARGETS:
+        for i, (name, data) in enumerate(arrays(os.path.join(MYSQL, source))):
+            found.setdefault(target, data)
+            prefix = struct.pack("<I", caps) if caps is not None else b""
+            write(target, "%s-%d" % (name, i), prefix + data)
+
+    lenenc = os.path.join(MYSQL, "mod.rs")
+    for i, (name, data) in enumerate(arrays(lenenc, CASE_ARRAY, 1)):
+        write("len_enc", "%s-%d" % (name, i), data)
+    # the cases one after the other
+    write("len_enc", "all", b"".join(data for _, data in arrays(lenenc, CASE_ARRAY, 1)))
+
+    greeting, login = mysql(0, found["greeting"]), mysql(1, found["login"])
+    ok, err = mysql(2, found["ok_packet"]), mysql(1, found["err_packet"])
+    query = mysql(0, found["query"])
+    handshake = record(False, greeting) + record(True, login) + record(False, ok)
+    write("session", "handshake", handshake)
+    write("session", "query", handshake + record(True, query) + record(False, err))
+
+
+if __name__ == "__main__":
+    main()
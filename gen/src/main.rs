@@ -0,0 +1,338 @@
+//! Synthetic MySQL traffic for load tests of the sensor: concurrent
+//! sessions built with the packet encoders, written to a pcap file or
+//! replayed on an interface. The same seed gives the same frames.
+
+mod mysql;
+mod rng;
+mod tcp;
+
+use clap::{ArgGroup, Parser};
+use mysql::{Options, Session, STATEMENTS};
+use pcap::{PcapWriter, LINKTYPE_ETHERNET};
+use pnet::datalink::{self, Channel, DataLinkSender};
+use rng::Rng;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+use tcp::{Anomalies, Connection};
+
+const SNAPLEN: u32 = 65535;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "rshark-gen",
+    version,
+    about = "Synthetic MySQL traffic generator",
+    group(ArgGroup::new("sink").required(true).args(["output", "interface"]))
+)]
+struct Cli {
+    /// Write the frames to this pcap file
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Send the frames on this interface instead, e.g. `lo`
+    #[arg(short, long, value_name = "IFACE")]
+    interface: Option<String>,
+
+    /// Frames per second on the interface, as fast as possible if unset
+    #[arg(long, requires = "interface")]
+    rate: Option<u32>,
+
+    /// Sessions in total
+    #[arg(long, default_value_t = 100)]
+    sessions: u32,
+
+    /// Sessions open at the same time, their packets interleave
+    #[arg(long, default_value_t = 8)]
+    concurrency: u32,
+
+    /// Commands per session, `N` or `MIN-MAX`
+    #[arg(long, default_value = "10-50", value_parser = parse_range)]
+    queries: (u64, u64),
+
+    /// Weights of the statements, e.g. `select=6,insert=2,update=1,delete=1`
+    #[arg(long, default_value = "select=6,insert=2,update=1,delete=1", value_parser = parse_mix)]
+    mix: [u32; 4],
+
+    /// Rows of a select and rows affected by an update or delete, `N` or `MIN-MAX`
+    #[arg(long, default_value = "0-20", value_parser = parse_range)]
+    rows: (u64, u64),
+
+    /// Bytes of each string value of a row
+    #[arg(long, default_value_t = 16)]
+    width: usize,
+
+    /// Probability of a login or a statement failing with an error
+    #[arg(long, default_value_t = 0.02, value_parser = parse_probability)]
+    error_rate: f64,
+
+    /// Probability of a statement running as a prepared statement
+    #[arg(long, default_value_t = 0.2, value_parser = parse_probability)]
+    prepared: f64,
+
+    /// Probability of a session switching to TLS after the greeting
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    tls: f64,
+
+    /// Probability of a segment arriving after the next one
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    reorder: f64,
+
+    /// Probability of a segment being sent twice
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    retransmit: f64,
+
+    /// Probability of a segment missing from the capture
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    loss: f64,
+
+    /// Address of the server, clients are 10.1.0.0/16
+    #[arg(long, default_value = "10.0.0.1:3306")]
+    server: SocketAddrV4,
+
+    /// Timestamp of the first frame, in seconds since the epoch
+    #[arg(long, default_value_t = 1_700_000_000)]
+    start: u64,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// `N` or `MIN-MAX`, the bounds included.
+fn parse_range(s: &str) -> Result<(u64, u64), String> {
+    let (min, max) = s.split_once('-').unwrap_or((s, s));
+    let min: u64 = min.trim().parse().map_err(|e| format!("{}: {}", min, e))?;
+    let max: u64 = max.trim().parse().map_err(|e| format!("{}: {}", max, e))?;
+    if min > max {
+        return Err(format!("{} is more than {}", min, max));
+    }
+    Ok((min, max))
+}
+
+/// `name=weight` pairs, statements left out get no weight.
+fn parse_mix(s: &str) -> Result<[u32; 4], String> {
+    let mut mix = [0; 4];
+    for pair in s.split(',') {
+        let (name, weight) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=weight, got {}", pair))?;
+        let i = STATEMENTS
+            .iter()
+            .position(|st| st.name() == name.trim())
+            .ok_or_else(|| format!("unknown statement {}", name))?;
+        mix[i] = weight
+            .trim()
+            .parse()
+            .map_err(|e| format!("{}: {}", weight, e))?;
+    }
+    if mix.iter().all(|w| *w == 0) {
+        return Err("every weight is zero".to_string());
+    }
+    Ok(mix)
+}
+
+fn parse_probability(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        Ok(p) => Err(format!("{} is not between 0 and 1", p)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Where the frames go.
+enum Sink {
+    Pcap(PcapWriter<BufWriter<File>>),
+    Interface {
+        tx: Box<dyn DataLinkSender>,
+        interval: Option<Duration>,
+    },
+}
+
+impl Sink {
+    fn open(cli: &Cli) -> Result<Self, Box<dyn Error>> {
+        if let Some(path) = &cli.output {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let writer = PcapWriter::new(BufWriter::new(file), LINKTYPE_ETHERNET, SNAPLEN)?;
+            return Ok(Sink::Pcap(writer));
+        }
+        let name = cli.interface.as_deref().unwrap_or_default();
+        let iface = datalink::interfaces()
+            .into_iter()
+            .find(|i| i.name == name)
+            .ok_or_else(|| format!("no interface {}", name))?;
+        let tx = match datalink::channel(&iface, Default::default())? {
+            Channel::Ethernet(tx, _) => tx,
+            _ => return Err(format!("{} is not an Ethernet interface", name).into()),
+        };
+        let interval = cli.rate.map(|r| Duration::from_secs(1) / r.max(1));
+        Ok(Sink::Interface { tx, interval })
+    }
+
+    fn write(&mut self, ts_nanos: u64, frame: &[u8]) -> io::Result<()> {
+        match self {
+            Sink::Pcap(writer) => {
+                let (secs, nanos) = (ts_nanos / 1_000_000_000, ts_nanos % 1_000_000_000);
+                writer.write_packet(secs as u32, nanos as u32, frame.len() as u32, frame)
+            }
+            Sink::Interface { tx, interval } => {
+                tx.send_to(frame, None)
+                    .unwrap_or_else(|| Err(io::ErrorKind::WriteZero.into()))?;
+                if let Some(interval) = interval {
+                    thread::sleep(*interval);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Pcap(writer) => writer.flush(),
+            Sink::Interface { .. } => Ok(()),
+        }
+    }
+}
+
+/// Address of the `n`th client, cycling through 10.1.0.0/16.
+fn client_addr(n: u32) -> SocketAddrV4 {
+    let host = n % 0xfffe + 1;
+    let ip = Ipv4Addr::new(10, 1, (host >> 8) as u8, host as u8);
+    SocketAddrV4::new(ip, 32768 + (n % 28000) as u16)
+}
+
+struct Totals {
+    sessions: u32,
+    frames: u64,
+    bytes: u64,
+}
+
+/// Run the sessions, `concurrency` of them at a time, and hand every
+/// frame to the sink as it is made.
+fn generate(cli: &Cli, sink: &mut Sink) -> io::Result<Totals> {
+    let opts = Options {
+        queries: cli.queries,
+        mix: cli.mix,
+        rows: cli.rows,
+        width: cli.width,
+        error_rate: cli.error_rate,
+        prepared: cli.prepared,
+        tls: cli.tls,
+    };
+    let anomalies = Anomalies {
+        reorder: cli.reorder,
+        retransmit: cli.retransmit,
+        loss: cli.loss,
+    };
+    let mut rng = Rng::new(cli.seed);
+    let mut clock = cli.start * 1_000_000_000;
+    let mut totals = Totals {
+        sessions: 0,
+        frames: 0,
+        bytes: 0,
+    };
+    let mut active: Vec<(Session, Connection)> = Vec::new();
+    let mut frames = Vec::new();
+    loop {
+        while active.len() < cli.concurrency.max(1) as usize && totals.sessions < cli.sessions {
+            let client = client_addr(totals.sessions);
+            let conn = Connection::open(client, cli.server, &mut rng, &mut frames);
+            let session = Session::new(totals.sessions + 1, &opts, &mut rng);
+            active.push((session, conn));
+            totals.sessions += 1;
+        }
+        if active.is_empty() {
+            break;
+        }
+
+        let i = rng.below(active.len() as u64) as usize;
+        let (session, conn) = &mut active[i];
+        match session.next(&mut rng) {
+            Some(messages) => {
+                for (from_client, data) in messages {
+                    conn.send(from_client, &data, &anomalies, &mut rng, &mut frames);
+                }
+            }
+            None => {
+                conn.close(&mut frames);
+                active.swap_remove(i);
+            }
+        }
+        for frame in frames.drain(..) {
+            clock += rng.range(10_000, 500_000);
+            sink.write(clock, &frame)?;
+            totals.frames += 1;
+            totals.bytes += frame.len() as u64;
+        }
+    }
+    sink.flush()?;
+    Ok(totals)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = Sink::open(&cli).and_then(|mut sink| Ok(generate(&cli, &mut sink)?));
+    match result {
+        Ok(totals) => {
+            eprintln!(
+                "{} sessions, {} frames, {} bytes",
+                totals.sessions, totals.frames, totals.bytes
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("rshark-gen: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_range("7"), Ok((7, 7)));
+        assert_eq!(parse_range("0-20"), Ok((0, 20)));
+        assert!(parse_range("20-0").is_err());
+        assert_eq!(parse_mix("select=3,delete=1"), Ok([3, 0, 0, 1]));
+        assert!(parse_mix("merge=1").is_err());
+        assert!(parse_mix("select=0").is_err());
+        assert!(parse_probability("1.5").is_err());
+    }
+
+    #[test]
+    fn test_same_seed_same_capture() {
+        let capture = |seed: &str| {
+            let path = std::env::temp_dir().join(format!("rshark-gen-{}.pcap", seed));
+            let cli = Cli::parse_from([
+                "rshark-gen",
+                "-o",
+                path.to_str().unwrap(),
+                "--sessions",
+                "10",
+                "--tls",
+                "0.2",
+                "--reorder",
+                "0.05",
+                "--seed",
+                seed,
+            ]);
+            let mut sink = Sink::open(&cli).unwrap();
+            let totals = generate(&cli, &mut sink).unwrap();
+            drop(sink);
+            assert_eq!(totals.sessions, 10);
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            bytes
+        };
+        let first = capture("1");
+        assert_eq!(first, capture("1"));
+        assert_ne!(first, capture("2"));
+    }
+}
@@ -0,0 +1,686 @@
+use crate::rng::Rng;
+use packets::mysql::binary::{Value, ValueType};
+use packets::mysql::client::login::Login;
+use packets::mysql::client::query::QueryPacket;
+use packets::mysql::client::stmt::{STMTExecutePacket, STMTPacket, STMTPreparePacket};
+use packets::mysql::command::MySqlCommand;
+use packets::mysql::common::*;
+use packets::mysql::server::eof::EOFPacket;
+use packets::mysql::server::err::ErrPacket;
+use packets::mysql::server::greeting::{Greeting, PROTOCOL_VERSION_10};
+use packets::mysql::server::ok::OKPacket;
+use packets::mysql::server::row::{BinaryRow, TextRow};
+use packets::mysql::server::stmt::STMTPrepareOKPacket;
+use packets::mysql::server::tabluar::ColDef;
+use std::collections::HashMap;
+
+const UTF8MB4: u8 = 45;
+const BINARY: u16 = 63;
+const STATUS_AUTOCOMMIT: u16 = SERVER_STATUS_AUTOCOMMIT as u16;
+
+// what libmysql asks for, without compression, query attributes or TLS
+const CLIENT_CAPS: u32 = CLIENT_LONG_PASSWORD
+    | CLIENT_LONG_FLAG
+    | CLIENT_CONNECT_WITH_DB
+    | CLIENT_PROTOCOL_41
+    | CLIENT_TRANSACTIONS
+    | CLIENT_SECURE_CONNECTION
+    | CLIENT_MULTI_STATEMENTS
+    | CLIENT_MULTI_RESULTS
+    | CLIENT_PS_MULTI_RESULTS
+    | CLIENT_PLUGIN_AUTH
+    | CLIENT_CONNECT_ATTRS
+    | CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA;
+const SERVER_CAPS: u32 = !CLIENT_ZSTD_COMPRESSION_ALGORITHM;
+
+/// A server the sessions talk to and the client capabilities it gets.
+struct Flavour {
+    version: &'static str,
+    plugin: &'static str,
+    caps: u32,
+}
+
+const FLAVOURS: [Flavour; 3] = [
+    Flavour {
+        version: "5.7.44-log",
+        plugin: "mysql_native_password",
+        caps: CLIENT_CAPS,
+    },
+    Flavour {
+        version: "8.0.36",
+        plugin: "caching_sha2_password",
+        caps: CLIENT_CAPS | CLIENT_SESSION_TRACK | CLIENT_DEPRECATE_EOF,
+    },
+    Flavour {
+        version: "5.5.5-10.11.6-MariaDB-log",
+        plugin: "mysql_native_password",
+        caps: CLIENT_CAPS & !CLIENT_LONG_PASSWORD,
+    },
+];
+
+const USERS: [&str; 4] = ["app", "report", "batch", "admin"];
+
+/// Statements of the query mix, in the order of their weights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statement {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+pub const STATEMENTS: [Statement; 4] = [
+    Statement::Select,
+    Statement::Insert,
+    Statement::Update,
+    Statement::Delete,
+];
+
+impl Statement {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Statement::Select => "select",
+            Statement::Insert => "insert",
+            Statement::Update => "update",
+            Statement::Delete => "delete",
+        }
+    }
+
+    /// The statement with `?` for its parameters, as it is prepared.
+    fn prepared(&self) -> &'static str {
+        match self {
+            Statement::Select => "select id, name, price from orders where id > ?",
+            Statement::Insert => "insert into orders (name, price) values (?, ?)",
+            Statement::Update => "update orders set price = ? where id = ?",
+            Statement::Delete => "delete from orders where id = ?",
+        }
+    }
+
+    fn params(&self, rng: &mut Rng, width: usize) -> Vec<(ValueType, Value)> {
+        let id = (long(), Value::Int(rng.range(1, 100_000) as i64));
+        let price = (
+            ValueType::new(MYSQL_TYPE_DOUBLE, false),
+            Value::Double(rng.range(1, 100_000) as f64 / 100.0),
+        );
+        match self {
+            Statement::Select | Statement::Delete => vec![id],
+            Statement::Insert => vec![(string(), Value::Bytes(word(rng, width))), price],
+            Statement::Update => vec![price, id],
+        }
+    }
+
+    /// The prepared statement with its parameters inlined, as a client
+    /// without prepared statements sends it.
+    fn text(&self, params: &[(ValueType, Value)]) -> String {
+        let mut text = String::new();
+        let mut params = params.iter();
+        for (i, part) in self.prepared().split('?').enumerate() {
+            if i > 0 {
+                match params.next() {
+                    Some((_, Value::Int(v))) => text.push_str(&v.to_string()),
+                    Some((_, Value::Double(v))) => text.push_str(&v.to_string()),
+                    Some((_, Value::Bytes(v))) => {
+                        text.push('\'');
+                        text.push_str(&String::from_utf8_lossy(v));
+                        text.push('\'');
+                    }
+                    _ => text.push_str("NULL"),
+                }
+            }
+            text.push_str(part);
+        }
+        text
+    }
+
+    fn error(&self) -> (u16, &'static str, &'static str) {
+        match self {
+            Statement::Select => (1146, "42S02", "Table 'shop.orders' doesn't exist"),
+            Statement::Insert => (
+                1062,
+                "23000",
+                "Duplicate entry '1' for key 'orders.PRIMARY'",
+            ),
+            Statement::Update | Statement::Delete => (
+                1213,
+                "40001",
+                "Deadlock found when trying to get lock; try restarting transaction",
+            ),
+        }
+    }
+}
+
+fn long() -> ValueType {
+    ValueType::new(MYSQL_TYPE_LONGLONG, false)
+}
+
+fn string() -> ValueType {
+    ValueType::new(MYSQL_TYPE_VAR_STRING, false)
+}
+
+fn word(rng: &mut Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| b'a' + rng.below(26) as u8).collect()
+}
+
+/// What the sessions do, shared by all of them.
+#[derive(Debug, Clone)]
+pub struct Options {
+    // commands of a session, the bounds included
+    pub queries: (u64, u64),
+    // weights of `STATEMENTS`
+    pub mix: [u32; 4],
+    // rows of a select, the bounds included
+    pub rows: (u64, u64),
+    // bytes of a string value
+    pub width: usize,
+    pub error_rate: f64,
+    pub prepared: f64,
+    pub tls: f64,
+}
+
+/// The MySQL packets of one direction of an exchange, numbered on from
+/// `seq`.
+struct Packets {
+    seq: u8,
+    buf: Vec<u8>,
+}
+
+impl Packets {
+    fn new(seq: u8) -> Self {
+        Packets {
+            seq,
+            buf: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, payload: &[u8]) {
+        self.buf.extend(write_packet(self.seq, payload));
+        let packets = payload.len() / MAX_PAYLOAD_LEN + 1;
+        self.seq = self.seq.wrapping_add(packets as u8);
+    }
+}
+
+enum State {
+    Handshake,
+    // TLS records left to exchange
+    Tls(u64),
+    // commands left to send
+    Commands(u64),
+    Done,
+}
+
+struct Prepared {
+    statement_id: u32,
+    // the parameter types are only sent on the first execution
+    executed: bool,
+}
+
+/// One client session, driven a step at a time so that the caller can
+/// interleave several.
+pub struct Session {
+    opts: Options,
+    flavour: &'static Flavour,
+    connection_id: u32,
+    tls: bool,
+    state: State,
+    prepared: [Option<Prepared>; 4],
+    statement_ids: u32,
+    insert_ids: u64,
+}
+
+impl Session {
+    pub fn new(connection_id: u32, opts: &Options, rng: &mut Rng) -> Self {
+        Session {
+            opts: opts.clone(),
+            flavour: rng.pick(&FLAVOURS),
+            connection_id,
+            tls: rng.chance(opts.tls),
+            state: State::Handshake,
+            prepared: Default::default(),
+            statement_ids: 0,
+            insert_ids: rng.range(1, 1_000_000),
+        }
+    }
+
+    fn caps(&self) -> u32 {
+        self.flavour.caps
+    }
+
+    fn deprecate_eof(&self) -> bool {
+        self.caps() & CLIENT_DEPRECATE_EOF > 0
+    }
+
+    /// The next messages of the session, each one written at once by the
+    /// client (true) or the server, None when the session is over.
+    pub fn next(&mut self, rng: &mut Rng) -> Option<Vec<(bool, Vec<u8>)>> {
+        match self.state {
+            State::Handshake => Some(self.handshake(rng)),
+            State::Tls(0) | State::Commands(0) => {
+                self.state = State::Done;
+                Some(self.quit(rng))
+            }
+            State::Tls(left) => {
+                self.state = State::Tls(left - 1);
+                // a command and its response, only the sizes are visible
+                let request = tls_record(0x17, rng.range(20, 200) as usize, rng);
+                let response = tls_record(0x17, rng.range(20, 4000) as usize, rng);
+                Some(vec![(true, request), (false, response)])
+            }
+            State::Commands(left) => {
+                self.state = State::Commands(left - 1);
+                Some(self.command(rng))
+            }
+            State::Done => None,
+        }
+    }
+
+    fn handshake(&mut self, rng: &mut Rng) -> Vec<(bool, Vec<u8>)> {
+        let queries = rng.range(self.opts.queries.0, self.opts.queries.1);
+        let scramble = word(rng, 20);
+        let greeting = Greeting {
+            protocol_version: PROTOCOL_VERSION_10,
+            server_version: self.flavour.version.to_string(),
+            connection_id: self.connection_id,
+            auth_plugin_data: scramble[..8].to_vec(),
+            capability_flags: SERVER_CAPS as u16,
+            server_language: UTF8MB4,
+            status_flags: STATUS_AUTOCOMMIT,
+            extended_capability_flags: (SERVER_CAPS >> 16) as u16,
+            auth_plugin_len: 21,
+            unused: [0; 10],
+            auth_plugin_data_2: scramble[8..].to_vec(),
+            auth_plugin_name: self.flavour.plugin.to_string(),
+        };
+        let mut server = Packets::new(0);
+        server.push(&greeting.encode());
+
+        let attrs = [
+            ("_client_name", "libmysql"),
+            ("_os", "Linux"),
+            ("_pid", &rng.range(1000, 65535).to_string()),
+            ("program_name", "rshark-gen"),
+        ];
+        let login = Login {
+            cap: self.caps(),
+            max_packet_size: 1 << 24,
+            charset: UTF8MB4,
+            username: Some(rng.pick(&USERS).to_string()),
+            auth_response: Some(rng.bytes(if self.deprecate_eof() { 32 } else { 20 })),
+            database: Some("shop".to_string()),
+            auth_plugin_name: Some(self.flavour.plugin.to_string()),
+            attrs: attrs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            ..Default::default()
+        };
+        let mut client = Packets::new(1);
+        if self.tls {
+            // the SSL request is the fixed part of the login, the rest
+            // follows inside TLS
+            let ssl_request = Login {
+                cap: self.caps() | CLIENT_SSL,
+                ..login
+            };
+            client.push(&ssl_request.encode()[..32]);
+            self.state = State::Tls(queries);
+            let client_hello = tls_record(0x16, rng.range(200, 500) as usize, rng);
+            let server_hello = tls_record(0x16, rng.range(2000, 4000) as usize, rng);
+            return vec![
+                (false, server.buf),
+                (true, client.buf),
+                (true, client_hello),
+                (false, server_hello),
+            ];
+        }
+        client.push(&login.encode());
+
+        let mut response = Packets::new(2);
+        if rng.chance(self.opts.error_rate) {
+            let user = login.username.as_deref().unwrap_or_default();
+            let message = format!(
+                "Access denied for user '{}'@'%' (using password: YES)",
+                user
+            );
+            response.push(&err(self.caps(), 1045, "28000", &message));
+            self.state = State::Done;
+        } else {
+            if self.flavour.plugin == "caching_sha2_password" {
+                // fast authentication from the server's cache
+                response.push(&[0x01, 0x03]);
+            }
+            response.push(&self.ok(0, 0));
+            self.state = State::Commands(queries);
+        }
+        vec![
+            (false, server.buf),
+            (true, client.buf),
+            (false, response.buf),
+        ]
+    }
+
+    fn command(&mut self, rng: &mut Rng) -> Vec<(bool, Vec<u8>)> {
+        let index = rng.weighted(&self.opts.mix);
+        let statement = STATEMENTS[index];
+        let params = statement.params(rng, self.opts.width);
+        let mut messages = Vec::new();
+        let (request, binary) = if rng.chance(self.opts.prepared) {
+            if self.prepared[index].is_none() {
+                self.statement_ids += 1;
+                messages.extend(self.prepare(statement, self.statement_ids));
+                self.prepared[index] = Some(Prepared {
+                    statement_id: self.statement_ids,
+                    executed: false,
+                });
+            }
+            let prepared = self.prepared[index].as_mut().unwrap();
+            let (types, values) = params.into_iter().unzip();
+            let execute = STMTExecutePacket {
+                statement_id: prepared.statement_id,
+                flags: 0,
+                iterations: 1,
+                new_params_bound: !prepared.executed,
+                types,
+                params: values,
+            };
+            prepared.executed = true;
+            (execute.encode(), true)
+        } else {
+            let query = QueryPacket {
+                cmd: MySqlCommand::Query,
+                attrs: Vec::new(),
+                query: statement.text(&params),
+            };
+            (query.encode(self.caps()), false)
+        };
+        let mut client = Packets::new(0);
+        client.push(&request);
+        messages.push((true, client.buf));
+
+        let mut response = Packets::new(1);
+        if rng.chance(self.opts.error_rate) {
+            let (code, state, message) = statement.error();
+            response.push(&err(self.caps(), code, state, message));
+        } else {
+            match statement {
+                Statement::Select => self.result_set(rng, binary, &mut response),
+                Statement::Insert => {
+                    self.insert_ids += 1;
+                    response.push(&self.ok(1, self.insert_ids));
+                }
+                Statement::Update | Statement::Delete => {
+                    let affected = rng.range(self.opts.rows.0, self.opts.rows.1);
+                    response.push(&self.ok(affected, 0));
+                }
+            }
+        }
+        messages.push((false, response.buf));
+        messages
+    }
+
+    fn prepare(&self, statement: Statement, statement_id: u32) -> Vec<(bool, Vec<u8>)> {
+        let prepare = STMTPreparePacket {
+            query: statement.prepared().to_string(),
+        };
+        let mut client = Packets::new(0);
+        client.push(&prepare.encode());
+
+        let params = statement.prepared().matches('?').count();
+        let columns = match statement {
+            Statement::Select => columns(),
+            _ => Vec::new(),
+        };
+        let prepare_ok = STMTPrepareOKPacket {
+            statement_id,
+            columns: columns.len() as u16,
+            params: params as u16,
+            warnings: 0,
+            metadata_follows: None,
+        };
+        let mut server = Packets::new(1);
+        server.push(&prepare_ok.encode(self.caps()));
+        for _ in 0..params {
+            server.push(&col_def(b"?", MYSQL_TYPE_LONGLONG, BINARY, 0).encode());
+        }
+        if params > 0 && !self.deprecate_eof() {
+            server.push(&self.eof());
+        }
+        for col in &columns {
+            server.push(&col.encode());
+        }
+        if !columns.is_empty() && !self.deprecate_eof() {
+            server.push(&self.eof());
+        }
+        vec![(true, client.buf), (false, server.buf)]
+    }
+
+    fn result_set(&self, rng: &mut Rng, binary: bool, out: &mut Packets) {
+        let columns = columns();
+        let mut count = Vec::new();
+        write_len_enc_int(&mut count, columns.len() as u64);
+        out.push(&count);
+        for col in &columns {
+            out.push(&col.encode());
+        }
+        if !self.deprecate_eof() {
+            out.push(&self.eof());
+        }
+        let types: Vec<ValueType> = columns.iter().map(ValueType::from).collect();
+        for id in 0..rng.range(self.opts.rows.0, self.opts.rows.1) {
+            let name = word(rng, self.opts.width);
+            let price = rng.range(1, 100_000) as f64 / 100.0;
+            if binary {
+                let row = BinaryRow {
+                    values: vec![
+                        Value::Int(id as i64),
+                        Value::Bytes(name),
+                        Value::Double(price),
+                    ],
+                };
+                out.push(&row.encode(&types));
+            } else {
+                let row = TextRow {
+                    values: vec![
+                        Some(id.to_string().into_bytes()),
+                        Some(name),
+                        Some(price.to_string().into_bytes()),
+                    ],
+                };
+                out.push(&row.encode());
+            }
+        }
+        if self.deprecate_eof() {
+            // an OK packet with the header of EOF ends the rows
+            let ok = OKPacket {
+                cmd: MySqlCommand::Eof,
+                ..self.ok_packet(0, 0)
+            };
+            out.push(&ok.encode(self.caps()));
+        } else {
+            out.push(&self.eof());
+        }
+    }
+
+    /// COM_STMT_CLOSE of the prepared statements and COM_QUIT, nothing
+    /// answers them. Inside TLS only the size of COM_QUIT shows.
+    fn quit(&self, rng: &mut Rng) -> Vec<(bool, Vec<u8>)> {
+        if self.tls {
+            return vec![(true, tls_record(0x17, 21, rng))];
+        }
+        let mut messages = Vec::new();
+        for prepared in self.prepared.iter().flatten() {
+            let close = STMTPacket {
+                cmd: MySqlCommand::StmtClose.code(),
+                statement_id: prepared.statement_id,
+                rows: None,
+            };
+            let mut client = Packets::new(0);
+            client.push(&close.encode());
+            messages.push((true, client.buf));
+        }
+        let mut client = Packets::new(0);
+        client.push(&[MySqlCommand::Quit.code()]);
+        messages.push((true, client.buf));
+        messages
+    }
+
+    fn ok_packet(&self, affected_rows: u64, last_insert_id: u64) -> OKPacket {
+        OKPacket {
+            cmd: MySqlCommand::Ok,
+            affected_rows,
+            last_insert_id,
+            status_flags: Some(STATUS_AUTOCOMMIT),
+            warnings: Some(0),
+            session_track_info: None,
+        }
+    }
+
+    fn ok(&self, affected_rows: u64, last_insert_id: u64) -> Vec<u8> {
+        self.ok_packet(affected_rows, last_insert_id)
+            .encode(self.caps())
+    }
+
+    fn eof(&self) -> Vec<u8> {
+        let eof = EOFPacket {
+            header: MySqlCommand::Eof,
+            warnings: 0,
+            status_flags: STATUS_AUTOCOMMIT,
+        };
+        eof.encode(self.caps())
+    }
+}
+
+fn err(cap: u32, error_code: u16, sql_state: &str, message: &str) -> Vec<u8> {
+    let err = ErrPacket {
+        header: MySqlCommand::Err,
+        error_code,
+        sql_state_marker: Some(b'#'),
+        sql_state: Some(sql_state.to_string()),
+        error_message: message.to_string(),
+    };
+    err.encode(cap)
+}
+
+fn col_def(name: &[u8], column_type: u8, charset: u16, flags: u16) -> ColDef {
+    ColDef {
+        catalog: b"def".to_vec(),
+        schema: b"shop".to_vec(),
+        table: b"orders".to_vec(),
+        org_table: b"orders".to_vec(),
+        name: name.to_vec(),
+        org_name: name.to_vec(),
+        length_of_fixed_length_fields: 0x0c,
+        charset,
+        column_length: 255,
+        column_type,
+        flags,
+        decimals: 0,
+    }
+}
+
+/// Columns of the `orders` table every select reads.
+fn columns() -> Vec<ColDef> {
+    vec![
+        col_def(
+            b"id",
+            MYSQL_TYPE_LONGLONG,
+            BINARY,
+            NOT_NULL_FLAG | PRI_KEY_FLAG,
+        ),
+        col_def(b"name", MYSQL_TYPE_VAR_STRING, UTF8MB4 as u16, 0),
+        col_def(b"price", MYSQL_TYPE_DOUBLE, BINARY, 0),
+    ]
+}
+
+/// A TLS record of `len` random bytes, only its header is readable.
+fn tls_record(content_type: u8, len: usize, rng: &mut Rng) -> Vec<u8> {
+    let mut record = vec![content_type, 0x03, 0x03];
+    record.extend_from_slice(&(len as u16).to_be_bytes());
+    record.extend(rng.bytes(len));
+    record
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn opts() -> Options {
+        Options {
+            queries: (5, 5),
+            mix: [1, 1, 1, 1],
+            rows: (0, 3),
+            width: 8,
+            error_rate: 0.0,
+            prepared: 0.5,
+            tls: 0.0,
+        }
+    }
+
+    /// Payloads of the MySQL packets of a message.
+    fn payloads(mut data: &[u8]) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        while !data.is_empty() {
+            let len = u32::from_le_bytes([data[0], data[1], data[2], 0]) as usize;
+            out.push(data[4..4 + len].to_vec());
+            data = &data[4 + len..];
+        }
+        out
+    }
+
+    #[test]
+    fn test_session_decodes() {
+        let mut rng = Rng::new(3);
+        for connection_id in 0..20 {
+            let mut session = Session::new(connection_id, &opts(), &mut rng);
+            let handshake = session.next(&mut rng).unwrap();
+            let greeting = Greeting::new(payloads(&handshake[0].1)[0].clone()).unwrap();
+            assert_eq!(greeting.connection_id, connection_id);
+            let login = Login::new(payloads(&handshake[1].1)[0].clone()).unwrap();
+            assert_eq!(login.cap, session.caps());
+            assert_eq!(login.database.as_deref(), Some("shop"));
+
+            let mut commands = 0;
+            while let Some(messages) = session.next(&mut rng) {
+                for (from_client, data) in messages {
+                    let payloads = payloads(&data);
+                    let first = &payloads[0];
+                    if from_client {
+                        commands += 1;
+                        match MySqlCommand::try_from(first[0]) {
+                            Ok(MySqlCommand::Query) => {
+                                assert!(QueryPacket::new(session.caps(), first.clone()).is_some())
+                            }
+                            Ok(MySqlCommand::StmtPrepare) => {
+                                assert!(STMTPreparePacket::new(first).is_some())
+                            }
+                            Ok(MySqlCommand::StmtExecute) => {
+                                assert!(STMTExecutePacket::new(0, &[], first).is_some())
+                            }
+                            Ok(MySqlCommand::StmtClose) => {
+                                assert!(STMTPacket::new(first).is_some())
+                            }
+                            cmd => assert_eq!(cmd, Ok(MySqlCommand::Quit)),
+                        }
+                    } else if MySqlCommand::response(first[0]) == Some(MySqlCommand::Ok)
+                        && payloads.len() == 1
+                    {
+                        assert!(OKPacket::new(session.caps(), first.clone()).is_some());
+                    }
+                }
+            }
+            // at least the 5 commands and COM_QUIT
+            assert!(commands >= 6, "{} commands", commands);
+        }
+    }
+
+    #[test]
+    fn test_text_statement() {
+        let params = [
+            (string(), Value::Bytes(b"pen".to_vec())),
+            (long(), Value::Double(1.5)),
+        ];
+        assert_eq!(
+            Statement::Insert.text(&params),
+            "insert into orders (name, price) values ('pen', 1.5)"
+        );
+    }
+}
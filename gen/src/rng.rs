@@ -0,0 +1,77 @@
+/// SplitMix64, small and the same on every platform so that a seed always
+/// gives the same capture.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, `n` must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // the bias of the modulo is far below what a test capture notices
+        self.next_u64() % n
+    }
+
+    /// Uniform in `lo..=hi`.
+    pub fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.below(hi - lo + 1)
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        // 53 random bits, the precision of an f64
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+
+    /// Index into `weights` with a probability proportional to its weight,
+    /// at least one weight must not be zero.
+    pub fn weighted(&mut self, weights: &[u32]) -> usize {
+        let total: u64 = weights.iter().map(|w| *w as u64).sum();
+        let mut n = self.below(total);
+        for (i, w) in weights.iter().enumerate() {
+            if n < *w as u64 {
+                return i;
+            }
+            n -= *w as u64;
+        }
+        unreachable!("n is below the total of the weights")
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(7).next_u64(), Rng::new(8).next_u64());
+
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            assert!((3..=5).contains(&rng.range(3, 5)));
+            assert_eq!(rng.weighted(&[0, 3, 0]), 1);
+        }
+        assert!(!rng.chance(0.0) && rng.chance(1.0));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::rng::Rng;
+use std::net::SocketAddrV4;
+
+const SYN: u8 = 0x02;
+const ACK: u8 = 0x10;
+const PSH_ACK: u8 = 0x18;
+const FIN_ACK: u8 = 0x11;
+
+// payload of a full segment on an Ethernet link
+const MSS: usize = 1460;
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const IPPROTO_TCP: u8 = 6;
+
+/// Probabilities, per data segment, of the TCP anomalies a sensor has to
+/// cope with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Anomalies {
+    // sent after the segment that follows it
+    pub reorder: f64,
+    // sent twice
+    pub retransmit: f64,
+    // never seen, the stream has a hole
+    pub loss: f64,
+}
+
+/// One TCP connection as Ethernet frames, sequence numbers follow the
+/// payload sent in either direction.
+pub struct Connection {
+    client: SocketAddrV4,
+    server: SocketAddrV4,
+    // next sequence number of the client and of the server
+    seq: [u32; 2],
+    // a segment waiting for the next one, to be sent out of order
+    held: Option<Vec<u8>>,
+}
+
+impl Connection {
+    /// The three-way handshake of a new connection, appended to `out`.
+    pub fn open(
+        client: SocketAddrV4,
+        server: SocketAddrV4,
+        rng: &mut Rng,
+        out: &mut Vec<Vec<u8>>,
+    ) -> Self {
+        let mut conn = Connection {
+            client,
+            server,
+            seq: [rng.next_u64() as u32, rng.next_u64() as u32],
+            held: None,
+        };
+        out.push(conn.segment(true, &[], SYN));
+        out.push(conn.segment(false, &[], SYN | ACK));
+        out.push(conn.segment(true, &[], ACK));
+        conn
+    }
+
+    /// Send `data` in segments of at most one MSS, each one subject to the
+    /// anomalies.
+    pub fn send(
+        &mut self,
+        from_client: bool,
+        data: &[u8],
+        anomalies: &Anomalies,
+        rng: &mut Rng,
+        out: &mut Vec<Vec<u8>>,
+    ) {
+        for chunk in data.chunks(MSS) {
+            let frame = self.segment(from_client, chunk, PSH_ACK);
+            if rng.chance(anomalies.loss) {
+                continue;
+            }
+            if rng.chance(anomalies.retransmit) {
+                out.push(frame.clone());
+            }
+            if self.held.is_none() && rng.chance(anomalies.reorder) {
+                self.held = Some(frame);
+                continue;
+            }
+            out.push(frame);
+            out.extend(self.held.take());
+        }
+    }
+
+    /// FIN from the client, FIN from the server and the last ACK.
+    pub fn close(&mut self, out: &mut Vec<Vec<u8>>) {
+        out.extend(self.held.take());
+        out.push(self.segment(true, &[], FIN_ACK));
+        out.push(self.segment(false, &[], FIN_ACK));
+        out.push(self.segment(true, &[], ACK));
+    }
+
+    fn segment(&mut self, from_client: bool, payload: &[u8], flags: u8) -> Vec<u8> {
+        let (src, dst) = if from_client {
+            (self.client, self.server)
+        } else {
+            (self.server, self.client)
+        };
+        let (side, peer) = if from_client { (0, 1) } else { (1, 0) };
+        let ack = if flags & ACK > 0 { self.seq[peer] } else { 0 };
+
+        let mut tcp = Vec::with_capacity(20 + payload.len());
+        tcp.extend_from_slice(&src.port().to_be_bytes());
+        tcp.extend_from_slice(&dst.port().to_be_bytes());
+        tcp.extend_from_slice(&self.seq[side].to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        // 5 words of header, flags, window, checksum and urgent pointer
+        tcp.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+        // SYN and FIN take a sequence number
+        let len = payload.len() as u32 + (flags & (SYN | 0x01) > 0) as u32;
+        self.seq[side] = self.seq[side].wrapping_add(len);
+
+        let mut pseudo = Vec::with_capacity(12 + tcp.len());
+        pseudo.extend_from_slice(&src.ip().octets());
+        pseudo.extend_from_slice(&dst.ip().octets());
+        pseudo.extend_from_slice(&[0, IPPROTO_TCP]);
+        pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        pseudo.extend_from_slice(&tcp);
+        let sum = checksum(&pseudo);
+        tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+
+        let mut ip = Vec::with_capacity(20);
+        ip.extend_from_slice(&[0x45, 0]);
+        ip.extend_from_slice(&(20 + tcp.len() as u16).to_be_bytes());
+        // id, don't fragment, ttl, protocol and checksum
+        ip.extend_from_slice(&[0, 0, 0x40, 0, 64, IPPROTO_TCP, 0, 0]);
+        ip.extend_from_slice(&src.ip().octets());
+        ip.extend_from_slice(&dst.ip().octets());
+        let sum = checksum(&ip);
+        ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        let mut frame = Vec::with_capacity(14 + ip.len() + tcp.len());
+        frame.extend_from_slice(&mac(dst));
+        frame.extend_from_slice(&mac(src));
+        frame.extend_from_slice(&ETHERTYPE_IPV4);
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&tcp);
+        frame
+    }
+}
+
+/// Locally administered MAC address ending with the IPv4 address.
+fn mac(addr: SocketAddrV4) -> [u8; 6] {
+    let ip = addr.ip().octets();
+    [0x02, 0x00, ip[0], ip[1], ip[2], ip[3]]
+}
+
+/// Internet checksum, RFC 1071.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn conn(rng: &mut Rng, out: &mut Vec<Vec<u8>>) -> Connection {
+        let client = "10.1.0.1:40000".parse().unwrap();
+        let server = "10.0.0.1:3306".parse().unwrap();
+        Connection::open(client, server, rng, out)
+    }
+
+    fn seq(frame: &[u8]) -> u32 {
+        u32::from_be_bytes([frame[38], frame[39], frame[40], frame[41]])
+    }
+
+    #[test]
+    fn test_segments() {
+        let (mut rng, mut out) = (Rng::new(0), Vec::new());
+        let mut conn = conn(&mut rng, &mut out);
+        conn.send(
+            true,
+            &[0x55; 3000],
+            &Anomalies::default(),
+            &mut rng,
+            &mut out,
+        );
+        conn.close(&mut out);
+        assert_eq!(out.len(), 3 + 3 + 3);
+
+        // checksums of the headers verify to zero
+        for frame in &out {
+            assert_eq!(checksum(&frame[14..34]), 0);
+        }
+        let data: Vec<&Vec<u8>> = out[3..6].iter().collect();
+        assert_eq!(data[0].len() - 54, MSS);
+        assert_eq!(seq(data[1]), seq(data[0]) + MSS as u32);
+        assert_eq!(data[2].len() - 54, 3000 - 2 * MSS);
+        // SYN takes one sequence number
+        assert_eq!(seq(data[0]), seq(&out[0]) + 1);
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let (mut rng, mut out) = (Rng::new(0), Vec::new());
+        let mut conn = conn(&mut rng, &mut out);
+        let retransmit = Anomalies {
+            retransmit: 1.0,
+            ..Default::default()
+        };
+        conn.send(true, b"select 1", &retransmit, &mut rng, &mut out);
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[3], out[4]);
+
+        let reorder = Anomalies {
+            reorder: 1.0,
+            ..Default::default()
+        };
+        conn.send(true, &[0x55; 2000], &reorder, &mut rng, &mut out);
+        assert_eq!(out.len(), 7);
+        assert_eq!(seq(&out[5]), seq(&out[6]) + MSS as u32);
+
+        let loss = Anomalies {
+            loss: 1.0,
+            ..Default::default()
+        };
+        conn.send(false, b"lost", &loss, &mut rng, &mut out);
+        assert_eq!(out.len(), 7);
+    }
+}
@@ -1,5 +1,171 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Monotonic counter, cheap to clone and update from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that can go up and down, e.g. a queue depth.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn set(&self, v: i64) {
+        self.0.store(v, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+}
+
+#[derive(Debug)]
+struct Family {
+    help: String,
+    // rendered label set -> metric
+    series: BTreeMap<String, Metric>,
+}
+
+/// Named metrics of the whole process, rendered in the Prometheus text
+/// exposition format.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Counter `name` with `labels`, created on first use.
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        match self.register(name, help, labels, || Metric::Counter(Counter::default())) {
+            Metric::Counter(c) => c,
+            Metric::Gauge(_) => panic!("metric {} is a gauge", name),
+        }
+    }
+
+    /// Gauge `name` with `labels`, created on first use.
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        match self.register(name, help, labels, || Metric::Gauge(Gauge::default())) {
+            Metric::Gauge(g) => g,
+            Metric::Counter(_) => panic!("metric {} is a counter", name),
+        }
+    }
+
+    fn register(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        new: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            series: BTreeMap::new(),
+        });
+        family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(new)
+            .clone()
+    }
+
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.series.values().next() {
+                Some(Metric::Counter(_)) => "counter",
+                Some(Metric::Gauge(_)) => "gauge",
+                None => continue,
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, metric) in &family.series {
+                let _ = match metric {
+                    Metric::Counter(c) => writeln!(out, "{}{} {}", name, labels, c.get()),
+                    Metric::Gauge(g) => writeln!(out, "{}{} {}", name, labels, g.get()),
+                };
+            }
+        }
+        out
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// Serve `registry` over HTTP on `addr` from a background thread, any path
+/// returns the metrics.
+pub fn serve(addr: SocketAddr, registry: Registry) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = respond(stream, &registry);
+            }
+        })
+}
+
+fn respond(mut stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    // skip the request head, the response does not depend on it
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let body = registry.render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
 }
 
 #[cfg(test)]
@@ -7,8 +173,27 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn test_render() {
+        let registry = Registry::new();
+        let dropped = registry.counter("dropped_total", "Dropped frames", &[("stage", "capture")]);
+        dropped.add(3);
+        // same series, same counter
+        registry
+            .counter("dropped_total", "Dropped frames", &[("stage", "capture")])
+            .inc();
+        registry
+            .gauge("queue_depth", "Queued items", &[("shard", "0")])
+            .set(7);
+
+        assert_eq!(dropped.get(), 4);
+        assert_eq!(
+            registry.render(),
+            "# HELP dropped_total Dropped frames\n\
+             # TYPE dropped_total counter\n\
+             dropped_total{stage=\"capture\"} 4\n\
+             # HELP queue_depth Queued items\n\
+             # TYPE queue_depth gauge\n\
+             queue_depth{shard=\"0\"} 7\n"
+        );
     }
 }
@@ -4,7 +4,7 @@ use std::error::Error;
 
 pub mod mysql;
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Command(pub u8);
 
 impl From<u8> for Command {
@@ -31,10 +31,12 @@ impl std::fmt::Display for DBType {
 impl std::str::FromStr for DBType {
     type Err = Box<dyn Error>;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "MySQL" => Ok(DBType::MySQL),
-            "Unknown" => Ok(DBType::Unknown),
-            _ => Err("Invalid DBType".into()),
+        if s.eq_ignore_ascii_case("mysql") {
+            Ok(DBType::MySQL)
+        } else if s.eq_ignore_ascii_case("unknown") {
+            Ok(DBType::Unknown)
+        } else {
+            Err("Invalid DBType".into())
         }
     }
 }
@@ -53,7 +55,8 @@ impl PrimitiveValues for DBType {
 pub trait DBPacket: Send + Sync + CloneBoxDBPacket {
     fn db_type(&self) -> DBType;
     fn get_command(&self) -> Command;
-    fn get_payload(&self) -> Vec<u8>;
+    // borrowed view, the packet keeps sharing the captured frame
+    fn get_payload(&self) -> &[u8];
     fn get_seq(&self) -> u8;
     fn get_len(&self) -> u32;
     fn as_any(&self) -> &dyn Any;
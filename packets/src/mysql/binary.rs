@@ -0,0 +1,416 @@
+//! Values of the binary protocol, the rows of a prepared statement result
+//! and the parameters of COM_STMT_EXECUTE.
+
+use crate::mysql::common::*;
+use crate::mysql::server::tabluar::ColDef;
+use bytes::Buf;
+use std::fmt;
+use std::io::Cursor;
+
+/// How a value is laid out, its column type and whether an integer is
+/// unsigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueType {
+    pub column_type: u8,
+    pub unsigned: bool,
+}
+
+impl ValueType {
+    pub fn new(column_type: u8, unsigned: bool) -> Self {
+        ValueType {
+            column_type,
+            unsigned,
+        }
+    }
+}
+
+impl From<&ColDef> for ValueType {
+    fn from(col: &ColDef) -> Self {
+        ValueType::new(col.column_type, col.flags & UNSIGNED_FLAG > 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Int(i64),
+    UInt(u64),
+    Float(f32),
+    Double(f64),
+    // strings, decimals, blobs, JSON and everything else sent length-encoded
+    Bytes(Vec<u8>),
+    // DATE, DATETIME and TIMESTAMP
+    Date {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+        micros: u32,
+    },
+    Time {
+        negative: bool,
+        days: u32,
+        hours: u8,
+        minutes: u8,
+        seconds: u8,
+        micros: u32,
+    },
+}
+
+/// Bytes of an integer column type, 8 for anything else.
+fn int_len(column_type: u8) -> usize {
+    match column_type {
+        MYSQL_TYPE_TINY => 1,
+        MYSQL_TYPE_SHORT | MYSQL_TYPE_YEAR => 2,
+        MYSQL_TYPE_LONG | MYSQL_TYPE_INT24 => 4,
+        _ => 8,
+    }
+}
+
+impl Value {
+    /// Value of type `ty` at the cursor, NULLs are only in the null bitmap
+    /// and never read here.
+    pub fn read(ty: ValueType, reader: &mut Cursor<&[u8]>) -> Option<Value> {
+        let value = match ty.column_type {
+            MYSQL_TYPE_NULL => Value::Null,
+            MYSQL_TYPE_TINY | MYSQL_TYPE_SHORT | MYSQL_TYPE_YEAR | MYSQL_TYPE_LONG
+            | MYSQL_TYPE_INT24 | MYSQL_TYPE_LONGLONG => {
+                let len = int_len(ty.column_type);
+                let v = reader.try_get_uint_le(len).ok()?;
+                if ty.unsigned {
+                    Value::UInt(v)
+                } else {
+                    // sign extend from the width of the column
+                    let shift = 64 - 8 * len as u32;
+                    Value::Int(((v << shift) as i64) >> shift)
+                }
+            }
+            MYSQL_TYPE_FLOAT => Value::Float(reader.try_get_f32_le().ok()?),
+            MYSQL_TYPE_DOUBLE => Value::Double(reader.try_get_f64_le().ok()?),
+            MYSQL_TYPE_DATE | MYSQL_TYPE_DATETIME | MYSQL_TYPE_TIMESTAMP => {
+                // 0, 4, 7 or 11 bytes, trailing zero parts are left out
+                let len = reader.try_get_u8().ok()?;
+                let mut date = [0u8; 11];
+                match len {
+                    0 | 4 | 7 | 11 if reader.remaining() >= len as usize => {
+                        reader.copy_to_slice(&mut date[..len as usize])
+                    }
+                    _ => return None,
+                }
+                Value::Date {
+                    year: u16::from_le_bytes([date[0], date[1]]),
+                    month: date[2],
+                    day: date[3],
+                    hour: date[4],
+                    minute: date[5],
+                    second: date[6],
+                    micros: u32::from_le_bytes([date[7], date[8], date[9], date[10]]),
+                }
+            }
+            MYSQL_TYPE_TIME => {
+                // 0, 8 or 12 bytes
+                let len = reader.try_get_u8().ok()?;
+                let mut time = [0u8; 12];
+                match len {
+                    0 | 8 | 12 if reader.remaining() >= len as usize => {
+                        reader.copy_to_slice(&mut time[..len as usize])
+                    }
+                    _ => return None,
+                }
+                Value::Time {
+                    negative: time[0] == 1,
+                    days: u32::from_le_bytes([time[1], time[2], time[3], time[4]]),
+                    hours: time[5],
+                    minutes: time[6],
+                    seconds: time[7],
+                    micros: u32::from_le_bytes([time[8], time[9], time[10], time[11]]),
+                }
+            }
+            _ => Value::Bytes(read_len_enc_bytes(reader)?.0),
+        };
+        Some(value)
+    }
+
+    /// Append the value in the layout of `ty`, integers are cut to the width
+    /// of the column. A NULL takes no space.
+    pub fn write(&self, ty: ValueType, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => {}
+            Value::Int(v) => buf.extend_from_slice(&v.to_le_bytes()[..int_len(ty.column_type)]),
+            Value::UInt(v) => buf.extend_from_slice(&v.to_le_bytes()[..int_len(ty.column_type)]),
+            Value::Float(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Value::Double(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Value::Bytes(v) => write_len_enc_str(buf, v),
+            Value::Date {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                micros,
+            } => {
+                let len = if *micros > 0 {
+                    11
+                } else if (hour, minute, second) != (&0, &0, &0) {
+                    7
+                } else if (year, month, day) != (&0, &0, &0) {
+                    4
+                } else {
+                    0
+                };
+                let mut date = [0u8; 11];
+                date[..2].copy_from_slice(&year.to_le_bytes());
+                date[2..7].copy_from_slice(&[*month, *day, *hour, *minute, *second]);
+                date[7..].copy_from_slice(&micros.to_le_bytes());
+                buf.push(len as u8);
+                buf.extend_from_slice(&date[..len]);
+            }
+            Value::Time {
+                negative,
+                days,
+                hours,
+                minutes,
+                seconds,
+                micros,
+            } => {
+                let len = if *micros > 0 {
+                    12
+                } else if *negative || (days, hours, minutes, seconds) != (&0, &0, &0, &0) {
+                    8
+                } else {
+                    0
+                };
+                let mut time = [0u8; 12];
+                time[0] = *negative as u8;
+                time[1..5].copy_from_slice(&days.to_le_bytes());
+                time[5..8].copy_from_slice(&[*hours, *minutes, *seconds]);
+                time[8..].copy_from_slice(&micros.to_le_bytes());
+                buf.push(len as u8);
+                buf.extend_from_slice(&time[..len]);
+            }
+        }
+    }
+}
+
+/// The value as the text protocol would send it, NULL as `NULL`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::UInt(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Double(v) => write!(f, "{}", v),
+            Value::Bytes(v) => write!(f, "{}", String::from_utf8_lossy(v)),
+            Value::Date {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+                micros,
+            } => {
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)?;
+                if (hour, minute, second, micros) != (&0, &0, &0, &0) {
+                    write!(f, " {:02}:{:02}:{:02}", hour, minute, second)?;
+                }
+                if *micros > 0 {
+                    write!(f, ".{:06}", micros)?;
+                }
+                Ok(())
+            }
+            Value::Time {
+                negative,
+                days,
+                hours,
+                minutes,
+                seconds,
+                micros,
+            } => {
+                let sign = if *negative { "-" } else { "" };
+                let hours = *days as u64 * 24 + *hours as u64;
+                write!(f, "{}{:02}:{:02}:{:02}", sign, hours, minutes, seconds)?;
+                if *micros > 0 {
+                    write!(f, ".{:06}", micros)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Null bitmap of `values`, bits start at `offset`: 2 in rows, 0 in
+/// COM_STMT_EXECUTE.
+pub(crate) fn null_bitmap(values: &[Value], offset: usize) -> Vec<u8> {
+    let mut bitmap = vec![0; (values.len() + offset).div_ceil(8)];
+    for (i, value) in values.iter().enumerate() {
+        if *value == Value::Null {
+            let bit = i + offset;
+            bitmap[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    bitmap
+}
+
+/// Null bitmap of `columns` values with bits from `offset`.
+pub(crate) fn read_null_bitmap<'a>(
+    columns: usize,
+    offset: usize,
+    reader: &mut Cursor<&'a [u8]>,
+) -> Option<&'a [u8]> {
+    let data: &'a [u8] = reader.get_ref();
+    let start = reader.position() as usize;
+    let bitmap = data.get(start..start + (columns + offset).div_ceil(8))?;
+    reader.advance(bitmap.len());
+    Some(bitmap)
+}
+
+/// Values of `types`, those flagged in `bitmap` are NULL.
+pub(crate) fn read_values(
+    types: &[ValueType],
+    bitmap: &[u8],
+    offset: usize,
+    reader: &mut Cursor<&[u8]>,
+) -> Option<Vec<Value>> {
+    let mut values = Vec::new();
+    for (i, ty) in types.iter().enumerate() {
+        let bit = i + offset;
+        if bitmap[bit / 8] & (1 << (bit % 8)) > 0 {
+            values.push(Value::Null);
+        } else {
+            values.push(Value::read(*ty, reader)?);
+        }
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// A type and a value it can hold without loss.
+    pub(crate) fn typed_value() -> impl Strategy<Value = (ValueType, Value)> {
+        let int = |column_type: u8, bits: u32| {
+            let max = (1i64 << (bits - 1)) - 1;
+            (-max - 1..=max).prop_map(move |v| (ValueType::new(column_type, false), Value::Int(v)))
+        };
+        prop_oneof![
+            int(MYSQL_TYPE_TINY, 8),
+            int(MYSQL_TYPE_SHORT, 16),
+            int(MYSQL_TYPE_LONG, 32),
+            any::<i64>().prop_map(|v| (ValueType::new(MYSQL_TYPE_LONGLONG, false), Value::Int(v))),
+            any::<u64>().prop_map(|v| (ValueType::new(MYSQL_TYPE_LONGLONG, true), Value::UInt(v))),
+            (0..=u32::MAX as u64)
+                .prop_map(|v| (ValueType::new(MYSQL_TYPE_LONG, true), Value::UInt(v))),
+            any::<f32>()
+                .prop_filter("NaN is not equal to itself", |v| !v.is_nan())
+                .prop_map(|v| (ValueType::new(MYSQL_TYPE_FLOAT, false), Value::Float(v))),
+            any::<f64>()
+                .prop_filter("NaN is not equal to itself", |v| !v.is_nan())
+                .prop_map(|v| (ValueType::new(MYSQL_TYPE_DOUBLE, false), Value::Double(v))),
+            proptest::collection::vec(any::<u8>(), 0..300).prop_map(|v| (
+                ValueType::new(MYSQL_TYPE_VAR_STRING, false),
+                Value::Bytes(v)
+            )),
+            (
+                any::<u16>(),
+                0..13u8,
+                0..32u8,
+                0..24u8,
+                0..60u8,
+                0..60u8,
+                0..1_000_000u32
+            )
+                .prop_map(|(year, month, day, hour, minute, second, micros)| {
+                    let date = Value::Date {
+                        year,
+                        month,
+                        day,
+                        hour,
+                        minute,
+                        second,
+                        micros,
+                    };
+                    (ValueType::new(MYSQL_TYPE_DATETIME, false), date)
+                }),
+            (
+                any::<bool>(),
+                0..35u32,
+                0..24u8,
+                0..60u8,
+                0..60u8,
+                0..1_000_000u32
+            )
+                .prop_map(|(negative, days, hours, minutes, seconds, micros)| {
+                    let time = Value::Time {
+                        negative,
+                        days,
+                        hours,
+                        minutes,
+                        seconds,
+                        micros,
+                    };
+                    (ValueType::new(MYSQL_TYPE_TIME, false), time)
+                }),
+        ]
+    }
+
+    #[test]
+    fn test_read_values() {
+        // NULL, TINY -1 and a DATE
+        let types = [
+            ValueType::new(MYSQL_TYPE_LONG, false),
+            ValueType::new(MYSQL_TYPE_TINY, false),
+            ValueType::new(MYSQL_TYPE_DATE, false),
+        ];
+        let bytes: &[u8] = &[0b0000_0100, 0xff, 0x04, 0xe8, 0x07, 0x02, 0x1d];
+        let read = |bytes| {
+            let mut reader = Cursor::new(bytes);
+            let bitmap = read_null_bitmap(types.len(), 2, &mut reader)?;
+            read_values(&types, bitmap, 2, &mut reader)
+        };
+        let values = read(bytes).unwrap();
+        let date = Value::Date {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            micros: 0,
+        };
+        assert_eq!(values, [Value::Null, Value::Int(-1), date]);
+        assert_eq!(null_bitmap(&values, 2), [0b0000_0100]);
+        for len in 0..bytes.len() {
+            assert_eq!(read(&bytes[..len]), None);
+        }
+
+        let text: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        assert_eq!(text, ["NULL", "-1", "2024-02-29"]);
+        let time = Value::Time {
+            negative: true,
+            days: 1,
+            hours: 2,
+            minutes: 3,
+            seconds: 4,
+            micros: 500,
+        };
+        assert_eq!(time.to_string(), "-26:03:04.000500");
+    }
+
+    proptest! {
+        #[test]
+        fn test_value_round_trip((ty, value) in typed_value()) {
+            let mut buf = Vec::new();
+            value.write(ty, &mut buf);
+            let mut reader = Cursor::new(buf.as_slice());
+            prop_assert_eq!(Value::read(ty, &mut reader), Some(value));
+            prop_assert!(!reader.has_remaining());
+        }
+    }
+}
@@ -0,0 +1,1099 @@
+//! Binlog events, as a source streams them to a replica after
+//! COM_BINLOG_DUMP or COM_BINLOG_DUMP_GTID, one event in each packet behind
+//! an OK header. The events that follow transactions and the rows they
+//! change are decoded, any other is `EventBody::Other`.
+
+use crate::mysql::binary::Value;
+use crate::mysql::common::*;
+use bytes::Buf;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+
+// event type
+pub const QUERY_EVENT: u8 = 2;
+pub const ROTATE_EVENT: u8 = 4;
+pub const FORMAT_DESCRIPTION_EVENT: u8 = 15;
+pub const XID_EVENT: u8 = 16;
+pub const TABLE_MAP_EVENT: u8 = 19;
+pub const WRITE_ROWS_EVENT_V1: u8 = 23;
+pub const UPDATE_ROWS_EVENT_V1: u8 = 24;
+pub const DELETE_ROWS_EVENT_V1: u8 = 25;
+pub const WRITE_ROWS_EVENT: u8 = 30;
+pub const UPDATE_ROWS_EVENT: u8 = 31;
+pub const DELETE_ROWS_EVENT: u8 = 32;
+pub const GTID_EVENT: u8 = 33;
+
+pub const EVENT_HEADER_LEN: usize = 19;
+const CHECKSUM_LEN: usize = 4;
+// binlog version, server version, create timestamp and header length
+const FORMAT_DESCRIPTION_LEN: usize = 57;
+const SERVER_VERSION_LEN: usize = 50;
+const CHECKSUM_CRC32: u8 = 1;
+// the last rows event of a statement, its table maps are done with
+const STMT_END_F: u16 = 1;
+// table maps kept at most, more than a statement ever needs
+const MAX_TABLES: usize = 1024;
+// digits of a decimal are stored 9 to 4 bytes, the rest in this many bytes
+const DIG2BYTES: [usize; 10] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventHeader {
+    // seconds since the epoch the source wrote the event at, 0 for the
+    // rotate event a dump starts with
+    pub timestamp: u32,
+    pub event_type: u8,
+    pub server_id: u32,
+    pub event_size: u32,
+    // position of the next event in the binlog file
+    pub log_pos: u32,
+    pub flags: u16,
+}
+
+impl EventHeader {
+    pub fn new(reader: &mut Cursor<&[u8]>) -> Option<Self> {
+        Some(EventHeader {
+            timestamp: reader.try_get_u32_le().ok()?,
+            event_type: reader.try_get_u8().ok()?,
+            server_id: reader.try_get_u32_le().ok()?,
+            event_size: reader.try_get_u32_le().ok()?,
+            log_pos: reader.try_get_u32_le().ok()?,
+            flags: reader.try_get_u16_le().ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub header: EventHeader,
+    pub body: EventBody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventBody {
+    FormatDescription(FormatDescriptionEvent),
+    Rotate(RotateEvent),
+    Gtid(GtidEvent),
+    Query(QueryEvent),
+    TableMap(TableMapEvent),
+    Rows(RowsEvent),
+    // the transaction committed
+    Xid(u64),
+    // any other event, by its type
+    Other(u8),
+}
+
+/// The first event of every binlog file, and of every dump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatDescriptionEvent {
+    pub binlog_version: u16,
+    pub server_version: String,
+    pub create_timestamp: u32,
+    pub header_len: u8,
+    // the events that follow end with a CRC32
+    pub checksum: bool,
+}
+
+impl FormatDescriptionEvent {
+    /// The body of the event, its checksum included.
+    pub fn new(body: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(body);
+        let binlog_version = reader.try_get_u16_le().ok()?;
+        let version = reader.chunk().get(..SERVER_VERSION_LEN)?;
+        let len = version
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(version.len());
+        let server_version = String::from_utf8_lossy(&version[..len]).to_string();
+        reader.advance(SERVER_VERSION_LEN);
+        let create_timestamp = reader.try_get_u32_le().ok()?;
+        let header_len = reader.try_get_u8().ok()?;
+        // since 5.6.1 the post-header lengths are followed by the checksum
+        // algorithm and a checksum, whatever the algorithm
+        let checksum = if checksum_version(&server_version) {
+            let alg = body.len().checked_sub(CHECKSUM_LEN + 1)?;
+            if alg < FORMAT_DESCRIPTION_LEN {
+                return None;
+            }
+            body[alg] == CHECKSUM_CRC32
+        } else {
+            false
+        };
+        Some(FormatDescriptionEvent {
+            binlog_version,
+            server_version,
+            create_timestamp,
+            header_len,
+            checksum,
+        })
+    }
+}
+
+/// Whether a server of `version` writes the checksum algorithm, 5.6.1 and
+/// later.
+fn checksum_version(version: &str) -> bool {
+    let mut parts = version.split('.').map(|part| {
+        part.bytes()
+            .take_while(u8::is_ascii_digit)
+            .fold(0u32, |n, d| {
+                n.saturating_mul(10).saturating_add((d - b'0') as u32)
+            })
+    });
+    let mut next = || parts.next().unwrap_or_default();
+    (next(), next(), next()) >= (5, 6, 1)
+}
+
+/// The binlog continues in another file, or, with a zero timestamp, the
+/// file a dump starts in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RotateEvent {
+    pub position: u64,
+    pub file: String,
+}
+
+impl RotateEvent {
+    pub fn new(body: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(body);
+        Some(RotateEvent {
+            position: reader.try_get_u64_le().ok()?,
+            file: String::from_utf8_lossy(reader.chunk()).to_string(),
+        })
+    }
+}
+
+/// The GTID of the transaction that follows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GtidEvent {
+    pub flags: u8,
+    // the UUID of the source that committed the transaction first
+    pub sid: [u8; 16],
+    pub gno: i64,
+}
+
+impl GtidEvent {
+    pub fn new(body: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(body);
+        let flags = reader.try_get_u8().ok()?;
+        let mut sid = [0; 16];
+        reader.chunk().get(..sid.len())?;
+        reader.copy_to_slice(&mut sid);
+        Some(GtidEvent {
+            flags,
+            sid,
+            gno: reader.try_get_i64_le().ok()?,
+        })
+    }
+}
+
+/// As MySQL writes it, e.g. `3e11fa47-71ca-11e1-9e33-c80aa9429562:23`.
+impl fmt::Display for GtidEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, b) in self.sid.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, ":{}", self.gno)
+    }
+}
+
+/// A statement logged as such: DDL, BEGIN, and any DML with statement based
+/// logging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryEvent {
+    pub thread_id: u32,
+    pub exec_time: u32,
+    pub error_code: u16,
+    // the default schema of the statement
+    pub schema: String,
+    pub query: String,
+}
+
+impl QueryEvent {
+    pub fn new(body: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(body);
+        let thread_id = reader.try_get_u32_le().ok()?;
+        let exec_time = reader.try_get_u32_le().ok()?;
+        let schema_len = reader.try_get_u8().ok()? as usize;
+        let error_code = reader.try_get_u16_le().ok()?;
+        // the session variables the statement ran with, skipped
+        let status_len = reader.try_get_u16_le().ok()? as usize;
+        reader.chunk().get(..status_len)?;
+        reader.advance(status_len);
+        let schema = String::from_utf8_lossy(reader.chunk().get(..schema_len)?).to_string();
+        reader.advance(schema_len);
+        if reader.try_get_u8().ok()? != 0 {
+            return None;
+        }
+        Some(QueryEvent {
+            thread_id,
+            exec_time,
+            error_code,
+            schema,
+            query: String::from_utf8_lossy(reader.chunk()).to_string(),
+        })
+    }
+}
+
+/// A column of a table map, what its values in rows events look like.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinlogColumn {
+    pub column_type: u8,
+    // lengths, precision and the like, depending on the type
+    pub meta: u16,
+    pub nullable: bool,
+    // only known with binlog_row_metadata=FULL, signed otherwise
+    pub unsigned: bool,
+}
+
+/// The table the rows events that follow change, by its table id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableMapEvent {
+    pub table_id: u64,
+    pub schema: String,
+    pub table: String,
+    pub columns: Vec<BinlogColumn>,
+}
+
+// the optional metadata field of the unsigned numeric columns
+const SIGNEDNESS: u8 = 1;
+
+impl TableMapEvent {
+    pub fn new(body: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(body);
+        let table_id = reader.try_get_uint_le(6).ok()?;
+        let _flags = reader.try_get_u16_le().ok()?;
+        let mut name = || {
+            let len = reader.try_get_u8().ok()? as usize;
+            let name = String::from_utf8_lossy(reader.chunk().get(..len)?).to_string();
+            reader.advance(len);
+            (reader.try_get_u8().ok()? == 0).then_some(name)
+        };
+        let (schema, table) = (name()?, name()?);
+        let (count, _) = read_len_enc_int(&mut reader)?;
+        // a type byte for each
+        if count > reader.remaining() as u64 {
+            return None;
+        }
+        let types = reader.chunk()[..count as usize].to_vec();
+        reader.advance(types.len());
+        let (meta, _) = read_len_enc_bytes(&mut reader)?;
+        let mut meta = Cursor::new(meta.as_slice());
+        let nulls = read_bitmap(types.len(), &mut reader)?;
+        let mut columns = Vec::with_capacity(types.len());
+        for (i, &column_type) in types.iter().enumerate() {
+            columns.push(BinlogColumn {
+                column_type,
+                meta: read_meta(column_type, &mut meta)?,
+                nullable: bit(&nulls, i),
+                unsigned: false,
+            });
+        }
+        // optional metadata, type, length and value
+        while reader.has_remaining() {
+            let field = reader.try_get_u8().ok()?;
+            let (value, _) = read_len_enc_bytes(&mut reader)?;
+            if field == SIGNEDNESS {
+                let numeric = columns.iter_mut().filter(|c| numeric_type(c.column_type));
+                for (i, column) in numeric.enumerate() {
+                    // most significant bit first
+                    column.unsigned = value.get(i / 8).is_some_and(|b| b & (0x80 >> (i % 8)) > 0);
+                }
+            }
+        }
+        Some(TableMapEvent {
+            table_id,
+            schema,
+            table,
+            columns,
+        })
+    }
+}
+
+/// The metadata of a column of `column_type` in a table map, 0 for the
+/// types without.
+fn read_meta(column_type: u8, reader: &mut Cursor<&[u8]>) -> Option<u16> {
+    Some(match column_type {
+        MYSQL_TYPE_FLOAT
+        | MYSQL_TYPE_DOUBLE
+        | MYSQL_TYPE_TINY_BLOB
+        | MYSQL_TYPE_MEDIUM_BLOB
+        | MYSQL_TYPE_LONG_BLOB
+        | MYSQL_TYPE_BLOB
+        | MYSQL_TYPE_GEOMETRY
+        | MYSQL_TYPE_JSON
+        | MYSQL_TYPE_TIMESTAMP2
+        | MYSQL_TYPE_DATETIME2
+        | MYSQL_TYPE_TIME2 => reader.try_get_u8().ok()? as u16,
+        MYSQL_TYPE_VARCHAR | MYSQL_TYPE_BIT => reader.try_get_u16_le().ok()?,
+        MYSQL_TYPE_STRING | MYSQL_TYPE_ENUM | MYSQL_TYPE_SET | MYSQL_TYPE_NEWDECIMAL => {
+            reader.try_get_u16().ok()?
+        }
+        _ => 0,
+    })
+}
+
+fn numeric_type(column_type: u8) -> bool {
+    matches!(
+        column_type,
+        MYSQL_TYPE_TINY
+            | MYSQL_TYPE_SHORT
+            | MYSQL_TYPE_INT24
+            | MYSQL_TYPE_LONG
+            | MYSQL_TYPE_LONGLONG
+            | MYSQL_TYPE_FLOAT
+            | MYSQL_TYPE_DOUBLE
+            | MYSQL_TYPE_DECIMAL
+            | MYSQL_TYPE_NEWDECIMAL
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowsKind {
+    Write,
+    Update,
+    Delete,
+}
+
+/// A row a rows event changes. The images hold a value for each column of
+/// the table, `None` for the columns binlog_row_image leaves out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    // the row before an update or delete
+    pub before: Option<Vec<Option<Value>>>,
+    // the row after a write or update
+    pub after: Option<Vec<Option<Value>>>,
+}
+
+/// Rows written, updated or deleted in the table of a table map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowsEvent {
+    pub kind: RowsKind,
+    pub table_id: u64,
+    pub flags: u16,
+    // `None` without the table map of the table or for a column type that
+    // is not decoded
+    pub rows: Option<Vec<Row>>,
+}
+
+impl RowsEvent {
+    /// A rows event of `event_type`, v1 or v2, with the values decoded after
+    /// its table in `tables`.
+    pub fn new(event_type: u8, body: &[u8], tables: &HashMap<u64, TableMapEvent>) -> Option<Self> {
+        let kind = match event_type {
+            WRITE_ROWS_EVENT_V1 | WRITE_ROWS_EVENT => RowsKind::Write,
+            UPDATE_ROWS_EVENT_V1 | UPDATE_ROWS_EVENT => RowsKind::Update,
+            DELETE_ROWS_EVENT_V1 | DELETE_ROWS_EVENT => RowsKind::Delete,
+            _ => return None,
+        };
+        let mut reader = Cursor::new(body);
+        let table_id = reader.try_get_uint_le(6).ok()?;
+        let flags = reader.try_get_u16_le().ok()?;
+        if event_type >= WRITE_ROWS_EVENT {
+            // extra data, its length counts itself
+            let extra = (reader.try_get_u16_le().ok()? as usize).checked_sub(2)?;
+            reader.chunk().get(..extra)?;
+            reader.advance(extra);
+        }
+        let (count, _) = read_len_enc_int(&mut reader)?;
+        if count > reader.remaining() as u64 * 8 {
+            return None;
+        }
+        let present = read_bitmap(count as usize, &mut reader)?;
+        let present_after = match kind {
+            RowsKind::Update => read_bitmap(count as usize, &mut reader)?,
+            _ => present.clone(),
+        };
+        let rows = tables
+            .get(&table_id)
+            .filter(|table| table.columns.len() as u64 == count)
+            .and_then(|table| {
+                let mut rows = Vec::new();
+                while reader.has_remaining() {
+                    let mut image =
+                        |present: &[u8]| read_image(&table.columns, present, &mut reader);
+                    rows.push(match kind {
+                        RowsKind::Write => Row {
+                            before: None,
+                            after: Some(image(&present_after)?),
+                        },
+                        RowsKind::Update => Row {
+                            before: Some(image(&present)?),
+                            after: Some(image(&present_after)?),
+                        },
+                        RowsKind::Delete => Row {
+                            before: Some(image(&present)?),
+                            after: None,
+                        },
+                    });
+                }
+                Some(rows)
+            });
+        Some(RowsEvent {
+            kind,
+            table_id,
+            flags,
+            rows,
+        })
+    }
+}
+
+/// A row image, a null bitmap of the columns in `present` and their values.
+fn read_image(
+    columns: &[BinlogColumn],
+    present: &[u8],
+    reader: &mut Cursor<&[u8]>,
+) -> Option<Vec<Option<Value>>> {
+    let start = reader.position();
+    let count = (0..columns.len()).filter(|i| bit(present, *i)).count();
+    let nulls = read_bitmap(count, reader)?;
+    let mut values = Vec::with_capacity(columns.len());
+    let mut n = 0;
+    for (i, column) in columns.iter().enumerate() {
+        if !bit(present, i) {
+            values.push(None);
+            continue;
+        }
+        let null = bit(&nulls, n);
+        n += 1;
+        values.push(Some(if null {
+            Value::Null
+        } else {
+            read_value(column, reader)?
+        }));
+    }
+    // an image of nothing would never end the rows
+    (reader.position() > start).then_some(values)
+}
+
+/// The value of `column` in a row image, `None` for the types not decoded:
+/// the pre-5.0 DECIMAL and VAR_STRING.
+fn read_value(column: &BinlogColumn, reader: &mut Cursor<&[u8]>) -> Option<Value> {
+    let meta = column.meta;
+    let value = match column.column_type {
+        MYSQL_TYPE_NULL => Value::Null,
+        MYSQL_TYPE_TINY | MYSQL_TYPE_SHORT | MYSQL_TYPE_INT24 | MYSQL_TYPE_LONG
+        | MYSQL_TYPE_LONGLONG => {
+            let len = match column.column_type {
+                MYSQL_TYPE_TINY => 1,
+                MYSQL_TYPE_SHORT => 2,
+                MYSQL_TYPE_INT24 => 3,
+                MYSQL_TYPE_LONG => 4,
+                _ => 8,
+            };
+            let v = reader.try_get_uint_le(len).ok()?;
+            if column.unsigned {
+                Value::UInt(v)
+            } else {
+                // sign extend from the width of the column
+                let shift = 64 - 8 * len as u32;
+                Value::Int(((v << shift) as i64) >> shift)
+            }
+        }
+        MYSQL_TYPE_FLOAT => Value::Float(reader.try_get_f32_le().ok()?),
+        MYSQL_TYPE_DOUBLE => Value::Double(reader.try_get_f64_le().ok()?),
+        MYSQL_TYPE_YEAR => match reader.try_get_u8().ok()? {
+            0 => Value::UInt(0),
+            year => Value::UInt(1900 + year as u64),
+        },
+        MYSQL_TYPE_DATE => {
+            let v = reader.try_get_uint_le(3).ok()?;
+            date(v >> 9, (v >> 5) & 0xf, v & 0x1f, 0, 0)
+        }
+        MYSQL_TYPE_DATETIME => {
+            // YYYYMMDDhhmmss as a number
+            let v = reader.try_get_u64_le().ok()?;
+            let (d, t) = (v / 1_000_000, v % 1_000_000);
+            date(d / 10000, d / 100 % 100, d % 100, t, 0)
+        }
+        MYSQL_TYPE_DATETIME2 => {
+            let v = reader.try_get_uint(5).ok()?.wrapping_sub(0x80_0000_0000);
+            let (ymd, hms) = (v >> 17, v & 0x1ffff);
+            let (ym, day) = (ymd >> 5, ymd & 0x1f);
+            let hms = (hms >> 12) * 10000 + (hms >> 6 & 0x3f) * 100 + (hms & 0x3f);
+            date(ym / 13, ym % 13, day, hms, read_micros(meta, reader)?)
+        }
+        MYSQL_TYPE_TIMESTAMP => timestamp(reader.try_get_u32_le().ok()?, 0),
+        MYSQL_TYPE_TIMESTAMP2 => {
+            let secs = reader.try_get_u32().ok()?;
+            timestamp(secs, read_micros(meta, reader)?)
+        }
+        MYSQL_TYPE_TIME => {
+            // hhmmss as a number
+            let v = reader.try_get_int_le(3).ok()?;
+            let abs = v.unsigned_abs();
+            time(v < 0, abs / 10000, abs / 100 % 100, abs % 100, 0)
+        }
+        MYSQL_TYPE_TIME2 => read_time2(meta, reader)?,
+        MYSQL_TYPE_VARCHAR => {
+            let len = if meta < 256 { 1 } else { 2 };
+            Value::Bytes(read_bytes(len, reader)?)
+        }
+        MYSQL_TYPE_STRING => {
+            let (byte0, byte1) = ((meta >> 8) as u8, meta & 0xff);
+            // CHAR longer than 255 keeps bits of its length in the type
+            let (real_type, len) = if byte0 & 0x30 != 0x30 {
+                (byte0 | 0x30, byte1 | (((byte0 as u16 & 0x30) ^ 0x30) << 4))
+            } else {
+                (byte0, byte1)
+            };
+            match real_type {
+                MYSQL_TYPE_ENUM | MYSQL_TYPE_SET => read_enum(byte1, reader)?,
+                _ => Value::Bytes(read_bytes(if len < 256 { 1 } else { 2 }, reader)?),
+            }
+        }
+        MYSQL_TYPE_ENUM | MYSQL_TYPE_SET => read_enum(meta & 0xff, reader)?,
+        MYSQL_TYPE_BIT => {
+            let len = (meta >> 8) as usize + (meta & 0xff > 0) as usize;
+            if !(1..=8).contains(&len) {
+                return None;
+            }
+            Value::UInt(reader.try_get_uint(len).ok()?)
+        }
+        MYSQL_TYPE_NEWDECIMAL => {
+            read_decimal((meta >> 8) as usize, (meta & 0xff) as usize, reader)?
+        }
+        MYSQL_TYPE_TINY_BLOB
+        | MYSQL_TYPE_MEDIUM_BLOB
+        | MYSQL_TYPE_LONG_BLOB
+        | MYSQL_TYPE_BLOB
+        | MYSQL_TYPE_GEOMETRY
+        | MYSQL_TYPE_JSON => {
+            // JSON stays in the binary form of the server
+            if !(1..=4).contains(&meta) {
+                return None;
+            }
+            Value::Bytes(read_bytes(meta as usize, reader)?)
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn date(year: u64, month: u64, day: u64, hms: u64, micros: u32) -> Value {
+    Value::Date {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+        hour: (hms / 10000) as u8,
+        minute: (hms / 100 % 100) as u8,
+        second: (hms % 100) as u8,
+        micros,
+    }
+}
+
+fn time(negative: bool, hours: u64, minutes: u64, seconds: u64, micros: u32) -> Value {
+    Value::Time {
+        negative,
+        days: (hours / 24) as u32,
+        hours: (hours % 24) as u8,
+        minutes: minutes as u8,
+        seconds: seconds as u8,
+        micros,
+    }
+}
+
+/// Seconds since the epoch as a UTC date, TIMESTAMP is stored so.
+fn timestamp(secs: u32, micros: u32) -> Value {
+    let (days, secs) = (secs as u64 / 86400, secs as u64 % 86400);
+    // days to a civil date, from the algorithms of Howard Hinnant
+    let z = days + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    let hms = secs / 3600 * 10000 + secs / 60 % 60 * 100 + secs % 60;
+    date(year, month, day, hms, micros)
+}
+
+/// The fractional seconds of a temporal with `fsp` digits, stored big
+/// endian in as few bytes as they fit.
+fn read_micros(fsp: u16, reader: &mut Cursor<&[u8]>) -> Option<u32> {
+    Some(match fsp {
+        0 => 0,
+        1 | 2 => reader.try_get_u8().ok()? as u32 * 10000,
+        3 | 4 => reader.try_get_u16().ok()? as u32 * 100,
+        5 | 6 => reader.try_get_uint(3).ok()? as u32,
+        _ => return None,
+    })
+}
+
+/// TIME2, hours, minutes and seconds in 3 bytes and the fractional seconds
+/// behind, the whole offset so that negative times sort first.
+fn read_time2(fsp: u16, reader: &mut Cursor<&[u8]>) -> Option<Value> {
+    let mut int = reader.try_get_uint(3).ok()? as i64 - 0x80_0000;
+    // the fraction of a negative time is negative too, from the next second
+    let frac = match fsp {
+        0 => 0,
+        1 | 2 => {
+            let frac = reader.try_get_u8().ok()? as i64;
+            if int < 0 && frac != 0 {
+                int += 1;
+                (frac - 0x100) * 10000
+            } else {
+                frac * 10000
+            }
+        }
+        3 | 4 => {
+            let frac = reader.try_get_u16().ok()? as i64;
+            if int < 0 && frac != 0 {
+                int += 1;
+                (frac - 0x10000) * 100
+            } else {
+                frac * 100
+            }
+        }
+        5 | 6 => reader.try_get_uint(3).ok()? as i64,
+        _ => return None,
+    };
+    let packed = (int << 24) + frac;
+    let abs = packed.unsigned_abs();
+    let (hms, micros) = (abs >> 24, (abs & 0xff_ffff) as u32);
+    Some(time(
+        packed < 0,
+        hms >> 12 & 0x3ff,
+        hms >> 6 & 0x3f,
+        hms & 0x3f,
+        micros,
+    ))
+}
+
+/// DECIMAL(precision, scale), as text like the text protocol sends it.
+fn read_decimal(precision: usize, scale: usize, reader: &mut Cursor<&[u8]>) -> Option<Value> {
+    let intg = precision.checked_sub(scale)?;
+    let (intg0, intg0x) = (intg / 9, intg % 9);
+    let (frac0, frac0x) = (scale / 9, scale % 9);
+    let len = intg0 * 4 + DIG2BYTES[intg0x] + frac0 * 4 + DIG2BYTES[frac0x];
+    if len == 0 || len > reader.remaining() {
+        return None;
+    }
+    let mut bytes = reader.chunk()[..len].to_vec();
+    reader.advance(len);
+    // the sign is the top bit, cleared for negative numbers which have
+    // every bit inverted
+    let negative = bytes[0] & 0x80 == 0;
+    bytes[0] ^= 0x80;
+    if negative {
+        bytes.iter_mut().for_each(|b| *b = !*b);
+    }
+    let mut digits = Cursor::new(bytes.as_slice());
+    let mut group = |len: usize, width: usize| match len {
+        0 => String::new(),
+        len => format!("{:0width$}", digits.get_uint(len), width = width),
+    };
+    let mut int = group(DIG2BYTES[intg0x], 0);
+    for _ in 0..intg0 {
+        int += &group(4, 9);
+    }
+    let mut text = if negative {
+        "-".to_string()
+    } else {
+        String::new()
+    };
+    match int.trim_start_matches('0') {
+        "" => text.push('0'),
+        int => text.push_str(int),
+    }
+    if scale > 0 {
+        text.push('.');
+        for _ in 0..frac0 {
+            text += &group(4, 9);
+        }
+        text += &group(DIG2BYTES[frac0x], frac0x);
+    }
+    Some(Value::Bytes(text.into_bytes()))
+}
+
+/// ENUM or SET, the index or bits in `len` bytes.
+fn read_enum(len: u16, reader: &mut Cursor<&[u8]>) -> Option<Value> {
+    if !(1..=8).contains(&len) {
+        return None;
+    }
+    Some(Value::UInt(reader.try_get_uint_le(len as usize).ok()?))
+}
+
+/// Bytes behind a little endian length of `len` bytes.
+fn read_bytes(len: usize, reader: &mut Cursor<&[u8]>) -> Option<Vec<u8>> {
+    let len = reader.try_get_uint_le(len).ok()? as usize;
+    let bytes = reader.chunk().get(..len)?.to_vec();
+    reader.advance(len);
+    Some(bytes)
+}
+
+/// A bitmap of `bits` bits, the first in the lowest bit of the first byte.
+fn read_bitmap(bits: usize, reader: &mut Cursor<&[u8]>) -> Option<Vec<u8>> {
+    let bitmap = reader.chunk().get(..bits.div_ceil(8))?.to_vec();
+    reader.advance(bitmap.len());
+    Some(bitmap)
+}
+
+fn bit(bitmap: &[u8], i: usize) -> bool {
+    bitmap.get(i / 8).is_some_and(|b| b & (1 << (i % 8)) > 0)
+}
+
+/// The CRC32 binlog checksums use, that of zlib.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 > 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Decodes the events of one binlog stream, keeping what later events
+/// depend on: whether they end in a checksum, which the format description
+/// event tells, and the table maps of the statement rows events belong to.
+#[derive(Debug, Default)]
+pub struct BinlogDecoder {
+    // unknown before the format description event
+    checksum: Option<bool>,
+    tables: HashMap<u64, TableMapEvent>,
+    // the last rows event ended its statement, its table maps go next
+    release: bool,
+}
+
+impl BinlogDecoder {
+    /// The event in `data`, a packet of the stream without its OK header.
+    pub fn decode(&mut self, data: &[u8]) -> Option<Event> {
+        if std::mem::take(&mut self.release) {
+            self.tables.clear();
+        }
+        let header = EventHeader::new(&mut Cursor::new(data))?;
+        let event = data.get(..header.event_size as usize)?;
+        let mut body = event.get(EVENT_HEADER_LEN..)?;
+        if header.event_type == FORMAT_DESCRIPTION_EVENT {
+            let format = FormatDescriptionEvent::new(body)?;
+            self.checksum = Some(format.checksum);
+            return Some(Event {
+                header,
+                body: EventBody::FormatDescription(format),
+            });
+        }
+        // the rotate event a dump starts with comes before the format
+        // description, a checksum there is one that matches
+        let checksum = self.checksum.unwrap_or_else(|| {
+            let (event, checksum) = event.split_at(event.len().saturating_sub(CHECKSUM_LEN));
+            checksum.len() == CHECKSUM_LEN && checksum == crc32(event).to_le_bytes()
+        });
+        if checksum {
+            body = body.get(..body.len().checked_sub(CHECKSUM_LEN)?)?;
+        }
+        let body = match header.event_type {
+            QUERY_EVENT => EventBody::Query(QueryEvent::new(body)?),
+            ROTATE_EVENT => EventBody::Rotate(RotateEvent::new(body)?),
+            XID_EVENT => EventBody::Xid(Cursor::new(body).try_get_u64_le().ok()?),
+            TABLE_MAP_EVENT => {
+                let table = TableMapEvent::new(body)?;
+                if self.tables.len() >= MAX_TABLES {
+                    self.tables.clear();
+                }
+                self.tables.insert(table.table_id, table.clone());
+                EventBody::TableMap(table)
+            }
+            WRITE_ROWS_EVENT_V1..=DELETE_ROWS_EVENT_V1 | WRITE_ROWS_EVENT..=DELETE_ROWS_EVENT => {
+                let rows = RowsEvent::new(header.event_type, body, &self.tables)?;
+                self.release = rows.flags & STMT_END_F > 0;
+                EventBody::Rows(rows)
+            }
+            GTID_EVENT => EventBody::Gtid(GtidEvent::new(body)?),
+            event_type => EventBody::Other(event_type),
+        };
+        Some(Event { header, body })
+    }
+
+    /// The table map of `table_id`, until the statement of its rows events
+    /// ends.
+    pub fn table(&self, table_id: u64) -> Option<&TableMapEvent> {
+        self.tables.get(&table_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn value(column_type: u8, meta: u16, bytes: &[u8]) -> Option<String> {
+        let column = BinlogColumn {
+            column_type,
+            meta,
+            nullable: false,
+            unsigned: false,
+        };
+        let mut reader = Cursor::new(bytes);
+        let value = read_value(&column, &mut reader)?;
+        assert!(!reader.has_remaining());
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn test_start_of_dump() {
+        // the rotate event made up for the replica, before the format
+        // description tells that events end with a checksum
+        let rotate = [
+            0x00, 0x00, 0x00, 0x00, 0x04, 0x01, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x20, 0x00, 0xd2, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x62,
+            0x69, 0x6e, 0x6c, 0x6f, 0x67, 0x2e, 0x30, 0x30, 0x30, 0x30, 0x30, 0x33, 0xe4, 0x9f,
+            0xa2, 0xb5,
+        ];
+        let format_description = [
+            0x70, 0x60, 0xec, 0x65, 0x0f, 0x01, 0x00, 0x00, 0x00, 0x7a, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x38, 0x2e, 0x30, 0x2e, 0x33, 0x36, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x70, 0x60, 0xec, 0x65, 0x13, 0x38, 0x0d, 0x00, 0x08, 0x00, 0x12, 0x00, 0x04,
+            0x04, 0x04, 0x04, 0x12, 0x00, 0x00, 0x5f, 0x00, 0x04, 0x1a, 0x08, 0x00, 0x00, 0x00,
+            0x08, 0x08, 0x08, 0x02, 0x00, 0x00, 0x00, 0x0a, 0x0a, 0x0a, 0x2a, 0x2a, 0x00, 0x12,
+            0x34, 0x00, 0x0a, 0x28, 0x00, 0x01, 0x0f, 0x85, 0x8b, 0xbe,
+        ];
+        let xid = [
+            0x80, 0x87, 0xec, 0x65, 0x10, 0x01, 0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00, 0xf1,
+            0x04, 0x00, 0x00, 0x00, 0x00, 0x4d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x13,
+            0x69, 0x48, 0x07,
+        ];
+        let mut decoder = BinlogDecoder::default();
+        let event = decoder.decode(&rotate).unwrap();
+        assert_eq!(event.header.timestamp, 0);
+        assert_eq!(
+            event.body,
+            EventBody::Rotate(RotateEvent {
+                position: 1234,
+                file: "binlog.000003".to_string(),
+            })
+        );
+        let event = decoder.decode(&format_description).unwrap();
+        assert_eq!(event.header.timestamp, 1709990000);
+        assert_eq!(
+            event.body,
+            EventBody::FormatDescription(FormatDescriptionEvent {
+                binlog_version: 4,
+                server_version: "8.0.36".to_string(),
+                create_timestamp: 1709990000,
+                header_len: 19,
+                checksum: true,
+            })
+        );
+        let event = decoder.decode(&xid).unwrap();
+        assert_eq!(event.header.log_pos, 1265);
+        assert_eq!(event.body, EventBody::Xid(77));
+        for len in 0..rotate.len() {
+            assert_eq!(BinlogDecoder::default().decode(&rotate[..len]), None);
+        }
+    }
+
+    #[test]
+    fn test_gtid_and_query() {
+        let events = [
+            0x80, 0x87, 0xec, 0x65, 0x21, 0x01, 0x00, 0x00, 0x00, 0x41, 0x00, 0x00, 0x00, 0x8f,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3e, 0x11, 0xfa, 0x47, 0x71, 0xca, 0x11, 0xe1,
+            0x9e, 0x33, 0xc8, 0x0a, 0xa9, 0x42, 0x95, 0x62, 0x17, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x02, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0xe9, 0xa2, 0xf7, 0x9d, 0x80, 0x87, 0xec, 0x65, 0x02,
+            0x01, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00, 0xc2, 0x10, 0x00, 0x00, 0x00, 0x00,
+            0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x05, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x73, 0x68, 0x6f, 0x70, 0x00, 0x42, 0x45, 0x47, 0x49, 0x4e,
+            0x22, 0x78, 0xb5, 0x9b,
+        ];
+        let mut decoder = BinlogDecoder::default();
+        match decoder.decode(&events).unwrap().body {
+            EventBody::Gtid(gtid) => {
+                assert_eq!(gtid.to_string(), "3e11fa47-71ca-11e1-9e33-c80aa9429562:23")
+            }
+            body => panic!("{:?}", body),
+        }
+        let event = decoder.decode(&events[65..]).unwrap();
+        assert_eq!(event.header.log_pos, 4290);
+        assert_eq!(
+            event.body,
+            EventBody::Query(QueryEvent {
+                thread_id: 30,
+                exec_time: 0,
+                error_code: 0,
+                schema: "shop".to_string(),
+                query: "BEGIN".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_rows() {
+        // the table map of shop.users (id int unsigned, name varchar(64),
+        // created datetime), a write of two rows and an update of one
+        let events = [
+            0x80, 0x87, 0xec, 0x65, 0x13, 0x01, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0xd8,
+            0x0f, 0x00, 0x00, 0x00, 0x00, 0x6c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04,
+            0x73, 0x68, 0x6f, 0x70, 0x00, 0x05, 0x75, 0x73, 0x65, 0x72, 0x73, 0x00, 0x03, 0x03,
+            0x0f, 0x12, 0x03, 0x00, 0x01, 0x00, 0x04, 0x01, 0x01, 0x80, 0x01, 0x96, 0xb0, 0x1a,
+            0x80, 0x87, 0xec, 0x65, 0x1e, 0x01, 0x00, 0x00, 0x00, 0x3e, 0x00, 0x00, 0x00, 0x16,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x6c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02,
+            0x00, 0x03, 0x07, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x61, 0x64, 0x61, 0x99,
+            0xb2, 0xd3, 0x00, 0x00, 0x04, 0xff, 0xff, 0xff, 0xff, 0x05, 0x00, 0x67, 0x72, 0x61,
+            0x63, 0x65, 0xd5, 0xed, 0xd2, 0x42, 0x81, 0x87, 0xec, 0x65, 0x1f, 0x01, 0x00, 0x00,
+            0x00, 0x38, 0x00, 0x00, 0x00, 0x4e, 0x10, 0x00, 0x00, 0x00, 0x00, 0x6c, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x07, 0x07, 0x04, 0x07, 0x00, 0x00,
+            0x00, 0x03, 0x00, 0x61, 0x64, 0x61, 0x04, 0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x41,
+            0x64, 0x61, 0xd6, 0xff, 0x6c, 0x10,
+        ];
+        let (table_map, rest) = events.split_at(56);
+        let (write, update) = rest.split_at(62);
+        let mut decoder = BinlogDecoder {
+            checksum: Some(true),
+            ..Default::default()
+        };
+
+        let table = match decoder.decode(table_map).unwrap().body {
+            EventBody::TableMap(table) => table,
+            body => panic!("{:?}", body),
+        };
+        assert_eq!((table.table_id, table.schema.as_str()), (108, "shop"));
+        assert_eq!(table.table, "users");
+        let types: Vec<_> = table.columns.iter().map(|c| c.column_type).collect();
+        assert_eq!(
+            types,
+            [MYSQL_TYPE_LONG, MYSQL_TYPE_VARCHAR, MYSQL_TYPE_DATETIME2]
+        );
+        assert_eq!(table.columns[1].meta, 256);
+        assert!(table.columns[0].unsigned && !table.columns[0].nullable);
+        assert!(table.columns[2].nullable);
+
+        let rows = match decoder.decode(write).unwrap().body {
+            EventBody::Rows(rows) => rows,
+            body => panic!("{:?}", body),
+        };
+        assert_eq!((rows.kind, rows.table_id), (RowsKind::Write, 108));
+        let bytes = |s: &str| Some(Value::Bytes(s.as_bytes().to_vec()));
+        let created = Value::Date {
+            year: 2024,
+            month: 3,
+            day: 9,
+            hour: 16,
+            minute: 0,
+            second: 0,
+            micros: 0,
+        };
+        assert_eq!(
+            rows.rows.unwrap(),
+            [
+                Row {
+                    before: None,
+                    after: Some(vec![Some(Value::UInt(7)), bytes("ada"), Some(created)]),
+                },
+                Row {
+                    before: None,
+                    after: Some(vec![
+                        Some(Value::UInt(u32::MAX as u64)),
+                        bytes("grace"),
+                        Some(Value::Null)
+                    ]),
+                },
+            ]
+        );
+        assert!(decoder.table(108).is_some());
+
+        // the write ended its statement, the table map with it
+        let rows = match decoder.decode(update).unwrap().body {
+            EventBody::Rows(rows) => rows,
+            body => panic!("{:?}", body),
+        };
+        assert_eq!((rows.kind, rows.rows), (RowsKind::Update, None));
+        assert!(decoder.table(108).is_none());
+
+        decoder.decode(table_map).unwrap();
+        match decoder.decode(update).unwrap().body {
+            EventBody::Rows(RowsEvent {
+                rows: Some(rows), ..
+            }) => {
+                assert_eq!(rows.len(), 1);
+                assert_eq!(rows[0].before.as_ref().unwrap()[1], bytes("ada"));
+                assert_eq!(rows[0].after.as_ref().unwrap()[1], bytes("Ada"));
+            }
+            body => panic!("{:?}", body),
+        }
+        for len in 0..write.len() - CHECKSUM_LEN {
+            decoder.decode(table_map).unwrap();
+            if let Some(Event {
+                body: EventBody::Rows(rows),
+                ..
+            }) = decoder.decode(&write[..len])
+            {
+                assert_ne!(rows.rows.map(|rows| rows.len()), Some(2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        let decimal = (MYSQL_TYPE_NEWDECIMAL, 10 << 8 | 3);
+        assert_eq!(
+            value(decimal.0, decimal.1, &[0x80, 0x00, 0x04, 0xd2, 0x02, 0x37]).as_deref(),
+            Some("1234.567")
+        );
+        assert_eq!(
+            value(decimal.0, decimal.1, &[0x7f, 0xff, 0xfb, 0x2d, 0xfd, 0xc8]).as_deref(),
+            Some("-1234.567")
+        );
+        assert_eq!(
+            value(decimal.0, decimal.1, &[0x80, 0x00, 0x00, 0x00, 0x00, 0x05]).as_deref(),
+            Some("0.005")
+        );
+        assert_eq!(
+            value(MYSQL_TYPE_TIME2, 0, &[0x7f, 0xef, 0x7d]).as_deref(),
+            Some("-01:02:03")
+        );
+        assert_eq!(
+            value(MYSQL_TYPE_TIME2, 2, &[0x7f, 0xff, 0xff, 0xce]).as_deref(),
+            Some("-00:00:00.500000")
+        );
+        assert_eq!(
+            value(
+                MYSQL_TYPE_TIMESTAMP2,
+                3,
+                &[0x65, 0xec, 0x87, 0x80, 0x04, 0xce]
+            )
+            .as_deref(),
+            Some("2024-03-09 16:00:00.123000")
+        );
+        assert_eq!(
+            value(MYSQL_TYPE_DATE, 0, &[0x69, 0xd0, 0x0f]).as_deref(),
+            Some("2024-03-09")
+        );
+        assert_eq!(value(MYSQL_TYPE_YEAR, 0, &[0x7c]).as_deref(), Some("2024"));
+        assert_eq!(
+            value(MYSQL_TYPE_INT24, 0, &[0xfe, 0xff, 0xff]).as_deref(),
+            Some("-2")
+        );
+        // CHAR(10), and ENUM logged as a string type
+        let char10 = (MYSQL_TYPE_STRING as u16) << 8 | 40;
+        assert_eq!(
+            value(MYSQL_TYPE_STRING, char10, &[0x02, b'h', b'i']).as_deref(),
+            Some("hi")
+        );
+        let enum_ = (MYSQL_TYPE_ENUM as u16) << 8 | 1;
+        assert_eq!(
+            value(MYSQL_TYPE_STRING, enum_, &[0x02]).as_deref(),
+            Some("2")
+        );
+        // BIT(10), a byte and 2 bits
+        assert_eq!(
+            value(MYSQL_TYPE_BIT, 0x0102, &[0x02, 0x01]).as_deref(),
+            Some("513")
+        );
+        assert_eq!(
+            value(MYSQL_TYPE_BLOB, 2, &[0x02, 0x00, 0xbe, 0xef]).as_deref(),
+            Some(String::from_utf8_lossy(&[0xbe, 0xef]).as_ref())
+        );
+        assert_eq!(value(MYSQL_TYPE_DECIMAL, 0, &[0x01]), None);
+        assert_eq!(value(MYSQL_TYPE_VARCHAR, 10, &[0x05, b'a']), None);
+    }
+}
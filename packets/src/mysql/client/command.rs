@@ -0,0 +1,471 @@
+use crate::mysql::client::login::{read_attrs, write_attrs};
+use crate::mysql::client::query::QueryPacket;
+use crate::mysql::client::stmt::{
+    STMTExecutePacket, STMTPacket, STMTPreparePacket, STMTSendLongDataPacket,
+};
+use crate::mysql::command::MySqlCommand;
+use crate::mysql::common::*;
+use bytes::Buf;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+// COM_BINLOG_DUMP_GTID flag, the GTID set follows the position
+const BINLOG_THROUGH_GTID: u16 = 0x04;
+
+/// COM_CHANGE_USER, a new login on an open connection.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeUserPacket {
+    pub username: String,
+    pub auth_response: Vec<u8>,
+    pub database: String,
+    // the rest only if the client sends it
+    pub charset: Option<u16>,
+    pub auth_plugin_name: Option<String>,
+    pub attrs: HashMap<String, String>,
+}
+
+impl ChangeUserPacket {
+    fn new(cap: u32, reader: &mut Cursor<&[u8]>) -> Option<Self> {
+        let mut change_user = ChangeUserPacket {
+            username: read_nul_str(reader)?,
+            ..Default::default()
+        };
+        change_user.auth_response = if cap & CLIENT_SECURE_CONNECTION > 0 {
+            let len = reader.try_get_u8().ok()? as usize;
+            let auth_response = reader.chunk().get(..len)?.to_vec();
+            reader.advance(len);
+            auth_response
+        } else {
+            read_nul_str(reader)?.into_bytes()
+        };
+        change_user.database = read_nul_str(reader)?;
+        if !reader.has_remaining() {
+            return Some(change_user);
+        }
+        change_user.charset = Some(reader.try_get_u16_le().ok()?);
+        if cap & CLIENT_PLUGIN_AUTH > 0 && reader.has_remaining() {
+            change_user.auth_plugin_name = Some(read_nul_str(reader)?);
+        }
+        if cap & CLIENT_CONNECT_ATTRS > 0 && reader.has_remaining() {
+            change_user.attrs = read_attrs(reader)?;
+        }
+        Some(change_user)
+    }
+
+    fn encode(&self, cap: u32, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.username.as_bytes());
+        buf.push(0);
+        if cap & CLIENT_SECURE_CONNECTION > 0 {
+            buf.push(self.auth_response.len() as u8);
+            buf.extend_from_slice(&self.auth_response);
+        } else {
+            buf.extend_from_slice(&self.auth_response);
+            buf.push(0);
+        }
+        buf.extend_from_slice(self.database.as_bytes());
+        buf.push(0);
+        let charset = match self.charset {
+            Some(charset) => charset,
+            None => return,
+        };
+        buf.extend_from_slice(&charset.to_le_bytes());
+        if let (true, Some(name)) = (cap & CLIENT_PLUGIN_AUTH > 0, &self.auth_plugin_name) {
+            buf.extend_from_slice(name.as_bytes());
+            buf.push(0);
+        }
+        if cap & CLIENT_CONNECT_ATTRS > 0 && !self.attrs.is_empty() {
+            write_attrs(buf, &self.attrs);
+        }
+    }
+}
+
+/// COM_BINLOG_DUMP, a replica asking for the binlog from a file and
+/// position.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BinlogDumpPacket {
+    pub position: u32,
+    pub flags: u16,
+    pub server_id: u32,
+    pub filename: String,
+}
+
+/// COM_BINLOG_DUMP_GTID, a replica asking for the binlog past the
+/// transactions of its GTID set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BinlogDumpGtidPacket {
+    pub flags: u16,
+    pub server_id: u32,
+    pub filename: String,
+    pub position: u64,
+    // encoded GTID set, only with BINLOG_THROUGH_GTID
+    pub gtid_set: Option<Vec<u8>>,
+}
+
+/// COM_REGISTER_SLAVE, a replica announcing itself before the dump.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegisterSlavePacket {
+    pub server_id: u32,
+    pub hostname: String,
+    pub user: String,
+    pub password: String,
+    pub port: u16,
+    pub replication_rank: u32,
+    pub master_id: u32,
+}
+
+/// A client command, the first packet of every exchange after the
+/// handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientCommand {
+    Quit,
+    InitDb { schema: String },
+    Query(QueryPacket),
+    FieldList { table: String, wildcard: String },
+    Statistics,
+    ProcessKill { connection_id: u32 },
+    Debug,
+    Ping,
+    ChangeUser(ChangeUserPacket),
+    BinlogDump(BinlogDumpPacket),
+    RegisterSlave(RegisterSlavePacket),
+    StmtPrepare(STMTPreparePacket),
+    // without its parameters, see `STMTExecutePacket::new`
+    StmtExecute(STMTExecutePacket),
+    StmtSendLongData(STMTSendLongDataPacket),
+    StmtClose(STMTPacket),
+    StmtReset(STMTPacket),
+    // 0 turns multi statements on, 1 off
+    SetOption { option: u16 },
+    StmtFetch(STMTPacket),
+    BinlogDumpGtid(BinlogDumpGtidPacket),
+    ResetConnection,
+    Clone,
+    // obsolete and internal commands, by their code
+    Other { command: u8, args: Vec<u8> },
+}
+
+impl ClientCommand {
+    /// The command in `payload`, `cap` are the client capabilities of the
+    /// session. A COM_STMT_EXECUTE is decoded without its parameters, whose
+    /// layout only the statement's COM_STMT_PREPARE_OK tells.
+    pub fn new(cap: u32, payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        let code = reader.try_get_u8().ok()?;
+        let rest = |reader: &Cursor<&[u8]>| String::from_utf8_lossy(reader.chunk()).to_string();
+        Some(match MySqlCommand::request(code) {
+            Some(MySqlCommand::Quit) => ClientCommand::Quit,
+            Some(MySqlCommand::InitDb) => ClientCommand::InitDb {
+                schema: rest(&reader),
+            },
+            Some(MySqlCommand::Query) => {
+                ClientCommand::Query(QueryPacket::new(cap, payload.to_vec())?)
+            }
+            Some(MySqlCommand::FieldList) => ClientCommand::FieldList {
+                table: read_nul_str(&mut reader)?,
+                wildcard: rest(&reader),
+            },
+            Some(MySqlCommand::Statistics) => ClientCommand::Statistics,
+            Some(MySqlCommand::ProcessKill) => ClientCommand::ProcessKill {
+                connection_id: reader.try_get_u32_le().ok()?,
+            },
+            Some(MySqlCommand::Debug) => ClientCommand::Debug,
+            Some(MySqlCommand::Ping) => ClientCommand::Ping,
+            Some(MySqlCommand::ChangeUser) => {
+                ClientCommand::ChangeUser(ChangeUserPacket::new(cap, &mut reader)?)
+            }
+            Some(MySqlCommand::BinlogDump) => ClientCommand::BinlogDump(BinlogDumpPacket {
+                position: reader.try_get_u32_le().ok()?,
+                flags: reader.try_get_u16_le().ok()?,
+                server_id: reader.try_get_u32_le().ok()?,
+                filename: rest(&reader),
+            }),
+            Some(MySqlCommand::RegisterSlave) => {
+                let server_id = reader.try_get_u32_le().ok()?;
+                let mut short_str = || {
+                    let len = reader.try_get_u8().ok()? as usize;
+                    let s = String::from_utf8_lossy(reader.chunk().get(..len)?).to_string();
+                    reader.advance(len);
+                    Some(s)
+                };
+                let (hostname, user, password) = (short_str()?, short_str()?, short_str()?);
+                ClientCommand::RegisterSlave(RegisterSlavePacket {
+                    server_id,
+                    hostname,
+                    user,
+                    password,
+                    port: reader.try_get_u16_le().ok()?,
+                    replication_rank: reader.try_get_u32_le().ok()?,
+                    master_id: reader.try_get_u32_le().ok()?,
+                })
+            }
+            Some(MySqlCommand::StmtPrepare) => {
+                ClientCommand::StmtPrepare(STMTPreparePacket::new(payload)?)
+            }
+            Some(MySqlCommand::StmtExecute) => {
+                ClientCommand::StmtExecute(STMTExecutePacket::new(0, &[], payload)?)
+            }
+            Some(MySqlCommand::StmtSendLongData) => {
+                ClientCommand::StmtSendLongData(STMTSendLongDataPacket::new(payload)?)
+            }
+            Some(MySqlCommand::StmtClose) => ClientCommand::StmtClose(STMTPacket::new(payload)?),
+            Some(MySqlCommand::StmtReset) => ClientCommand::StmtReset(STMTPacket::new(payload)?),
+            Some(MySqlCommand::SetOption) => ClientCommand::SetOption {
+                option: reader.try_get_u16_le().ok()?,
+            },
+            Some(MySqlCommand::StmtFetch) => ClientCommand::StmtFetch(STMTPacket::new(payload)?),
+            Some(MySqlCommand::BinlogDumpGtid) => {
+                let flags = reader.try_get_u16_le().ok()?;
+                let server_id = reader.try_get_u32_le().ok()?;
+                let len = reader.try_get_u32_le().ok()? as usize;
+                let filename = String::from_utf8_lossy(reader.chunk().get(..len)?).to_string();
+                reader.advance(len);
+                let position = reader.try_get_u64_le().ok()?;
+                let gtid_set = if flags & BINLOG_THROUGH_GTID > 0 {
+                    let len = reader.try_get_u32_le().ok()? as usize;
+                    Some(reader.chunk().get(..len)?.to_vec())
+                } else {
+                    None
+                };
+                ClientCommand::BinlogDumpGtid(BinlogDumpGtidPacket {
+                    flags,
+                    server_id,
+                    filename,
+                    position,
+                    gtid_set,
+                })
+            }
+            Some(MySqlCommand::ResetConnection) => ClientCommand::ResetConnection,
+            Some(MySqlCommand::Clone) => ClientCommand::Clone,
+            _ => ClientCommand::Other {
+                command: code,
+                args: reader.chunk().to_vec(),
+            },
+        })
+    }
+
+    /// Code of the command, its first byte.
+    pub fn code(&self) -> u8 {
+        match self {
+            ClientCommand::Quit => MySqlCommand::Quit.code(),
+            ClientCommand::InitDb { .. } => MySqlCommand::InitDb.code(),
+            ClientCommand::Query(_) => MySqlCommand::Query.code(),
+            ClientCommand::FieldList { .. } => MySqlCommand::FieldList.code(),
+            ClientCommand::Statistics => MySqlCommand::Statistics.code(),
+            ClientCommand::ProcessKill { .. } => MySqlCommand::ProcessKill.code(),
+            ClientCommand::Debug => MySqlCommand::Debug.code(),
+            ClientCommand::Ping => MySqlCommand::Ping.code(),
+            ClientCommand::ChangeUser(_) => MySqlCommand::ChangeUser.code(),
+            ClientCommand::BinlogDump(_) => MySqlCommand::BinlogDump.code(),
+            ClientCommand::RegisterSlave(_) => MySqlCommand::RegisterSlave.code(),
+            ClientCommand::StmtPrepare(_) => MySqlCommand::StmtPrepare.code(),
+            ClientCommand::StmtExecute(_) => MySqlCommand::StmtExecute.code(),
+            ClientCommand::StmtSendLongData(_) => MySqlCommand::StmtSendLongData.code(),
+            ClientCommand::StmtClose(_) => MySqlCommand::StmtClose.code(),
+            ClientCommand::StmtReset(_) => MySqlCommand::StmtReset.code(),
+            ClientCommand::SetOption { .. } => MySqlCommand::SetOption.code(),
+            ClientCommand::StmtFetch(_) => MySqlCommand::StmtFetch.code(),
+            ClientCommand::BinlogDumpGtid(_) => MySqlCommand::BinlogDumpGtid.code(),
+            ClientCommand::ResetConnection => MySqlCommand::ResetConnection.code(),
+            ClientCommand::Clone => MySqlCommand::Clone.code(),
+            ClientCommand::Other { command, .. } => *command,
+        }
+    }
+
+    pub fn encode(&self, cap: u32) -> Vec<u8> {
+        let mut buf = vec![self.code()];
+        match self {
+            ClientCommand::Quit
+            | ClientCommand::Statistics
+            | ClientCommand::Debug
+            | ClientCommand::Ping
+            | ClientCommand::ResetConnection
+            | ClientCommand::Clone => {}
+            ClientCommand::InitDb { schema } => buf.extend_from_slice(schema.as_bytes()),
+            ClientCommand::Query(query) => return query.encode(cap),
+            ClientCommand::FieldList { table, wildcard } => {
+                buf.extend_from_slice(table.as_bytes());
+                buf.push(0);
+                buf.extend_from_slice(wildcard.as_bytes());
+            }
+            ClientCommand::ProcessKill { connection_id } => {
+                buf.extend_from_slice(&connection_id.to_le_bytes())
+            }
+            ClientCommand::ChangeUser(change_user) => change_user.encode(cap, &mut buf),
+            ClientCommand::BinlogDump(dump) => {
+                buf.extend_from_slice(&dump.position.to_le_bytes());
+                buf.extend_from_slice(&dump.flags.to_le_bytes());
+                buf.extend_from_slice(&dump.server_id.to_le_bytes());
+                buf.extend_from_slice(dump.filename.as_bytes());
+            }
+            ClientCommand::RegisterSlave(register) => {
+                buf.extend_from_slice(&register.server_id.to_le_bytes());
+                for s in [&register.hostname, &register.user, &register.password] {
+                    buf.push(s.len() as u8);
+                    buf.extend_from_slice(s.as_bytes());
+                }
+                buf.extend_from_slice(&register.port.to_le_bytes());
+                buf.extend_from_slice(&register.replication_rank.to_le_bytes());
+                buf.extend_from_slice(&register.master_id.to_le_bytes());
+            }
+            ClientCommand::StmtPrepare(prepare) => return prepare.encode(),
+            ClientCommand::StmtExecute(execute) => return execute.encode(),
+            ClientCommand::StmtSendLongData(long_data) => return long_data.encode(),
+            ClientCommand::StmtClose(stmt)
+            | ClientCommand::StmtReset(stmt)
+            | ClientCommand::StmtFetch(stmt) => return stmt.encode(),
+            ClientCommand::SetOption { option } => buf.extend_from_slice(&option.to_le_bytes()),
+            ClientCommand::BinlogDumpGtid(dump) => {
+                buf.extend_from_slice(&dump.flags.to_le_bytes());
+                buf.extend_from_slice(&dump.server_id.to_le_bytes());
+                buf.extend_from_slice(&(dump.filename.len() as u32).to_le_bytes());
+                buf.extend_from_slice(dump.filename.as_bytes());
+                buf.extend_from_slice(&dump.position.to_le_bytes());
+                if let (true, Some(gtid_set)) =
+                    (dump.flags & BINLOG_THROUGH_GTID > 0, &dump.gtid_set)
+                {
+                    buf.extend_from_slice(&(gtid_set.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(gtid_set);
+                }
+            }
+            ClientCommand::Other { args, .. } => buf.extend_from_slice(args),
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(cap: u32, command: ClientCommand) {
+        let payload = command.encode(cap);
+        assert_eq!(ClientCommand::new(cap, &payload).as_ref(), Some(&command));
+        // cut anywhere, it may still be a command but never the same one
+        for len in 0..payload.len() {
+            assert_ne!(
+                ClientCommand::new(cap, &payload[..len]).as_ref(),
+                Some(&command)
+            );
+        }
+    }
+
+    #[test]
+    fn test_simple_commands() {
+        // COM_INIT_DB as the mysql client sends it for `use billing`
+        let payload = b"\x02billing";
+        let init_db = ClientCommand::new(0, payload).unwrap();
+        assert_eq!(
+            init_db,
+            ClientCommand::InitDb {
+                schema: "billing".to_string()
+            }
+        );
+        assert_eq!(init_db.encode(0), payload);
+
+        for command in [
+            ClientCommand::Quit,
+            ClientCommand::Statistics,
+            ClientCommand::Debug,
+            ClientCommand::Ping,
+            ClientCommand::ResetConnection,
+            ClientCommand::Clone,
+            ClientCommand::ProcessKill { connection_id: 42 },
+            ClientCommand::SetOption { option: 1 },
+            ClientCommand::FieldList {
+                table: "orders".to_string(),
+                wildcard: "id%".to_string(),
+            },
+            ClientCommand::Query(QueryPacket {
+                cmd: MySqlCommand::Query,
+                attrs: Vec::new(),
+                query: "select 1".to_string(),
+            }),
+            ClientCommand::StmtReset(STMTPacket {
+                cmd: MySqlCommand::StmtReset.code(),
+                statement_id: 3,
+                rows: None,
+            }),
+            ClientCommand::Other {
+                command: 0x0b,
+                args: vec![1, 2],
+            },
+        ] {
+            round_trip(CLIENT_PROTOCOL_41, command);
+        }
+        assert_eq!(
+            ClientCommand::new(0, &[0x1b, 0x01, 0x00]).unwrap().code(),
+            0x1b
+        );
+        assert_eq!(
+            ClientCommand::new(0, &[0x1a, 3, 0, 0, 0]).unwrap().code(),
+            0x1a
+        );
+        assert!(ClientCommand::new(0, &[]).is_none());
+    }
+
+    #[test]
+    fn test_change_user() {
+        let cap = CLIENT_PROTOCOL_41
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH
+            | CLIENT_CONNECT_ATTRS;
+        let mut change_user = ChangeUserPacket {
+            username: "report".to_string(),
+            auth_response: vec![0x11; 20],
+            database: "shop".to_string(),
+            charset: Some(45),
+            auth_plugin_name: Some("mysql_native_password".to_string()),
+            attrs: HashMap::from([("_client_name".to_string(), "libmysql".to_string())]),
+        };
+        round_trip(cap, ClientCommand::ChangeUser(change_user.clone()));
+
+        // an old client stops after the database
+        change_user.charset = None;
+        change_user.auth_plugin_name = None;
+        change_user.attrs.clear();
+        round_trip(0, ClientCommand::ChangeUser(change_user));
+    }
+
+    #[test]
+    fn test_replication_commands() {
+        // mysqlbinlog --read-from-remote-server --stop-never
+        let payload = [
+            0x12, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x62, 0x69, 0x6e,
+            0x6c, 0x6f, 0x67, 0x2e, 0x30, 0x30, 0x30, 0x30, 0x30, 0x31,
+        ];
+        let dump = ClientCommand::new(0, &payload).unwrap();
+        assert_eq!(
+            dump,
+            ClientCommand::BinlogDump(BinlogDumpPacket {
+                position: 4,
+                flags: 0,
+                server_id: 1,
+                filename: "binlog.000001".to_string(),
+            })
+        );
+        assert_eq!(dump.encode(0), payload);
+
+        round_trip(
+            0,
+            ClientCommand::BinlogDumpGtid(BinlogDumpGtidPacket {
+                flags: BINLOG_THROUGH_GTID,
+                server_id: 2,
+                filename: String::new(),
+                position: 4,
+                gtid_set: Some(vec![1, 0, 0, 0, 0, 0, 0, 0]),
+            }),
+        );
+        round_trip(
+            0,
+            ClientCommand::RegisterSlave(RegisterSlavePacket {
+                server_id: 2,
+                hostname: "replica".to_string(),
+                user: "repl".to_string(),
+                password: String::new(),
+                port: 3306,
+                replication_rank: 0,
+                master_id: 0,
+            }),
+        );
+    }
+}
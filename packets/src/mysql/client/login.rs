@@ -3,7 +3,7 @@ use bytes::Buf;
 use log::{error, info};
 use std::collections::HashMap;
 use std::io::Cursor;
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Login {
     pub cap: u32,
     pub max_packet_size: u32,
@@ -20,21 +20,21 @@ pub struct Login {
 
 impl Login {
     pub fn new(payload: Vec<u8>) -> Option<Self> {
-        let mut buf = Cursor::new(payload);
+        let mut buf = Cursor::new(payload.as_slice());
         let mut login = Login::default();
-        let cap = buf.get_u32_le();
+        let cap = buf.try_get_u32_le().ok()?;
         login.cap = cap;
         info!("login cap: {}", cap);
-        let max_packet_size = buf.get_u32_le();
+        let max_packet_size = buf.try_get_u32_le().ok()?;
         login.max_packet_size = max_packet_size;
         info!("login max_packet_size: {}", max_packet_size);
-        let charset = buf.get_u8();
+        let charset = buf.try_get_u8().ok()?;
         login.charset = charset;
         info!("login charset: {}", charset);
         let mut filler = [0; 23];
-        for i in 0..23 {
-            filler[i] = buf.get_u8();
-            if filler[i] != 0 {
+        for byte in filler.iter_mut() {
+            *byte = buf.try_get_u8().ok()?;
+            if *byte != 0 {
                 error!("mysql login filler field is not 0");
                 return None;
             }
@@ -45,7 +45,7 @@ impl Login {
 
         let mut username = String::new();
         loop {
-            let c = buf.get_u8();
+            let c = buf.try_get_u8().ok()?;
             if c == 0x00 {
                 break;
             }
@@ -54,24 +54,40 @@ impl Login {
 
         login.username = Option::from(username);
 
-        if buf.remaining() <= 0 {
+        if !buf.has_remaining() {
             return Some(login);
         }
 
         if cap & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA > 0  {
-            let auth_response_length = buf.get_u8();
+            let auth_response = read_len_enc_bytes(&mut buf)?.0;
+            login.auth_response_length = auth_response.len() as u8;
+            login.auth_response = Some(auth_response);
+        } else if cap & CLIENT_SECURE_CONNECTION > 0 {
+            let auth_response_length = buf.try_get_u8().ok()?;
             login.auth_response_length = auth_response_length;
             let mut auth_response = Vec::new();
             for _ in 0..auth_response_length {
-                auth_response.push(buf.get_u8());
+                auth_response.push(buf.try_get_u8().ok()?);
             }
             login.auth_response = Some(auth_response);
+        } else {
+            // terminated by 0x00 before 4.1
+            let mut auth_response = Vec::new();
+            loop {
+                let c = buf.try_get_u8().ok()?;
+                if c == 0 {
+                    break;
+                }
+                auth_response.push(c);
+            }
+            login.auth_response_length = auth_response.len() as u8;
+            login.auth_response = Some(auth_response);
         }
 
         if cap & CLIENT_CONNECT_WITH_DB > 0 && buf.remaining() > 0 {
             let mut database = String::new();
             loop {
-                let c = buf.get_u8();
+                let c = buf.try_get_u8().ok()?;
                 if c == 0 {
                     break;
                 }
@@ -83,10 +99,10 @@ impl Login {
         if cap & CLIENT_PLUGIN_AUTH > 0 && buf.remaining() > 0 {
             let mut auth_plugin_name = String::new();
             loop {
-                if buf.remaining() <= 0 {
+                if !buf.has_remaining() {
                     break;
                 }
-                let c = buf.get_u8();
+                let c = buf.try_get_u8().ok()?;
                 if c == 0 {
                     break;
                 }
@@ -96,46 +112,91 @@ impl Login {
         }
 
         if cap & CLIENT_CONNECT_ATTRS > 0  {
-            let mut attrs = HashMap::new();
-            // discard len
-            let totol_len = buf.get_u8();
-            let mut read_len = 0;
-            loop {
-                if read_len >= totol_len  {
-                    break;
-                }
-                let mut key = String::new();
-                let len = buf.get_u8();
-                for _ in 0..len {
-                    key.push(buf.get_u8() as char);
-                }
-
-                read_len = read_len + len + 1;
-                let mut value = String::new();
-
-                let len = buf.get_u8();
-                for _ in 0..len {
-                    value.push(buf.get_u8() as char);
-                }
-                read_len = read_len + len + 1;
-                attrs.insert(key, value);
-            }
-            login.attrs = attrs;
+            login.attrs = read_attrs(&mut buf)?;
         }
 
         let zstd_compression_level = if cap & CLIENT_ZSTD_COMPRESSION_ALGORITHM > 0 {
-            Some(buf.get_u8())
+            buf.try_get_u8().ok()
         } else {
             None
         };
         login.zstd_compression_level = zstd_compression_level;
         Some(login)
     }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let cap = self.cap;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&cap.to_le_bytes());
+        buf.extend_from_slice(&self.max_packet_size.to_le_bytes());
+        buf.push(self.charset);
+        buf.extend_from_slice(&self.filler);
+        buf.extend_from_slice(self.username.as_deref().unwrap_or_default().as_bytes());
+        buf.push(0);
+
+        let auth_response = self.auth_response.as_deref().unwrap_or_default();
+        if cap & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA > 0 {
+            write_len_enc_str(&mut buf, auth_response);
+        } else if cap & CLIENT_SECURE_CONNECTION > 0 {
+            buf.push(auth_response.len() as u8);
+            buf.extend_from_slice(auth_response);
+        } else {
+            buf.extend_from_slice(auth_response);
+            buf.push(0);
+        }
+
+        if let (true, Some(database)) = (cap & CLIENT_CONNECT_WITH_DB > 0, &self.database) {
+            buf.extend_from_slice(database.as_bytes());
+            buf.push(0);
+        }
+        if let (true, Some(name)) = (cap & CLIENT_PLUGIN_AUTH > 0, &self.auth_plugin_name) {
+            buf.extend_from_slice(name.as_bytes());
+            buf.push(0);
+        }
+        if cap & CLIENT_CONNECT_ATTRS > 0 {
+            write_attrs(&mut buf, &self.attrs);
+        }
+        if let (true, Some(level)) = (
+            cap & CLIENT_ZSTD_COMPRESSION_ALGORITHM > 0,
+            self.zstd_compression_level,
+        ) {
+            buf.push(level);
+        }
+        buf
+    }
+}
+
+/// Connection attributes, length-encoded pairs behind their total length.
+/// COM_CHANGE_USER sends them the same way.
+pub(crate) fn read_attrs(buf: &mut Cursor<&[u8]>) -> Option<HashMap<String, String>> {
+    let mut attrs = HashMap::new();
+    let total_len = read_len_enc_int(buf)?.0;
+    let end = buf.position().saturating_add(total_len);
+    while buf.position() < end {
+        let key = read_len_enc_str(buf)?.0;
+        let value = read_len_enc_str(buf)?.0;
+        attrs.insert(key, value);
+    }
+    Some(attrs)
+}
+
+pub(crate) fn write_attrs(buf: &mut Vec<u8>, attrs: &HashMap<String, String>) {
+    // sorted, a HashMap has no order of its own
+    let mut attrs: Vec<_> = attrs.iter().collect();
+    attrs.sort();
+    let mut encoded = Vec::new();
+    for (key, value) in attrs {
+        write_len_enc_str(&mut encoded, key.as_bytes());
+        write_len_enc_str(&mut encoded, value.as_bytes());
+    }
+    write_len_enc_str(buf, &encoded);
 }
 
 #[cfg(test)]
 mod test {
     use super::Login;
+    use crate::mysql::common::*;
+    use proptest::prelude::*;
     #[test]
     pub fn test_mysql57_login() {
         env_logger::init();
@@ -160,7 +221,74 @@ mod test {
         println!("{:?}", login);
         assert!(login.is_some());
         assert!(login.unwrap().username.is_some());
+        // a login cut after the user name is kept, one cut in the middle
+        // of the connection attributes is not
+        let attrs = packet_bytes.len() - 0x70;
+        for len in 0..packet_bytes.len() {
+            let login = Login::new(packet_bytes[..len].to_vec());
+            assert!(len < attrs || login.is_none(), "truncated at {}", len);
+        }
+
+
+    }
 
+    /// Capabilities that change the layout of the packet.
+    fn login_cap() -> impl Strategy<Value = u32> {
+        proptest::sample::subsequence(
+            vec![
+                CLIENT_SECURE_CONNECTION,
+                CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA,
+                CLIENT_CONNECT_WITH_DB,
+                CLIENT_PLUGIN_AUTH,
+                CLIENT_CONNECT_ATTRS,
+                CLIENT_ZSTD_COMPRESSION_ALGORITHM,
+            ],
+            0..=6,
+        )
+        .prop_map(|flags| flags.into_iter().fold(CLIENT_PROTOCOL_41, |cap, flag| cap | flag))
+    }
 
+    fn login() -> impl Strategy<Value = Login> {
+        (
+            login_cap(),
+            any::<u32>(),
+            any::<u8>(),
+            "[a-z_]{0,16}",
+            proptest::collection::vec(1..=255u8, 0..64),
+            "[a-z_]{0,16}",
+            "[a-z_]{1,32}",
+            proptest::collection::hash_map("_[a-z_]{1,16}", "[ -~]{0,32}", 0..8),
+            any::<u8>(),
+        )
+            .prop_map(
+                |(cap, max_packet_size, charset, user, auth, db, plugin, attrs, level)| {
+                    let flag = |flag: u32| cap & flag > 0;
+                    Login {
+                        cap,
+                        max_packet_size,
+                        charset,
+                        username: Some(user),
+                        auth_response_length: auth.len() as u8,
+                        auth_response: Some(auth),
+                        database: flag(CLIENT_CONNECT_WITH_DB).then_some(db),
+                        auth_plugin_name: flag(CLIENT_PLUGIN_AUTH).then_some(plugin),
+                        attrs: if flag(CLIENT_CONNECT_ATTRS) {
+                            attrs
+                        } else {
+                            Default::default()
+                        },
+                        zstd_compression_level: flag(CLIENT_ZSTD_COMPRESSION_ALGORITHM)
+                            .then_some(level),
+                        ..Default::default()
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_login_round_trip(login in login()) {
+            prop_assert_eq!(Login::new(login.encode()), Some(login));
+        }
     }
 }
@@ -1,3 +1,4 @@
+pub mod command;
 pub mod login;
-mod stmt;
-mod query;
\ No newline at end of file
+pub mod stmt;
+pub mod query;
\ No newline at end of file
@@ -1,41 +1,117 @@
 use std::io::Cursor;
 use bytes::Buf;
-use crate::Command;
+use crate::mysql::binary::{null_bitmap, read_null_bitmap, read_values, Value, ValueType};
+use crate::mysql::common::{
+    read_len_enc_int, read_len_enc_str, write_len_enc_int, write_len_enc_str,
+    CLIENT_QUERY_ATTRIBUTES,
+};
+use crate::mysql::command::MySqlCommand;
+
+// the flags byte after each attribute type
+const PARAM_UNSIGNED: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct  QueryPacket {
-    pub cmd: Command,
+    pub cmd: MySqlCommand,
+    // with CLIENT_QUERY_ATTRIBUTES, e.g. the trace id of a tracing tool
+    pub attrs: Vec<QueryAttr>,
     pub query: String,
 }
 
+/// A query attribute, a named parameter of the binary protocol sent ahead
+/// of the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryAttr {
+    pub name: String,
+    pub ty: ValueType,
+    pub value: Value,
+}
+
 impl QueryPacket {
-    pub fn new(payload: Vec<u8>) -> Self {
-        let mut reader = Cursor::new(payload);
-        let mut query = String::new();
-        let cmd = Command::from(reader.get_u8());
-        loop {
-            if reader.remaining() <= 0 {
-                break;
-            }
-            let c = reader.get_u8();
-            if c == 0 {
-                break;
-            }
-            query.push(c as char);
-        }
-        QueryPacket {
+    /// COM_QUERY, `cap` are the client capabilities of the session.
+    pub fn new(cap: u32, payload: Vec<u8>) -> Option<Self> {
+        let mut reader = Cursor::new(payload.as_slice());
+        let cmd = MySqlCommand::request(reader.try_get_u8().ok()?)?;
+        let attrs = if cap & CLIENT_QUERY_ATTRIBUTES > 0 {
+            read_attrs(&mut reader)?
+        } else {
+            Vec::new()
+        };
+        let query = String::from_utf8_lossy(reader.chunk()).to_string();
+        Some(QueryPacket {
             cmd,
+            attrs,
             query
+        })
+    }
+
+    pub fn encode(&self, cap: u32) -> Vec<u8> {
+        let mut buf = vec![self.cmd.code()];
+        if cap & CLIENT_QUERY_ATTRIBUTES > 0 {
+            write_len_enc_int(&mut buf, self.attrs.len() as u64);
+            // parameter sets, always 1
+            buf.push(1);
+            if !self.attrs.is_empty() {
+                let values: Vec<Value> = self.attrs.iter().map(|a| a.value.clone()).collect();
+                buf.extend(null_bitmap(&values, 0));
+                // the types and names are always bound
+                buf.push(1);
+                for attr in &self.attrs {
+                    let flags = if attr.ty.unsigned { PARAM_UNSIGNED } else { 0 };
+                    buf.extend_from_slice(&[attr.ty.column_type, flags]);
+                    write_len_enc_str(&mut buf, attr.name.as_bytes());
+                }
+                for attr in &self.attrs {
+                    attr.value.write(attr.ty, &mut buf);
+                }
+            }
         }
+        buf.extend_from_slice(self.query.as_bytes());
+        buf
     }
 }
 
+/// Parameter count, parameter set count, null bitmap, types and names, then
+/// the values.
+fn read_attrs(reader: &mut Cursor<&[u8]>) -> Option<Vec<QueryAttr>> {
+    let (count, _) = read_len_enc_int(reader)?;
+    let _sets = read_len_enc_int(reader)?;
+    // a type, its flags and a name length at least for each
+    if count == 0 || count > reader.remaining() as u64 / 3 {
+        return (count == 0).then(Vec::new);
+    }
+    let bitmap = read_null_bitmap(count as usize, 0, reader)?;
+    if reader.try_get_u8().ok()? != 1 {
+        // unbound types, never sent with a query
+        return None;
+    }
+    let mut types = Vec::new();
+    let mut names = Vec::new();
+    for _ in 0..count {
+        let column_type = reader.try_get_u8().ok()?;
+        let flags = reader.try_get_u8().ok()?;
+        types.push(ValueType::new(column_type, flags & PARAM_UNSIGNED > 0));
+        names.push(read_len_enc_str(reader)?.0);
+    }
+    let values = read_values(&types, bitmap, 0, reader)?;
+    Some(
+        names
+            .into_iter()
+            .zip(types)
+            .zip(values)
+            .map(|((name, ty), value)| QueryAttr { name, ty, value })
+            .collect(),
+    )
+}
+
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::mysql::common::*;
+
     #[test]
     pub fn query_test() {
-        use super::QueryPacket;
-        use std::io::Cursor;
-
         let packet_bytes = [
             0x03, 0x53, 0x45, 0x54,
             0x20, 0x6e, 0x65, 0x74, 0x5f, 0x77, 0x72, 0x69,
@@ -44,9 +120,67 @@ mod test {
         ];
 
 
-        let mut reader = Cursor::new(packet_bytes);
-        let query_packet = QueryPacket::new(packet_bytes.to_vec());
-        assert_eq!(query_packet.cmd.0, 0x03);
+        let query_packet = QueryPacket::new(0, packet_bytes.to_vec()).unwrap();
+        assert_eq!(query_packet.cmd, MySqlCommand::Query);
         assert_eq!(query_packet.query, "SET net_write_timeout=60");
+        assert_eq!(query_packet.encode(0), packet_bytes);
+        // not only ASCII
+        let query_packet = QueryPacket {
+            cmd: query_packet.cmd,
+            attrs: Vec::new(),
+            query: "select 'héllo'".to_string(),
+        };
+        assert_eq!(QueryPacket::new(0, query_packet.encode(0)), Some(query_packet));
+    }
+
+    #[test]
+    fn test_query_attributes() {
+        // select 1 with the traceparent attribute, as the 8.0 client sends it
+        let packet_bytes = [
+            0x03, 0x01, 0x01, 0x00, 0x01, 0xfe, 0x00, 0x0b,
+            0x74, 0x72, 0x61, 0x63, 0x65, 0x70, 0x61, 0x72,
+            0x65, 0x6e, 0x74, 0x05, 0x30, 0x30, 0x2d, 0x61,
+            0x62, 0x73, 0x65, 0x6c, 0x65, 0x63, 0x74, 0x20,
+            0x31
+        ];
+        let cap = CLIENT_QUERY_ATTRIBUTES;
+        let query_packet = QueryPacket::new(cap, packet_bytes.to_vec()).unwrap();
+        assert_eq!(query_packet.query, "select 1");
+        assert_eq!(
+            query_packet.attrs,
+            [QueryAttr {
+                name: "traceparent".to_string(),
+                ty: ValueType::new(MYSQL_TYPE_STRING, false),
+                value: Value::Bytes(b"00-ab".to_vec()),
+            }]
+        );
+        assert_eq!(query_packet.encode(cap), packet_bytes);
+        // without attributes only the counts are sent
+        let plain = [0x03, 0x00, 0x01, 0x6f, 0x6b];
+        let query_packet = QueryPacket::new(cap, plain.to_vec()).unwrap();
+        assert!(query_packet.attrs.is_empty());
+        assert_eq!(query_packet.query, "ok");
+
+        let query_packet = QueryPacket {
+            cmd: MySqlCommand::Query,
+            attrs: vec![
+                QueryAttr {
+                    name: "id".to_string(),
+                    ty: ValueType::new(MYSQL_TYPE_LONGLONG, true),
+                    value: Value::UInt(7),
+                },
+                QueryAttr {
+                    name: "gone".to_string(),
+                    ty: ValueType::new(MYSQL_TYPE_NULL, false),
+                    value: Value::Null,
+                },
+            ],
+            query: "select 2".to_string(),
+        };
+        let encoded = query_packet.encode(cap);
+        assert_eq!(QueryPacket::new(cap, encoded.clone()), Some(query_packet));
+        for len in 1..encoded.len() - 8 {
+            assert_eq!(QueryPacket::new(cap, encoded[..len].to_vec()), None);
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,278 @@
+use crate::mysql::binary::{null_bitmap, read_null_bitmap, read_values, Value, ValueType};
+use crate::mysql::command::MySqlCommand;
+use bytes::Buf;
+use std::io::Cursor;
+
+// the flags byte after each parameter type
+const PARAM_UNSIGNED: u8 = 0x80;
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct STMTPreparePacket {
     pub query: String,
-}
\ No newline at end of file
+}
+
+impl STMTPreparePacket {
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        match payload.split_first() {
+            Some((&cmd, query))
+                if MySqlCommand::request(cmd) == Some(MySqlCommand::StmtPrepare) =>
+            {
+                Some(STMTPreparePacket {
+                    query: String::from_utf8_lossy(query).to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![MySqlCommand::StmtPrepare.code()];
+        buf.extend_from_slice(self.query.as_bytes());
+        buf
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct STMTExecutePacket {
+    pub statement_id: u32,
+    // cursor type
+    pub flags: u8,
+    // always 1
+    pub iterations: u32,
+    // whether the parameter types are sent, they are only on the first
+    // execution of a statement or when they changed
+    pub new_params_bound: bool,
+    pub types: Vec<ValueType>,
+    // NULLs included, empty when the types are not known
+    pub params: Vec<Value>,
+}
+
+impl STMTExecutePacket {
+    /// COM_STMT_EXECUTE of a statement with `params` parameters, as its
+    /// COM_STMT_PREPARE_OK announced. `types` are those of the previous
+    /// execution, used when the packet does not bind new ones.
+    pub fn new(params: u16, types: &[ValueType], payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        if MySqlCommand::request(reader.try_get_u8().ok()?) != Some(MySqlCommand::StmtExecute) {
+            return None;
+        }
+        let mut execute = STMTExecutePacket {
+            statement_id: reader.try_get_u32_le().ok()?,
+            flags: reader.try_get_u8().ok()?,
+            iterations: reader.try_get_u32_le().ok()?,
+            ..Default::default()
+        };
+        if params == 0 {
+            return Some(execute);
+        }
+
+        // the types come between the null bitmap and the values
+        let bitmap = read_null_bitmap(params as usize, 0, &mut reader)?;
+        execute.new_params_bound = reader.try_get_u8().ok()? == 1;
+        if execute.new_params_bound {
+            for _ in 0..params {
+                let column_type = reader.try_get_u8().ok()?;
+                let flags = reader.try_get_u8().ok()?;
+                execute
+                    .types
+                    .push(ValueType::new(column_type, flags & PARAM_UNSIGNED > 0));
+            }
+        } else if types.len() == params as usize {
+            execute.types = types.to_vec();
+        } else {
+            return Some(execute);
+        }
+        execute.params = read_values(&execute.types, bitmap, 0, &mut reader)?;
+        Some(execute)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![MySqlCommand::StmtExecute.code()];
+        buf.extend_from_slice(&self.statement_id.to_le_bytes());
+        buf.push(self.flags);
+        buf.extend_from_slice(&self.iterations.to_le_bytes());
+        if self.params.is_empty() {
+            return buf;
+        }
+        buf.extend(null_bitmap(&self.params, 0));
+        buf.push(self.new_params_bound as u8);
+        if self.new_params_bound {
+            for ty in &self.types {
+                let flags = if ty.unsigned { PARAM_UNSIGNED } else { 0 };
+                buf.extend_from_slice(&[ty.column_type, flags]);
+            }
+        }
+        for (value, ty) in self.params.iter().zip(&self.types) {
+            value.write(*ty, &mut buf);
+        }
+        buf
+    }
+}
+
+/// COM_STMT_SEND_LONG_DATA, a chunk of a parameter sent ahead of
+/// COM_STMT_EXECUTE.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct STMTSendLongDataPacket {
+    pub statement_id: u32,
+    pub param_id: u16,
+    pub data: Vec<u8>,
+}
+
+impl STMTSendLongDataPacket {
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        if MySqlCommand::request(reader.try_get_u8().ok()?) != Some(MySqlCommand::StmtSendLongData)
+        {
+            return None;
+        }
+        Some(STMTSendLongDataPacket {
+            statement_id: reader.try_get_u32_le().ok()?,
+            param_id: reader.try_get_u16_le().ok()?,
+            data: reader.chunk().to_vec(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![MySqlCommand::StmtSendLongData.code()];
+        buf.extend_from_slice(&self.statement_id.to_le_bytes());
+        buf.extend_from_slice(&self.param_id.to_le_bytes());
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+}
+
+/// COM_STMT_CLOSE, COM_STMT_RESET and COM_STMT_FETCH with the number of
+/// rows to fetch, the other two only name the statement.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct STMTPacket {
+    pub cmd: u8,
+    pub statement_id: u32,
+    pub rows: Option<u32>,
+}
+
+impl STMTPacket {
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        let cmd = reader.try_get_u8().ok()?;
+        let fetch = match MySqlCommand::request(cmd)? {
+            MySqlCommand::StmtClose | MySqlCommand::StmtReset => false,
+            MySqlCommand::StmtFetch => true,
+            _ => return None,
+        };
+        let statement_id = reader.try_get_u32_le().ok()?;
+        let rows = if fetch {
+            Some(reader.try_get_u32_le().ok()?)
+        } else {
+            None
+        };
+        Some(STMTPacket {
+            cmd,
+            statement_id,
+            rows,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![self.cmd];
+        buf.extend_from_slice(&self.statement_id.to_le_bytes());
+        if let Some(rows) = self.rows {
+            buf.extend_from_slice(&rows.to_le_bytes());
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mysql::binary::test::typed_value;
+    use crate::mysql::common::{MYSQL_TYPE_LONG, MYSQL_TYPE_VAR_STRING};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_stmt_execute() {
+        // execute statement 1 with (42, NULL), then again with ('a', 7)
+        let payload = [
+            0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0b10, 0x01, 0x03, 0x00,
+            0xfd, 0x00, 0x2a, 0x00, 0x00, 0x00,
+        ];
+        let execute = STMTExecutePacket::new(2, &[], &payload).unwrap();
+        assert_eq!(execute.statement_id, 1);
+        assert_eq!(execute.params, [Value::Int(42), Value::Null]);
+        assert_eq!(
+            execute.types,
+            [
+                ValueType::new(MYSQL_TYPE_LONG, false),
+                ValueType::new(MYSQL_TYPE_VAR_STRING, false)
+            ]
+        );
+        assert_eq!(execute.encode(), payload);
+
+        let again = [
+            0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0b00, 0x00, 0x07, 0x00,
+            0x00, 0x00, 0x01, b'a',
+        ];
+        let unknown = STMTExecutePacket::new(2, &[], &again).unwrap();
+        assert!(!unknown.new_params_bound && unknown.params.is_empty());
+        let again = STMTExecutePacket::new(2, &execute.types, &again).unwrap();
+        assert_eq!(again.params, [Value::Int(7), Value::Bytes(b"a".to_vec())]);
+        assert!(STMTExecutePacket::new(2, &[], &payload[..payload.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_stmt_packets() {
+        let prepare = STMTPreparePacket {
+            query: "select * from t where id = ?".to_string(),
+        };
+        assert_eq!(STMTPreparePacket::new(&prepare.encode()), Some(prepare));
+
+        let long_data = STMTSendLongDataPacket {
+            statement_id: 3,
+            param_id: 1,
+            data: b"chunk".to_vec(),
+        };
+        assert_eq!(
+            STMTSendLongDataPacket::new(&long_data.encode()),
+            Some(long_data)
+        );
+
+        for (cmd, rows) in [
+            (MySqlCommand::StmtClose, None),
+            (MySqlCommand::StmtReset, None),
+            (MySqlCommand::StmtFetch, Some(100)),
+        ] {
+            let stmt = STMTPacket {
+                cmd: cmd.code(),
+                statement_id: 7,
+                rows,
+            };
+            assert_eq!(STMTPacket::new(&stmt.encode()), Some(stmt));
+        }
+        assert!(STMTPacket::new(&[MySqlCommand::StmtExecute.code(), 7, 0, 0, 0]).is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn test_stmt_execute_round_trip(
+            statement_id in any::<u32>(),
+            flags in 0..8u8,
+            new_params_bound in any::<bool>(),
+            params in proptest::collection::vec((typed_value(), any::<bool>()), 0..20),
+        ) {
+            let types: Vec<ValueType> = params.iter().map(|((ty, _), _)| *ty).collect();
+            let execute = STMTExecutePacket {
+                statement_id,
+                flags,
+                iterations: 1,
+                new_params_bound: new_params_bound && !params.is_empty(),
+                types: types.clone(),
+                params: params
+                    .into_iter()
+                    .map(|((_, value), null)| if null { Value::Null } else { value })
+                    .collect(),
+            };
+            let decoded = STMTExecutePacket::new(types.len() as u16, &types, &execute.encode());
+            prop_assert_eq!(decoded, Some(execute));
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::Command;
+use std::fmt;
+
+/// What the first byte of a MySQL packet says. A client packet starts with
+/// the code of its command, a server packet answering one with a header,
+/// 0x00, 0xfb, 0xfe and 0xff, that means something else than the command of
+/// the same code, or with no header at all, e.g. the column count of a
+/// result set. Which side sent the packet decides, see `request` and
+/// `response`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MySqlCommand {
+    Sleep,
+    Quit,
+    InitDb,
+    Query,
+    FieldList,
+    CreateDb,
+    DropDb,
+    Refresh,
+    Shutdown,
+    Statistics,
+    ProcessInfo,
+    Connect,
+    ProcessKill,
+    Debug,
+    Ping,
+    Time,
+    DelayedInsert,
+    ChangeUser,
+    BinlogDump,
+    TableDump,
+    ConnectOut,
+    RegisterSlave,
+    StmtPrepare,
+    StmtExecute,
+    StmtSendLongData,
+    StmtClose,
+    StmtReset,
+    SetOption,
+    StmtFetch,
+    Daemon,
+    BinlogDumpGtid,
+    ResetConnection,
+    Clone,
+    // server side only
+    Ok,
+    LocalInfile,
+    Eof,
+    Err,
+}
+
+// client commands by code, 0x00 to 0x20 without a gap
+const REQUESTS: [MySqlCommand; 33] = [
+    MySqlCommand::Sleep,
+    MySqlCommand::Quit,
+    MySqlCommand::InitDb,
+    MySqlCommand::Query,
+    MySqlCommand::FieldList,
+    MySqlCommand::CreateDb,
+    MySqlCommand::DropDb,
+    MySqlCommand::Refresh,
+    MySqlCommand::Shutdown,
+    MySqlCommand::Statistics,
+    MySqlCommand::ProcessInfo,
+    MySqlCommand::Connect,
+    MySqlCommand::ProcessKill,
+    MySqlCommand::Debug,
+    MySqlCommand::Ping,
+    MySqlCommand::Time,
+    MySqlCommand::DelayedInsert,
+    MySqlCommand::ChangeUser,
+    MySqlCommand::BinlogDump,
+    MySqlCommand::TableDump,
+    MySqlCommand::ConnectOut,
+    MySqlCommand::RegisterSlave,
+    MySqlCommand::StmtPrepare,
+    MySqlCommand::StmtExecute,
+    MySqlCommand::StmtSendLongData,
+    MySqlCommand::StmtClose,
+    MySqlCommand::StmtReset,
+    MySqlCommand::SetOption,
+    MySqlCommand::StmtFetch,
+    MySqlCommand::Daemon,
+    MySqlCommand::BinlogDumpGtid,
+    MySqlCommand::ResetConnection,
+    MySqlCommand::Clone,
+];
+
+impl MySqlCommand {
+    /// The command of a packet the client sent.
+    pub fn request(code: u8) -> Option<Self> {
+        REQUESTS.get(code as usize).copied()
+    }
+
+    /// The header of a packet the server sent, `None` for anything that
+    /// does not start with one.
+    pub fn response(code: u8) -> Option<Self> {
+        match code {
+            0x00 => Some(MySqlCommand::Ok),
+            0xfb => Some(MySqlCommand::LocalInfile),
+            0xfe => Some(MySqlCommand::Eof),
+            0xff => Some(MySqlCommand::Err),
+            _ => None,
+        }
+    }
+
+    /// The first byte of a packet of the client, `request`, or of the server.
+    pub fn new(code: u8, request: bool) -> Option<Self> {
+        if request {
+            Self::request(code)
+        } else {
+            Self::response(code)
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            MySqlCommand::Ok => 0x00,
+            MySqlCommand::LocalInfile => 0xfb,
+            MySqlCommand::Eof => 0xfe,
+            MySqlCommand::Err => 0xff,
+            command => REQUESTS
+                .iter()
+                .position(|c| c == command)
+                .unwrap_or_default() as u8,
+        }
+    }
+
+    /// Whether the client sends it, the server headers are not commands.
+    pub fn is_request(&self) -> bool {
+        !matches!(
+            self,
+            MySqlCommand::Ok | MySqlCommand::LocalInfile | MySqlCommand::Eof | MySqlCommand::Err
+        )
+    }
+
+    /// Name as in the MySQL sources, e.g. `COM_QUERY` or `OK`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MySqlCommand::Sleep => "COM_SLEEP",
+            MySqlCommand::Quit => "COM_QUIT",
+            MySqlCommand::InitDb => "COM_INIT_DB",
+            MySqlCommand::Query => "COM_QUERY",
+            MySqlCommand::FieldList => "COM_FIELD_LIST",
+            MySqlCommand::CreateDb => "COM_CREATE_DB",
+            MySqlCommand::DropDb => "COM_DROP_DB",
+            MySqlCommand::Refresh => "COM_REFRESH",
+            MySqlCommand::Shutdown => "COM_SHUTDOWN",
+            MySqlCommand::Statistics => "COM_STATISTICS",
+            MySqlCommand::ProcessInfo => "COM_PROCESS_INFO",
+            MySqlCommand::Connect => "COM_CONNECT",
+            MySqlCommand::ProcessKill => "COM_PROCESS_KILL",
+            MySqlCommand::Debug => "COM_DEBUG",
+            MySqlCommand::Ping => "COM_PING",
+            MySqlCommand::Time => "COM_TIME",
+            MySqlCommand::DelayedInsert => "COM_DELAYED_INSERT",
+            MySqlCommand::ChangeUser => "COM_CHANGE_USER",
+            MySqlCommand::BinlogDump => "COM_BINLOG_DUMP",
+            MySqlCommand::TableDump => "COM_TABLE_DUMP",
+            MySqlCommand::ConnectOut => "COM_CONNECT_OUT",
+            MySqlCommand::RegisterSlave => "COM_REGISTER_SLAVE",
+            MySqlCommand::StmtPrepare => "COM_STMT_PREPARE",
+            MySqlCommand::StmtExecute => "COM_STMT_EXECUTE",
+            MySqlCommand::StmtSendLongData => "COM_STMT_SEND_LONG_DATA",
+            MySqlCommand::StmtClose => "COM_STMT_CLOSE",
+            MySqlCommand::StmtReset => "COM_STMT_RESET",
+            MySqlCommand::SetOption => "COM_SET_OPTION",
+            MySqlCommand::StmtFetch => "COM_STMT_FETCH",
+            MySqlCommand::Daemon => "COM_DAEMON",
+            MySqlCommand::BinlogDumpGtid => "COM_BINLOG_DUMP_GTID",
+            MySqlCommand::ResetConnection => "COM_RESET_CONNECTION",
+            MySqlCommand::Clone => "COM_CLONE",
+            MySqlCommand::Ok => "OK",
+            MySqlCommand::LocalInfile => "LOCAL_INFILE",
+            MySqlCommand::Eof => "EOF",
+            MySqlCommand::Err => "ERR",
+        }
+    }
+}
+
+/// A client command, the error is the unknown code.
+impl TryFrom<u8> for MySqlCommand {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        Self::request(code).ok_or(code)
+    }
+}
+
+impl TryFrom<Command> for MySqlCommand {
+    type Error = u8;
+
+    fn try_from(command: Command) -> Result<Self, Self::Error> {
+        Self::try_from(command.0)
+    }
+}
+
+impl From<MySqlCommand> for Command {
+    fn from(command: MySqlCommand) -> Self {
+        Command(command.code())
+    }
+}
+
+impl fmt::Display for MySqlCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        for code in 0..=u8::MAX {
+            if let Some(command) = MySqlCommand::request(code) {
+                assert_eq!(command.code(), code);
+                assert!(command.is_request());
+            }
+            if let Some(header) = MySqlCommand::response(code) {
+                assert_eq!(header.code(), code);
+                assert!(!header.is_request());
+            }
+        }
+        assert_eq!(MySqlCommand::try_from(0x21), Err(0x21));
+        assert_eq!(
+            MySqlCommand::try_from(Command(0x03)),
+            Ok(MySqlCommand::Query)
+        );
+        assert_eq!(Command::from(MySqlCommand::StmtReset), Command(0x1a));
+        assert_eq!(Command::from(MySqlCommand::SetOption), Command(0x1b));
+        assert_eq!(MySqlCommand::Statistics.to_string(), "COM_STATISTICS");
+    }
+
+    #[test]
+    fn test_request_or_response() {
+        assert_eq!(MySqlCommand::new(0x00, true), Some(MySqlCommand::Sleep));
+        assert_eq!(MySqlCommand::new(0x00, false), Some(MySqlCommand::Ok));
+        assert_eq!(MySqlCommand::new(0xfe, true), None);
+        assert_eq!(MySqlCommand::new(0xfe, false), Some(MySqlCommand::Eof));
+        assert_eq!(MySqlCommand::new(0xff, false), Some(MySqlCommand::Err));
+        // the column count of a result set
+        assert_eq!(MySqlCommand::new(0x03, false), None);
+    }
+}
@@ -1,9 +1,19 @@
+//! Parsers of the MySQL client/server protocol. They read bytes straight off
+//! the wire and hold to one invariant, exercised by the targets in `fuzz/`:
+//! no input makes them panic. A packet that ends early, or whose length
+//! fields point past its end, gives `None`, and a length is checked against
+//! the bytes left before anything is allocated for it, so no parser
+//! allocates more than a small multiple of its input.
+
+pub mod binary;
+pub mod binlog;
 pub mod client;
+pub mod command;
 pub mod server;
 
 pub mod common {
     use crate::{Command, DBPacket, DBType};
-    use bytes::Buf;
+    use bytes::{Buf, Bytes};
     use std::any::Any;
     use std::io::Cursor;
 
@@ -24,6 +34,8 @@ pub mod common {
     pub const CLIENT_TRANSACTIONS: u32 = 8192;
     pub const CLIENT_RESERVED: u32 = 16384;
     pub const CLIENT_RESERVED2: u32 = 32768;
+    // the 4.1 name of CLIENT_RESERVED2, a 1 byte length before the auth response
+    pub const CLIENT_SECURE_CONNECTION: u32 = CLIENT_RESERVED2;
     pub const CLIENT_MULTI_STATEMENTS: u32 = 1 << 16;
     pub const CLIENT_MULTI_RESULTS: u32 = 1 << 17;
     pub const CLIENT_PS_MULTI_RESULTS: u32 = 1 << 18;
@@ -57,38 +69,62 @@ pub mod common {
     pub const SERVER_STATUS_IN_TRANS_READONLY: u32 = 8192;
     pub const SERVER_SESSION_STATE_CHANGED: u16 = 1 << 14;
 
-    // command
-    pub const QUIT: Command = Command(0x01);
-    pub const INIT_DB: Command = Command(0x02);
-    pub const QUERY: Command = Command(0x03);
-    pub const FIELD_LIST: Command = Command(0x04);
-    pub const STATISTICS: Command = Command(0x08);
-    pub const DEBUG: Command = Command(0x0D);
-    pub const PING: Command = Command(0x0E);
-    pub const RESET_CONNECTION: Command = Command(0x1F);
-    pub const SET_OPTION: Command = Command(0x1A);
-    pub const CHANGE_USER: Command = Command(0x11);
-    pub const BINLOG_DUMP: Command = Command(0x12);
-    pub const STMT_PREPARE: Command = Command(0x16);
-    pub const STMT_EXECUTE: Command = Command(0x17);
-    pub const STMT_CLOSE: Command = Command(0x19);
-    pub const STMT_RESET: Command = Command(0x1A);
-    pub const STMT_SEND_LONG_DATA: Command = Command(0x18);
-    pub const LOCAL_INFILE: Command = Command(0xfb);
-    pub const OK: Command = Command(0x00);
-    pub const ERR: Command = Command(0xff);
-    pub const EOF: Command = Command(0xfe);
+    // column type
+    pub const MYSQL_TYPE_DECIMAL: u8 = 0x00;
+    pub const MYSQL_TYPE_TINY: u8 = 0x01;
+    pub const MYSQL_TYPE_SHORT: u8 = 0x02;
+    pub const MYSQL_TYPE_LONG: u8 = 0x03;
+    pub const MYSQL_TYPE_FLOAT: u8 = 0x04;
+    pub const MYSQL_TYPE_DOUBLE: u8 = 0x05;
+    pub const MYSQL_TYPE_NULL: u8 = 0x06;
+    pub const MYSQL_TYPE_TIMESTAMP: u8 = 0x07;
+    pub const MYSQL_TYPE_LONGLONG: u8 = 0x08;
+    pub const MYSQL_TYPE_INT24: u8 = 0x09;
+    pub const MYSQL_TYPE_DATE: u8 = 0x0a;
+    pub const MYSQL_TYPE_TIME: u8 = 0x0b;
+    pub const MYSQL_TYPE_DATETIME: u8 = 0x0c;
+    pub const MYSQL_TYPE_YEAR: u8 = 0x0d;
+    pub const MYSQL_TYPE_VARCHAR: u8 = 0x0f;
+    pub const MYSQL_TYPE_BIT: u8 = 0x10;
+    // the binlog types of temporals with fractional seconds
+    pub const MYSQL_TYPE_TIMESTAMP2: u8 = 0x11;
+    pub const MYSQL_TYPE_DATETIME2: u8 = 0x12;
+    pub const MYSQL_TYPE_TIME2: u8 = 0x13;
+    pub const MYSQL_TYPE_JSON: u8 = 0xf5;
+    pub const MYSQL_TYPE_NEWDECIMAL: u8 = 0xf6;
+    pub const MYSQL_TYPE_ENUM: u8 = 0xf7;
+    pub const MYSQL_TYPE_SET: u8 = 0xf8;
+    pub const MYSQL_TYPE_TINY_BLOB: u8 = 0xf9;
+    pub const MYSQL_TYPE_MEDIUM_BLOB: u8 = 0xfa;
+    pub const MYSQL_TYPE_LONG_BLOB: u8 = 0xfb;
+    pub const MYSQL_TYPE_BLOB: u8 = 0xfc;
+    pub const MYSQL_TYPE_VAR_STRING: u8 = 0xfd;
+    pub const MYSQL_TYPE_STRING: u8 = 0xfe;
+    pub const MYSQL_TYPE_GEOMETRY: u8 = 0xff;
+
+    // column flag
+    pub const NOT_NULL_FLAG: u16 = 1;
+    pub const PRI_KEY_FLAG: u16 = 2;
+    pub const UNSIGNED_FLAG: u16 = 32;
+    pub const BINARY_FLAG: u16 = 128;
+
+    // NULL in a text row
+    pub const NULL_VALUE: u8 = 0xfb;
+
+    // longest payload of a single packet, longer ones are split
+    pub const MAX_PAYLOAD_LEN: usize = 0xffffff;
 
     #[derive(Debug, Clone)]
     pub struct MySQLPacketRequest {
         len: u32,
         pub seq: u8,
         cmd: Command,
-        payload: Vec<u8>,
+        // shares the buffer of the captured frame
+        payload: Bytes,
     }
 
     impl MySQLPacketRequest {
-        pub fn new(payload: &[u8]) -> Option<MySQLPacketRequest> {
+        pub fn new(payload: &Bytes) -> Option<MySQLPacketRequest> {
             if payload.len() < 5 {
                 return None;
             }
@@ -100,7 +136,7 @@ pub mod common {
                 len,
                 seq,
                 cmd,
-                payload: payload[5..].to_vec(),
+                payload: payload.slice(5..),
             })
         }
     }
@@ -114,8 +150,8 @@ pub mod common {
             self.cmd.clone()
         }
 
-        fn get_payload(&self) -> Vec<u8> {
-            self.payload.clone()
+        fn get_payload(&self) -> &[u8] {
+            &self.payload
         }
 
         fn get_seq(&self) -> u8 {
@@ -140,11 +176,11 @@ pub mod common {
         pub first_pkt_len: u32,
         pub first_pkt_seq: u8,
         pub first_pkt_cmd: Command,
-        pub payload: Vec<u8>,
+        pub payload: Bytes,
     }
 
     impl MySQLPacketResponse {
-        pub fn new(payload: &[u8]) -> Option<MySQLPacketResponse> {
+        pub fn new(payload: &Bytes) -> Option<MySQLPacketResponse> {
             if payload.len() < 5 {
                 return None;
             }
@@ -156,7 +192,7 @@ pub mod common {
                 first_pkt_len,
                 first_pkt_seq,
                 first_pkt_cmd,
-                payload: payload.to_vec(),
+                payload: payload.clone(),
             })
         }
     }
@@ -170,8 +206,8 @@ pub mod common {
             self.first_pkt_cmd.clone()
         }
 
-        fn get_payload(&self) -> Vec<u8> {
-            self.payload.clone()
+        fn get_payload(&self) -> &[u8] {
+            &self.payload
         }
 
         fn get_seq(&self) -> u8 {
@@ -190,6 +226,7 @@ pub mod common {
         }
     }
 
+    #[derive(Default)]
     pub struct MySQLParser {}
 
     impl MySQLParser {
@@ -198,27 +235,146 @@ pub mod common {
         }
     }
 
-    pub fn read_len_enc_int(payload: &mut Cursor<&[u8]>) -> (u64, usize) {
-        let mut len = 0;
-        let mut pos = 0;
-        let mut shift = 0;
+    /// Length-encoded integer and the number of bytes it took, `None` when
+    /// the cursor ends before it does.
+    pub fn read_len_enc_int(payload: &mut Cursor<&[u8]>) -> Option<(u64, usize)> {
+        Some(match payload.try_get_u8().ok()? {
+            0xfc => (payload.try_get_u16_le().ok()? as u64, 3),
+            0xfd => (payload.try_get_uint_le(3).ok()?, 4),
+            0xfe => (payload.try_get_u64_le().ok()?, 9),
+            b => (b as u64, 1),
+        })
+    }
+
+    /// Length-encoded byte string and the number of bytes it took. The
+    /// length is checked against the bytes left before anything is
+    /// allocated.
+    pub fn read_len_enc_bytes(payload: &mut Cursor<&[u8]>) -> Option<(Vec<u8>, usize)> {
+        let (len, pos) = read_len_enc_int(payload)?;
+        if len > payload.remaining() as u64 {
+            return None;
+        }
+        let bytes = payload.chunk()[..len as usize].to_vec();
+        payload.advance(len as usize);
+        Some((bytes, pos + len as usize))
+    }
+
+    /// Length-encoded string and the number of bytes it took.
+    pub fn read_len_enc_str(payload: &mut Cursor<&[u8]>) -> Option<(String, usize)> {
+        let (bytes, len) = read_len_enc_bytes(payload)?;
+        Some((String::from_utf8_lossy(&bytes).to_string(), len))
+    }
+
+    /// String up to a NUL byte, which is skipped, `None` without one.
+    pub fn read_nul_str(payload: &mut Cursor<&[u8]>) -> Option<String> {
+        let len = payload.chunk().iter().position(|c| *c == 0)?;
+        let s = String::from_utf8_lossy(&payload.chunk()[..len]).to_string();
+        payload.advance(len + 1);
+        Some(s)
+    }
+
+    /// Append `v` as a length-encoded integer, in as few bytes as it fits.
+    pub fn write_len_enc_int(buf: &mut Vec<u8>, v: u64) {
+        match v {
+            0..=0xfa => buf.push(v as u8),
+            0xfb..=0xffff => {
+                buf.push(0xfc);
+                buf.extend_from_slice(&(v as u16).to_le_bytes());
+            }
+            0x10000..=0xffffff => {
+                buf.push(0xfd);
+                buf.extend_from_slice(&(v as u32).to_le_bytes()[..3]);
+            }
+            _ => {
+                buf.push(0xfe);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+
+    pub fn write_len_enc_str(buf: &mut Vec<u8>, s: &[u8]) {
+        write_len_enc_int(buf, s.len() as u64);
+        buf.extend_from_slice(s);
+    }
+
+    /// `payload` behind packet headers starting at `seq`, split in packets
+    /// of `MAX_PAYLOAD_LEN` bytes and an empty one if the last is full.
+    pub fn write_packet(seq: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(payload.len() + 4);
+        let mut chunks = payload.chunks(MAX_PAYLOAD_LEN);
+        let mut seq = seq;
         loop {
-            let b = payload.get_u8();
-            pos += 1;
-            len |= ((b & 0x7f) as u64) << shift;
-            if b & 0x80 == 0 {
-                break;
+            let chunk = chunks.next().unwrap_or_default();
+            buf.extend_from_slice(&(chunk.len() as u32).to_le_bytes()[..3]);
+            buf.push(seq);
+            buf.extend_from_slice(chunk);
+            if chunk.len() < MAX_PAYLOAD_LEN {
+                return buf;
             }
-            shift += 7;
+            seq = seq.wrapping_add(1);
         }
-        (len, pos)
     }
 
-    pub fn read_len_enc_str(payload: &mut Cursor<&[u8]>) -> (String, usize) {
-        let (len, pos) = read_len_enc_int(payload);
-        let start = payload.position() as usize;
-        let end = start + len as usize;
-        let s = String::from_utf8(payload.get_ref()[start..end].to_vec()).unwrap();
-        (s, pos + len as usize)
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_read_len_enc_int() {
+            let cases: [(&[u8], u64, usize); 4] = [
+                (&[0xfa], 250, 1),
+                (&[0xfc, 0x2c, 0x01], 300, 3),
+                (&[0xfd, 0x01, 0x00, 0x01], 65537, 4),
+                (&[0xfe, 0, 0, 0, 0, 1, 0, 0, 0], 1 << 32, 9),
+            ];
+            for (bytes, value, len) in cases {
+                assert_eq!(read_len_enc_int(&mut Cursor::new(bytes)), Some((value, len)));
+                for end in 0..bytes.len() {
+                    assert_eq!(read_len_enc_int(&mut Cursor::new(&bytes[..end])), None);
+                }
+            }
+        }
+
+        #[test]
+        fn test_read_len_enc_str() {
+            let bytes: &[u8] = &[0x03, b'a', b'b', b'c', 0x01, b'd'];
+            let mut reader = Cursor::new(bytes);
+            assert_eq!(read_len_enc_str(&mut reader), Some(("abc".to_string(), 4)));
+            assert_eq!(read_len_enc_str(&mut reader), Some(("d".to_string(), 2)));
+            assert_eq!(read_len_enc_str(&mut reader), None);
+            // a length past the end of the packet
+            let bytes: &[u8] = &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, b'a'];
+            assert_eq!(read_len_enc_str(&mut Cursor::new(bytes)), None);
+        }
+
+        #[test]
+        fn test_write_len_enc_int() {
+            for v in [0, 250, 251, 0xffff, 0x10000, 0xffffff, 0x1000000, u64::MAX] {
+                let mut buf = Vec::new();
+                write_len_enc_int(&mut buf, v);
+                let mut reader = Cursor::new(buf.as_slice());
+                assert_eq!(read_len_enc_int(&mut reader), Some((v, buf.len())));
+                assert!(!reader.has_remaining());
+            }
+        }
+
+        #[test]
+        fn test_read_nul_str() {
+            let mut reader = Cursor::new(&b"root\0shop"[..]);
+            assert_eq!(read_nul_str(&mut reader), Some("root".to_string()));
+            assert_eq!(read_nul_str(&mut reader), None);
+            assert_eq!(reader.remaining(), 4);
+        }
+
+        #[test]
+        fn test_write_packet() {
+            assert_eq!(write_packet(3, b""), [0, 0, 0, 3]);
+            assert_eq!(write_packet(0, b"\x0e"), [1, 0, 0, 0, 0x0e]);
+            let payload = vec![0; MAX_PAYLOAD_LEN];
+            let buf = write_packet(255, &payload);
+            assert_eq!(buf.len(), MAX_PAYLOAD_LEN + 8);
+            assert_eq!(buf[..4], [0xff, 0xff, 0xff, 255]);
+            assert_eq!(buf[MAX_PAYLOAD_LEN + 4..], [0, 0, 0, 0]);
+        }
     }
 }
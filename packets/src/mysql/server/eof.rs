@@ -1,30 +1,40 @@
 use crate::mysql::common::CLIENT_PROTOCOL_41;
 use bytes::Buf;
 use std::io::Cursor;
-use crate::Command;
+use crate::mysql::command::MySqlCommand;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct EOFPacket {
-    pub header: Command,
+    pub header: MySqlCommand,
     pub warnings: u16,
     pub status_flags: u16,
 }
 
 impl EOFPacket {
-    pub fn new(cap: u32, reader: &mut Cursor<&[u8]>) -> Self {
-        let header = reader.get_u8();
+    pub fn new(cap: u32, reader: &mut Cursor<&[u8]>) -> Option<Self> {
+        let header = MySqlCommand::response(reader.try_get_u8().ok()?)?;
         let mut eof_pkt = EOFPacket {
-            header: Command::from(header),
+            header,
             warnings: 0,
             status_flags: 0,
         };
         if cap & CLIENT_PROTOCOL_41 > 0 {
-            let warnings = reader.get_u16_le();
-            let status_flags = reader.get_u16_le();
+            let warnings = reader.try_get_u16_le().ok()?;
+            let status_flags = reader.try_get_u16_le().ok()?;
             eof_pkt.warnings = warnings;
             eof_pkt.status_flags = status_flags;
         }
 
-        eof_pkt
+        Some(eof_pkt)
+    }
+
+    pub fn encode(&self, cap: u32) -> Vec<u8> {
+        let mut buf = vec![self.header.code()];
+        if cap & CLIENT_PROTOCOL_41 > 0 {
+            buf.extend_from_slice(&self.warnings.to_le_bytes());
+            buf.extend_from_slice(&self.status_flags.to_le_bytes());
+        }
+        buf
     }
 }
 
@@ -36,9 +46,12 @@ mod test {
     #[test]
     pub fn test_mysql57_eof() {
         let packet_bytes = [0xfe, 0x00, 0x00, 0x02, 0x00];
-        let eof_pkt = EOFPacket::new(CLIENT_PROTOCOL_41, packet_bytes.to_vec());
-        assert_eq!(eof_pkt.header.0, 0xfe);
+        let eof_pkt = EOFPacket::new(CLIENT_PROTOCOL_41, &mut Cursor::new(&packet_bytes[..])).unwrap();
+        assert_eq!(eof_pkt.header, MySqlCommand::Eof);
         assert_eq!(eof_pkt.warnings, 0x00);
         assert_eq!(eof_pkt.status_flags, 2);
+        assert_eq!(eof_pkt.encode(CLIENT_PROTOCOL_41), packet_bytes);
+        let eof_pkt = EOFPacket::new(0, &mut Cursor::new(&packet_bytes[..1])).unwrap();
+        assert_eq!(eof_pkt.encode(0), [0xfe]);
     }
 }
@@ -1,10 +1,11 @@
 use std::io::Cursor;
 use bytes::Buf;
-use crate::Command;
+use crate::mysql::command::MySqlCommand;
 use crate::mysql::common::CLIENT_PROTOCOL_41;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct  ErrPacket {
-    pub header: Command,
+    pub header: MySqlCommand,
     pub error_code: u16,
     pub sql_state_marker: Option<u8>,
     pub sql_state: Option<String>,
@@ -12,48 +13,55 @@ pub struct  ErrPacket {
 }
 
 impl ErrPacket {
-    pub fn new(cap:u32, payload: Vec<u8>) -> Self {
+    pub fn new(cap:u32, payload: Vec<u8>) -> Option<Self> {
         let mut reader = Cursor::new(payload);
+        let header = MySqlCommand::response(reader.try_get_u8().ok()?)?;
         let mut err_pkt = ErrPacket {
-            header: Command(0),
+            header,
             error_code:0,
             sql_state_marker: None,
             sql_state: None,
             error_message: String::new(),
         };
-        let header = reader.get_u8();
-        err_pkt.header = Command::from(header);
-        let error_code = reader.get_u16_le();
+        let error_code = reader.try_get_u16_le().ok()?;
         err_pkt.error_code = error_code;
         if cap & CLIENT_PROTOCOL_41 >0{
-            let sql_state_marker = reader.get_u8();
+            let sql_state_marker = reader.try_get_u8().ok()?;
             err_pkt.sql_state_marker = Some(sql_state_marker);
             let mut sql_state = String::new();
             for _ in 0..5 {
-                let c = reader.get_u8();
+                let c = reader.try_get_u8().ok()?;
                 sql_state.push(c as char);
             }
             err_pkt.sql_state = Some(sql_state);
         }
-        let mut error_message = String::new();
-        loop {
-            if  reader.remaining() <= 0 {
-                break;
-            }
-            let c = reader.get_u8();
-            if c == 0  {
-                break;
+        err_pkt.error_message = String::from_utf8_lossy(reader.chunk()).to_string();
+        Some(err_pkt)
+    }
+
+    pub fn encode(&self, cap: u32) -> Vec<u8> {
+        let mut buf = vec![self.header.code()];
+        buf.extend_from_slice(&self.error_code.to_le_bytes());
+        if cap & CLIENT_PROTOCOL_41 > 0 {
+            buf.push(self.sql_state_marker.unwrap_or(b'#'));
+            // always 5 characters
+            let mut sql_state = [b'0'; 5];
+            if let Some(state) = &self.sql_state {
+                for (c, s) in sql_state.iter_mut().zip(state.bytes()) {
+                    *c = s;
+                }
             }
-            error_message.push(c as char);
+            buf.extend_from_slice(&sql_state);
         }
-        err_pkt.error_message = error_message;
-        err_pkt
+        buf.extend_from_slice(self.error_message.as_bytes());
+        buf
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     pub fn test_mysql57_err() {
@@ -65,20 +73,41 @@ mod test {
             0x64
         ];
 
-        let err_packet = ErrPacket::new(CLIENT_PROTOCOL_41, packet_bytes.to_vec());
+        let err_packet = ErrPacket::new(CLIENT_PROTOCOL_41, packet_bytes.to_vec()).unwrap();
 
-        if let ErrPacket {
+        let ErrPacket {
             header,
             error_code,
             sql_state_marker,
             sql_state,
             error_message,
-        } = err_packet {
-            assert_eq!(header.0, 0xff);
-            assert_eq!(error_code, 1046);
-            assert_eq!(sql_state_marker, Some(0x23));
-            assert_eq!(sql_state, Some("3D000".to_string()));
-            assert_eq!(error_message, "No database selected".to_string());
+        } = err_packet;
+        assert_eq!(header, MySqlCommand::Err);
+        assert_eq!(error_code, 1046);
+        assert_eq!(sql_state_marker, Some(0x23));
+        assert_eq!(sql_state, Some("3D000".to_string()));
+        assert_eq!(error_message, "No database selected".to_string());
+        let err_packet = ErrPacket::new(CLIENT_PROTOCOL_41, packet_bytes.to_vec()).unwrap();
+        assert_eq!(err_packet.encode(CLIENT_PROTOCOL_41), packet_bytes);
+    }
+
+    proptest! {
+        #[test]
+        fn test_err_round_trip(
+            protocol_41 in any::<bool>(),
+            error_code in any::<u16>(),
+            sql_state in "[0-9A-Z]{5}",
+            error_message in "\\PC{0,64}",
+        ) {
+            let cap = if protocol_41 { CLIENT_PROTOCOL_41 } else { 0 };
+            let err_packet = ErrPacket {
+                header: MySqlCommand::Err,
+                error_code,
+                sql_state_marker: protocol_41.then_some(b'#'),
+                sql_state: protocol_41.then_some(sql_state),
+                error_message,
+            };
+            prop_assert_eq!(ErrPacket::new(cap, err_packet.encode(cap)), Some(err_packet));
         }
     }
 }
\ No newline at end of file
@@ -3,7 +3,7 @@ use log::error;
 
 use std::io::Cursor;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Greeting {
     pub protocol_version: u8,
     pub server_version: String,
@@ -19,41 +19,87 @@ pub struct Greeting {
     pub auth_plugin_name: String,    // terminated by 0x00
 }
 
+pub const PROTOCOL_VERSION_10: u8 = 0x0a;
+
+// MariaDB sends its extended capabilities in the last 4 bytes of the filler
+const MARIADB_CAPS_OFFSET: usize = 6;
+
+// server version strings are short, anything longer is not a greeting
+const MAX_SERVER_VERSION_LEN: usize = 64;
+
 impl Greeting {
+    /// Bounds-checked structural check that `payload` (without the packet
+    /// header) is a protocol 10 greeting, safe to call on arbitrary bytes.
+    pub fn probe(payload: &[u8]) -> bool {
+        if payload.first() != Some(&PROTOCOL_VERSION_10) {
+            return false;
+        }
+        let version_end = match payload
+            .iter()
+            .skip(1)
+            .take(MAX_SERVER_VERSION_LEN)
+            .position(|c| *c == 0)
+        {
+            Some(pos) => pos + 1,
+            None => return false,
+        };
+        let version = &payload[1..version_end];
+        if version.is_empty() || !version.iter().all(|c| c.is_ascii_graphic()) {
+            return false;
+        }
+        // connection id, auth data part 1 and its filler
+        let mut pos = version_end + 1 + 4;
+        pos += match payload.get(pos..).and_then(|rest| rest.iter().position(|c| *c == 0)) {
+            Some(len) => len + 1,
+            None => return false,
+        };
+        // capability flags, language, status, extended capability flags, auth plugin len
+        pos += 2 + 1 + 2 + 2 + 1;
+        match payload.get(pos..pos + 10) {
+            Some(unused) if unused[..MARIADB_CAPS_OFFSET].iter().all(|c| *c == 0) => pos += 10,
+            _ => return false,
+        }
+        // auth data part 2 and the auth plugin name are both NUL terminated
+        match payload.get(pos..) {
+            Some(rest) => rest.iter().filter(|c| **c == 0).count() >= 2,
+            None => false,
+        }
+    }
+
     pub fn new(payload: Vec<u8>) -> Option<Self> {
         let mut reader = Cursor::new(payload);
 
-        let protocol_version = reader.get_u8();
+        let protocol_version = reader.try_get_u8().ok()?;
 
         let mut server_version = String::new();
         loop {
-            let c = reader.get_u8();
+            let c = reader.try_get_u8().ok()?;
             if c == 0 {
                 break;
             }
             server_version.push(c as char);
         }
 
-        let connection_id = reader.get_u32_le();
+        let connection_id = reader.try_get_u32_le().ok()?;
 
         let mut auth_plugin_data = Vec::new();
         loop {
-            let c = reader.get_u8();
+            let c = reader.try_get_u8().ok()?;
             if c == 0 {
                 break;
             }
             auth_plugin_data.push(c);
         }
 
-        let capability_flags = reader.get_u16_le();
-        let server_language = reader.get_u8();
-        let status_flags = reader.get_u16_le();
-        let extended_capability_flags = reader.get_u16_le();
-        let auth_plugin_len = reader.get_u8();
+        let capability_flags = reader.try_get_u16_le().ok()?;
+        let server_language = reader.try_get_u8().ok()?;
+        let status_flags = reader.try_get_u16_le().ok()?;
+        let extended_capability_flags = reader.try_get_u16_le().ok()?;
+        let auth_plugin_len = reader.try_get_u8().ok()?;
         let mut unused = [0; 10];
-        for i in 0..10 {
-            unused[i] = reader.get_u8();
-            if unused[i] != 0 {
+        for (i, byte) in unused.iter_mut().enumerate() {
+            *byte = reader.try_get_u8().ok()?;
+            if i < MARIADB_CAPS_OFFSET && *byte != 0 {
                 error!("mysql server greeting unused field is not 0");
                 return None;
             }
@@ -61,7 +107,7 @@ impl Greeting {
 
         let mut auth_plugin_data_2 = Vec::new();
         loop {
-            let c = reader.get_u8();
+            let c = reader.try_get_u8().ok()?;
             if c == 0 {
                 break;
             }
@@ -70,7 +116,7 @@ impl Greeting {
 
         let mut auth_plugin_name = String::new();
         loop {
-            let c = reader.get_u8();
+            let c = reader.try_get_u8().ok()?;
             if c == 0 {
                 break;
             }
@@ -94,6 +140,28 @@ impl Greeting {
     }
 }
 
+impl Greeting {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![self.protocol_version];
+        buf.extend_from_slice(self.server_version.as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(&self.connection_id.to_le_bytes());
+        buf.extend_from_slice(&self.auth_plugin_data);
+        buf.push(0);
+        buf.extend_from_slice(&self.capability_flags.to_le_bytes());
+        buf.push(self.server_language);
+        buf.extend_from_slice(&self.status_flags.to_le_bytes());
+        buf.extend_from_slice(&self.extended_capability_flags.to_le_bytes());
+        buf.push(self.auth_plugin_len);
+        buf.extend_from_slice(&self.unused);
+        buf.extend_from_slice(&self.auth_plugin_data_2);
+        buf.push(0);
+        buf.extend_from_slice(self.auth_plugin_name.as_bytes());
+        buf.push(0);
+        buf
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -127,6 +195,50 @@ mod test {
             auth_plugin_data_2: vec![37, 103, 29, 57, 64, 27, 108, 122, 102, 47, 106, 98],
             auth_plugin_name: "mysql_native_password".to_string(),
         };
-        assert_eq!(greeting.protocol_version, expected.protocol_version);
+        assert_eq!(greeting, expected);
+        assert_eq!(greeting.encode(), payload);
+    }
+
+    #[test]
+    pub fn test_probe_greeting() {
+        use super::Greeting;
+
+        let payload = [
+            0x0a, 0x35, 0x2e, 0x37, 0x2e, 0x34, 0x34, 0x00, 0xee, 0x08, 0x00, 0x00, 0x65, 0x6d,
+            0x50, 0x7f, 0x1f, 0x19, 0x2c, 0x32, 0x00, 0xff, 0xff, 0x08, 0x02, 0x00, 0xff, 0xc1,
+            0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x67, 0x1d,
+            0x39, 0x40, 0x1b, 0x6c, 0x7a, 0x66, 0x2f, 0x6a, 0x62, 0x00, 0x6d, 0x79, 0x73, 0x71,
+            0x6c, 0x5f, 0x6e, 0x61, 0x74, 0x69, 0x76, 0x65, 0x5f, 0x70, 0x61, 0x73, 0x73, 0x77,
+            0x6f, 0x72, 0x64, 0x00,
+        ];
+        assert!(Greeting::probe(&payload));
+        for len in 0..payload.len() - 1 {
+            assert!(!Greeting::probe(&payload[..len]), "truncated at {}", len);
+        }
+        assert!(!Greeting::probe(b"\x0aGET / HTTP/1.1\r\n"));
+        for len in 0..payload.len() {
+            assert!(Greeting::new(payload[..len].to_vec()).is_none(), "truncated at {}", len);
+        }
+    }
+
+    #[test]
+    pub fn test_mariadb_greeting() {
+        use super::Greeting;
+
+        let mut payload = vec![0x0a];
+        payload.extend_from_slice(b"5.5.5-10.11.6-MariaDB\0");
+        payload.extend_from_slice(&[0x2a, 0, 0, 0]);
+        payload.extend_from_slice(b"abcdefgh\0");
+        payload.extend_from_slice(&[0xfe, 0xf7, 0x2d, 0x02, 0x00, 0xff, 0x81, 0x15]);
+        // filler followed by the MariaDB extended capabilities
+        payload.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0x1d, 0, 0, 0]);
+        payload.extend_from_slice(b"ijklmnopqrst\0mysql_native_password\0");
+
+        assert!(Greeting::probe(&payload));
+        let greeting = Greeting::new(payload.clone()).unwrap();
+        assert_eq!(greeting.encode(), payload);
+        assert_eq!(greeting.server_version, "5.5.5-10.11.6-MariaDB");
+        assert_eq!(greeting.connection_id, 42);
+        assert_eq!(greeting.auth_plugin_name, "mysql_native_password");
     }
 }
@@ -0,0 +1,47 @@
+use crate::mysql::command::MySqlCommand;
+
+/// LOCAL INFILE request, the server asks the client for a file in answer
+/// to LOAD DATA LOCAL INFILE. The client sends the file in packets of any
+/// size and an empty packet after them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LocalInfilePacket {
+    pub filename: String,
+}
+
+impl LocalInfilePacket {
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        match payload.split_first() {
+            Some((&header, filename))
+                if MySqlCommand::response(header) == Some(MySqlCommand::LocalInfile) =>
+            {
+                Some(LocalInfilePacket {
+                    filename: String::from_utf8_lossy(filename).to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![MySqlCommand::LocalInfile.code()];
+        buf.extend_from_slice(self.filename.as_bytes());
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_local_infile() {
+        let payload = [
+            0xfb, 0x2f, 0x65, 0x74, 0x63, 0x2f, 0x70, 0x61, 0x73, 0x73, 0x77, 0x64,
+        ];
+        let request = LocalInfilePacket::new(&payload).unwrap();
+        assert_eq!(request.filename, "/etc/passwd");
+        assert_eq!(request.encode(), payload);
+        assert_eq!(LocalInfilePacket::new(&payload[1..]), None);
+        assert_eq!(LocalInfilePacket::new(&[]), None);
+    }
+}
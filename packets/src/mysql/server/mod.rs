@@ -2,10 +2,14 @@ pub mod ok;
 pub mod greeting;
 pub mod err;
 pub mod eof;
-mod tabluar;
+pub mod infile;
+pub mod tabluar;
+pub mod row;
+pub mod stmt;
 
-#[derive(Debug, Default)]
-pub(self) enum SessionTrackType {
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SessionTrackType {
+    SessionTrackSystemVariables,
     #[default]
     SessionTrackSchema,
     SessionTrackStateChange,
@@ -18,6 +22,7 @@ pub(self) enum SessionTrackType {
 impl From<u8> for SessionTrackType {
     fn from(v: u8) -> Self {
         match v {
+            0x0 => SessionTrackType::SessionTrackSystemVariables,
             0x1 => SessionTrackType::SessionTrackSchema,
             0x2 => SessionTrackType::SessionTrackStateChange,
             0x3 => SessionTrackType::SessionTrackGtids,
@@ -1,15 +1,15 @@
+use crate::mysql::command::MySqlCommand;
 use crate::mysql::common;
 use crate::mysql::common::{
-    CLIENT_PROTOCOL_41, CLIENT_TRANSACTIONS, SERVER_SESSION_STATE_CHANGED,
+    CLIENT_PROTOCOL_41, CLIENT_SESSION_TRACK, CLIENT_TRANSACTIONS, SERVER_SESSION_STATE_CHANGED,
 };
 use crate::mysql::server::SessionTrackType;
-use crate::Command;
 use bytes::Buf;
 use std::io::Cursor;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OKPacket {
-    pub cmd: Command,
+    pub cmd: MySqlCommand,
     pub affected_rows: u64,
     pub last_insert_id: u64,
     pub status_flags: Option<u16>,
@@ -17,90 +17,214 @@ pub struct OKPacket {
     pub session_track_info: Option<SessionTrackInfo>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct SessionTrackInfo {
-    pub r#type: SessionTrackType,
     pub info: String,
-    pub schema_change_info: Option<String>,
+    // every entry of the session state, with SERVER_SESSION_STATE_CHANGED
+    pub changes: Vec<SessionStateChange>,
+}
+
+/// An entry of the session state changes, by its `SessionTrackType`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionStateChange {
+    SystemVariable { name: String, value: String },
+    Schema(String),
+    // "1" if any tracked state changed
+    StateChange(String),
+    // the GTIDs in the format of `spec`, 0 for the GTID set of the session
+    Gtids { spec: u8, gtids: String },
+    // the statements that restart the transaction, e.g.
+    // "SET TRANSACTION ISOLATION LEVEL READ COMMITTED; START TRANSACTION;"
+    TransactionCharacteristics(String),
+    // 8 characters, an explicit ('T') or implicit ('I') transaction first
+    TransactionState(String),
+    // an entry of a type this version does not know, kept as is
+    Other { r#type: u8, data: Vec<u8> },
+}
+
+impl SessionStateChange {
+    /// Type, length-encoded data, and the data of a known type read whole.
+    fn new(reader: &mut Cursor<&[u8]>) -> Option<Self> {
+        let r#type = reader.try_get_u8().ok()?;
+        let data = common::read_len_enc_bytes(reader)?.0;
+        let mut reader = Cursor::new(data.as_slice());
+        let reader = &mut reader;
+        let change = match SessionTrackType::from(r#type) {
+            SessionTrackType::SessionTrackSystemVariables => SessionStateChange::SystemVariable {
+                name: common::read_len_enc_str(reader)?.0,
+                value: common::read_len_enc_str(reader)?.0,
+            },
+            SessionTrackType::SessionTrackSchema => {
+                SessionStateChange::Schema(common::read_len_enc_str(reader)?.0)
+            }
+            SessionTrackType::SessionTrackStateChange => {
+                SessionStateChange::StateChange(common::read_len_enc_str(reader)?.0)
+            }
+            SessionTrackType::SessionTrackGtids => SessionStateChange::Gtids {
+                spec: reader.try_get_u8().ok()?,
+                gtids: common::read_len_enc_str(reader)?.0,
+            },
+            SessionTrackType::SessionTrackTransactionCharacteristics => {
+                SessionStateChange::TransactionCharacteristics(common::read_len_enc_str(reader)?.0)
+            }
+            SessionTrackType::SessionTrackTransactionState => {
+                SessionStateChange::TransactionState(common::read_len_enc_str(reader)?.0)
+            }
+            SessionTrackType::None => return Some(SessionStateChange::Other { r#type, data }),
+        };
+        Some(change)
+    }
+
+    pub fn r#type(&self) -> SessionTrackType {
+        match self {
+            SessionStateChange::SystemVariable { .. } => {
+                SessionTrackType::SessionTrackSystemVariables
+            }
+            SessionStateChange::Schema(_) => SessionTrackType::SessionTrackSchema,
+            SessionStateChange::StateChange(_) => SessionTrackType::SessionTrackStateChange,
+            SessionStateChange::Gtids { .. } => SessionTrackType::SessionTrackGtids,
+            SessionStateChange::TransactionCharacteristics(_) => {
+                SessionTrackType::SessionTrackTransactionCharacteristics
+            }
+            SessionStateChange::TransactionState(_) => {
+                SessionTrackType::SessionTrackTransactionState
+            }
+            SessionStateChange::Other { .. } => SessionTrackType::None,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mut data = Vec::new();
+        let mut str = |s: &String| common::write_len_enc_str(&mut data, s.as_bytes());
+        let r#type = match self {
+            SessionStateChange::SystemVariable { name, value } => {
+                str(name);
+                str(value);
+                0x0
+            }
+            SessionStateChange::Schema(schema) => {
+                str(schema);
+                0x1
+            }
+            SessionStateChange::StateChange(changed) => {
+                str(changed);
+                0x2
+            }
+            SessionStateChange::Gtids { spec, gtids } => {
+                data.push(*spec);
+                common::write_len_enc_str(&mut data, gtids.as_bytes());
+                0x3
+            }
+            SessionStateChange::TransactionCharacteristics(characteristics) => {
+                str(characteristics);
+                0x4
+            }
+            SessionStateChange::TransactionState(state) => {
+                str(state);
+                0x5
+            }
+            SessionStateChange::Other { r#type, data: raw } => {
+                data.extend_from_slice(raw);
+                *r#type
+            }
+        };
+        buf.push(r#type);
+        common::write_len_enc_str(buf, &data);
+    }
 }
 
 impl OKPacket {
     pub fn new(cap: u32, payload: Vec<u8>) -> Option<Self> {
-        let mut reader = Cursor::new(payload);
-        let mut ok_pkt = OKPacket::default();
-        let cmd = Command::from(reader.get_u8());
-        ok_pkt.cmd = cmd;
-
-        let affected_rows = common::read_len_enc_int(&mut reader).0;
-        let last_insert_id = common::read_len_enc_int(&mut reader).0;
-        ok_pkt.affected_rows = affected_rows;
-        ok_pkt.last_insert_id = last_insert_id;
+        let mut reader = Cursor::new(payload.as_slice());
+        let cmd = MySqlCommand::response(reader.try_get_u8().ok()?)?;
+        let affected_rows = common::read_len_enc_int(&mut reader)?.0;
+        let last_insert_id = common::read_len_enc_int(&mut reader)?.0;
+        let mut ok_pkt = OKPacket {
+            cmd,
+            affected_rows,
+            last_insert_id,
+            status_flags: None,
+            warnings: None,
+            session_track_info: None,
+        };
 
         if cap & CLIENT_PROTOCOL_41 > 0 {
-            let status_flags = Some(reader.get_u16_le());
-            let warnings = Some(reader.get_u16_le());
+            let status_flags = Some(reader.try_get_u16_le().ok()?);
+            let warnings = Some(reader.try_get_u16_le().ok()?);
             ok_pkt.status_flags = status_flags;
             ok_pkt.warnings = warnings;
         } else if cap & CLIENT_TRANSACTIONS > 0 {
-            let status_flags = Some(reader.get_u16_le());
+            let status_flags = Some(reader.try_get_u16_le().ok()?);
             ok_pkt.status_flags = status_flags;
         }
 
-        if reader.remaining() <= 0 {
+        if !reader.has_remaining() {
             return Some(ok_pkt);
         }
 
-        if reader.remaining() <= 0 {
+        if cap & CLIENT_SESSION_TRACK == 0 {
+            // human readable info up to the end of the packet
+            ok_pkt.session_track_info = Some(SessionTrackInfo {
+                info: String::from_utf8_lossy(reader.chunk()).to_string(),
+                changes: Vec::new(),
+            });
             return Some(ok_pkt);
         }
-        let unknown = reader.get_u8(); // skip 0x00
-        if unknown != 0 {
-            return None;
-        }
 
-        if SERVER_SESSION_STATE_CHANGED & ok_pkt.status_flags? > 0 {
-            reader.get_u8();
-            let session_track_info_type = reader.get_u8();
-            reader.get_u8();
-            match SessionTrackType::from(session_track_info_type) {
-                SessionTrackType::SessionTrackSchema => {
-                    let schema_change_length = reader.get_u8();
-                    if schema_change_length > reader.remaining() as u8 {
-                        return Some(ok_pkt);
-                    }
-
-                    let schema_change_info = String::from_utf8_lossy(
-                        &reader.get_ref()[reader.position() as usize
-                            ..reader.position() as usize + schema_change_length as usize],
-                    )
-                    .to_string();
-                    reader.set_position(reader.position() + schema_change_length as u64);
-                    ok_pkt.session_track_info = Some(SessionTrackInfo {
-                        r#type: SessionTrackType::SessionTrackSchema,
-                        info: "".to_string(),
-                        schema_change_info: Some(schema_change_info),
-                    });
-                }
-                _ => {
-                    // nothing to do
-                }
+        let mut session_track_info = SessionTrackInfo {
+            info: common::read_len_enc_str(&mut reader)?.0,
+            changes: Vec::new(),
+        };
+        if SERVER_SESSION_STATE_CHANGED & ok_pkt.status_flags.unwrap_or_default() > 0 {
+            // length of all the entries, each a type and length-encoded data
+            let changes = common::read_len_enc_bytes(&mut reader)?.0;
+            let mut changes = Cursor::new(changes.as_slice());
+            while changes.has_remaining() {
+                let change = SessionStateChange::new(&mut changes)?;
+                session_track_info.changes.push(change);
             }
-        } else {
-            let session_track_info = common::read_len_enc_str(&mut reader).0;
-            ok_pkt.session_track_info = Some(SessionTrackInfo {
-                r#type: SessionTrackType::None,
-                info: session_track_info,
-                schema_change_info: None,
-            });
         }
+        ok_pkt.session_track_info = Some(session_track_info);
 
         Some(ok_pkt)
     }
+
+    pub fn encode(&self, cap: u32) -> Vec<u8> {
+        let mut buf = vec![self.cmd.code()];
+        common::write_len_enc_int(&mut buf, self.affected_rows);
+        common::write_len_enc_int(&mut buf, self.last_insert_id);
+        let status_flags = self.status_flags.unwrap_or_default();
+        if cap & CLIENT_PROTOCOL_41 > 0 {
+            buf.extend_from_slice(&status_flags.to_le_bytes());
+            buf.extend_from_slice(&self.warnings.unwrap_or_default().to_le_bytes());
+        } else if cap & CLIENT_TRANSACTIONS > 0 {
+            buf.extend_from_slice(&status_flags.to_le_bytes());
+        }
+
+        let track = match &self.session_track_info {
+            Some(track) => track,
+            None => return buf,
+        };
+        if cap & CLIENT_SESSION_TRACK == 0 {
+            buf.extend_from_slice(track.info.as_bytes());
+            return buf;
+        }
+        common::write_len_enc_str(&mut buf, track.info.as_bytes());
+        if SERVER_SESSION_STATE_CHANGED & status_flags > 0 {
+            let mut changes = Vec::new();
+            for change in &track.changes {
+                change.encode(&mut changes);
+            }
+            common::write_len_enc_str(&mut buf, &changes);
+        }
+        buf
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_ok_packet_without_session_track() {
@@ -119,11 +243,165 @@ mod test {
             0x66, 0x6f, 0x72, 0x6d, 0x61, 0x74, 0x69, 0x6f, 0x6e, 0x5f, 0x73, 0x63, 0x68, 0x65,
             0x6d, 0x61,
         ];
-        let ok_pkt = OKPacket::new(16754309, payload).unwrap();
+        let ok_pkt = OKPacket::new(16754309, payload.clone()).unwrap();
 
         assert_eq!(ok_pkt.affected_rows, 0);
         assert_eq!(ok_pkt.last_insert_id, 0);
         assert_eq!(ok_pkt.status_flags, Some(0x4002));
         println!("{:?}", ok_pkt);
+        let track = ok_pkt.session_track_info.as_ref().unwrap();
+        assert_eq!(
+            track.changes,
+            vec![SessionStateChange::Schema("information_schema".to_string())]
+        );
+        assert_eq!(ok_pkt.encode(16754309), payload);
+    }
+
+    #[test]
+    fn test_ok_packet_with_session_state_changes() {
+        // SET autocommit = 0 with session_track_state_change and
+        // session_track_transaction_info on
+        let mut changes = b"\x00\x0f\x0aautocommit\x03OFF".to_vec();
+        changes.extend_from_slice(b"\x02\x02\x011");
+        changes.extend_from_slice(b"\x05\x09\x08________");
+        changes.extend_from_slice(b"\x03\x2a\x00\x28");
+        changes.extend_from_slice(b"3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5");
+        changes.extend_from_slice(b"\x07\x02\x01x");
+        let mut payload = vec![0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00];
+        payload.push(changes.len() as u8);
+        payload.extend_from_slice(&changes);
+        let ok_pkt = OKPacket::new(16754309, payload.clone()).unwrap();
+
+        let track = ok_pkt.session_track_info.as_ref().unwrap();
+        assert_eq!(
+            track.changes,
+            vec![
+                SessionStateChange::SystemVariable {
+                    name: "autocommit".to_string(),
+                    value: "OFF".to_string(),
+                },
+                SessionStateChange::StateChange("1".to_string()),
+                SessionStateChange::TransactionState("________".to_string()),
+                SessionStateChange::Gtids {
+                    spec: 0,
+                    gtids: "3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5".to_string(),
+                },
+                SessionStateChange::Other {
+                    r#type: 0x07,
+                    data: vec![0x01, b'x'],
+                },
+            ]
+        );
+        assert_eq!(
+            track.changes[0].r#type(),
+            SessionTrackType::SessionTrackSystemVariables
+        );
+        assert_eq!(ok_pkt.encode(16754309), payload);
+
+        // an entry longer than the changes
+        let len = payload.len();
+        payload[len - 3] = 0x03;
+        assert!(OKPacket::new(16754309, payload).is_none());
+    }
+
+    #[test]
+    fn test_truncated_ok_packet() {
+        let payload = vec![0x00, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(OKPacket::new(16754309, payload).is_none());
+        let payload = vec![0x00, 0x00, 0x00, 0x02, 0x00];
+        assert!(OKPacket::new(16754309, payload).is_none());
+        // info longer than the packet
+        let payload = vec![0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0xfc, 0xff];
+        assert!(OKPacket::new(16754309, payload).is_none());
+    }
+
+    #[test]
+    fn test_ok_packet_info() {
+        // an INSERT of several rows, with and without CLIENT_SESSION_TRACK
+        let info = b"Records: 3  Duplicates: 0  Warnings: 0";
+        let mut payload = vec![0x00, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00];
+        payload.extend_from_slice(info);
+        let ok_pkt = OKPacket::new(CLIENT_PROTOCOL_41, payload.clone()).unwrap();
+        assert_eq!(ok_pkt.affected_rows, 3);
+        assert_eq!(
+            ok_pkt.session_track_info.as_ref().unwrap().info.as_bytes(),
+            info
+        );
+        assert_eq!(ok_pkt.encode(CLIENT_PROTOCOL_41), payload);
+
+        payload.insert(7, info.len() as u8);
+        let ok_pkt = OKPacket::new(16754309, payload.clone()).unwrap();
+        assert_eq!(
+            ok_pkt.session_track_info.as_ref().unwrap().info.as_bytes(),
+            info
+        );
+        assert_eq!(ok_pkt.encode(16754309), payload);
+    }
+
+    fn change() -> impl Strategy<Value = SessionStateChange> {
+        let s = || "[ -~]{0,32}";
+        prop_oneof![
+            (s(), s()).prop_map(|(name, value)| SessionStateChange::SystemVariable { name, value }),
+            s().prop_map(SessionStateChange::Schema),
+            s().prop_map(SessionStateChange::StateChange),
+            (any::<u8>(), s()).prop_map(|(spec, gtids)| SessionStateChange::Gtids { spec, gtids }),
+            s().prop_map(SessionStateChange::TransactionCharacteristics),
+            s().prop_map(SessionStateChange::TransactionState),
+            (6..=u8::MAX, proptest::collection::vec(any::<u8>(), 0..16))
+                .prop_map(|(r#type, data)| SessionStateChange::Other { r#type, data }),
+        ]
+    }
+
+    fn ok_packet() -> impl Strategy<Value = (u32, OKPacket)> {
+        let cap = prop_oneof![
+            Just(0),
+            Just(CLIENT_TRANSACTIONS),
+            Just(CLIENT_PROTOCOL_41),
+            Just(CLIENT_PROTOCOL_41 | CLIENT_SESSION_TRACK),
+        ];
+        (
+            cap,
+            any::<u64>(),
+            any::<u64>(),
+            any::<u16>(),
+            any::<u16>(),
+            proptest::option::of(("[ -~]{0,64}", proptest::collection::vec(change(), 0..4))),
+        )
+            .prop_map(
+                |(cap, affected_rows, last_insert_id, status, warnings, track)| {
+                    let mut status = status & !SERVER_SESSION_STATE_CHANGED;
+                    let track = track.and_then(|(info, changes)| {
+                        if cap & CLIENT_SESSION_TRACK == 0 {
+                            // the info is all that is left, an empty one is no info
+                            return (!info.is_empty()).then_some(SessionTrackInfo {
+                                info,
+                                changes: Vec::new(),
+                            });
+                        }
+                        if !changes.is_empty() {
+                            status |= SERVER_SESSION_STATE_CHANGED;
+                        }
+                        Some(SessionTrackInfo { info, changes })
+                    });
+                    let protocol_41 = cap & CLIENT_PROTOCOL_41 > 0;
+                    let ok_pkt = OKPacket {
+                        cmd: MySqlCommand::Ok,
+                        affected_rows,
+                        last_insert_id,
+                        status_flags: (protocol_41 || cap & CLIENT_TRANSACTIONS > 0)
+                            .then_some(status),
+                        warnings: protocol_41.then_some(warnings),
+                        session_track_info: track,
+                    };
+                    (cap, ok_pkt)
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_ok_packet_round_trip((cap, ok_pkt) in ok_packet()) {
+            prop_assert_eq!(OKPacket::new(cap, ok_pkt.encode(cap)), Some(ok_pkt));
+        }
     }
 }
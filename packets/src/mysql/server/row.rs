@@ -0,0 +1,135 @@
+use crate::mysql::binary::{null_bitmap, read_null_bitmap, read_values, Value, ValueType};
+use crate::mysql::common::{read_len_enc_bytes, write_len_enc_str, NULL_VALUE};
+use bytes::Buf;
+use std::io::Cursor;
+
+// header of a binary row, where an OK packet has its header
+const BINARY_ROW_HEADER: u8 = 0x00;
+// the first 2 bits of the null bitmap of a binary row are unused
+const BINARY_ROW_NULL_OFFSET: usize = 2;
+
+/// A row of a text result set, every value as a string and NULL as None.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextRow {
+    pub values: Vec<Option<Vec<u8>>>,
+}
+
+impl TextRow {
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        let mut values = Vec::new();
+        while reader.has_remaining() {
+            if reader.chunk()[0] == NULL_VALUE {
+                reader.advance(1);
+                values.push(None);
+            } else {
+                values.push(Some(read_len_enc_bytes(&mut reader)?.0));
+            }
+        }
+        Some(TextRow { values })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for value in &self.values {
+            match value {
+                Some(value) => write_len_enc_str(&mut buf, value),
+                None => buf.push(NULL_VALUE),
+            }
+        }
+        buf
+    }
+}
+
+/// A row of a binary result set, the answer to COM_STMT_EXECUTE. The
+/// values only make sense with the types of the column definitions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BinaryRow {
+    pub values: Vec<Value>,
+}
+
+impl BinaryRow {
+    pub fn new(types: &[ValueType], payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        if reader.try_get_u8().ok()? != BINARY_ROW_HEADER {
+            return None;
+        }
+        let bitmap = read_null_bitmap(types.len(), BINARY_ROW_NULL_OFFSET, &mut reader)?;
+        let values = read_values(types, bitmap, BINARY_ROW_NULL_OFFSET, &mut reader)?;
+        Some(BinaryRow { values })
+    }
+
+    pub fn encode(&self, types: &[ValueType]) -> Vec<u8> {
+        let mut buf = vec![BINARY_ROW_HEADER];
+        buf.extend(null_bitmap(&self.values, BINARY_ROW_NULL_OFFSET));
+        for (value, ty) in self.values.iter().zip(types) {
+            value.write(*ty, &mut buf);
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mysql::binary::test::typed_value;
+    use crate::mysql::common::{MYSQL_TYPE_LONGLONG, MYSQL_TYPE_VAR_STRING};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_text_row() {
+        // the row of the result set in the tabular test
+        let payload = b"\x0fREPEATABLE-READ\xfb";
+        let row = TextRow::new(payload).unwrap();
+        assert_eq!(row.values, [Some(b"REPEATABLE-READ".to_vec()), None]);
+        assert_eq!(row.encode(), payload);
+        assert!(TextRow::new(&payload[..10]).is_none());
+    }
+
+    #[test]
+    fn test_binary_row() {
+        let types = [
+            ValueType::new(MYSQL_TYPE_LONGLONG, false),
+            ValueType::new(MYSQL_TYPE_VAR_STRING, false),
+            ValueType::new(MYSQL_TYPE_VAR_STRING, false),
+        ];
+        let payload = [0x00, 0b0001_0000, 42, 0, 0, 0, 0, 0, 0, 0, 2, b'h', b'i'];
+        let row = BinaryRow::new(&types, &payload).unwrap();
+        assert_eq!(
+            row.values,
+            [Value::Int(42), Value::Bytes(b"hi".to_vec()), Value::Null]
+        );
+        assert_eq!(row.encode(&types), payload);
+        // an OK packet is not a row
+        assert!(BinaryRow::new(&types, &[0xfe, 0, 0, 2, 0]).is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn test_text_row_round_trip(
+            values in proptest::collection::vec(
+                proptest::option::of(proptest::collection::vec(any::<u8>(), 0..300)),
+                0..20,
+            )
+        ) {
+            let row = TextRow { values };
+            prop_assert_eq!(TextRow::new(&row.encode()), Some(row));
+        }
+
+        #[test]
+        fn test_binary_row_round_trip(
+            columns in proptest::collection::vec(
+                (typed_value(), any::<bool>()),
+                0..20,
+            )
+        ) {
+            let types: Vec<ValueType> = columns.iter().map(|((ty, _), _)| *ty).collect();
+            let values = columns
+                .into_iter()
+                .map(|((_, value), null)| if null { Value::Null } else { value })
+                .collect();
+            let row = BinaryRow { values };
+            prop_assert_eq!(BinaryRow::new(&types, &row.encode(&types)), Some(row));
+        }
+    }
+}
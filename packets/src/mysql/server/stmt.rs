@@ -0,0 +1,80 @@
+use crate::mysql::command::MySqlCommand;
+use crate::mysql::common::CLIENT_OPTIONAL_RESULTSET_METADATA;
+use bytes::Buf;
+use std::io::Cursor;
+
+/// COM_STMT_PREPARE_OK, followed by the parameter and column definitions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct STMTPrepareOKPacket {
+    pub statement_id: u32,
+    pub columns: u16,
+    pub params: u16,
+    pub warnings: u16,
+    // with CLIENT_OPTIONAL_RESULTSET_METADATA, whether definitions follow
+    pub metadata_follows: Option<u8>,
+}
+
+impl STMTPrepareOKPacket {
+    pub fn new(cap: u32, payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        if MySqlCommand::response(reader.try_get_u8().ok()?) != Some(MySqlCommand::Ok) {
+            return None;
+        }
+        let statement_id = reader.try_get_u32_le().ok()?;
+        let columns = reader.try_get_u16_le().ok()?;
+        let params = reader.try_get_u16_le().ok()?;
+        // filler
+        reader.try_get_u8().ok()?;
+        let warnings = reader.try_get_u16_le().ok()?;
+        let metadata_follows = if cap & CLIENT_OPTIONAL_RESULTSET_METADATA > 0 {
+            reader.try_get_u8().ok()
+        } else {
+            None
+        };
+        Some(STMTPrepareOKPacket {
+            statement_id,
+            columns,
+            params,
+            warnings,
+            metadata_follows,
+        })
+    }
+
+    pub fn encode(&self, cap: u32) -> Vec<u8> {
+        let mut buf = vec![MySqlCommand::Ok.code()];
+        buf.extend_from_slice(&self.statement_id.to_le_bytes());
+        buf.extend_from_slice(&self.columns.to_le_bytes());
+        buf.extend_from_slice(&self.params.to_le_bytes());
+        buf.push(0);
+        buf.extend_from_slice(&self.warnings.to_le_bytes());
+        if cap & CLIENT_OPTIONAL_RESULTSET_METADATA > 0 {
+            if let Some(metadata_follows) = self.metadata_follows {
+                buf.push(metadata_follows);
+            }
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mysql::common::CLIENT_PROTOCOL_41;
+
+    #[test]
+    fn test_prepare_ok() {
+        let payload = [
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let ok = STMTPrepareOKPacket::new(CLIENT_PROTOCOL_41, &payload).unwrap();
+        assert_eq!((ok.statement_id, ok.columns, ok.params), (1, 2, 1));
+        assert_eq!(ok.encode(CLIENT_PROTOCOL_41), payload);
+
+        let cap = CLIENT_PROTOCOL_41 | CLIENT_OPTIONAL_RESULTSET_METADATA;
+        let ok = STMTPrepareOKPacket {
+            metadata_follows: Some(1),
+            ..ok
+        };
+        assert_eq!(STMTPrepareOKPacket::new(cap, &ok.encode(cap)), Some(ok));
+    }
+}
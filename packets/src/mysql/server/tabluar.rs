@@ -1,6 +1,8 @@
 use std::io::Cursor;
 use bytes::Buf;
 use crate::mysql::common::{CLIENT_DEPRECATE_EOF, CLIENT_OPTIONAL_RESULTSET_METADATA};
+use crate::mysql::common::{read_len_enc_bytes, read_len_enc_int, write_len_enc_int, write_len_enc_str};
+use crate::mysql::command::MySqlCommand;
 use crate::mysql::common;
 use crate::mysql::server::eof::EOFPacket;
 
@@ -14,6 +16,7 @@ pub enum MetadataType {
     ResultSetMetadataFull,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct ColDef {
     pub catalog: Vec<u8>,
     pub schema: Vec<u8>,
@@ -32,6 +35,50 @@ pub struct ColDef {
     pub decimals: u8,
 }
 
+impl ColDef {
+    /// Column definition in the 4.1 format, the only one servers send since.
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        let mut reader = Cursor::new(payload);
+        Some(ColDef {
+            catalog: read_len_enc_bytes(&mut reader)?.0,
+            schema: read_len_enc_bytes(&mut reader)?.0,
+            table: read_len_enc_bytes(&mut reader)?.0,
+            org_table: read_len_enc_bytes(&mut reader)?.0,
+            name: read_len_enc_bytes(&mut reader)?.0,
+            org_name: read_len_enc_bytes(&mut reader)?.0,
+            length_of_fixed_length_fields: read_len_enc_int(&mut reader)?.0,
+            charset: reader.try_get_u16_le().ok()?,
+            column_length: reader.try_get_u32_le().ok()?,
+            column_type: reader.try_get_u8().ok()?,
+            flags: reader.try_get_u16_le().ok()?,
+            decimals: reader.try_get_u8().ok()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for s in [
+            &self.catalog,
+            &self.schema,
+            &self.table,
+            &self.org_table,
+            &self.name,
+            &self.org_name,
+        ] {
+            write_len_enc_str(&mut buf, s);
+        }
+        write_len_enc_int(&mut buf, self.length_of_fixed_length_fields);
+        buf.extend_from_slice(&self.charset.to_le_bytes());
+        buf.extend_from_slice(&self.column_length.to_le_bytes());
+        buf.push(self.column_type);
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.push(self.decimals);
+        // filler
+        buf.extend_from_slice(&[0, 0]);
+        buf
+    }
+}
+
 
 
 pub struct TextResult {
@@ -69,13 +116,13 @@ impl TextResult {
             Some(MetadataType::ResultSetMetadataNone)
         };
 
-        let column_count = common::read_len_enc_int(&mut reader).0;
+        let column_count = common::read_len_enc_int(&mut reader)?.0;
 
         if column_count == 0 {
             return None;
         }
 
-        if (cap & CLIENT_OPTIONAL_RESULTSET_METADATA) > 0 || (cap & CLIENT_OPTIONAL_RESULTSET_METADATA) > 0 {
+        if cap & CLIENT_OPTIONAL_RESULTSET_METADATA > 0 {
             let mut column_defs = Vec::new();
             for _ in 0..column_count {
                 let catalog = Vec::new();
@@ -85,11 +132,11 @@ impl TextResult {
                 let name = Vec::new();
                 let org_name = Vec::new();
                 let length_of_fixed_length_fields = 0x0c;
-                let charset = reader.get_u16_le();
-                let column_length = reader.get_u32_le();
-                let column_type = reader.get_u8();
-                let flags = reader.get_u16_le();
-                let decimals = reader.get_u8();
+                let charset = reader.try_get_u16_le().ok()?;
+                let column_length = reader.try_get_u32_le().ok()?;
+                let column_type = reader.try_get_u8().ok()?;
+                let flags = reader.try_get_u16_le().ok()?;
+                let decimals = reader.try_get_u8().ok()?;
                 column_defs.push(ColDef {
                     catalog,
                     schema,
@@ -108,32 +155,32 @@ impl TextResult {
         }
 
 
-        if cap & CLIENT_DEPRECATE_EOF <= 0  {
-            let eof = EOFPacket::new(cap, &mut reader);
+        if cap & CLIENT_DEPRECATE_EOF == 0 {
+            EOFPacket::new(cap, &mut reader)?;
         }
 
         // discard the row
         loop {
-            if reader.remaining() <= 0 {
+            if !reader.has_remaining() {
                 break;
             }
 
-            let c = reader.get_u8();
+            let c = reader.try_get_u8().ok()?;
             if c == 0xfb {
                 break;
             }
         }
 
         // end of result sets
-        let terminator = reader.get_u8();
+        let terminator = reader.try_get_u8().ok()?;
 
         // If the SERVER_MORE_RESULTS_EXISTS flag is set in the last EOF_Packet / OK_Packet, another Text Result Set will follow.
-        // todo handle more results exists
-        match terminator {
-            0xfe => {
+        // only the first is read here, the session exchange follows the flag
+        match MySqlCommand::response(terminator) {
+            Some(MySqlCommand::Eof) => {
                 let mut error_details = Vec::new();
                 loop {
-                    let c = reader.get_u8();
+                    let c = reader.try_get_u8().ok()?;
                     if c == 0 {
                         break;
                     }
@@ -151,10 +198,10 @@ impl TextResult {
                     eof_marker: None,
                 })
             }
-            0x00 => {
+            Some(MySqlCommand::Ok) => {
                 let mut execution_details = Vec::new();
                 loop {
-                    let c = reader.get_u8();
+                    let c = reader.try_get_u8().ok()?;
                     if c == 0 {
                         break;
                     }
@@ -172,8 +219,8 @@ impl TextResult {
                     eof_marker: None,
                 })
             }
-            0xff => {
-                let eof_marker = EOFPacket::new(cap, &mut reader);
+            Some(MySqlCommand::Err) => {
+                let eof_marker = EOFPacket::new(cap, &mut reader)?;
                 Some(TextResult {
                     metadata_follows,
                     column_count,
@@ -190,9 +237,6 @@ impl TextResult {
                 None
             }
         }
-
-
-        None
     }
 
 
@@ -207,41 +251,27 @@ pub struct Tabular {
 
 impl Tabular {
     pub fn new(cap: u32, mut reader: Cursor<&[u8]>) -> Option<Tabular> {
-        loop {
-            let packet_type = reader.get_u8();
-            return match packet_type {
-                0x00 => {
-                    let result_sets = TextResult::new(cap, reader);
-                    Some(Tabular {
-                        packet_type,
-                        result_sets,
-                        local_inlines: None,
-                    })
-                }
-                0xfe => {
-                    let mut local_inlines = Vec::new();
-                    loop {
-                        let packet_type = reader.get_u8();
-                        if packet_type == 0x00 {
-                            break;
-                        }
-                        let payload_len = reader.get_u8();
-                        let mut payload = vec![0; payload_len as usize];
-                        reader.copy_to_slice(&mut payload);
-                        local_inlines.push(LocalInline {
-                            packet_type,
-                            payload: Some(payload),
-                        });
-                    }
-                    Some(Tabular {
-                        packet_type,
-                        result_sets: None,
-                        local_inlines: Some(local_inlines),
-                    })
-                }
-                _ => {
-                    None
-                }
+        let packet_type = reader.try_get_u8().ok()?;
+        match MySqlCommand::response(packet_type) {
+            Some(MySqlCommand::Ok) => {
+                let result_sets = TextResult::new(cap, reader);
+                Some(Tabular {
+                    packet_type,
+                    result_sets,
+                    local_inlines: None,
+                })
+            }
+            // LOCAL INFILE request, the rest is the file name
+            Some(MySqlCommand::LocalInfile) => Some(Tabular {
+                packet_type,
+                result_sets: None,
+                local_inlines: Some(vec![LocalInline {
+                    packet_type,
+                    payload: Some(reader.chunk().to_vec()),
+                }]),
+            }),
+            _ => {
+                None
             }
         }
     }
@@ -273,13 +303,33 @@ mod test {
             0x00, 0x02, 0x00, 0x00, 0x00
         ];
 
-        let mut reader = Cursor::new(packet_bytes);
-        let tabular = Tabular::new(16754309, reader);
+        let reader = Cursor::new(packet_bytes);
+        let _tabular = Tabular::new(16754309, reader);
 
     }
 
 
-    ///! Test for local file tabular
+    #[test]
+    pub fn test_col_def() {
+        // the column definition of the result set above
+        let payload = &[
+            0x03, 0x64, 0x65, 0x66, 0x00, 0x00, 0x00, 0x1f, 0x40, 0x40, 0x73, 0x65, 0x73, 0x73,
+            0x69, 0x6f, 0x6e, 0x2e, 0x74, 0x72, 0x61, 0x6e, 0x73, 0x61, 0x63, 0x74, 0x69, 0x6f,
+            0x6e, 0x5f, 0x69, 0x73, 0x6f, 0x6c, 0x61, 0x74, 0x69, 0x6f, 0x6e, 0x00, 0x0c, 0x2d,
+            0x00, 0x3c, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x00, 0x1f, 0x00, 0x00,
+        ];
+        let col = ColDef::new(payload).unwrap();
+        assert_eq!(col.catalog, b"def");
+        assert_eq!(col.name, b"@@session.transaction_isolation");
+        assert_eq!(col.column_type, 0xfd);
+        assert_eq!(col.column_length, 60);
+        assert_eq!(col.encode(), payload);
+        for len in 0..payload.len() - 2 {
+            assert!(ColDef::new(&payload[..len]).is_none(), "truncated at {}", len);
+        }
+    }
+
+    // Test for local file tabular
     #[test]
     pub fn test_local_file_tabular() {
 
@@ -0,0 +1,250 @@
+use std::io::{self, Read, Write};
+
+pub const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+pub const MAGIC_NANOS: u32 = 0xa1b23c4d;
+pub const MAGIC_PCAPNG: u32 = 0x0a0d0d0a;
+
+// link types, see https://www.tcpdump.org/linktypes.html
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LOOP: u32 = 108;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Records larger than this are treated as corruption rather than allocated.
+pub const MAX_RECORD_LEN: u32 = 256 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct PcapRecord {
+    pub ts_sec: u32,
+    // nanoseconds, converted from micros for classic files
+    pub ts_nsec: u32,
+    pub orig_len: u32,
+    pub data: Vec<u8>,
+}
+
+/// Reader for classic libpcap files, in either byte order and either
+/// timestamp resolution.
+pub struct PcapReader<R: Read> {
+    inner: R,
+    swapped: bool,
+    nanos: bool,
+    snaplen: u32,
+    link_type: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        inner.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == MAGIC_NANOS => (true, true),
+            MAGIC_PCAPNG => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "pcapng files are not supported, convert with `editcap -F pcap`",
+                ))
+            }
+            m => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("not a pcap file (magic {:#010x})", m),
+                ))
+            }
+        };
+        let read_u32 = |b: &[u8]| {
+            let v = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            if swapped {
+                v.swap_bytes()
+            } else {
+                v
+            }
+        };
+        let snaplen = read_u32(&header[16..20]);
+        let link_type = read_u32(&header[20..24]) & 0x0fff_ffff;
+        Ok(PcapReader {
+            inner,
+            swapped,
+            nanos,
+            snaplen,
+            link_type,
+        })
+    }
+
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    pub fn snaplen(&self) -> u32 {
+        self.snaplen
+    }
+
+    fn u32_at(&self, b: &[u8]) -> u32 {
+        let v = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        if self.swapped {
+            v.swap_bytes()
+        } else {
+            v
+        }
+    }
+
+    /// Next record, `None` at a clean end of file.
+    pub fn next_record(&mut self) -> io::Result<Option<PcapRecord>> {
+        let mut header = [0u8; 16];
+        let mut read = 0;
+        while read < header.len() {
+            match self.inner.read(&mut header[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        let ts_sec = self.u32_at(&header[0..4]);
+        let ts_frac = self.u32_at(&header[4..8]);
+        let incl_len = self.u32_at(&header[8..12]);
+        let orig_len = self.u32_at(&header[12..16]);
+        if incl_len > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record length {} exceeds {}", incl_len, MAX_RECORD_LEN),
+            ));
+        }
+        let mut data = vec![0u8; incl_len as usize];
+        self.inner.read_exact(&mut data)?;
+        Ok(Some(PcapRecord {
+            ts_sec,
+            ts_nsec: if self.nanos { ts_frac } else { ts_frac * 1000 },
+            orig_len,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<PcapRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Writer of classic little endian pcap files with nanosecond timestamps.
+pub struct PcapWriter<W: Write> {
+    inner: W,
+    snaplen: u32,
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut inner: W, link_type: u32, snaplen: u32) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        header[0..4].copy_from_slice(&MAGIC_NANOS.to_le_bytes());
+        header[4..6].copy_from_slice(&2u16.to_le_bytes());
+        header[6..8].copy_from_slice(&4u16.to_le_bytes());
+        // thiszone and sigfigs stay zero
+        header[16..20].copy_from_slice(&snaplen.to_le_bytes());
+        header[20..24].copy_from_slice(&link_type.to_le_bytes());
+        inner.write_all(&header)?;
+        Ok(PcapWriter { inner, snaplen })
+    }
+
+    /// Append one record, `data` is cut to the snaplen of the file.
+    pub fn write_packet(
+        &mut self,
+        ts_sec: u32,
+        ts_nsec: u32,
+        orig_len: u32,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let data = &data[..data.len().min(self.snaplen as usize)];
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&ts_sec.to_le_bytes());
+        header[4..8].copy_from_slice(&ts_nsec.to_le_bytes());
+        header[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&orig_len.max(data.len() as u32).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_records() {
+        let mut bytes = Vec::new();
+        // big endian, microseconds, ethernet
+        bytes.extend_from_slice(&MAGIC_MICROS.to_be_bytes());
+        bytes.extend_from_slice(&[0x00, 0x02, 0x00, 0x04]);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&65535u32.to_be_bytes());
+        bytes.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        for (i, payload) in [&[1u8, 2, 3][..], &[4u8][..]].iter().enumerate() {
+            bytes.extend_from_slice(&(100 + i as u32).to_be_bytes());
+            bytes.extend_from_slice(&7u32.to_be_bytes());
+            bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&60u32.to_be_bytes());
+            bytes.extend_from_slice(payload);
+        }
+
+        let reader = PcapReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+        assert_eq!(reader.snaplen(), 65535);
+        let records: Vec<PcapRecord> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].ts_sec, 100);
+        assert_eq!(records[0].ts_nsec, 7000);
+        assert_eq!(records[0].orig_len, 60);
+        assert_eq!(records[1].data, vec![4]);
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC_NANOS.to_le_bytes());
+        bytes.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&65535u32.to_le_bytes());
+        bytes.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        bytes.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+        let mut reader = PcapReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_RAW);
+        assert!(reader.next_record().is_err());
+    }
+
+    #[test]
+    fn test_write_records() {
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_ETHERNET, 4).unwrap();
+        writer.write_packet(100, 5, 3, &[1, 2, 3]).unwrap();
+        writer.write_packet(101, 0, 60, &[4, 5, 6, 7, 8]).unwrap();
+
+        let bytes = writer.into_inner();
+        let mut reader = PcapReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+        assert_eq!(reader.snaplen(), 4);
+        let first = reader.next_record().unwrap().unwrap();
+        assert_eq!((first.ts_sec, first.ts_nsec, first.orig_len), (100, 5, 3));
+        assert_eq!(first.data, vec![1, 2, 3]);
+        // cut to the snaplen, the wire length is kept
+        let second = reader.next_record().unwrap().unwrap();
+        assert_eq!(second.data, vec![4, 5, 6, 7]);
+        assert_eq!(second.orig_len, 60);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+}
@@ -0,0 +1,96 @@
+//! Allocations and time per packet of the decoding path, from a captured
+//! frame to the MySQL packet handed to `Session::accept`.
+//!
+//! Run with `cargo bench -p session --bench decode`.
+
+use packets::mysql::common::{MySQLPacketRequest, MySQLPacketResponse};
+use session::testing::tcp_frame;
+use session::{CapturedFrame, Decoder, LinkType, SessionPacket, Timestamp};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+struct CountingAlloc;
+
+static ALLOCS: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const PACKETS: usize = 100_000;
+
+fn main() {
+    let conf = config::Config::default();
+    let mut decoder = Decoder::new(&conf);
+
+    let mut query = vec![0, 0, 0, 0, 0x03];
+    query.extend_from_slice(&[b'x'; 200]);
+    query[0] = (query.len() - 4) as u8;
+    let mut ok = vec![7, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0];
+    ok.extend_from_slice(&[0; 0]);
+    let templates = [tcp_frame(true, 1, &query), tcp_frame(false, 1, &ok)];
+
+    // frames are built before counting, capture owns that allocation
+    let frames: Vec<CapturedFrame> = (0..PACKETS)
+        .map(|i| CapturedFrame {
+            timestamp: Timestamp::from_nanos(i as u64 * 1000),
+            interface: None,
+            link_type: LinkType::Ethernet,
+            orig_len: templates[i % 2].len() as u32,
+            data: templates[i % 2].clone().into(),
+        })
+        .collect();
+
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let started = Instant::now();
+    let mut decoded = 0;
+    for frame in frames {
+        let pkt = match SessionPacket::new(&conf, &mut decoder, frame) {
+            Some(pkt) => pkt,
+            None => continue,
+        };
+        let parsed = if pkt.request {
+            MySQLPacketRequest::new(&pkt.tcp_layer.payload).is_some()
+        } else {
+            MySQLPacketResponse::new(&pkt.tcp_layer.payload).is_some()
+        };
+        if parsed {
+            decoded += 1;
+        }
+    }
+    let elapsed = started.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+    let bytes = BYTES.load(Ordering::Relaxed) - bytes;
+
+    assert_eq!(decoded, PACKETS);
+    println!("packets:            {}", PACKETS);
+    println!(
+        "allocations/packet: {:.2}",
+        allocs as f64 / PACKETS as f64
+    );
+    println!("bytes/packet:       {:.1}", bytes as f64 / PACKETS as f64);
+    println!(
+        "ns/packet:          {:.0}",
+        elapsed.as_nanos() as f64 / PACKETS as f64
+    );
+}
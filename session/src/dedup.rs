@@ -0,0 +1,142 @@
+use crate::frame::Timestamp;
+use crate::ip::IpLayer;
+use crate::TcpLayer;
+use config::{CaptureConfig, ANY_DEVICE};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+// bound on remembered segments whatever the window and packet rate
+const MAX_TRACKED: usize = 1 << 16;
+
+/// Drops TCP segments already seen on another interface, e.g. when both
+/// ends of a link or a SPAN port and its source are captured.
+pub struct Deduplicator {
+    window: Duration,
+    enabled: bool,
+    // segment hash -> interface and time first seen
+    seen: HashMap<u64, (Arc<str>, Timestamp)>,
+    order: VecDeque<(u64, Timestamp)>,
+    duplicates: u64,
+}
+
+impl Deduplicator {
+    pub fn new(conf: &CaptureConfig) -> Deduplicator {
+        // a single interface cannot see its own frames twice
+        let multi = conf.devices.len() > 1 || conf.devices.iter().any(|d| d == ANY_DEVICE);
+        Deduplicator {
+            window: Duration::from_millis(conf.dedup_window_ms),
+            enabled: multi && conf.dedup_window_ms > 0,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Segments dropped as copies so far.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Whether this segment was already seen on another interface within
+    /// the window. Link and IP headers are left out of the comparison, they
+    /// may differ between capture points (MACs, VLAN tags, TTL).
+    pub fn is_duplicate(
+        &mut self,
+        interface: Option<&Arc<str>>,
+        ip: &IpLayer,
+        tcp: &TcpLayer,
+        now: Timestamp,
+    ) -> bool {
+        let interface = match interface {
+            Some(interface) if self.enabled => interface,
+            _ => return false,
+        };
+        self.expire(now);
+
+        let mut hasher = DefaultHasher::new();
+        (ip.src_ip, ip.dst_ip, tcp.src_port, tcp.dst_port).hash(&mut hasher);
+        (tcp.seq, tcp.flags).hash(&mut hasher);
+        tcp.payload.hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some((first, _)) = self.seen.get(&key) {
+            if first != interface {
+                self.duplicates += 1;
+                return true;
+            }
+        }
+        // a retransmission on the same interface is kept and restarts the window
+        self.seen.insert(key, (interface.clone(), now));
+        self.order.push_back((key, now));
+        false
+    }
+
+    fn expire(&mut self, now: Timestamp) {
+        while let Some(&(key, seen_at)) = self.order.front() {
+            if now.duration_since(seen_at) <= self.window && self.order.len() <= MAX_TRACKED {
+                break;
+            }
+            self.order.pop_front();
+            // the key may have been refreshed since
+            if self.seen.get(&key).is_some_and(|(_, at)| *at == seen_at) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+
+    fn segment(seq: u32, payload: &'static [u8]) -> (IpLayer, TcpLayer) {
+        let ip = IpLayer {
+            src_ip: "10.0.0.1".parse().unwrap(),
+            dst_ip: "10.0.0.2".parse().unwrap(),
+            protocol: crate::IPPROTO_TCP,
+            reassembled: false,
+            payload: Bytes::new(),
+        };
+        let tcp = TcpLayer {
+            src_port: 50000,
+            dst_port: 3306,
+            seq,
+            flags: 0x18,
+            options: Bytes::new(),
+            payload: Bytes::from_static(payload),
+        };
+        (ip, tcp)
+    }
+
+    #[test]
+    fn test_dedup_across_interfaces() {
+        let conf = CaptureConfig {
+            devices: vec!["eth0".to_string(), "eth1".to_string()],
+            dedup_window_ms: 50,
+            ..Default::default()
+        };
+        let mut dedup = Deduplicator::new(&conf);
+        let (eth0, eth1): (Arc<str>, Arc<str>) = ("eth0".into(), "eth1".into());
+        let now = Timestamp::now();
+        let (ip, tcp) = segment(1, b"select 1");
+
+        assert!(!dedup.is_duplicate(Some(&eth0), &ip, &tcp, now));
+        assert!(dedup.is_duplicate(Some(&eth1), &ip, &tcp, now));
+        // retransmitted on the same interface
+        assert!(!dedup.is_duplicate(Some(&eth0), &ip, &tcp, now));
+        let (ip2, tcp2) = segment(2, b"select 1");
+        assert!(!dedup.is_duplicate(Some(&eth1), &ip2, &tcp2, now));
+
+        // outside of the window it is new traffic
+        let later = now + Duration::from_millis(51);
+        assert!(!dedup.is_duplicate(Some(&eth1), &ip, &tcp, later));
+        assert_eq!(dedup.duplicates(), 1);
+
+        // frames read from files have no interface
+        assert!(!dedup.is_duplicate(None, &ip, &tcp, later));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::frame::Timestamp;
+use config::{Config, DiscoveryConfig, IpNetwork};
+use log::{debug, info, warn};
+use packets::mysql::server::greeting::Greeting;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// A server endpoint registered from its greeting.
+#[derive(Debug, Clone)]
+pub struct ServerDiscovered {
+    pub ip: IpAddr,
+    pub port: u16,
+    pub db_type: String,
+    pub server_version: String,
+    // capture time of the greeting
+    pub timestamp: Timestamp,
+}
+
+/// Classifies endpoints as database servers, by the configured ports first
+/// and then by servers registered dynamically when discovery is enabled.
+pub struct Discovery {
+    enabled: bool,
+    allow: Vec<IpNetwork>,
+    deny: Vec<IpNetwork>,
+    max_servers: usize,
+    servers: HashMap<(IpAddr, u16), String>,
+    events: Vec<ServerDiscovered>,
+}
+
+impl Discovery {
+    pub fn new(conf: &DiscoveryConfig) -> Discovery {
+        Discovery {
+            enabled: conf.enabled,
+            allow: conf.allow_networks(),
+            deny: conf.deny_networks(),
+            max_servers: conf.max_servers,
+            servers: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Database type served on `ip:port`.
+    pub fn db_type<'a>(&'a self, config: &'a Config, ip: IpAddr, port: u16) -> Option<&'a str> {
+        config
+            .db_type(port)
+            .or_else(|| self.servers.get(&(ip, port)).map(|db| db.as_str()))
+    }
+
+    fn permitted(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|n| n.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|n| n.contains(ip))
+    }
+
+    /// Look at a segment of an unclassified flow and register its sender as a
+    /// MySQL server if the segment is a complete greeting.
+    pub fn inspect(
+        &mut self,
+        src_ip: IpAddr,
+        src_port: u16,
+        payload: &[u8],
+        timestamp: Timestamp,
+    ) -> bool {
+        if !self.enabled || payload.len() < 5 {
+            return false;
+        }
+        let len = u32::from_le_bytes([payload[0], payload[1], payload[2], 0]) as usize;
+        // the greeting is always the first packet and sent on its own
+        if payload[3] != 0 || len != payload.len() - 4 || !Greeting::probe(&payload[4..]) {
+            return false;
+        }
+        if !self.permitted(src_ip) {
+            debug!("Ignoring greeting from {}:{}, not allowed", src_ip, src_port);
+            return false;
+        }
+        if self.servers.len() >= self.max_servers {
+            warn!(
+                "Ignoring greeting from {}:{}, {} servers already discovered",
+                src_ip, src_port, self.max_servers
+            );
+            return false;
+        }
+
+        let server_version = Greeting::new(payload[4..].to_vec())
+            .map(|g| g.server_version)
+            .unwrap_or_default();
+        info!(
+            "Discovered mysql server {}:{} ({})",
+            src_ip, src_port, server_version
+        );
+        self.servers.insert((src_ip, src_port), "mysql".to_string());
+        self.events.push(ServerDiscovered {
+            ip: src_ip,
+            port: src_port,
+            db_type: "mysql".to_string(),
+            server_version,
+            timestamp,
+        });
+        true
+    }
+
+    /// Servers discovered since the last call.
+    pub fn take_events(&mut self) -> Vec<ServerDiscovered> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn servers(&self) -> usize {
+        self.servers.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn greeting_packet() -> Vec<u8> {
+        let payload = [
+            0x0a, 0x35, 0x2e, 0x37, 0x2e, 0x34, 0x34, 0x00, 0xee, 0x08, 0x00, 0x00, 0x65, 0x6d,
+            0x50, 0x7f, 0x1f, 0x19, 0x2c, 0x32, 0x00, 0xff, 0xff, 0x08, 0x02, 0x00, 0xff, 0xc1,
+            0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x25, 0x67, 0x1d,
+            0x39, 0x40, 0x1b, 0x6c, 0x7a, 0x66, 0x2f, 0x6a, 0x62, 0x00, 0x6d, 0x79, 0x73, 0x71,
+            0x6c, 0x5f, 0x6e, 0x61, 0x74, 0x69, 0x76, 0x65, 0x5f, 0x70, 0x61, 0x73, 0x73, 0x77,
+            0x6f, 0x72, 0x64, 0x00,
+        ];
+        let mut pkt = vec![payload.len() as u8, 0, 0, 0];
+        pkt.extend_from_slice(&payload);
+        pkt
+    }
+
+    #[test]
+    fn test_discover_server() {
+        let config = Config::default();
+        let mut conf = DiscoveryConfig {
+            enabled: true,
+            allow: vec!["10.0.0.0/8".to_string()],
+            deny: vec!["10.9.0.0/16".to_string()],
+            ..Default::default()
+        };
+        let mut discovery = Discovery::new(&conf);
+        let server: IpAddr = "10.1.2.3".parse().unwrap();
+        let ts = Timestamp::new(1_700_000_000, 0);
+
+        assert_eq!(discovery.db_type(&config, server, 6033), None);
+        assert!(discovery.inspect(server, 6033, &greeting_packet(), ts));
+        assert_eq!(discovery.db_type(&config, server, 6033), Some("mysql"));
+        let events = discovery.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].server_version, "5.7.44");
+        assert_eq!(events[0].timestamp, ts);
+
+        assert!(!discovery.inspect("10.9.0.1".parse().unwrap(), 6033, &greeting_packet(), ts));
+        assert!(!discovery.inspect("192.168.0.1".parse().unwrap(), 6033, &greeting_packet(), ts));
+        assert!(!discovery.inspect(server, 80, b"HTTP/1.1 200 OK\r\n", ts));
+
+        conf.enabled = false;
+        let mut disabled = Discovery::new(&conf);
+        assert!(!disabled.inspect(server, 6033, &greeting_packet(), ts));
+    }
+}
@@ -0,0 +1,488 @@
+use crate::flow::FlowKey;
+use crate::frame::Timestamp;
+use packets::mysql::command::MySqlCommand;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+
+/// Something a session did, in the order it was seen on the wire.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionEvent {
+    // capture time of the packet that completed the event
+    pub timestamp: Timestamp,
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl SessionEvent {
+    pub fn new(timestamp: Timestamp, flow: &FlowKey, kind: EventKind) -> SessionEvent {
+        SessionEvent {
+            timestamp,
+            client: flow.client(),
+            server: flow.server(),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    Greeting {
+        server_version: String,
+        connection_id: u32,
+        auth_plugin: String,
+    },
+    Login {
+        user: Option<String>,
+        database: Option<String>,
+        client_name: Option<String>,
+        client_version: Option<String>,
+    },
+    // the client asked for TLS, the rest of the session is encrypted
+    TlsRequest,
+    Query {
+        sql: String,
+        // query attributes by name, e.g. a trace id
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        attrs: BTreeMap<String, String>,
+    },
+    Prepare {
+        sql: String,
+    },
+    Execute {
+        statement_id: u32,
+    },
+    CloseStatement {
+        statement_id: u32,
+    },
+    InitDb {
+        schema: String,
+    },
+    FieldList {
+        table: String,
+    },
+    ProcessKill {
+        connection_id: u32,
+    },
+    ChangeUser {
+        user: String,
+        database: String,
+    },
+    SetOption {
+        option: u16,
+    },
+    // a replica asking for the binlog, by GTID set or from the position
+    BinlogDump {
+        file: String,
+        position: u64,
+        server_id: u32,
+        gtid: bool,
+    },
+    RegisterSlave {
+        server_id: u32,
+        host: String,
+        port: u16,
+    },
+    // an event of the binlog stream following a binlog dump
+    Binlog {
+        // when the source wrote the event, seconds since the epoch
+        source_timestamp: u32,
+        server_id: u32,
+        // position of the next event in the binlog file, 0 for the rotate
+        // and format description a dump starts with
+        log_pos: u32,
+        #[serde(flatten)]
+        binlog: BinlogEvent,
+        // seconds from the source timestamp to the capture of the event,
+        // only for the events of the binlog file
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lag: Option<i64>,
+    },
+    // a file the client sent in answer to a LOCAL INFILE request, a request
+    // without a LOAD DATA LOCAL statement before it is unsolicited
+    LocalInfile {
+        filename: String,
+        bytes: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        statement: Option<String>,
+        unsolicited: bool,
+    },
+    // any other command, by its code
+    Command {
+        command: u8,
+    },
+    Ok {
+        affected_rows: u64,
+        last_insert_id: u64,
+        status_flags: u16,
+        warnings: u16,
+        // the session state the server reported changed
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        session_state: Vec<SessionChange>,
+        // the statement of a multi-statement query the result belongs to
+        #[serde(skip_serializing_if = "Option::is_none")]
+        statement: Option<String>,
+    },
+    Error {
+        code: u16,
+        sql_state: Option<String>,
+        message: String,
+        // the statement of a multi-statement query the result belongs to
+        #[serde(skip_serializing_if = "Option::is_none")]
+        statement: Option<String>,
+    },
+    ResultSet {
+        columns: u64,
+        rows: u64,
+        // of the EOF or OK that ends the rows, its session state changes
+        // only with CLIENT_DEPRECATE_EOF
+        status_flags: u16,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        session_state: Vec<SessionChange>,
+        // the statement of a multi-statement query the result belongs to
+        #[serde(skip_serializing_if = "Option::is_none")]
+        statement: Option<String>,
+    },
+    Prepared {
+        statement_id: u32,
+        columns: u16,
+        params: u16,
+    },
+}
+
+impl EventKind {
+    /// Name of the event, as in the `event` field of its JSON form.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Greeting { .. } => "greeting",
+            EventKind::Login { .. } => "login",
+            EventKind::TlsRequest => "tls_request",
+            EventKind::Query { .. } => "query",
+            EventKind::Prepare { .. } => "prepare",
+            EventKind::Execute { .. } => "execute",
+            EventKind::CloseStatement { .. } => "close_statement",
+            EventKind::InitDb { .. } => "init_db",
+            EventKind::FieldList { .. } => "field_list",
+            EventKind::ProcessKill { .. } => "process_kill",
+            EventKind::ChangeUser { .. } => "change_user",
+            EventKind::SetOption { .. } => "set_option",
+            EventKind::BinlogDump { .. } => "binlog_dump",
+            EventKind::RegisterSlave { .. } => "register_slave",
+            EventKind::Binlog { .. } => "binlog",
+            EventKind::LocalInfile { .. } => "local_infile",
+            EventKind::Command { .. } => "command",
+            EventKind::Ok { .. } => "ok",
+            EventKind::Error { .. } => "error",
+            EventKind::ResultSet { .. } => "result_set",
+            EventKind::Prepared { .. } => "prepared",
+        }
+    }
+}
+
+/// Details of the event on one line, without its name.
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opt = |v: &Option<String>| v.clone().unwrap_or_default();
+        let sql = |f: &mut fmt::Formatter, statement: &Option<String>| match statement {
+            Some(statement) => write!(f, " sql={:?}", statement),
+            None => Ok(()),
+        };
+        let changes = |f: &mut fmt::Formatter, session_state: &[SessionChange]| {
+            if session_state.is_empty() {
+                return Ok(());
+            }
+            let changes: Vec<String> = session_state.iter().map(|c| c.to_string()).collect();
+            write!(f, " [{}]", changes.join(" "))
+        };
+        match self {
+            EventKind::Greeting {
+                server_version,
+                connection_id,
+                auth_plugin,
+            } => write!(f, "{} id={} {}", server_version, connection_id, auth_plugin),
+            EventKind::Login {
+                user,
+                database,
+                client_name,
+                client_version,
+            } => write!(
+                f,
+                "user={} db={} client={} {}",
+                opt(user),
+                opt(database),
+                opt(client_name),
+                opt(client_version)
+            ),
+            EventKind::TlsRequest => Ok(()),
+            EventKind::Query { sql, attrs } => {
+                write!(f, "{}", sql)?;
+                if !attrs.is_empty() {
+                    let attrs: Vec<String> =
+                        attrs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                    write!(f, " [{}]", attrs.join(" "))?;
+                }
+                Ok(())
+            }
+            EventKind::Prepare { sql } => write!(f, "{}", sql),
+            EventKind::Execute { statement_id } | EventKind::CloseStatement { statement_id } => {
+                write!(f, "stmt={}", statement_id)
+            }
+            EventKind::InitDb { schema } => write!(f, "{}", schema),
+            EventKind::FieldList { table } => write!(f, "{}", table),
+            EventKind::ProcessKill { connection_id } => write!(f, "id={}", connection_id),
+            EventKind::ChangeUser { user, database } => {
+                write!(f, "user={} db={}", user, database)
+            }
+            EventKind::SetOption { option } => write!(f, "option={}", option),
+            EventKind::BinlogDump {
+                file,
+                position,
+                server_id,
+                gtid,
+            } => write!(
+                f,
+                "{}:{} server_id={}{}",
+                file,
+                position,
+                server_id,
+                if *gtid { " gtid" } else { "" }
+            ),
+            EventKind::RegisterSlave {
+                server_id,
+                host,
+                port,
+            } => write!(f, "server_id={} {}:{}", server_id, host, port),
+            EventKind::Binlog {
+                server_id,
+                log_pos,
+                binlog,
+                lag,
+                ..
+            } => {
+                write!(f, "{} server_id={} log_pos={}", binlog, server_id, log_pos)?;
+                match lag {
+                    Some(lag) => write!(f, " lag={}s", lag),
+                    None => Ok(()),
+                }
+            }
+            EventKind::LocalInfile {
+                filename,
+                bytes,
+                statement,
+                unsolicited,
+            } => {
+                write!(f, "{} bytes={}", filename, bytes)?;
+                if *unsolicited {
+                    write!(f, " unsolicited")?;
+                }
+                sql(f, statement)
+            }
+            EventKind::Command { command } => match MySqlCommand::request(*command) {
+                Some(command) => write!(f, "{}", command),
+                None => write!(f, "0x{:02x}", command),
+            },
+            EventKind::Ok {
+                affected_rows,
+                last_insert_id,
+                status_flags,
+                warnings,
+                session_state,
+                statement,
+            } => {
+                write!(
+                    f,
+                    "affected={} insert_id={} status=0x{:04x} warnings={}",
+                    affected_rows, last_insert_id, status_flags, warnings
+                )?;
+                changes(f, session_state)?;
+                sql(f, statement)
+            }
+            EventKind::Error {
+                code,
+                sql_state,
+                message,
+                statement,
+            } => {
+                write!(f, "{} ({}) {}", code, opt(sql_state), message)?;
+                sql(f, statement)
+            }
+            EventKind::ResultSet {
+                columns,
+                rows,
+                status_flags,
+                session_state,
+                statement,
+            } => {
+                write!(
+                    f,
+                    "columns={} rows={} status=0x{:04x}",
+                    columns, rows, status_flags
+                )?;
+                changes(f, session_state)?;
+                sql(f, statement)
+            }
+            EventKind::Prepared {
+                statement_id,
+                columns,
+                params,
+            } => write!(
+                f,
+                "stmt={} columns={} params={}",
+                statement_id, columns, params
+            ),
+        }
+    }
+}
+
+/// A binlog event, the ones that follow transactions and the rows they
+/// change. Tables are named `schema.table`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "binlog_event", rename_all = "snake_case")]
+pub enum BinlogEvent {
+    FormatDescription {
+        server_version: String,
+        binlog_version: u16,
+    },
+    Rotate {
+        file: String,
+        position: u64,
+    },
+    Gtid {
+        gtid: String,
+    },
+    Query {
+        schema: String,
+        sql: String,
+    },
+    TableMap {
+        table_id: u64,
+        table: String,
+    },
+    // rows of a table map of the same statement, counted if every column
+    // type is decoded
+    WriteRows {
+        table_id: u64,
+        table: Option<String>,
+        rows: Option<u64>,
+    },
+    UpdateRows {
+        table_id: u64,
+        table: Option<String>,
+        rows: Option<u64>,
+    },
+    DeleteRows {
+        table_id: u64,
+        table: Option<String>,
+        rows: Option<u64>,
+    },
+    Xid {
+        xid: u64,
+    },
+}
+
+impl BinlogEvent {
+    /// Name of the event, as in the `binlog_event` field of its JSON form.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinlogEvent::FormatDescription { .. } => "format_description",
+            BinlogEvent::Rotate { .. } => "rotate",
+            BinlogEvent::Gtid { .. } => "gtid",
+            BinlogEvent::Query { .. } => "query",
+            BinlogEvent::TableMap { .. } => "table_map",
+            BinlogEvent::WriteRows { .. } => "write_rows",
+            BinlogEvent::UpdateRows { .. } => "update_rows",
+            BinlogEvent::DeleteRows { .. } => "delete_rows",
+            BinlogEvent::Xid { .. } => "xid",
+        }
+    }
+}
+
+/// The name of the event and its details.
+impl fmt::Display for BinlogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        match self {
+            BinlogEvent::FormatDescription {
+                server_version,
+                binlog_version,
+            } => write!(f, " {} v{}", server_version, binlog_version),
+            BinlogEvent::Rotate { file, position } => write!(f, " {}:{}", file, position),
+            BinlogEvent::Gtid { gtid } => write!(f, " {}", gtid),
+            BinlogEvent::Query { schema, sql } => write!(f, " {}: {}", schema, sql),
+            BinlogEvent::TableMap { table_id, table } => write!(f, " {} id={}", table, table_id),
+            BinlogEvent::WriteRows {
+                table_id,
+                table,
+                rows,
+            }
+            | BinlogEvent::UpdateRows {
+                table_id,
+                table,
+                rows,
+            }
+            | BinlogEvent::DeleteRows {
+                table_id,
+                table,
+                rows,
+            } => {
+                match table {
+                    Some(table) => write!(f, " {}", table)?,
+                    None => write!(f, " id={}", table_id)?,
+                }
+                match rows {
+                    Some(rows) => write!(f, " rows={}", rows),
+                    None => Ok(()),
+                }
+            }
+            BinlogEvent::Xid { xid } => write!(f, " {}", xid),
+        }
+    }
+}
+
+/// An entry of the session state changes of an OK packet, the ones the
+/// server tracks with its `session_track_*` variables.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "track", rename_all = "snake_case")]
+pub enum SessionChange {
+    SystemVariable { name: String, value: String },
+    Schema { schema: String },
+    // some tracked state changed, e.g. a user variable
+    StateChange,
+    Gtids { gtids: String },
+    // the statements that would start the transaction again
+    TransactionCharacteristics { characteristics: String },
+    // 8 characters, an explicit ('T') or implicit ('I') transaction first
+    TransactionState { state: String },
+}
+
+impl fmt::Display for SessionChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionChange::SystemVariable { name, value } => write!(f, "{}={}", name, value),
+            SessionChange::Schema { schema } => write!(f, "schema={}", schema),
+            SessionChange::StateChange => write!(f, "state_change"),
+            SessionChange::Gtids { gtids } => write!(f, "gtids={}", gtids),
+            SessionChange::TransactionCharacteristics { characteristics } => {
+                write!(f, "characteristics={:?}", characteristics)
+            }
+            SessionChange::TransactionState { state } => write!(f, "trx={}", state),
+        }
+    }
+}
+
+/// Receives the events of every session of a session manager.
+pub trait EventSink: Send {
+    fn emit(&mut self, event: SessionEvent);
+}
+
+impl EventSink for mpsc::Sender<SessionEvent> {
+    fn emit(&mut self, event: SessionEvent) {
+        // the receiving end only goes away on shutdown
+        let _ = self.send(event);
+    }
+}
@@ -0,0 +1,983 @@
+use crate::event::{BinlogEvent, EventKind, SessionChange};
+use crate::sql::{keyword, load_local, split_statements};
+use packets::mysql::binlog::{BinlogDecoder, EventBody, RowsKind};
+use packets::mysql::client::command::ClientCommand;
+use packets::mysql::command::MySqlCommand;
+use packets::mysql::common::{
+    self, CLIENT_DEPRECATE_EOF, CLIENT_MULTI_STATEMENTS, CLIENT_PROTOCOL_41, MAX_PAYLOAD_LEN,
+    SERVER_MORE_RESULTS_EXISTS,
+};
+use packets::mysql::server::eof::EOFPacket;
+use packets::mysql::server::err::ErrPacket;
+use packets::mysql::server::infile::LocalInfilePacket;
+use packets::mysql::server::ok::{OKPacket, SessionStateChange};
+use std::collections::VecDeque;
+use std::io::Cursor;
+
+const HEADER_LEN: usize = 4;
+// an EOF packet is shorter than any row starting with 0xfe
+const MAX_EOF_LEN: u32 = 9;
+// binlog events kept whole when they span segments, rows events are 8 KiB
+// at most by default
+const MAX_BINLOG_EVENT_LEN: usize = 1 << 20;
+// header, 2 byte error code, SQL state marker and 5 byte SQL state
+const MIN_ERR_LEN: usize = 9;
+// header, affected rows, last insert id, status flags and warnings
+const MIN_OK_LEN: usize = 7;
+// header, statement id, columns, params, filler and warnings
+const PREPARE_OK_LEN: usize = 12;
+
+/// What the next server packets answer.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Expect {
+    // nothing outstanding, e.g. after COM_QUIT or when joined mid-stream
+    #[default]
+    Nothing,
+    // OK or ERR ending the authentication, after auth switches and more data
+    Auth,
+    // OK, ERR or a result set
+    Response,
+    // COM_STMT_PREPARE_OK or ERR
+    Prepare,
+    // column definitions left, including the EOF that ends them if any
+    Columns {
+        columns: u64,
+        left: u64,
+    },
+    Rows {
+        columns: u64,
+        rows: u64,
+    },
+    // parameter and column definitions following COM_STMT_PREPARE_OK
+    Definitions(u64),
+    // column definitions answering COM_FIELD_LIST, up to an EOF
+    Fields,
+    // the binlog events following a binlog dump, up to an EOF or ERR
+    Binlog,
+}
+
+/// A MySQL packet, `body` is cut short if the packet continues in the
+/// next segment.
+struct Packet<'a> {
+    len: u32,
+    seq: u8,
+    body: &'a [u8],
+}
+
+/// Finds packet boundaries in one direction of a connection, packets are
+/// coalesced into segments and span segments at will.
+#[derive(Debug, Default)]
+struct Framing {
+    // body bytes of the packet cut at the end of the previous segment
+    skip: usize,
+    // header, and the first body byte, cut at the end of the previous segment
+    header: Vec<u8>,
+    // packets up to this long are delivered whole rather than cut at the
+    // end of a segment
+    whole: usize,
+    // header and body so far of the packet cut at the end of the previous
+    // segment, if it is delivered whole
+    partial: Vec<u8>,
+}
+
+impl Framing {
+    fn packets(&mut self, segment: &[u8], mut f: impl FnMut(Packet)) {
+        let skip = self.skip.min(segment.len());
+        self.skip -= skip;
+        let mut rest = &segment[skip..];
+        if !self.partial.is_empty() {
+            self.partial.extend_from_slice(&segment[..skip]);
+            if self.skip > 0 {
+                return;
+            }
+            let whole = std::mem::take(&mut self.partial);
+            f(Packet {
+                len: (whole.len() - HEADER_LEN) as u32,
+                seq: whole[3],
+                body: &whole[HEADER_LEN..],
+            });
+        }
+
+        let joined;
+        if !self.header.is_empty() {
+            // rare enough that copying the segment does not matter
+            self.header.extend_from_slice(rest);
+            joined = std::mem::take(&mut self.header);
+            rest = &joined;
+        }
+        while !rest.is_empty() {
+            // the first body byte tells what the packet is, keep it with the header
+            let len = match rest.get(..HEADER_LEN) {
+                Some(h) if rest.len() > HEADER_LEN || h[..3] == [0, 0, 0] => {
+                    u32::from_le_bytes([h[0], h[1], h[2], 0])
+                }
+                _ => {
+                    self.header = rest.to_vec();
+                    return;
+                }
+            };
+            let end = (HEADER_LEN + len as usize).min(rest.len());
+            self.skip = HEADER_LEN + len as usize - end;
+            if self.skip > 0 && len as usize <= self.whole {
+                self.partial = rest.to_vec();
+                return;
+            }
+            f(Packet {
+                len,
+                seq: rest[3],
+                body: &rest[HEADER_LEN..end],
+            });
+            rest = &rest[end..];
+        }
+    }
+}
+
+/// Follows the commands of a MySQL session and the responses to them,
+/// reporting both as events.
+#[derive(Debug, Default)]
+pub(crate) struct Exchange {
+    expect: Expect,
+    // statements of a multi-statement query still to be answered
+    statements: VecDeque<String>,
+    // SQL of the last command, if it was a query
+    query: Option<String>,
+    // the file the client is sending, if any
+    infile: Option<Infile>,
+    // the binlog stream of a replica
+    binlog: Binlog,
+    requests: Framing,
+    responses: Framing,
+}
+
+/// A file the client sends in answer to a LOCAL INFILE request.
+#[derive(Debug)]
+struct Infile {
+    filename: String,
+    // the statement the request answers
+    statement: Option<String>,
+    bytes: u64,
+    // the last packet was a full one, an empty packet only continues it
+    full: bool,
+}
+
+impl Infile {
+    /// Count a packet of the file, the event once an empty packet ends it.
+    fn packet(&mut self, len: u32) -> Option<EventKind> {
+        if len == 0 && !self.full {
+            let statement = self.statement.take();
+            return Some(EventKind::LocalInfile {
+                filename: std::mem::take(&mut self.filename),
+                bytes: self.bytes,
+                unsolicited: !statement.as_deref().is_some_and(load_local),
+                statement,
+            });
+        }
+        self.bytes += len as u64;
+        self.full = len as usize == MAX_PAYLOAD_LEN;
+        None
+    }
+}
+
+/// The binlog stream a source sends a replica, an event in each packet
+/// behind an OK header.
+#[derive(Debug, Default)]
+struct Binlog {
+    decoder: BinlogDecoder,
+    // the last packet was a full one, the next continues its event
+    full: bool,
+}
+
+impl Binlog {
+    /// A packet of the stream, the event in it if it is one to report. An
+    /// EOF or ERR ends the stream.
+    fn packet(&mut self, pkt: &Packet, expect: &mut Expect, caps: u32) -> Option<EventKind> {
+        // an event longer than a packet is not kept, nor what continues it
+        if std::mem::replace(&mut self.full, pkt.len as usize == MAX_PAYLOAD_LEN) {
+            return None;
+        }
+        match MySqlCommand::response(*pkt.body.first()?) {
+            Some(MySqlCommand::Ok) if !self.full && pkt.body.len() == pkt.len as usize => {
+                self.event(&pkt.body[1..])
+            }
+            Some(MySqlCommand::Eof) if pkt.len < MAX_EOF_LEN => {
+                *expect = Expect::Nothing;
+                None
+            }
+            Some(MySqlCommand::Err) => expect.done(pkt, caps),
+            _ => None,
+        }
+    }
+
+    fn event(&mut self, data: &[u8]) -> Option<EventKind> {
+        let event = self.decoder.decode(data)?;
+        let binlog = match event.body {
+            EventBody::FormatDescription(format) => BinlogEvent::FormatDescription {
+                server_version: format.server_version,
+                binlog_version: format.binlog_version,
+            },
+            EventBody::Rotate(rotate) => BinlogEvent::Rotate {
+                file: rotate.file,
+                position: rotate.position,
+            },
+            EventBody::Gtid(gtid) => BinlogEvent::Gtid {
+                gtid: gtid.to_string(),
+            },
+            EventBody::Query(query) => BinlogEvent::Query {
+                schema: query.schema,
+                sql: query.query,
+            },
+            EventBody::TableMap(map) => BinlogEvent::TableMap {
+                table_id: map.table_id,
+                table: format!("{}.{}", map.schema, map.table),
+            },
+            EventBody::Rows(rows) => {
+                let table_id = rows.table_id;
+                let table = self
+                    .decoder
+                    .table(table_id)
+                    .map(|map| format!("{}.{}", map.schema, map.table));
+                let count = rows.rows.map(|rows| rows.len() as u64);
+                match rows.kind {
+                    RowsKind::Write => BinlogEvent::WriteRows {
+                        table_id,
+                        table,
+                        rows: count,
+                    },
+                    RowsKind::Update => BinlogEvent::UpdateRows {
+                        table_id,
+                        table,
+                        rows: count,
+                    },
+                    RowsKind::Delete => BinlogEvent::DeleteRows {
+                        table_id,
+                        table,
+                        rows: count,
+                    },
+                }
+            }
+            EventBody::Xid(xid) => BinlogEvent::Xid { xid },
+            EventBody::Other(_) => return None,
+        };
+        Some(EventKind::Binlog {
+            source_timestamp: event.header.timestamp,
+            server_id: event.header.server_id,
+            log_pos: event.header.log_pos,
+            binlog,
+            lag: None,
+        })
+    }
+}
+
+impl Exchange {
+    /// The client sent its handshake response, the server answers it next.
+    pub fn authenticating(&mut self) {
+        self.expect = Expect::Auth;
+    }
+
+    /// Hand the events of a client segment to `emit`, `caps` are the client
+    /// capabilities of the session. Only the part of a command in this
+    /// segment is decoded.
+    pub fn request(&mut self, segment: &[u8], caps: u32, mut emit: impl FnMut(EventKind)) {
+        let (expect, statements) = (&mut self.expect, &mut self.statements);
+        let (query, infile) = (&mut self.query, &mut self.infile);
+        let binlog = &mut self.binlog;
+        self.requests.packets(segment, |pkt| {
+            if let Some(file) = infile.as_mut() {
+                // the file continues the sequence of the request
+                if pkt.seq != 0 {
+                    if let Some(event) = file.packet(pkt.len) {
+                        *infile = None;
+                        emit(event);
+                    }
+                    return;
+                }
+                *infile = None;
+            }
+            // commands start a new sequence, the rest are handshake
+            // packets or continue a command
+            if pkt.seq != 0 {
+                return;
+            }
+            if let Some(event) = expect.command(pkt.body, caps) {
+                statements.clear();
+                *query = None;
+                if let EventKind::Query { sql, .. } = &event {
+                    let split = split_statements(sql);
+                    if split.len() > 1 && caps & CLIENT_MULTI_STATEMENTS > 0 {
+                        statements.extend(split.into_iter().map(String::from));
+                    }
+                    *query = Some(sql.clone());
+                }
+                if *expect == Expect::Binlog {
+                    *binlog = Binlog::default();
+                }
+                emit(event);
+            }
+        });
+        self.binlog_framing();
+    }
+
+    /// Hand the events of a server segment to `emit`, `caps` are the
+    /// client capabilities of the session.
+    pub fn response(&mut self, segment: &[u8], caps: u32, mut emit: impl FnMut(EventKind)) {
+        let (expect, statements) = (&mut self.expect, &mut self.statements);
+        let (query, infile) = (&self.query, &mut self.infile);
+        let binlog = &mut self.binlog;
+        self.responses.packets(segment, |pkt| {
+            if *expect == Expect::Binlog {
+                if let Some(event) = binlog.packet(&pkt, expect, caps) {
+                    emit(event);
+                }
+                return;
+            }
+            if *expect == Expect::Response {
+                if let Some(request) = LocalInfilePacket::new(pkt.body) {
+                    *infile = Some(Infile {
+                        filename: request.filename,
+                        statement: statements.front().or(query.as_ref()).cloned(),
+                        bytes: 0,
+                        full: false,
+                    });
+                }
+            }
+            if let Some(mut event) = expect.reply(&pkt, caps) {
+                attribute(&mut event, statements);
+                emit(event);
+            }
+            if *expect == Expect::Nothing {
+                statements.clear();
+            }
+        });
+        self.binlog_framing();
+    }
+
+    /// Binlog events are decoded whole, the packets of the stream are kept
+    /// when they span segments.
+    fn binlog_framing(&mut self) {
+        self.responses.whole = if self.expect == Expect::Binlog {
+            MAX_BINLOG_EVENT_LEN
+        } else {
+            0
+        };
+    }
+}
+
+/// Name the statement of a multi-statement query a result belongs to. A
+/// CALL answers with any number of result sets and then an OK, the other
+/// statements with a single result, and the first error ends the query.
+fn attribute(event: &mut EventKind, statements: &mut VecDeque<String>) {
+    let result_set = matches!(event, EventKind::ResultSet { .. });
+    let error = matches!(event, EventKind::Error { .. });
+    let statement = match event {
+        EventKind::Ok { statement, .. }
+        | EventKind::Error { statement, .. }
+        | EventKind::ResultSet { statement, .. } => statement,
+        _ => return,
+    };
+    let call = match statements.front() {
+        Some(front) => keyword(front).as_deref() == Some("CALL"),
+        None => return,
+    };
+    *statement = if call && result_set {
+        statements.front().cloned()
+    } else {
+        statements.pop_front()
+    };
+    if error {
+        statements.clear();
+    }
+}
+
+/// Status flags and session state changes of the EOF, or of the OK behind
+/// an EOF header, that ends a result set. Only the OK tracks the state.
+fn result_end(body: &[u8], caps: u32) -> (u16, Vec<SessionChange>) {
+    if caps & CLIENT_DEPRECATE_EOF > 0 {
+        match OKPacket::new(caps, body.to_vec()) {
+            Some(ok) => (ok.status_flags.unwrap_or_default(), session_changes(ok)),
+            None => (0, Vec::new()),
+        }
+    } else {
+        let status = EOFPacket::new(caps, &mut Cursor::new(body)).map(|eof| eof.status_flags);
+        (status.unwrap_or_default(), Vec::new())
+    }
+}
+
+/// The session state changes an OK packet tracks.
+fn session_changes(ok: OKPacket) -> Vec<SessionChange> {
+    ok.session_track_info
+        .map(|track| {
+            track
+                .changes
+                .into_iter()
+                .filter_map(session_change)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A session state change of an OK packet, those of unknown types are
+/// dropped.
+fn session_change(change: SessionStateChange) -> Option<SessionChange> {
+    Some(match change {
+        SessionStateChange::SystemVariable { name, value } => {
+            SessionChange::SystemVariable { name, value }
+        }
+        SessionStateChange::Schema(schema) => SessionChange::Schema { schema },
+        SessionStateChange::StateChange(_) => SessionChange::StateChange,
+        SessionStateChange::Gtids { gtids, .. } => SessionChange::Gtids { gtids },
+        SessionStateChange::TransactionCharacteristics(characteristics) => {
+            SessionChange::TransactionCharacteristics { characteristics }
+        }
+        SessionStateChange::TransactionState(state) => SessionChange::TransactionState { state },
+        SessionStateChange::Other { .. } => return None,
+    })
+}
+
+/// Whether another result follows the one ended with `status`.
+fn more_results(status: u16) -> bool {
+    status as u32 & SERVER_MORE_RESULTS_EXISTS > 0
+}
+
+impl Expect {
+    fn command(&mut self, body: &[u8], caps: u32) -> Option<EventKind> {
+        let code = *body.first()?;
+        let command = match ClientCommand::new(caps, body) {
+            Some(command) => command,
+            None => {
+                // cut short by the end of the segment, or not a command
+                *self = Expect::Response;
+                return Some(EventKind::Command { command: code });
+            }
+        };
+        *self = match command {
+            // COM_STATISTICS is answered with a bare string
+            ClientCommand::Quit
+            | ClientCommand::Statistics
+            | ClientCommand::StmtSendLongData(_)
+            | ClientCommand::StmtClose(_)
+            | ClientCommand::StmtFetch(_) => Expect::Nothing,
+            ClientCommand::BinlogDump(_) | ClientCommand::BinlogDumpGtid(_) => Expect::Binlog,
+            ClientCommand::StmtPrepare(_) => Expect::Prepare,
+            ClientCommand::ChangeUser(_) => Expect::Auth,
+            ClientCommand::FieldList { .. } => Expect::Fields,
+            _ => Expect::Response,
+        };
+        Some(match command {
+            ClientCommand::Query(query) => EventKind::Query {
+                sql: query.query,
+                attrs: query
+                    .attrs
+                    .into_iter()
+                    .map(|attr| (attr.name, attr.value.to_string()))
+                    .collect(),
+            },
+            ClientCommand::StmtPrepare(prepare) => EventKind::Prepare { sql: prepare.query },
+            ClientCommand::StmtExecute(execute) => EventKind::Execute {
+                statement_id: execute.statement_id,
+            },
+            ClientCommand::StmtClose(close) => EventKind::CloseStatement {
+                statement_id: close.statement_id,
+            },
+            ClientCommand::InitDb { schema } => EventKind::InitDb { schema },
+            ClientCommand::FieldList { table, .. } => EventKind::FieldList { table },
+            ClientCommand::ProcessKill { connection_id } => {
+                EventKind::ProcessKill { connection_id }
+            }
+            ClientCommand::ChangeUser(change_user) => EventKind::ChangeUser {
+                user: change_user.username,
+                database: change_user.database,
+            },
+            ClientCommand::SetOption { option } => EventKind::SetOption { option },
+            ClientCommand::BinlogDump(dump) => EventKind::BinlogDump {
+                file: dump.filename,
+                position: dump.position as u64,
+                server_id: dump.server_id,
+                gtid: false,
+            },
+            ClientCommand::BinlogDumpGtid(dump) => EventKind::BinlogDump {
+                file: dump.filename,
+                position: dump.position,
+                server_id: dump.server_id,
+                gtid: true,
+            },
+            ClientCommand::RegisterSlave(register) => EventKind::RegisterSlave {
+                server_id: register.server_id,
+                host: register.hostname,
+                port: register.port,
+            },
+            command => EventKind::Command {
+                command: command.code(),
+            },
+        })
+    }
+
+    fn reply(&mut self, pkt: &Packet, caps: u32) -> Option<EventKind> {
+        let header = MySqlCommand::response(*pkt.body.first()?);
+        match *self {
+            // the binlog stream is decoded by the exchange
+            Expect::Nothing | Expect::Binlog => None,
+            Expect::Auth => match header {
+                Some(MySqlCommand::Ok | MySqlCommand::Err) => self.done(pkt, caps),
+                // auth switch request or more auth data
+                _ => None,
+            },
+            Expect::Response => match header {
+                Some(MySqlCommand::Ok | MySqlCommand::Err) => self.done(pkt, caps),
+                // the OK or ERR follows the file of the client
+                Some(MySqlCommand::LocalInfile) => None,
+                _ => {
+                    let columns = match common::read_len_enc_int(&mut Cursor::new(pkt.body)) {
+                        Some((columns, _)) => columns,
+                        None => {
+                            *self = Expect::Nothing;
+                            return None;
+                        }
+                    };
+                    let eof = if caps & CLIENT_DEPRECATE_EOF > 0 {
+                        0
+                    } else {
+                        1
+                    };
+                    *self = Expect::Columns {
+                        columns,
+                        left: columns.saturating_add(eof),
+                    };
+                    None
+                }
+            },
+            Expect::Prepare => match header {
+                Some(MySqlCommand::Ok) if pkt.body.len() >= PREPARE_OK_LEN => {
+                    let b = pkt.body;
+                    let statement_id = u32::from_le_bytes([b[1], b[2], b[3], b[4]]);
+                    let columns = u16::from_le_bytes([b[5], b[6]]);
+                    let params = u16::from_le_bytes([b[7], b[8]]);
+                    let eofs = if caps & CLIENT_DEPRECATE_EOF > 0 {
+                        0
+                    } else {
+                        (columns > 0) as u64 + (params > 0) as u64
+                    };
+                    let left = columns as u64 + params as u64 + eofs;
+                    *self = if left > 0 {
+                        Expect::Definitions(left)
+                    } else {
+                        Expect::Nothing
+                    };
+                    Some(EventKind::Prepared {
+                        statement_id,
+                        columns,
+                        params,
+                    })
+                }
+                _ => self.done(pkt, caps),
+            },
+            Expect::Columns { columns, left } => {
+                *self = if left > 1 {
+                    Expect::Columns {
+                        columns,
+                        left: left - 1,
+                    }
+                } else {
+                    Expect::Rows { columns, rows: 0 }
+                };
+                None
+            }
+            Expect::Rows { columns, rows } => {
+                let deprecate_eof = caps & CLIENT_DEPRECATE_EOF > 0;
+                match header {
+                    Some(MySqlCommand::Err) => self.done(pkt, caps),
+                    // with CLIENT_DEPRECATE_EOF the rows end with an OK packet
+                    // behind an EOF header, a row that starts with 0xfe fills
+                    // a whole packet
+                    Some(MySqlCommand::Eof)
+                        if pkt.len < MAX_EOF_LEN
+                            || (deprecate_eof && (pkt.len as usize) < MAX_PAYLOAD_LEN) =>
+                    {
+                        let (status_flags, session_state) = result_end(pkt.body, caps);
+                        *self = if more_results(status_flags) {
+                            Expect::Response
+                        } else {
+                            Expect::Nothing
+                        };
+                        Some(EventKind::ResultSet {
+                            columns,
+                            rows,
+                            status_flags,
+                            session_state,
+                            statement: None,
+                        })
+                    }
+                    _ => {
+                        *self = Expect::Rows {
+                            columns,
+                            rows: rows + 1,
+                        };
+                        None
+                    }
+                }
+            }
+            Expect::Definitions(left) => {
+                *self = if left > 1 {
+                    Expect::Definitions(left - 1)
+                } else {
+                    Expect::Nothing
+                };
+                None
+            }
+            Expect::Fields => match header {
+                Some(MySqlCommand::Err) => self.done(pkt, caps),
+                Some(MySqlCommand::Eof) if pkt.len < MAX_EOF_LEN => {
+                    *self = Expect::Nothing;
+                    None
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// OK or ERR packet ending the exchange, anything else only ends it. An
+    /// OK with SERVER_MORE_RESULTS_EXISTS is followed by the next result.
+    fn done(&mut self, pkt: &Packet, caps: u32) -> Option<EventKind> {
+        *self = Expect::Nothing;
+        let body = pkt.body;
+        match MySqlCommand::response(body[0]) {
+            Some(MySqlCommand::Ok) if body.len() >= MIN_OK_LEN => {
+                let ok = OKPacket::new(caps, body.to_vec())?;
+                let status_flags = ok.status_flags.unwrap_or_default();
+                if more_results(status_flags) {
+                    *self = Expect::Response;
+                }
+                Some(EventKind::Ok {
+                    affected_rows: ok.affected_rows,
+                    last_insert_id: ok.last_insert_id,
+                    status_flags,
+                    warnings: ok.warnings.unwrap_or_default(),
+                    session_state: session_changes(ok),
+                    statement: None,
+                })
+            }
+            Some(MySqlCommand::Err)
+                if body.len() >= MIN_ERR_LEN || caps & CLIENT_PROTOCOL_41 == 0 =>
+            {
+                let err = ErrPacket::new(caps, body.to_vec())?;
+                Some(EventKind::Error {
+                    code: err.error_code,
+                    sql_state: err.sql_state,
+                    message: err.error_message,
+                    statement: None,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn packet(seq: u8, body: &[u8]) -> Vec<u8> {
+        let mut pkt = (body.len() as u32).to_le_bytes()[..3].to_vec();
+        pkt.push(seq);
+        pkt.extend_from_slice(body);
+        pkt
+    }
+
+    fn events(exchange: &mut Exchange, request: bool, segment: &[u8]) -> Vec<EventKind> {
+        let mut events = Vec::new();
+        if request {
+            exchange.request(segment, CLIENT_PROTOCOL_41, |e| events.push(e));
+        } else {
+            exchange.response(segment, CLIENT_PROTOCOL_41, |e| events.push(e));
+        }
+        events
+    }
+
+    #[test]
+    fn test_result_set_across_segments() {
+        let mut exchange = Exchange::default();
+        let query = packet(0, b"\x03select a, b from t");
+        assert_eq!(
+            events(&mut exchange, true, &query),
+            vec![EventKind::Query {
+                sql: "select a, b from t".to_string(),
+                attrs: BTreeMap::new(),
+            }]
+        );
+
+        let eof = [MySqlCommand::Eof.code(), 0, 0, 2, 0];
+        let mut response = packet(1, &[2]);
+        response.extend(packet(2, b"\x03def..."));
+        response.extend(packet(3, b"\x03def..."));
+        response.extend(packet(4, &eof));
+        response.extend(packet(5, b"\x011\x01x"));
+        response.extend(packet(6, b"\x012\xfb"));
+        response.extend(packet(7, &eof));
+        // cut inside a header and inside a row
+        for cut in [response.len() - 12, response.len() - 7] {
+            let mut exchange = Exchange {
+                expect: Expect::Response,
+                ..Default::default()
+            };
+            assert!(events(&mut exchange, false, &response[..cut]).is_empty());
+            assert_eq!(
+                events(&mut exchange, false, &response[cut..]),
+                vec![EventKind::ResultSet {
+                    columns: 2,
+                    rows: 2,
+                    status_flags: 2,
+                    session_state: Vec::new(),
+                    statement: None,
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_prepare_and_error() {
+        let mut exchange = Exchange::default();
+        events(&mut exchange, true, &packet(0, b"\x16select ?"));
+        let mut response = packet(1, &[0, 7, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0]);
+        for seq in 2..6 {
+            response.extend(packet(seq, b"\x03def"));
+        }
+        assert_eq!(
+            events(&mut exchange, false, &response),
+            vec![EventKind::Prepared {
+                statement_id: 7,
+                columns: 1,
+                params: 1
+            }]
+        );
+
+        events(
+            &mut exchange,
+            true,
+            &packet(0, &[0x17, 7, 0, 0, 0, 0, 1, 0, 0, 0]),
+        );
+        let err = packet(1, b"\xff\x7a\x04#42S02Table 't' doesn't exist");
+        assert_eq!(
+            events(&mut exchange, false, &err),
+            vec![EventKind::Error {
+                code: 1146,
+                sql_state: Some("42S02".to_string()),
+                message: "Table 't' doesn't exist".to_string(),
+                statement: None,
+            }]
+        );
+        // nothing outstanding any more
+        assert!(events(&mut exchange, false, &err).is_empty());
+    }
+
+    #[test]
+    fn test_other_commands() {
+        let mut exchange = Exchange::default();
+        assert_eq!(
+            events(&mut exchange, true, &packet(0, b"\x02billing")),
+            vec![EventKind::InitDb {
+                schema: "billing".to_string()
+            }]
+        );
+        let ok = packet(1, &[MySqlCommand::Ok.code(), 0, 0, 2, 0, 0, 0]);
+        assert_eq!(events(&mut exchange, false, &ok).len(), 1);
+
+        // column definitions up to the EOF, then nothing outstanding
+        assert_eq!(
+            events(&mut exchange, true, &packet(0, b"\x04users\0")),
+            vec![EventKind::FieldList {
+                table: "users".to_string()
+            }]
+        );
+        let mut response = packet(1, b"\x03def...");
+        response.extend(packet(2, b"\x03def..."));
+        response.extend(packet(3, &[MySqlCommand::Eof.code(), 0, 0, 2, 0]));
+        assert!(events(&mut exchange, false, &response).is_empty());
+        assert!(events(&mut exchange, false, &ok).is_empty());
+
+        // answered with a bare string that looks like nothing else
+        assert_eq!(
+            events(&mut exchange, true, &packet(0, b"\x09")),
+            vec![EventKind::Command { command: 0x09 }]
+        );
+        assert!(events(&mut exchange, false, &packet(1, b"Uptime: 42")).is_empty());
+    }
+
+    #[test]
+    fn test_multi_statements() {
+        let caps = CLIENT_PROTOCOL_41 | CLIENT_MULTI_STATEMENTS;
+        let mut exchange = Exchange::default();
+        let sql = b"\x03call p(); update t set a = 1; select 2; select 3";
+        exchange.request(&packet(0, sql), caps, |_| {});
+
+        let more = [0xfe, 0, 0, SERVER_MORE_RESULTS_EXISTS as u8 | 2, 0];
+        let result_set = |seq: u8| {
+            let mut packets = packet(seq, &[1]);
+            packets.extend(packet(seq + 1, b"\x03def..."));
+            packets.extend(packet(seq + 2, &more));
+            packets.extend(packet(seq + 3, b"\x011"));
+            packets.extend(packet(seq + 4, &more));
+            packets
+        };
+        let mut response = result_set(1);
+        response.extend(result_set(6));
+        response.extend(packet(
+            11,
+            &[0, 0, 0, SERVER_MORE_RESULTS_EXISTS as u8, 0, 0, 0],
+        ));
+        response.extend(packet(
+            12,
+            &[0, 1, 0, SERVER_MORE_RESULTS_EXISTS as u8, 0, 0, 0],
+        ));
+        response.extend(result_set(13));
+        response.extend(packet(18, b"\xff\x7a\x04#42S02Table 't' doesn't exist"));
+
+        let mut events = Vec::new();
+        exchange.response(&response, caps, |e| events.push(e));
+        let statements: Vec<String> = events
+            .iter()
+            .map(|e| match e {
+                EventKind::Ok { statement, .. }
+                | EventKind::Error { statement, .. }
+                | EventKind::ResultSet { statement, .. } => statement.clone().unwrap(),
+                e => panic!("{:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            statements,
+            [
+                "call p()",
+                "call p()",
+                "call p()",
+                "update t set a = 1",
+                "select 2",
+                "select 3"
+            ]
+        );
+        assert_eq!(exchange.expect, Expect::Nothing);
+        assert!(exchange.statements.is_empty());
+
+        // one query without CLIENT_MULTI_STATEMENTS, as far as the server knows
+        exchange.request(&packet(0, sql), CLIENT_PROTOCOL_41, |_| {});
+        assert!(exchange.statements.is_empty());
+    }
+
+    #[test]
+    fn test_local_infile() {
+        let mut exchange = Exchange::default();
+        let sql = b"\x03load data local infile 'a.csv' into table t";
+        events(&mut exchange, true, &packet(0, sql));
+        assert!(events(&mut exchange, false, &packet(1, b"\xfba.csv")).is_empty());
+
+        // a full packet, the empty one that continues it and the end; the
+        // rest of the full packet is not captured
+        let mut file = (MAX_PAYLOAD_LEN as u32).to_le_bytes()[..3].to_vec();
+        file.extend([2, b'1']);
+        exchange.request(&file, CLIENT_PROTOCOL_41, |_| unreachable!());
+        exchange.requests.skip = 0;
+        let mut end = packet(3, b"");
+        end.extend(packet(4, b""));
+        assert_eq!(
+            events(&mut exchange, true, &end),
+            vec![EventKind::LocalInfile {
+                filename: "a.csv".to_string(),
+                bytes: MAX_PAYLOAD_LEN as u64,
+                statement: Some(std::str::from_utf8(&sql[1..]).unwrap().to_string()),
+                unsolicited: false,
+            }]
+        );
+        let ok = packet(5, &[0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(events(&mut exchange, false, &ok).len(), 1);
+        assert!(exchange.infile.is_none());
+    }
+
+    fn binlog_event(event_type: u8, timestamp: u32, log_pos: u32, body: &[u8]) -> Vec<u8> {
+        let mut event = vec![0];
+        event.extend(timestamp.to_le_bytes());
+        event.push(event_type);
+        event.extend(7u32.to_le_bytes());
+        event.extend((19 + body.len() as u32).to_le_bytes());
+        event.extend(log_pos.to_le_bytes());
+        event.extend([0, 0]);
+        event.extend(body);
+        event
+    }
+
+    #[test]
+    fn test_binlog_dump() {
+        let mut exchange = Exchange::default();
+        let mut dump = vec![0x12, 4, 0, 0, 0, 0, 0, 2, 0, 0, 0];
+        dump.extend(b"binlog.000001");
+        assert_eq!(
+            events(&mut exchange, true, &packet(0, &dump)),
+            vec![EventKind::BinlogDump {
+                file: "binlog.000001".to_string(),
+                position: 4,
+                server_id: 2,
+                gtid: false,
+            }]
+        );
+
+        // no checksums before 5.6, and events cut at the end of a segment
+        let mut rotate = 4u64.to_le_bytes().to_vec();
+        rotate.extend(b"binlog.000001");
+        let mut format = vec![4, 0];
+        format.extend(b"5.5.62");
+        format.resize(57, 0);
+        let mut query = vec![9, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0];
+        query.extend(b"shop\0BEGIN");
+        let mut stream = packet(1, &binlog_event(4, 0, 0, &rotate));
+        stream.extend(packet(2, &binlog_event(15, 1700000000, 0, &format)));
+        stream.extend(packet(3, &binlog_event(2, 1700000000, 150, &query)));
+        stream.extend(packet(
+            4,
+            &binlog_event(16, 1700000001, 181, &[42, 0, 0, 0, 0, 0, 0, 0]),
+        ));
+        stream.extend(packet(5, b"\xfe\0\0\x02\0"));
+        let (first, rest) = stream.split_at(stream.len() - 50);
+        let binlog = |source_timestamp, log_pos, binlog| EventKind::Binlog {
+            source_timestamp,
+            server_id: 7,
+            log_pos,
+            binlog,
+            lag: None,
+        };
+        assert_eq!(
+            events(&mut exchange, false, first),
+            vec![
+                binlog(
+                    0,
+                    0,
+                    BinlogEvent::Rotate {
+                        file: "binlog.000001".to_string(),
+                        position: 4,
+                    }
+                ),
+                binlog(
+                    1700000000,
+                    0,
+                    BinlogEvent::FormatDescription {
+                        server_version: "5.5.62".to_string(),
+                        binlog_version: 4,
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            events(&mut exchange, false, rest),
+            vec![
+                binlog(
+                    1700000000,
+                    150,
+                    BinlogEvent::Query {
+                        schema: "shop".to_string(),
+                        sql: "BEGIN".to_string(),
+                    }
+                ),
+                binlog(1700000001, 181, BinlogEvent::Xid { xid: 42 }),
+            ]
+        );
+        // the EOF of a non-blocking dump ends the stream
+        assert_eq!(exchange.expect, Expect::Nothing);
+        assert_eq!(exchange.responses.whole, 0);
+    }
+}
@@ -0,0 +1,387 @@
+use crate::frame::CapturedFrame;
+use crate::link::LinkType;
+use crate::{SessionCtx, SessionState, Totals};
+use config::{ExportConfig, ExportRule};
+use log::{error, info, warn};
+use pcap::PcapWriter;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+// global and record headers
+const PCAP_HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: u64 = 16;
+
+/// Picks sessions to export and writes their frames to one pcap file each.
+pub struct Exporter {
+    dir: PathBuf,
+    ring_frames: usize,
+    ring_bytes: usize,
+    max_file_bytes: u64,
+    max_sessions: usize,
+    flows: Vec<(SocketAddr, SocketAddr)>,
+    users: Vec<String>,
+    rules: Vec<ExportRule>,
+    // `max_sessions` applies to the sessions selected by every shard
+    totals: Arc<Totals>,
+    exported: usize,
+    limit_warned: bool,
+}
+
+#[derive(Default)]
+enum State {
+    // not selected yet, recent frames are kept in the ring
+    #[default]
+    Waiting,
+    Writing(Export),
+    // file limit reached or writing failed
+    Done,
+}
+
+struct Export {
+    writer: PcapWriter<BufWriter<File>>,
+    link_type: LinkType,
+    written: u64,
+}
+
+/// Export state of one session.
+#[derive(Default)]
+pub struct Recording {
+    ring: VecDeque<CapturedFrame>,
+    ring_bytes: usize,
+    state: State,
+    path: Option<PathBuf>,
+}
+
+impl Recording {
+    /// File the session is exported to, once selected.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+impl Exporter {
+    /// None unless exporting is enabled.
+    pub fn new(conf: &ExportConfig, totals: Arc<Totals>) -> Option<Exporter> {
+        if !conf.enabled {
+            return None;
+        }
+        Some(Exporter {
+            dir: conf.dir.clone(),
+            ring_frames: conf.ring_frames,
+            ring_bytes: conf.ring_bytes,
+            max_file_bytes: conf.max_file_bytes,
+            max_sessions: conf.max_sessions,
+            flows: conf.flow_keys(),
+            users: conf.users.clone(),
+            rules: conf.rules.clone(),
+            totals,
+            exported: 0,
+            limit_warned: false,
+        })
+    }
+
+    /// Sessions selected so far by this exporter.
+    pub fn exported(&self) -> usize {
+        self.exported
+    }
+
+    /// Whether the session matches a flow, user or rule selector.
+    pub fn selects(&self, ctx: &SessionCtx) -> bool {
+        let (client, server) = (ctx.flow.client(), ctx.flow.server());
+        if self.flows.contains(&(client, server)) {
+            return true;
+        }
+        let user = ctx.username.as_deref();
+        if user.is_some_and(|u| self.users.iter().any(|s| s == u)) {
+            return true;
+        }
+        let client_name = ctx.client_attrs.get("_client_name").map(|c| c.as_str());
+        self.rules.iter().any(|rule| {
+            rule.matches_server(server)
+                && matches(&rule.user, user)
+                && matches(&rule.database, ctx.database.as_deref())
+                && matches(&rule.client_name, client_name)
+        })
+    }
+
+    /// Record a frame of the session described by `ctx`, after the session
+    /// has seen it so selectors on the handshake apply to the frame that
+    /// carried it.
+    pub fn record(&mut self, ctx: &SessionCtx, recording: &mut Recording, frame: CapturedFrame) {
+        if let State::Waiting = recording.state {
+            if self.selects(ctx) {
+                self.start(ctx, recording);
+            }
+        }
+        match &mut recording.state {
+            // nothing selects a session after it ended, its ring goes
+            State::Waiting if ctx.state == SessionState::Logout => {
+                recording.ring = VecDeque::new();
+                recording.ring_bytes = 0;
+            }
+            State::Waiting => self.remember(recording, frame),
+            State::Writing(export) => {
+                let path = recording.path.as_deref().unwrap_or(Path::new(""));
+                if !self.write(export, &frame, path) {
+                    recording.state = State::Done;
+                }
+            }
+            State::Done => {}
+        }
+    }
+
+    fn remember(&self, recording: &mut Recording, frame: CapturedFrame) {
+        recording.ring_bytes += frame.data.len();
+        recording.ring.push_back(frame);
+        while recording.ring.len() > self.ring_frames || recording.ring_bytes > self.ring_bytes {
+            match recording.ring.pop_front() {
+                Some(old) => recording.ring_bytes -= old.data.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn start(&mut self, ctx: &SessionCtx, recording: &mut Recording) {
+        let max_sessions = self.max_sessions;
+        let reserved = self
+            .totals
+            .exported
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max_sessions).then_some(n + 1)
+            })
+            .is_ok();
+        if !reserved {
+            if !self.limit_warned {
+                warn!(
+                    "Not exporting {}, {} sessions already exported",
+                    ctx.flow, self.max_sessions
+                );
+                self.limit_warned = true;
+            }
+            recording.state = State::Done;
+            return;
+        }
+        let path = self.dir.join(file_name(ctx));
+        let link_type = recording
+            .ring
+            .front()
+            .map(|f| f.link_type)
+            .unwrap_or(LinkType::Ethernet);
+        let mut export = match create(&path, link_type) {
+            Ok(export) => export,
+            Err(e) => {
+                error!("Failed to export {} to {}: {}", ctx.flow, path.display(), e);
+                self.totals.exported.fetch_sub(1, Ordering::Relaxed);
+                recording.state = State::Done;
+                return;
+            }
+        };
+        info!("Exporting {} to {}", ctx.flow, path.display());
+        self.exported += 1;
+        let mut done = false;
+        for frame in recording.ring.drain(..) {
+            if !done && !self.write(&mut export, &frame, &path) {
+                done = true;
+            }
+        }
+        recording.ring_bytes = 0;
+        recording.path = Some(path);
+        recording.state = if done {
+            State::Done
+        } else {
+            State::Writing(export)
+        };
+    }
+
+    /// False once the export is finished, by its size limit or an error.
+    fn write(&self, export: &mut Export, frame: &CapturedFrame, path: &Path) -> bool {
+        if frame.link_type != export.link_type {
+            // one link type per pcap file, e.g. after a tunnel change
+            return true;
+        }
+        let len = RECORD_HEADER_LEN + frame.data.len() as u64;
+        if export.written + len > self.max_file_bytes {
+            info!(
+                "Export {} reached {} bytes",
+                path.display(),
+                self.max_file_bytes
+            );
+            return false;
+        }
+        let ts = frame.timestamp;
+        let result = export
+            .writer
+            .write_packet(
+                ts.secs() as u32,
+                ts.subsec_nanos(),
+                frame.orig_len,
+                &frame.data,
+            )
+            // evidence should be on disk when the alert is looked at
+            .and_then(|_| export.writer.flush());
+        match result {
+            Ok(_) => {
+                export.written += len;
+                true
+            }
+            Err(e) => {
+                error!("Failed to write {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+}
+
+fn matches(expected: &Option<String>, actual: Option<&str>) -> bool {
+    match expected {
+        None => true,
+        Some(expected) => actual == Some(expected.as_str()),
+    }
+}
+
+fn create(path: &Path, link_type: LinkType) -> io::Result<Export> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = BufWriter::new(File::create(path)?);
+    Ok(Export {
+        writer: PcapWriter::new(file, link_type.to_pcap(), pcap::MAX_RECORD_LEN)?,
+        link_type,
+        written: PCAP_HEADER_LEN,
+    })
+}
+
+/// `<first seen>-<client>-<server>.pcap`, with `:` replaced so the name
+/// is valid everywhere.
+fn file_name(ctx: &SessionCtx) -> String {
+    format!(
+        "{}-{}-{}.pcap",
+        ctx.first_seen.secs(),
+        ctx.flow.client(),
+        ctx.flow.server()
+    )
+    .replace([':', '[', ']'], "_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Timestamp;
+    use crate::testing::tcp_frame;
+    use crate::{Decoder, SessionManager, SessionPacket};
+    use config::Config;
+    use pcap::PcapReader;
+
+    fn frame(request: bool, secs: u64) -> CapturedFrame {
+        let data = tcp_frame(request, 1, &[1, 0, 0, 0, 0x0e]);
+        CapturedFrame {
+            timestamp: Timestamp::new(secs, 0),
+            interface: None,
+            link_type: LinkType::Ethernet,
+            orig_len: data.len() as u32,
+            data: data.into(),
+        }
+    }
+
+    #[test]
+    fn test_export_with_ring() {
+        let dir = std::env::temp_dir().join(format!("rshark-export-{}", std::process::id()));
+        let mut config = Config::default();
+        config.export.enabled = true;
+        config.export.dir = dir.clone();
+        config.export.ring_frames = 2;
+        config.export.users = vec!["root".to_string()];
+        let mut decoder = Decoder::new(&config);
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let manager = SessionManager::new(config.clone(), rx, Default::default());
+
+        let packets: Vec<SessionPacket> = (0..6)
+            .map(|i| SessionPacket::new(&config, &mut decoder, frame(i % 2 == 0, i)).unwrap())
+            .collect();
+        let mut ctx = manager.create_session_ctx(&packets[0]);
+        let mut exporter = Exporter::new(&config.export, Default::default()).unwrap();
+        let mut recording = Recording::default();
+
+        for pkt in &packets[..3] {
+            exporter.record(&ctx, &mut recording, pkt.frame.clone());
+        }
+        assert!(recording.path().is_none());
+
+        // selected by the user once the handshake was seen, the two frames
+        // before it come from the ring
+        ctx.username = Some("root".to_string());
+        for pkt in &packets[3..] {
+            exporter.record(&ctx, &mut recording, pkt.frame.clone());
+        }
+        assert_eq!(exporter.exported(), 1);
+        let path = recording.path().unwrap().to_path_buf();
+        assert_eq!(
+            path.file_name().unwrap(),
+            "0-10.0.0.1_50000-10.0.0.2_3306.pcap"
+        );
+
+        let reader = PcapReader::new(File::open(&path).unwrap()).unwrap();
+        let secs: Vec<u32> = reader.map(|r| r.unwrap().ts_sec).collect();
+        assert_eq!(secs, vec![1, 2, 3, 4, 5]);
+
+        // over the size limit the export stops
+        let mut config = config.export.clone();
+        config.max_file_bytes = PCAP_HEADER_LEN + 2 * (RECORD_HEADER_LEN + 59);
+        let mut exporter = Exporter::new(&config, Default::default()).unwrap();
+        let mut recording = Recording::default();
+        for pkt in &packets {
+            exporter.record(&ctx, &mut recording, pkt.frame.clone());
+        }
+        let reader = PcapReader::new(File::open(recording.path().unwrap()).unwrap()).unwrap();
+        assert_eq!(reader.count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_limit_and_ended_sessions() {
+        let dir = std::env::temp_dir().join(format!("rshark-limit-{}", std::process::id()));
+        let mut config = Config::default();
+        config.export.enabled = true;
+        config.export.dir = dir.clone();
+        config.export.max_sessions = 1;
+        config.export.users = vec!["root".to_string()];
+        let mut decoder = Decoder::new(&config);
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let manager = SessionManager::new(config.clone(), rx, Default::default());
+        let pkt = SessionPacket::new(&config, &mut decoder, frame(true, 1)).unwrap();
+        let mut ctx = manager.create_session_ctx(&pkt);
+
+        // the limit holds across the exporters of all shards
+        let totals = Arc::new(Totals::default());
+        let mut shards = [
+            Exporter::new(&config.export, totals.clone()).unwrap(),
+            Exporter::new(&config.export, totals.clone()).unwrap(),
+        ];
+        let mut recordings = [Recording::default(), Recording::default()];
+        ctx.username = Some("root".to_string());
+        for (exporter, recording) in shards.iter_mut().zip(&mut recordings) {
+            exporter.record(&ctx, recording, pkt.frame.clone());
+        }
+        assert!(recordings[0].path().is_some());
+        assert!(recordings[1].path().is_none());
+        assert_eq!(totals.exported.load(Ordering::Relaxed), 1);
+
+        // the ring of a session that was never selected goes when it ends
+        ctx.username = None;
+        let mut recording = Recording::default();
+        shards[0].record(&ctx, &mut recording, pkt.frame.clone());
+        assert_eq!(recording.ring.len(), 1);
+        ctx.state = SessionState::Logout;
+        shards[0].record(&ctx, &mut recording, pkt.frame.clone());
+        assert!(recording.ring.is_empty());
+        assert_eq!(recording.ring_bytes, 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
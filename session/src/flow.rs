@@ -0,0 +1,86 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+pub const IPPROTO_TCP: u8 = 6;
+
+/// Direction-normalized 5-tuple of a connection, the client side always
+/// comes first regardless of which side sent the packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub client_ip: IpAddr,
+    pub client_port: u16,
+    pub server_ip: IpAddr,
+    pub server_port: u16,
+    pub protocol: u8,
+}
+
+impl FlowKey {
+    /// Build the key of a packet sent from `src` to `dst`, `request` tells
+    /// whether `src` is the client.
+    pub fn new(
+        src_ip: IpAddr,
+        src_port: u16,
+        dst_ip: IpAddr,
+        dst_port: u16,
+        protocol: u8,
+        request: bool,
+    ) -> FlowKey {
+        if request {
+            FlowKey {
+                client_ip: src_ip,
+                client_port: src_port,
+                server_ip: dst_ip,
+                server_port: dst_port,
+                protocol,
+            }
+        } else {
+            FlowKey {
+                client_ip: dst_ip,
+                client_port: dst_port,
+                server_ip: src_ip,
+                server_port: src_port,
+                protocol,
+            }
+        }
+    }
+
+    pub fn client(&self) -> SocketAddr {
+        SocketAddr::new(self.client_ip, self.client_port)
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        SocketAddr::new(self.server_ip, self.server_port)
+    }
+}
+
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.client(), self.server())?;
+        match self.protocol {
+            IPPROTO_TCP => write!(f, "/tcp"),
+            p => write!(f, "/{}", p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flow_key_normalized() {
+        let client: IpAddr = "1.2.3.4".parse().unwrap();
+        let server: IpAddr = "1.2.3.41".parse().unwrap();
+        let req = FlowKey::new(client, 50000, server, 3306, IPPROTO_TCP, true);
+        let resp = FlowKey::new(server, 3306, client, 50000, IPPROTO_TCP, false);
+        assert_eq!(req, resp);
+        assert_eq!(req.to_string(), "1.2.3.4:50000 -> 1.2.3.41:3306/tcp");
+
+        // "1.2.3.4" + "1" + port used to collide with "1.2.3.41" + port
+        let other = FlowKey::new(server, 50000, client, 13306, IPPROTO_TCP, true);
+        assert_ne!(req, other);
+
+        let v6 = FlowKey::new("::1".parse().unwrap(), 1, server, 3306, IPPROTO_TCP, true);
+        assert_eq!(v6.to_string(), "[::1]:1 -> 1.2.3.41:3306/tcp");
+    }
+}
@@ -0,0 +1,110 @@
+use crate::link::LinkType;
+use bytes::Bytes;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::ops::Add;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Capture time in nanoseconds since the Unix epoch, from the kernel or the
+/// pcap record so that replaying a file yields the same timings as live.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub fn new(secs: u64, nanos: u32) -> Timestamp {
+        Timestamp(secs * NANOS_PER_SEC + nanos as u64)
+    }
+
+    pub fn from_nanos(nanos: u64) -> Timestamp {
+        Timestamp(nanos)
+    }
+
+    /// Wall clock time, for sources that do not timestamp frames.
+    pub fn now() -> Timestamp {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Timestamp(since_epoch.as_nanos() as u64)
+    }
+
+    pub fn as_nanos(self) -> u64 {
+        self.0
+    }
+
+    pub fn secs(self) -> u64 {
+        self.0 / NANOS_PER_SEC
+    }
+
+    pub fn subsec_nanos(self) -> u32 {
+        (self.0 % NANOS_PER_SEC) as u32
+    }
+
+    /// Time elapsed since `earlier`, zero if it is actually later.
+    pub fn duration_since(self, earlier: Timestamp) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, rhs: Duration) -> Timestamp {
+        Timestamp(self.0 + rhs.as_nanos() as u64)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:09}", self.secs(), self.subsec_nanos())
+    }
+}
+
+// as its Display form, integers would lose precision in JSON
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A frame as delivered by a capture source.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub timestamp: Timestamp,
+    // interface it was captured on, None when read from a file
+    pub interface: Option<Arc<str>>,
+    pub link_type: LinkType,
+    // length on the wire, `data` may be cut short by the snaplen
+    pub orig_len: u32,
+    // every layer decoded from the frame is a slice of this buffer
+    pub data: Bytes,
+}
+
+impl CapturedFrame {
+    /// Bytes actually captured.
+    pub fn captured_len(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    /// Whether the snaplen cut the frame short.
+    pub fn truncated(&self) -> bool {
+        self.captured_len() < self.orig_len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        let ts = Timestamp::new(1_700_000_000, 5);
+        assert_eq!(ts.to_string(), "1700000000.000000005");
+        let later = ts + Duration::from_micros(1500);
+        assert_eq!(later.duration_since(ts), Duration::from_micros(1500));
+        assert_eq!(ts.duration_since(later), Duration::ZERO);
+        assert!(Timestamp::now() > ts);
+    }
+}
@@ -0,0 +1,490 @@
+use crate::frame::Timestamp;
+use bytes::Bytes;
+use config::IpConfig;
+use log::debug;
+use pnet_packet::ethernet::{EtherType, EtherTypes};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+// IPv6 extension headers walked to reach the upper layer
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_AH: u8 = 51;
+const IPV6_DEST_OPTIONS: u8 = 60;
+const IPV6_MOBILITY: u8 = 135;
+const IPV6_NO_NEXT_HEADER: u8 = 59;
+
+// hop-by-hop and routing headers beyond this are treated as garbage
+const MAX_IPV6_EXT_HEADERS: usize = 8;
+
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+#[derive(Debug, Clone)]
+pub struct IpLayer {
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    // upper layer protocol, after any IPv6 extension headers
+    pub protocol: u8,
+    // true when the payload was reassembled from fragments
+    pub reassembled: bool,
+    // a slice of the frame, unless reassembled
+    pub payload: Bytes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    protocol: u8,
+    id: u32,
+}
+
+struct FragmentBuffer {
+    first_seen: Timestamp,
+    // (offset, data) in arrival order
+    fragments: Vec<(usize, Vec<u8>)>,
+    // set once the fragment without "more fragments" arrived
+    total_len: Option<usize>,
+    bytes: usize,
+}
+
+impl FragmentBuffer {
+    /// Payload of the whole datagram once there are no holes left.
+    fn assemble(&mut self) -> Option<Vec<u8>> {
+        let total_len = self.total_len?;
+        self.fragments.sort_by_key(|(offset, _)| *offset);
+        let mut payload = Vec::with_capacity(total_len);
+        for (offset, data) in &self.fragments {
+            if *offset > payload.len() {
+                return None;
+            }
+            // overlapping bytes keep the data that arrived first in order
+            let end = offset + data.len();
+            if end > payload.len() {
+                payload.extend_from_slice(&data[payload.len() - offset..]);
+            }
+        }
+        if payload.len() < total_len {
+            return None;
+        }
+        payload.truncate(total_len);
+        Some(payload)
+    }
+}
+
+/// Reassembles IPv4 and IPv6 fragments, bounded in time and memory.
+pub struct Defragmenter {
+    timeout: Duration,
+    max_bytes: usize,
+    max_datagrams: usize,
+    buffers: HashMap<FragmentKey, FragmentBuffer>,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl Defragmenter {
+    pub fn new(conf: &IpConfig) -> Defragmenter {
+        Defragmenter {
+            timeout: Duration::from_secs(conf.fragment_timeout_secs),
+            max_bytes: conf.max_fragment_bytes,
+            max_datagrams: conf.max_fragmented_datagrams,
+            buffers: HashMap::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Datagrams given up on because of timeouts or limits.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Bytes currently buffered.
+    pub fn buffered(&self) -> usize {
+        self.bytes
+    }
+
+    fn remove(&mut self, key: &FragmentKey) -> Option<FragmentBuffer> {
+        let buffer = self.buffers.remove(key)?;
+        self.bytes -= buffer.bytes;
+        Some(buffer)
+    }
+
+    fn expire(&mut self, now: Timestamp) {
+        let timeout = self.timeout;
+        let expired: Vec<FragmentKey> = self
+            .buffers
+            .iter()
+            .filter(|(_, b)| now.duration_since(b.first_seen) >= timeout)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in expired {
+            debug!("Fragments of {:?} timed out", key);
+            self.remove(&key);
+            self.dropped += 1;
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .buffers
+            .iter()
+            .min_by_key(|(_, b)| b.first_seen)
+            .map(|(k, _)| *k);
+        match oldest {
+            Some(key) => {
+                self.remove(&key);
+                self.dropped += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add a fragment, returning the datagram payload once it is complete.
+    fn insert(
+        &mut self,
+        key: FragmentKey,
+        offset: usize,
+        more: bool,
+        data: &[u8],
+        now: Timestamp,
+    ) -> Option<Vec<u8>> {
+        self.expire(now);
+        let end = offset + data.len();
+        if end > MAX_DATAGRAM_LEN || data.len() > self.max_bytes {
+            self.dropped += 1;
+            self.remove(&key);
+            return None;
+        }
+        if !self.buffers.contains_key(&key) && self.buffers.len() >= self.max_datagrams {
+            self.evict_oldest();
+        }
+        while self.bytes + data.len() > self.max_bytes {
+            if !self.evict_oldest() {
+                break;
+            }
+        }
+
+        let buffer = self.buffers.entry(key).or_insert_with(|| FragmentBuffer {
+            first_seen: now,
+            fragments: Vec::new(),
+            total_len: None,
+            bytes: 0,
+        });
+        if !more {
+            buffer.total_len = Some(end);
+        }
+        // copied so a buffered fragment does not pin its whole frame
+        buffer.fragments.push((offset, data.to_vec()));
+        buffer.bytes += data.len();
+        self.bytes += data.len();
+
+        let payload = self.buffers.get_mut(&key)?.assemble()?;
+        self.remove(&key);
+        Some(payload)
+    }
+
+    /// Decode the IPv4 or IPv6 packet in `data`. Fragments are buffered and
+    /// `None` is returned until the datagram they belong to is complete.
+    pub fn decode(&mut self, eth_type: EtherType, data: &Bytes, now: Timestamp) -> Option<IpLayer> {
+        if eth_type == EtherTypes::Ipv4 {
+            self.decode_v4(data, now)
+        } else if eth_type == EtherTypes::Ipv6 {
+            self.decode_v6(data, now)
+        } else {
+            None
+        }
+    }
+
+    fn decode_v4(&mut self, data: &Bytes, now: Timestamp) -> Option<IpLayer> {
+        if data.len() < 20 || data[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = ((data[0] & 0x0f) as usize) * 4;
+        // ignore link layer padding after the datagram
+        let total_len = (u16::from_be_bytes([data[2], data[3]]) as usize).min(data.len());
+        if header_len < 20 || total_len < header_len {
+            return None;
+        }
+        let id = u16::from_be_bytes([data[4], data[5]]) as u32;
+        let flags_offset = u16::from_be_bytes([data[6], data[7]]);
+        let more = flags_offset & 0x2000 != 0;
+        let offset = ((flags_offset & 0x1fff) as usize) * 8;
+        let protocol = data[9];
+        let src_ip = IpAddr::V4(Ipv4Addr::new(data[12], data[13], data[14], data[15]));
+        let dst_ip = IpAddr::V4(Ipv4Addr::new(data[16], data[17], data[18], data[19]));
+        if !more && offset == 0 {
+            return Some(IpLayer {
+                src_ip,
+                dst_ip,
+                protocol,
+                reassembled: false,
+                payload: data.slice(header_len..total_len),
+            });
+        }
+        let key = FragmentKey {
+            src_ip,
+            dst_ip,
+            protocol,
+            id,
+        };
+        let payload = self.insert(key, offset, more, &data[header_len..total_len], now)?;
+        Some(IpLayer {
+            src_ip,
+            dst_ip,
+            protocol,
+            reassembled: true,
+            payload: payload.into(),
+        })
+    }
+
+    fn decode_v6(&mut self, data: &Bytes, now: Timestamp) -> Option<IpLayer> {
+        if data.len() < 40 || data[0] >> 4 != 6 {
+            return None;
+        }
+        let payload_len = u16::from_be_bytes([data[4], data[5]]) as usize;
+        let end = (40 + payload_len).min(data.len());
+        let src: [u8; 16] = data[8..24].try_into().ok()?;
+        let dst: [u8; 16] = data[24..40].try_into().ok()?;
+        let src_ip = IpAddr::V6(Ipv6Addr::from(src));
+        let dst_ip = IpAddr::V6(Ipv6Addr::from(dst));
+
+        let (protocol, pos, fragment) = ext_headers(data, data[6], 40)?;
+        if pos > end {
+            return None;
+        }
+        let Fragment { offset, more, id } = match fragment {
+            Some(fragment) => fragment,
+            None => {
+                return Some(IpLayer {
+                    src_ip,
+                    dst_ip,
+                    protocol,
+                    reassembled: false,
+                    payload: data.slice(pos..end),
+                })
+            }
+        };
+        let key = FragmentKey {
+            src_ip,
+            dst_ip,
+            protocol,
+            id,
+        };
+        let payload = Bytes::from(self.insert(key, offset, more, &data[pos..end], now)?);
+        // the fragmentable part may start with more extension headers
+        let (protocol, pos, fragment) = ext_headers(&payload, protocol, 0)?;
+        if fragment.is_some() || pos > payload.len() {
+            return None;
+        }
+        Some(IpLayer {
+            src_ip,
+            dst_ip,
+            protocol,
+            reassembled: true,
+            payload: payload.slice(pos..),
+        })
+    }
+}
+
+/// Where an IPv6 fragment goes in its datagram.
+struct Fragment {
+    offset: usize,
+    more: bool,
+    id: u32,
+}
+
+/// Walk the IPv6 extension headers from `pos`, `next_header` being the type
+/// of the first. Returns the upper layer protocol and where it starts, or,
+/// at the fragment header of a fragment, the type of the fragmentable part,
+/// where it starts and the fragment offset, "more fragments" flag and id.
+/// What follows the fragment header of a fragment other than the first is
+/// fragment data, not headers.
+fn ext_headers(
+    data: &[u8],
+    mut next_header: u8,
+    mut pos: usize,
+) -> Option<(u8, usize, Option<Fragment>)> {
+    for _ in 0..MAX_IPV6_EXT_HEADERS {
+        match next_header {
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DEST_OPTIONS | IPV6_MOBILITY => {
+                let len = (*data.get(pos + 1)? as usize + 1) * 8;
+                next_header = *data.get(pos)?;
+                pos += len;
+            }
+            IPV6_AH => {
+                let len = (*data.get(pos + 1)? as usize + 2) * 4;
+                next_header = *data.get(pos)?;
+                pos += len;
+            }
+            IPV6_FRAGMENT => {
+                let header = data.get(pos..pos + 8)?;
+                next_header = header[0];
+                let offset_flags = u16::from_be_bytes([header[2], header[3]]);
+                let id = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                let offset = ((offset_flags >> 3) as usize) * 8;
+                let more = offset_flags & 0x1 != 0;
+                pos += 8;
+                // an atomic fragment, offset 0 and no more fragments, is
+                // the whole datagram
+                if offset > 0 || more {
+                    return Some((next_header, pos, Some(Fragment { offset, more, id })));
+                }
+            }
+            IPV6_NO_NEXT_HEADER => return None,
+            _ => break,
+        }
+    }
+    Some((next_header, pos, None))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ipv4(id: u16, offset: usize, more: bool, payload: &[u8]) -> Bytes {
+        let total_len = (20 + payload.len()) as u16;
+        let flags_offset = (offset / 8) as u16 | if more { 0x2000 } else { 0 };
+        let mut pkt = vec![0x45, 0x00];
+        pkt.extend_from_slice(&total_len.to_be_bytes());
+        pkt.extend_from_slice(&id.to_be_bytes());
+        pkt.extend_from_slice(&flags_offset.to_be_bytes());
+        pkt.extend_from_slice(&[64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        pkt.extend_from_slice(payload);
+        pkt.into()
+    }
+
+    fn ipv6(next_header: u8, ext: &[u8], payload: &[u8]) -> Bytes {
+        let mut pkt = vec![0x60, 0, 0, 0];
+        pkt.extend_from_slice(&((ext.len() + payload.len()) as u16).to_be_bytes());
+        pkt.extend_from_slice(&[next_header, 64]);
+        pkt.extend_from_slice(&[0; 15]);
+        pkt.push(1);
+        pkt.extend_from_slice(&[0; 15]);
+        pkt.push(2);
+        pkt.extend_from_slice(ext);
+        pkt.extend_from_slice(payload);
+        pkt.into()
+    }
+
+    #[test]
+    fn test_ipv4_reassembly() {
+        let mut defrag = Defragmenter::new(&IpConfig::default());
+        let now = Timestamp::now();
+        let data: Vec<u8> = (0..40).collect();
+
+        // padding after the datagram is not payload
+        let mut padded = ipv4(1, 0, false, &data[..4]).to_vec();
+        padded.extend_from_slice(&[0, 0]);
+        let ip = defrag
+            .decode(EtherTypes::Ipv4, &padded.into(), now)
+            .unwrap();
+        assert_eq!(ip.payload, &data[..4]);
+        assert!(!ip.reassembled);
+
+        // out of order, with an overlap
+        let last = ipv4(7, 24, false, &data[24..]);
+        let first = ipv4(7, 0, true, &data[..16]);
+        let middle = ipv4(7, 8, true, &data[8..24]);
+        assert!(defrag.decode(EtherTypes::Ipv4, &last, now).is_none());
+        assert!(defrag.decode(EtherTypes::Ipv4, &first, now).is_none());
+        let ip = defrag.decode(EtherTypes::Ipv4, &middle, now).unwrap();
+        assert!(ip.reassembled);
+        assert_eq!(ip.protocol, 6);
+        assert_eq!(ip.payload, data);
+        assert_eq!(defrag.buffered(), 0);
+    }
+
+    #[test]
+    fn test_fragment_limits() {
+        let conf = IpConfig {
+            fragment_timeout_secs: 5,
+            max_fragment_bytes: 32,
+            max_fragmented_datagrams: 2,
+        };
+        let mut defrag = Defragmenter::new(&conf);
+        let now = Timestamp::now();
+        let data = [0u8; 16];
+
+        assert!(defrag
+            .decode(EtherTypes::Ipv4, &ipv4(1, 0, true, &data), now)
+            .is_none());
+        let later = now + Duration::from_secs(6);
+        assert!(defrag
+            .decode(EtherTypes::Ipv4, &ipv4(2, 0, true, &data), later)
+            .is_none());
+        assert_eq!(defrag.dropped(), 1);
+        // the expired first fragment cannot complete datagram 1 anymore
+        assert!(defrag
+            .decode(EtherTypes::Ipv4, &ipv4(1, 16, false, &data), later)
+            .is_none());
+
+        // over the byte cap the oldest datagram goes first
+        assert!(defrag
+            .decode(EtherTypes::Ipv4, &ipv4(3, 0, true, &data), later)
+            .is_none());
+        assert!(defrag.buffered() <= 32);
+        assert_eq!(defrag.dropped(), 2);
+    }
+
+    #[test]
+    fn test_ipv6_extension_headers() {
+        let mut defrag = Defragmenter::new(&IpConfig::default());
+        let now = Timestamp::now();
+        let tcp = [0xaa; 20];
+
+        // hop-by-hop -> routing -> tcp
+        let mut ext = vec![IPV6_ROUTING, 0, 0, 0, 0, 0, 0, 0];
+        ext.extend_from_slice(&[6, 1, 0, 0, 0, 0, 0, 0]);
+        ext.extend_from_slice(&[0; 8]);
+        let ip = defrag
+            .decode(EtherTypes::Ipv6, &ipv6(IPV6_HOP_BY_HOP, &ext, &tcp), now)
+            .unwrap();
+        assert_eq!(ip.protocol, 6);
+        assert_eq!(ip.payload, &tcp[..]);
+
+        // truncated extension header
+        let pkt = ipv6(IPV6_HOP_BY_HOP, &[6, 4], &[]);
+        assert!(defrag.decode(EtherTypes::Ipv6, &pkt, now).is_none());
+
+        // two fragments
+        let first = ipv6(IPV6_FRAGMENT, &[6, 0, 0x00, 0x01, 0, 0, 0, 9], &tcp[..8]);
+        let last = ipv6(IPV6_FRAGMENT, &[6, 0, 0x00, 0x08, 0, 0, 0, 9], &tcp[8..]);
+        assert!(defrag.decode(EtherTypes::Ipv6, &first, now).is_none());
+        let ip = defrag.decode(EtherTypes::Ipv6, &last, now).unwrap();
+        assert!(ip.reassembled);
+        assert_eq!(ip.payload, &tcp[..]);
+    }
+
+    #[test]
+    fn test_ipv6_fragment_with_extension_headers() {
+        let mut defrag = Defragmenter::new(&IpConfig::default());
+        let now = Timestamp::now();
+        // the second fragment starts with what looks like a routing header
+        let mut tcp = [0xaa; 24];
+        tcp[8..10].copy_from_slice(&[IPV6_ROUTING, 0]);
+
+        // destination options -> tcp, fragmented after the options and the
+        // first 8 bytes of tcp
+        let mut fragmentable = vec![6, 0, 0, 0, 0, 0, 0, 0];
+        fragmentable.extend_from_slice(&tcp);
+        let frag = |offset: u16, more: u16| {
+            let offset_flags = (offset / 8) << 3 | more;
+            let mut header = vec![IPV6_DEST_OPTIONS, 0];
+            header.extend_from_slice(&offset_flags.to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, 3]);
+            header
+        };
+        let first = ipv6(IPV6_FRAGMENT, &frag(0, 1), &fragmentable[..16]);
+        let last = ipv6(IPV6_FRAGMENT, &frag(16, 0), &fragmentable[16..]);
+        assert!(defrag.decode(EtherTypes::Ipv6, &last, now).is_none());
+        let ip = defrag.decode(EtherTypes::Ipv6, &first, now).unwrap();
+        assert!(ip.reassembled);
+        assert_eq!(ip.protocol, 6);
+        assert_eq!(ip.payload, &tcp[..]);
+        assert_eq!(defrag.buffered(), 0);
+    }
+}
@@ -1,23 +1,51 @@
 use config::Config;
-use log::{debug, error, info};
-use packets::mysql::common::{MySQLPacketRequest, MySQLPacketResponse};
+use log::{debug, error, info, warn};
+use packets::mysql::common::{
+    MySQLPacketRequest, MySQLPacketResponse, CLIENT_PROTOCOL_41, SERVER_STATUS_AUTOCOMMIT,
+    SERVER_STATUS_IN_TRANS,
+};
+use packets::mysql::client::login::Login;
+use packets::mysql::command::MySqlCommand;
 use packets::mysql::server;
 use packets::{DBPacket, DBType};
-use pnet::packet::ethernet::EthernetPacket;
-use pnet::packet::tcp::TcpOption;
-use pnet::packet::Packet;
-use pnet_packet::ethernet::{EtherType, EtherTypes};
-use pnet_packet::ipv4::Ipv4Packet;
-use pnet_packet::ipv6::Ipv6Packet;
-use pnet_packet::tcp::TcpPacket;
+use bytes::Bytes;
+use exchange::Exchange;
 use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::error::Error;
-use std::os::macos;
+use std::net::IpAddr;
+use std::path::Path;
 use std::str::FromStr;
-use tokio::sync::mpsc::UnboundedReceiver;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+
+mod dedup;
+mod discovery;
+mod event;
+mod exchange;
+mod export;
+mod flow;
+mod frame;
+mod ip;
+mod link;
+mod sql;
+pub mod testing;
+mod tunnel;
+
+pub use dedup::Deduplicator;
+pub use discovery::{Discovery, ServerDiscovered};
+pub use event::{EventKind, EventSink, SessionChange, SessionEvent};
+pub use export::{Exporter, Recording};
+pub use flow::{FlowKey, IPPROTO_TCP};
+pub use frame::{CapturedFrame, Timestamp};
+pub use ip::{Defragmenter, IpLayer};
+pub use link::{mac_to_string, EthLayer, LinkType};
+pub use tunnel::{Decapsulator, Tunnel, TunnelKind, IPPROTO_GRE, IPPROTO_UDP};
+
 #[derive(Debug, Clone)]
-enum SessionState {
+pub enum SessionState {
     ServerGreeting,
     ClientHandshakeResponse,
     Login,
@@ -28,13 +56,21 @@ enum SessionState {
 #[derive(Debug, Clone)]
 pub struct SessionCtx {
     pub state: SessionState,
-    pub src_ip: String,
-    pub dst_ip: String,
+    pub flow: FlowKey,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
     pub src_port: u16,
     pub dst_port: u16,
     pub src_mac: String,
     pub dst_mac: String,
     pub db_type: String,
+    // tunnel id of mirrored traffic, e.g. the VXLAN VNI
+    pub tunnel: Option<Tunnel>,
+    // capture interfaces the session was observed on, in order of appearance
+    pub interfaces: Vec<String>,
+    // capture time of the first and latest packet
+    pub first_seen: Timestamp,
+    pub last_seen: Timestamp,
 
     // parse mysql greeting packet
     pub server_cap: u32,
@@ -45,6 +81,22 @@ pub struct SessionCtx {
     pub client_language: u8,
     pub server_version: String,
     pub client_version: String,
+
+    pub connection_id: u32,
+    pub auth_plugin_name: String,
+
+    // parse mysql handshake response packet, the database follows the
+    // schema changes of the session
+    pub username: Option<String>,
+    pub database: Option<String>,
+    pub client_attrs: HashMap<String, String>,
+
+    // session state of the latest OK packet, the isolation level and
+    // character set only if the server tracks their system variables
+    pub autocommit: Option<bool>,
+    pub in_transaction: Option<bool>,
+    pub isolation_level: Option<String>,
+    pub character_set: Option<String>,
 }
 
 impl SessionCtx {
@@ -60,7 +112,7 @@ impl SessionCtx {
     }
 
     pub fn set_connection_id(&mut self, id: u32) {
-        self.server_cap = id;
+        self.connection_id = id;
     }
 
     pub fn set_capability_flags(&mut self, flags: u32) {
@@ -88,31 +140,98 @@ impl SessionCtx {
     }
 
     pub fn set_auth_plugin_name(&mut self, name: String) {
-        self.server_version = name;
+        self.auth_plugin_name = name;
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct EthLayer {
-    pub src_mac: String,
-    pub dst_mac: String,
-    pub eth_type: EtherType,
+    pub fn add_interface(&mut self, interface: &str) {
+        if !self.interfaces.iter().any(|i| i == interface) {
+            self.interfaces.push(interface.to_string());
+        }
+    }
+
+    pub fn set_login(&mut self, login: Login) {
+        self.client_cap = login.cap;
+        self.client_language = login.charset;
+        self.username = login.username;
+        self.database = login.database;
+        if let Some(version) = login.attrs.get("_client_version") {
+            self.client_version = version.clone();
+        }
+        self.client_attrs = login.attrs;
+    }
+
+    /// Follow the status flags and session state changes of an OK packet,
+    /// or of the EOF or OK that ends a result set.
+    pub fn set_session_state(&mut self, status_flags: u16, changes: &[SessionChange]) {
+        self.server_status = status_flags;
+        self.autocommit = Some(status_flags as u32 & SERVER_STATUS_AUTOCOMMIT > 0);
+        self.in_transaction = Some(status_flags as u32 & SERVER_STATUS_IN_TRANS > 0);
+        for change in changes {
+            match change {
+                SessionChange::Schema { schema } => self.database = Some(schema.clone()),
+                SessionChange::SystemVariable { name, value } => match name.as_str() {
+                    // tx_isolation before 8.0
+                    "transaction_isolation" | "tx_isolation" => {
+                        self.isolation_level = Some(value.clone())
+                    }
+                    "character_set_client" => self.character_set = Some(value.clone()),
+                    _ => {}
+                },
+                SessionChange::TransactionState { state } => {
+                    self.in_transaction = Some(matches!(state.chars().next(), Some('T' | 'I')))
+                }
+                SessionChange::TransactionCharacteristics { characteristics } => {
+                    if let Some(level) = isolation_level(characteristics) {
+                        self.isolation_level = Some(level);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct IpLayer {
-    pub src_ip: String,
-    pub dst_ip: String,
-    pub payload: Vec<u8>,
+/// Isolation level set by the statements of a transaction characteristics
+/// entry, e.g. `SET TRANSACTION ISOLATION LEVEL READ COMMITTED;`, spelled
+/// like the value of `transaction_isolation`.
+fn isolation_level(characteristics: &str) -> Option<String> {
+    const ISOLATION_LEVEL: &str = "ISOLATION LEVEL ";
+    let upper = characteristics.to_ascii_uppercase();
+    let start = upper.find(ISOLATION_LEVEL)? + ISOLATION_LEVEL.len();
+    let level = upper[start..].split([';', ',']).next()?.trim();
+    if level.is_empty() {
+        return None;
+    }
+    Some(level.split_whitespace().collect::<Vec<_>>().join("-"))
 }
 
 #[derive(Debug, Clone)]
 pub struct TcpLayer {
     pub src_port: u16,
     pub dst_port: u16,
+    pub seq: u32,
     pub flags: u8,
-    pub options: Vec<TcpOption>,
-    pub payload: Vec<u8>,
+    // raw option bytes, and the segment payload, both slices of the frame
+    pub options: Bytes,
+    pub payload: Bytes,
+}
+
+impl TcpLayer {
+    pub fn new(segment: &Bytes) -> Option<TcpLayer> {
+        let header = segment.get(..20)?;
+        let header_len = ((header[12] >> 4) as usize) * 4;
+        if header_len < 20 || header_len > segment.len() {
+            return None;
+        }
+        Some(TcpLayer {
+            src_port: u16::from_be_bytes([header[0], header[1]]),
+            dst_port: u16::from_be_bytes([header[2], header[3]]),
+            seq: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            flags: header[13],
+            options: segment.slice(20..header_len),
+            payload: segment.slice(header_len..),
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -122,141 +241,150 @@ pub struct SessionPacket {
     pub tcp_layer: TcpLayer,
     pub request: bool,
     pub db: DBType,
-    pub session_key: String,
+    pub flow: FlowKey,
+    // outermost tunnel the packet was mirrored through
+    pub tunnel: Option<Tunnel>,
+    // the frame as captured, every layer above is a slice of its data
+    pub frame: CapturedFrame,
+}
+
+/// Decoding state shared by all packets a consumer handles.
+pub struct Decoder {
+    pub discovery: Discovery,
+    pub defrag: Defragmenter,
+    pub tunnels: Decapsulator,
+    pub dedup: Deduplicator,
+}
+
+impl Decoder {
+    pub fn new(config: &Config) -> Decoder {
+        Decoder {
+            discovery: Discovery::new(&config.discovery),
+            defrag: Defragmenter::new(&config.ip),
+            tunnels: Decapsulator::new(&config.tunnel),
+            dedup: Deduplicator::new(&config.capture),
+        }
+    }
 }
 
 impl SessionPacket {
-    pub fn new(config: &config::Config, raw_pkt: Vec<u8>) -> Option<Self> {
-        // format eth
-        let eth = EthernetPacket::new(raw_pkt.as_slice());
+    pub fn new(config: &config::Config, decoder: &mut Decoder, frame: CapturedFrame) -> Option<Self> {
+        let mut link_type = frame.link_type;
+        let mut data = frame.data.clone();
+        let mut tunnel = None;
+        let mut depth = 0;
+        let (eth_layer, ip_layer) = loop {
+            // format link layer
+            let (eth_layer, offset) = match link::decode(link_type, &data) {
+                Some(link) => link,
+                None => {
+                    debug!("Failed to parse {:?} link layer", link_type);
+                    return None;
+                }
+            };
 
-        if eth.is_none() {
-            debug!("Failed to parse ethernet packet");
-            return None;
+            // fragments are held back until their datagram is complete
+            let ip_layer = decoder
+                .defrag
+                .decode(eth_layer.eth_type, &data.slice(offset..), frame.timestamp)?;
+            if depth < decoder.tunnels.max_depth() {
+                if let Some((outer, inner_type, inner)) = decoder.tunnels.decap(&ip_layer) {
+                    tunnel.get_or_insert(outer);
+                    link_type = inner_type;
+                    data = ip_layer.payload.slice_ref(inner);
+                    depth += 1;
+                    continue;
+                }
+            }
+            break (eth_layer, ip_layer);
         };
+        if ip_layer.protocol != IPPROTO_TCP {
+            debug!("Skipping ip protocol {}", ip_layer.protocol);
+            return None;
+        }
 
-        let eth_pkt = eth?.consume_to_immutable();
-
-        let ip_layer = if eth_pkt.get_ethertype() == EtherTypes::Ipv4 {
-            let ip = Ipv4Packet::new(eth_pkt.payload());
-            if ip.is_none() {
-                error!("Failed to parse ipv4 packet");
-                return None;
-            };
-            let ip_pkt = ip?.consume_to_immutable();
-            Some(IpLayer {
-                src_ip: ip_pkt.get_source().to_string(),
-                dst_ip: ip_pkt.get_destination().to_string(),
-                payload: ip_pkt.payload().to_vec(),
-            })
-        } else if eth_pkt.get_ethertype() == EtherTypes::Ipv6 {
-            let ip = Ipv6Packet::new(eth_pkt.payload());
-            if ip.is_none() {
-                error!("Failed to parse ipv6 packet");
+        let tcp_layer = match TcpLayer::new(&ip_layer.payload) {
+            Some(tcp) => tcp,
+            None => {
+                error!("Failed to parse tcp packet");
                 return None;
-            };
-            let ip_pkt = ip?.consume_to_immutable();
-            Some(IpLayer {
-                src_ip: ip_pkt.get_source().to_string(),
-                dst_ip: ip_pkt.get_destination().to_string(),
-                payload: ip_pkt.payload().to_vec(),
-            })
-        } else {
-            error!("Packet is not ipv4 or ipv6");
-            None
+            }
         };
-
-        if ip_layer.is_none() {
-            error!("Failed to parse ip layer");
+        if decoder.dedup.is_duplicate(
+            frame.interface.as_ref(),
+            &ip_layer,
+            &tcp_layer,
+            frame.timestamp,
+        ) {
+            debug!("Dropping segment already seen on another interface");
             return None;
         }
 
-        let ip_layer = ip_layer.clone()?;
-        let tcp_pkt = TcpPacket::new(ip_layer.payload.as_slice());
-
-        if tcp_pkt.is_none() {
-            error!("Failed to parse tcp packet");
+        let (src_ip, dst_ip) = (ip_layer.src_ip, ip_layer.dst_ip);
+        let discovery = &mut decoder.discovery;
+        let request = discovery
+            .db_type(config, dst_ip, tcp_layer.dst_port)
+            .is_some();
+        if !request
+            && discovery
+                .db_type(config, src_ip, tcp_layer.src_port)
+                .is_none()
+            && !discovery.inspect(
+                src_ip,
+                tcp_layer.src_port,
+                &tcp_layer.payload,
+                frame.timestamp,
+            )
+        {
             return None;
         }
 
-        let tcp = tcp_pkt?.consume_to_immutable();
-
-        let tcp_layer = TcpLayer {
-            src_port: tcp.get_source(),
-            dst_port: tcp.get_destination(),
-            flags: tcp.get_flags(),
-            options: tcp.get_options(),
-            payload: tcp.payload().to_vec(),
-        };
-
-        let eth_layer = EthLayer {
-            src_mac: eth_pkt.get_source().to_string(),
-            dst_mac: eth_pkt.get_destination().to_string(),
-            eth_type: eth_pkt.get_ethertype(),
-        };
-
-        let request = config
-            .support_db
-            .contains_key(&tcp.get_destination().to_string());
-
-        let (db_type, sk) = if request {
-            let dt = config.support_db.get(&tcp.get_destination().to_string());
-            if dt.is_none() {
-                debug!("Failed to get db type port {:?}", &tcp.get_destination());
-                return None;
-            } else {
-                (
-                    dt?.to_string(),
-                    format!(
-                        "{}{}{}-{}{}{}",
-                        eth_layer.src_mac,
-                        ip_layer.src_ip,
-                        tcp_layer.src_port,
-                        eth_layer.dst_mac,
-                        ip_layer.dst_ip,
-                        tcp_layer.dst_port
-                    ),
-                )
-            }
-        } else {
-            let dt = config.support_db.get(&tcp.get_source().to_string());
-            if dt.is_none() {
-                debug!(
-                    "Failed to get db type port {:?}",
-                    &tcp.get_source().to_string()
-                );
+        let flow = FlowKey::new(
+            src_ip,
+            tcp_layer.src_port,
+            dst_ip,
+            tcp_layer.dst_port,
+            IPPROTO_TCP,
+            request,
+        );
+        let db = match discovery
+            .db_type(config, flow.server_ip, flow.server_port)
+            .and_then(|dt| DBType::from_str(dt).ok())
+        {
+            Some(db) => db,
+            None => {
+                debug!("Failed to get db type of {}", flow);
                 return None;
-            } else {
-                (
-                    dt?.to_string(),
-                    format!(
-                        "{}{}{}-{}{}{}",
-                        eth_layer.dst_mac,
-                        ip_layer.dst_ip,
-                        tcp_layer.dst_port,
-                        eth_layer.src_mac,
-                        ip_layer.src_ip,
-                        tcp_layer.src_port
-                    ),
-                )
             }
         };
 
         Some(SessionPacket {
             eth_layer,
-            ip_layer: ip_layer.clone(),
+            ip_layer,
             tcp_layer,
             request,
-            db: DBType::from_str(db_type.as_str()).unwrap(),
-            session_key: sk,
+            db,
+            flow,
+            tunnel,
+            frame,
         })
     }
 }
 
+// capability flags, max packet size, charset and filler
+const SSL_REQUEST_LEN: usize = 32;
+
 pub struct Session {
     session_ctx: SessionCtx,
     pkt_seq: u8,
     flow_packets: Vec<Box<dyn DBPacket>>,
+    packets: u64,
+    recording: Recording,
+    exchange: Exchange,
+    // the client switched to TLS, nothing after it can be decoded
+    encrypted: bool,
+    // not yet handed to the event sink
+    events: Vec<SessionEvent>,
 }
 
 impl PartialEq for SessionState {
@@ -299,11 +427,57 @@ impl Session {
             session_ctx,
             pkt_seq: 0,
             flow_packets: Vec::new(),
+            packets: 0,
+            recording: Recording::default(),
+            exchange: Exchange::default(),
+            encrypted: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn ctx(&self) -> &SessionCtx {
+        &self.session_ctx
+    }
+
+    /// Pcap file the session is exported to, if it was selected.
+    pub fn export_path(&self) -> Option<&Path> {
+        self.recording.path()
+    }
+
+    /// Number of packets accepted by this session.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Events seen since the previous call.
+    pub fn take_events(&mut self) -> Vec<SessionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn emit(&mut self, timestamp: Timestamp, kind: EventKind) {
+        self.events
+            .push(SessionEvent::new(timestamp, &self.session_ctx.flow, kind));
+    }
+
+    /// Client capabilities, the 4.1 protocol every supported server speaks
+    /// when the handshake was not seen.
+    fn caps(&self) -> u32 {
+        match self.session_ctx.client_cap {
+            0 => CLIENT_PROTOCOL_41,
+            caps => caps,
         }
     }
 
     pub async fn accept(&mut self, pkt: SessionPacket) {
-        // do something
+        self.packets += 1;
+        if let Some(interface) = &pkt.frame.interface {
+            self.session_ctx.add_interface(interface);
+        }
+        self.session_ctx.last_seen = pkt.frame.timestamp;
+        if self.encrypted {
+            return;
+        }
+        let timestamp = pkt.frame.timestamp;
         match pkt.db {
             DBType::MySQL => {
                 if pkt.request {
@@ -318,6 +492,48 @@ impl Session {
                         info!("got client handshake response");
                         self.session_ctx
                             .set_state(SessionState::ClientHandshakeResponse);
+                        // an SSLRequest stops after the filler, nothing more to read
+                        if req_pkt.get_len() as usize > SSL_REQUEST_LEN {
+                            let mut payload = vec![req_pkt.get_command().0];
+                            payload.extend(req_pkt.get_payload());
+                            if let Some(login) = Login::new(payload) {
+                                self.session_ctx.set_login(login);
+                                let ctx = &self.session_ctx;
+                                let attr = |key: &str| ctx.client_attrs.get(key).cloned();
+                                let event = EventKind::Login {
+                                    user: ctx.username.clone(),
+                                    database: ctx.database.clone(),
+                                    client_name: attr("_client_name"),
+                                    client_version: attr("_client_version"),
+                                };
+                                self.emit(timestamp, event);
+                                self.exchange.authenticating();
+                            }
+                        } else {
+                            self.encrypted = true;
+                            self.emit(timestamp, EventKind::TlsRequest);
+                        }
+                    }
+
+                    if !self.encrypted {
+                        let caps = self.caps();
+                        let (events, ctx) = (&mut self.events, &mut self.session_ctx);
+                        let flow = ctx.flow;
+                        self.exchange.request(&pkt.tcp_layer.payload, caps, |kind| {
+                            let quit = MySqlCommand::Quit.code();
+                            if matches!(kind, EventKind::Command { command } if command == quit) {
+                                ctx.set_state(SessionState::Logout);
+                            }
+                            if let EventKind::LocalInfile {
+                                filename,
+                                unsolicited: true,
+                                ..
+                            } = &kind
+                            {
+                                warn!("{} server read {} without LOAD DATA LOCAL", flow, filename);
+                            }
+                            events.push(SessionEvent::new(timestamp, &flow, kind))
+                        });
                     }
 
                     if req_pkt.get_seq() < self.pkt_seq {
@@ -334,7 +550,8 @@ impl Session {
 
                     if self.pkt_seq == 0 && resp_pkt.get_seq() == 0 {
                         info!("got server hello packet");
-                        match server::greeting::Greeting::new(resp_pkt.get_payload()) {
+                        // the response payload still carries the 4 byte packet header
+                        match server::greeting::Greeting::new(resp_pkt.get_payload()[4..].to_vec()) {
                             Some(greeting) => {
                                 info!("server greeting: {:?}", &greeting);
                                 self.session_ctx.set_state(SessionState::ServerGreeting);
@@ -356,6 +573,13 @@ impl Session {
                                     .set_auth_plugin_data_2(greeting.auth_plugin_data_2);
                                 self.session_ctx
                                     .set_auth_plugin_name(greeting.auth_plugin_name);
+                                let ctx = &self.session_ctx;
+                                let event = EventKind::Greeting {
+                                    server_version: ctx.server_version.clone(),
+                                    connection_id: ctx.connection_id,
+                                    auth_plugin: ctx.auth_plugin_name.clone(),
+                                };
+                                self.emit(timestamp, event);
                             }
                             None => {
                                 info!("failed to parse server greeting");
@@ -364,44 +588,134 @@ impl Session {
                         return;
                     }
 
+                    let caps = self.caps();
+                    let (events, ctx) = (&mut self.events, &mut self.session_ctx);
+                    self.exchange.response(&pkt.tcp_layer.payload, caps, |mut kind| {
+                        if let EventKind::Binlog {
+                            source_timestamp,
+                            log_pos,
+                            lag,
+                            ..
+                        } = &mut kind
+                        {
+                            if *log_pos > 0 {
+                                *lag = Some(timestamp.secs() as i64 - *source_timestamp as i64);
+                            }
+                        }
+                        // an OK, and the end of a result set, carry the session state
+                        if let EventKind::Ok {
+                            status_flags,
+                            session_state,
+                            ..
+                        }
+                        | EventKind::ResultSet {
+                            status_flags,
+                            session_state,
+                            ..
+                        } = &kind
+                        {
+                            ctx.set_session_state(*status_flags, session_state);
+                        }
+                        // the OK or ERR that ends the authentication
+                        if ctx.state == SessionState::ClientHandshakeResponse {
+                            match kind {
+                                EventKind::Ok { .. } => ctx.set_state(SessionState::Login),
+                                EventKind::Error { .. } => ctx.set_state(SessionState::Logout),
+                                _ => {}
+                            }
+                        }
+                        events.push(SessionEvent::new(timestamp, &ctx.flow, kind))
+                    });
                     self.flow_packets.push(Box::new(resp_pkt));
                 }
             }
-            _ => {}
+            DBType::Unknown => debug!("Skipping packet of an unknown database"),
         }
     }
+
+    /// Whether the session went idle, or is stuck in a handshake it began
+    /// too long ago, at `now`.
+    fn timed_out(&self, now: Timestamp, idle: Duration, handshake: Duration) -> bool {
+        let ctx = &self.session_ctx;
+        // the rest of a TLS handshake cannot be followed
+        let handshaking = !self.encrypted
+            && matches!(
+                ctx.state,
+                SessionState::ServerGreeting | SessionState::ClientHandshakeResponse
+            );
+        now.duration_since(ctx.last_seen) >= idle
+            || (handshaking && now.duration_since(ctx.first_seen) >= handshake)
+    }
     fn flush(&self) {
         // do something
     }
 }
 
+// timed out sessions are looked for at most this often
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts shared by the session manager shards, the limits of the
+/// configuration apply to their sum.
+#[derive(Debug, Default)]
+pub struct Totals {
+    // sessions tracked right now
+    pub sessions: AtomicUsize,
+    // sessions selected for export so far
+    pub exported: AtomicUsize,
+}
+
 pub struct SessionManager {
     config: Config,
-    rx: UnboundedReceiver<SessionPacket>,
+    rx: Receiver<SessionPacket>,
     state: bool,
-    sessions: HashMap<String, Session>,
+    sessions: HashMap<FlowKey, Session>,
+    totals: Arc<Totals>,
+    // capture time of the latest look for timed out sessions
+    expired_at: Timestamp,
+    // sessions dropped for timeouts or to make room for new ones
+    expired: u64,
+    limit_warned: bool,
+    exporter: Option<Exporter>,
+    sink: Option<Box<dyn EventSink>>,
 }
 
 impl SessionManager {
-    pub fn new(config: Config, rx: UnboundedReceiver<SessionPacket>) -> SessionManager {
+    pub fn new(config: Config, rx: Receiver<SessionPacket>, totals: Arc<Totals>) -> SessionManager {
         SessionManager {
+            exporter: Exporter::new(&config.export, totals.clone()),
             config,
             rx,
             state: false,
             sessions: HashMap::new(),
+            totals,
+            expired_at: Timestamp::default(),
+            expired: 0,
+            limit_warned: false,
+            sink: None,
         }
     }
 
+    /// Hand the events of every session to `sink`, they are dropped
+    /// otherwise.
+    pub fn set_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.sink = Some(sink);
+    }
+
     pub async fn run(&mut self) {
         self.state = true;
         loop {
             match self.rx.recv().await {
                 None => {
-                    error!("Executor channel closed");
+                    info!("Session channel closed, {} sessions", self.sessions.len());
+                    break;
                 }
                 Some(session_pkt) => {
-                    if !self.check_session(&session_pkt.session_key) {
-                        self.create_session(session_pkt.clone());
+                    self.expire(session_pkt.frame.timestamp);
+                    if !self.check_session(&session_pkt.flow) {
+                        if !self.make_room(&session_pkt.flow) {
+                            continue;
+                        }
+                        self.create_session(&session_pkt);
                     }
 
                    match   self.parse_session_pkt(session_pkt).await {
@@ -415,33 +729,95 @@ impl SessionManager {
                 }
             }
         }
+        self.state = false;
     }
 
-    fn check_session(&self, session_key: &str) -> bool {
-        self.sessions.contains_key(session_key)
+    fn check_session(&self, flow: &FlowKey) -> bool {
+        self.sessions.contains_key(flow)
     }
 
-    fn get_session(&mut self, session_key: &str) -> Option<&mut Session> {
-        self.sessions.get_mut(session_key)
+    fn create_session(&mut self, sess_pkt: &SessionPacket) {
+        let sctx = self.create_session_ctx(sess_pkt);
+        let session = Session::new(sctx);
+        self.sessions.insert(sess_pkt.flow, session);
+        self.totals.sessions.fetch_add(1, Ordering::Relaxed);
     }
 
-    fn create_session(&mut self, sess_pkt: SessionPacket) {
-        let sctx = self.create_session_ctx(sess_pkt.clone());
-        let session = Session::new(sctx);
-        self.sessions
-            .insert(sess_pkt.session_key.to_string(), session);
+    fn remove_session(&mut self, flow: &FlowKey) -> Option<Session> {
+        let session = self.sessions.remove(flow)?;
+        self.totals.sessions.fetch_sub(1, Ordering::Relaxed);
+        self.expired += 1;
+        Some(session)
+    }
+
+    /// Drop the sessions idle for `session.idle_timeout_secs`, or still in
+    /// a handshake `session.handshake_timeout_secs` after it began.
+    fn expire(&mut self, now: Timestamp) {
+        if now.duration_since(self.expired_at) < EXPIRE_INTERVAL {
+            return;
+        }
+        self.expired_at = now;
+        let idle = Duration::from_secs(self.config.session.idle_timeout_secs);
+        let handshake = Duration::from_secs(self.config.session.handshake_timeout_secs);
+        let expired: Vec<FlowKey> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.timed_out(now, idle, handshake))
+            .map(|(k, _)| *k)
+            .collect();
+        for flow in expired {
+            debug!("Session {} timed out", flow);
+            self.remove_session(&flow);
+        }
     }
 
-    fn create_session_ctx(&self, sp: SessionPacket) -> SessionCtx {
+    /// Keep the sessions of all shards within `session.max_sessions` by
+    /// dropping the least recently seen one of this shard. False if this
+    /// shard has none, `flow` is not tracked then.
+    fn make_room(&mut self, flow: &FlowKey) -> bool {
+        let max_sessions = self.config.session.max_sessions;
+        if self.totals.sessions.load(Ordering::Relaxed) < max_sessions {
+            return true;
+        }
+        let idlest = self
+            .sessions
+            .iter()
+            .min_by_key(|(_, s)| s.session_ctx.last_seen)
+            .map(|(k, _)| *k);
+        match idlest {
+            Some(idlest) => {
+                debug!("Dropping session {} for {}", idlest, flow);
+                self.remove_session(&idlest);
+                true
+            }
+            None => {
+                if !self.limit_warned {
+                    warn!(
+                        "Not tracking {}, {} sessions already tracked",
+                        flow, max_sessions
+                    );
+                    self.limit_warned = true;
+                }
+                false
+            }
+        }
+    }
+
+    fn create_session_ctx(&self, sp: &SessionPacket) -> SessionCtx {
         SessionCtx {
             state: SessionState::Unknown,
-            src_ip: sp.ip_layer.src_ip.clone(),
-            dst_ip: sp.ip_layer.dst_ip.clone(),
+            flow: sp.flow,
+            src_ip: sp.ip_layer.src_ip,
+            dst_ip: sp.ip_layer.dst_ip,
             src_port: sp.tcp_layer.src_port,
             dst_port: sp.tcp_layer.dst_port,
-            src_mac: sp.eth_layer.src_mac.clone(),
-            dst_mac: sp.eth_layer.dst_mac.clone(),
+            src_mac: link::mac_to_string(&sp.eth_layer.src_mac),
+            dst_mac: link::mac_to_string(&sp.eth_layer.dst_mac),
             db_type: sp.db.to_string(),
+            tunnel: sp.tunnel,
+            interfaces: Vec::new(),
+            first_seen: sp.frame.timestamp,
+            last_seen: sp.frame.timestamp,
             server_cap: 0,
             client_cap: 0,
             server_status: 0,
@@ -450,18 +826,36 @@ impl SessionManager {
             client_language: 0,
             server_version: "".to_string(),
             client_version: "".to_string(),
+            connection_id: 0,
+            auth_plugin_name: "".to_string(),
+            username: None,
+            database: None,
+            client_attrs: HashMap::new(),
+            autocommit: None,
+            in_transaction: None,
+            isolation_level: None,
+            character_set: None,
         }
     }
 
     async fn parse_session_pkt(&mut self, pkt: SessionPacket) -> Result<(), Box<dyn Error>> {
-        let session = self.get_session(&pkt.session_key);
+        let session = self.sessions.get_mut(&pkt.flow);
         match session {
             None => {
-                let err = format!("Session not found: {}", pkt.session_key);
+                let err = format!("Session not found: {}", pkt.flow);
                 Err(err.into())
             }
             Some(session) => {
+                let frame = self.exporter.as_ref().map(|_| pkt.frame.clone());
                 session.accept(pkt).await;
+                for event in session.take_events() {
+                    if let Some(sink) = &mut self.sink {
+                        sink.emit(event);
+                    }
+                }
+                if let (Some(exporter), Some(frame)) = (&mut self.exporter, frame) {
+                    exporter.record(&session.session_ctx, &mut session.recording, frame);
+                }
                 Ok(())
             }
         }
@@ -470,4 +864,151 @@ impl SessionManager {
     pub fn is_running(&self) -> bool {
         self.state
     }
+
+    /// Sessions dropped because they timed out or to stay within
+    /// `session.max_sessions`.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = (&FlowKey, &Session)> {
+        self.sessions.iter()
+    }
+
+    /// Sessions written to pcap files.
+    pub fn exported(&self) -> usize {
+        self.exporter.as_ref().map_or(0, |e| e.exported())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pnet_packet::ethernet::EtherTypes;
+
+    /// A packet from the client on `port` to the MySQL server, captured at
+    /// `secs`.
+    fn packet(port: u16, secs: u64) -> SessionPacket {
+        let ip_layer = IpLayer {
+            src_ip: "10.0.0.1".parse().unwrap(),
+            dst_ip: "10.0.0.2".parse().unwrap(),
+            protocol: IPPROTO_TCP,
+            reassembled: false,
+            payload: Bytes::new(),
+        };
+        SessionPacket {
+            eth_layer: EthLayer {
+                src_mac: Bytes::from_static(&[0, 0, 0, 0, 0, 1]),
+                dst_mac: Bytes::from_static(&[0, 0, 0, 0, 0, 2]),
+                eth_type: EtherTypes::Ipv4,
+                vlans: Vec::new(),
+            },
+            flow: FlowKey::new(
+                ip_layer.src_ip,
+                port,
+                ip_layer.dst_ip,
+                3306,
+                IPPROTO_TCP,
+                true,
+            ),
+            ip_layer,
+            tcp_layer: TcpLayer {
+                src_port: port,
+                dst_port: 3306,
+                seq: 0,
+                flags: 0,
+                options: Bytes::new(),
+                payload: Bytes::new(),
+            },
+            request: true,
+            db: DBType::MySQL,
+            tunnel: None,
+            frame: CapturedFrame {
+                timestamp: Timestamp::new(secs, 0),
+                interface: None,
+                link_type: LinkType::Ethernet,
+                orig_len: 0,
+                data: Bytes::new(),
+            },
+        }
+    }
+
+    fn manager(config: &Config, totals: Arc<Totals>) -> SessionManager {
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        SessionManager::new(config.clone(), rx, totals)
+    }
+
+    #[test]
+    fn test_session_timeouts() {
+        let mut config = Config::default();
+        config.session.idle_timeout_secs = 60;
+        config.session.handshake_timeout_secs = 30;
+        let mut sm = manager(&config, Arc::default());
+        let (handshake, idle) = (packet(50000, 0), packet(50001, 0));
+        sm.create_session(&handshake);
+        sm.create_session(&idle);
+        sm.sessions
+            .get_mut(&handshake.flow)
+            .unwrap()
+            .session_ctx
+            .set_state(SessionState::ServerGreeting);
+
+        sm.expire(Timestamp::new(29, 0));
+        assert_eq!(sm.sessions.len(), 2);
+        // the handshake began too long ago, the other session is just idle
+        sm.expire(Timestamp::new(30, 0));
+        assert!(!sm.check_session(&handshake.flow));
+        assert!(sm.check_session(&idle.flow));
+        sm.expire(Timestamp::new(60, 0));
+        assert!(sm.sessions.is_empty());
+        assert_eq!(sm.expired(), 2);
+        assert_eq!(sm.totals.sessions.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_max_sessions() {
+        let mut config = Config::default();
+        config.session.max_sessions = 2;
+        let totals = Arc::new(Totals::default());
+        let mut sm = manager(&config, totals.clone());
+        let packets: Vec<SessionPacket> = (0..3).map(|i| packet(50000 + i, i as u64)).collect();
+        for pkt in &packets {
+            assert!(sm.make_room(&pkt.flow));
+            sm.create_session(pkt);
+        }
+        // the least recently seen session made room for the third
+        assert!(!sm.check_session(&packets[0].flow));
+        assert_eq!(totals.sessions.load(Ordering::Relaxed), 2);
+        assert_eq!(sm.expired(), 1);
+
+        // the limit applies to all shards, one without sessions of its own
+        // cannot make room
+        let mut other = manager(&config, totals);
+        assert!(!other.make_room(&packets[0].flow));
+    }
+
+    #[test]
+    fn test_transaction_characteristics() {
+        let config = Config::default();
+        let sm = manager(&config, Arc::default());
+        let mut ctx = sm.create_session_ctx(&packet(50000, 0));
+        let characteristics = |characteristics: &str| SessionChange::TransactionCharacteristics {
+            characteristics: characteristics.to_string(),
+        };
+
+        let set = characteristics("SET TRANSACTION ISOLATION LEVEL READ COMMITTED;");
+        ctx.set_session_state(SERVER_STATUS_AUTOCOMMIT as u16, &[set]);
+        assert_eq!(ctx.isolation_level.as_deref(), Some("READ-COMMITTED"));
+        // no level in the statements, the previous one still applies
+        let start = characteristics("START TRANSACTION READ ONLY;");
+        ctx.set_session_state(SERVER_STATUS_IN_TRANS as u16, &[start]);
+        assert_eq!(ctx.isolation_level.as_deref(), Some("READ-COMMITTED"));
+        assert_eq!(ctx.in_transaction, Some(true));
+
+        assert_eq!(
+            isolation_level("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE, READ WRITE;").as_deref(),
+            Some("SERIALIZABLE")
+        );
+        assert_eq!(isolation_level(""), None);
+    }
 }
@@ -0,0 +1,289 @@
+use bytes::Bytes;
+use pnet_packet::ethernet::{EtherType, EtherTypes};
+
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+pub const ETHERTYPE_QINQ: u16 = 0x88a8;
+pub const ETHERTYPE_QINQ_OLD: u16 = 0x9100;
+
+// 802.1Q tags stacked deeper than this are treated as garbage
+const MAX_VLAN_DEPTH: usize = 4;
+
+const SLL_HEADER_LEN: usize = 16;
+const SLL2_HEADER_LEN: usize = 20;
+
+// address families used by the BSD loopback header
+const AF_INET: u32 = 2;
+const AF_INET6_LINUX: u32 = 10;
+const AF_INET6_BSD: u32 = 24;
+const AF_INET6_FREEBSD: u32 = 28;
+const AF_INET6_DARWIN: u32 = 30;
+
+// Linux hardware types, ARPHRD_*, as in /sys/class/net/<interface>/type
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_PPP: u16 = 512;
+const ARPHRD_RAWIP: u16 = 519;
+const ARPHRD_TUNNEL: u16 = 768;
+const ARPHRD_TUNNEL6: u16 = 769;
+const ARPHRD_LOOPBACK: u16 = 772;
+const ARPHRD_SIT: u16 = 776;
+const ARPHRD_NONE: u16 = 65534;
+
+/// Link layer of a capture source, from the pcap/DLT link type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    LinuxSll,
+    LinuxSll2,
+    // BSD loopback, family in host byte order
+    Null,
+    // OpenBSD loopback, family in network byte order
+    Loop,
+    Raw,
+}
+
+impl LinkType {
+    pub fn from_pcap(link_type: u32) -> Option<LinkType> {
+        match link_type {
+            pcap::LINKTYPE_ETHERNET => Some(LinkType::Ethernet),
+            pcap::LINKTYPE_LINUX_SLL => Some(LinkType::LinuxSll),
+            pcap::LINKTYPE_LINUX_SLL2 => Some(LinkType::LinuxSll2),
+            pcap::LINKTYPE_NULL => Some(LinkType::Null),
+            pcap::LINKTYPE_LOOP => Some(LinkType::Loop),
+            pcap::LINKTYPE_RAW | pcap::LINKTYPE_IPV4 | pcap::LINKTYPE_IPV6 => Some(LinkType::Raw),
+            _ => None,
+        }
+    }
+
+    /// Link type of the frames an AF_PACKET raw socket reads on a Linux
+    /// interface of hardware type `hatype`. The loopback fakes an Ethernet
+    /// header, tun, IP tunnels and PPP deliver bare IP packets.
+    pub fn from_arphrd(hatype: u16) -> Option<LinkType> {
+        match hatype {
+            ARPHRD_ETHER | ARPHRD_LOOPBACK => Some(LinkType::Ethernet),
+            ARPHRD_PPP | ARPHRD_RAWIP | ARPHRD_TUNNEL | ARPHRD_TUNNEL6 | ARPHRD_SIT
+            | ARPHRD_NONE => Some(LinkType::Raw),
+            _ => None,
+        }
+    }
+
+    /// Link type to write to a pcap header.
+    pub fn to_pcap(self) -> u32 {
+        match self {
+            LinkType::Ethernet => pcap::LINKTYPE_ETHERNET,
+            LinkType::LinuxSll => pcap::LINKTYPE_LINUX_SLL,
+            LinkType::LinuxSll2 => pcap::LINKTYPE_LINUX_SLL2,
+            LinkType::Null => pcap::LINKTYPE_NULL,
+            LinkType::Loop => pcap::LINKTYPE_LOOP,
+            LinkType::Raw => pcap::LINKTYPE_RAW,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EthLayer {
+    // raw addresses, empty when the link layer carries no such address
+    pub src_mac: Bytes,
+    pub dst_mac: Bytes,
+    pub eth_type: EtherType,
+    // outermost tag first
+    pub vlans: Vec<u16>,
+}
+
+/// Format a hardware address as `aa:bb:cc:dd:ee:ff`.
+pub fn mac_to_string(mac: &[u8]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+fn be16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+/// Strip the 802.1Q/802.1ad tags announced by `ether_type`, `pos` is where
+/// the first tag would start.
+fn strip_tags(
+    mut ether_type: u16,
+    data: &[u8],
+    mut pos: usize,
+    vlans: &mut Vec<u16>,
+) -> Option<(EtherType, usize)> {
+    while matches!(
+        ether_type,
+        ETHERTYPE_VLAN | ETHERTYPE_QINQ | ETHERTYPE_QINQ_OLD
+    ) {
+        if vlans.len() >= MAX_VLAN_DEPTH {
+            return None;
+        }
+        vlans.push(be16(data, pos)? & 0x0fff);
+        ether_type = be16(data, pos + 2)?;
+        pos += 4;
+    }
+    Some((EtherType(ether_type), pos))
+}
+
+fn ether_type_of_family(family: u32) -> Option<EtherType> {
+    match family {
+        AF_INET => Some(EtherTypes::Ipv4),
+        AF_INET6_LINUX | AF_INET6_BSD | AF_INET6_FREEBSD | AF_INET6_DARWIN => {
+            Some(EtherTypes::Ipv6)
+        }
+        _ => None,
+    }
+}
+
+/// Decode the link layer of `data`, returning it with the offset of the
+/// network layer header.
+pub fn decode(link_type: LinkType, data: &Bytes) -> Option<(EthLayer, usize)> {
+    let mut vlans = Vec::new();
+    let (src_mac, dst_mac, eth_type, offset) = match link_type {
+        LinkType::Ethernet => {
+            if data.len() < 14 {
+                return None;
+            }
+            let (eth_type, offset) = strip_tags(be16(data, 12)?, data, 14, &mut vlans)?;
+            (data.slice(6..12), data.slice(0..6), eth_type, offset)
+        }
+        LinkType::LinuxSll => {
+            if data.len() < SLL_HEADER_LEN {
+                return None;
+            }
+            let addr_len = (be16(data, 4)? as usize).min(8);
+            let (eth_type, offset) = strip_tags(be16(data, 14)?, data, SLL_HEADER_LEN, &mut vlans)?;
+            (data.slice(6..6 + addr_len), Bytes::new(), eth_type, offset)
+        }
+        LinkType::LinuxSll2 => {
+            if data.len() < SLL2_HEADER_LEN {
+                return None;
+            }
+            let addr_len = (data[11] as usize).min(8);
+            // the protocol comes first, tags follow the fixed header
+            let (eth_type, offset) = strip_tags(be16(data, 0)?, data, SLL2_HEADER_LEN, &mut vlans)?;
+            (
+                data.slice(12..12 + addr_len),
+                Bytes::new(),
+                eth_type,
+                offset,
+            )
+        }
+        LinkType::Null | LinkType::Loop => {
+            let header: [u8; 4] = data.get(0..4)?.try_into().ok()?;
+            let family = if link_type == LinkType::Loop {
+                u32::from_be_bytes(header)
+            } else {
+                // host byte order of the capturing machine, try both
+                let le = u32::from_le_bytes(header);
+                if ether_type_of_family(le).is_some() {
+                    le
+                } else {
+                    u32::from_be_bytes(header)
+                }
+            };
+            (Bytes::new(), Bytes::new(), ether_type_of_family(family)?, 4)
+        }
+        LinkType::Raw => {
+            let eth_type = match data.first()? >> 4 {
+                4 => EtherTypes::Ipv4,
+                6 => EtherTypes::Ipv6,
+                _ => return None,
+            };
+            (Bytes::new(), Bytes::new(), eth_type, 0)
+        }
+    };
+    Some((
+        EthLayer {
+            src_mac,
+            dst_mac,
+            eth_type,
+            vlans,
+        },
+        offset,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IPV4: [u8; 2] = [0x45, 0x00];
+
+    #[test]
+    fn test_ethernet_qinq() {
+        let mut frame = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+        frame.extend_from_slice(&[0x88, 0xa8, 0x00, 0x64, 0x81, 0x00, 0x20, 0x0a, 0x08, 0x00]);
+        frame.extend_from_slice(&IPV4);
+        let frame = Bytes::from(frame);
+        let (eth, offset) = decode(LinkType::Ethernet, &frame).unwrap();
+        assert_eq!(eth.eth_type, EtherTypes::Ipv4);
+        assert_eq!(eth.vlans, vec![100, 10]);
+        assert_eq!(mac_to_string(&eth.src_mac), "06:07:08:09:0a:0b");
+        assert_eq!(&frame[offset..], &IPV4);
+
+        // truncated inside a tag
+        assert!(decode(LinkType::Ethernet, &frame.slice(..16)).is_none());
+    }
+
+    #[test]
+    fn test_linux_sll() {
+        let mut frame = vec![0x00, 0x00, 0x00, 0x01, 0x00, 0x06];
+        frame.extend_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x00, 0x00]);
+        frame.extend_from_slice(&[0x86, 0xdd]);
+        frame.extend_from_slice(&[0x60, 0x00]);
+        let (eth, offset) = decode(LinkType::LinuxSll, &frame.into()).unwrap();
+        assert_eq!(eth.eth_type, EtherTypes::Ipv6);
+        assert_eq!(mac_to_string(&eth.src_mac), "aa:bb:cc:dd:ee:ff");
+        assert_eq!(offset, 16);
+    }
+
+    #[test]
+    fn test_linux_sll2() {
+        let mut frame = vec![0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
+        frame.extend_from_slice(&[0x00, 0x01, 0x00, 0x06]);
+        frame.extend_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x00, 0x00]);
+        frame.extend_from_slice(&[0x00, 0x07, 0x08, 0x00]);
+        frame.extend_from_slice(&IPV4);
+        let frame = Bytes::from(frame);
+        let (eth, offset) = decode(LinkType::LinuxSll2, &frame).unwrap();
+        assert_eq!(eth.eth_type, EtherTypes::Ipv4);
+        assert_eq!(eth.vlans, vec![7]);
+        assert_eq!(&frame[offset..], &IPV4);
+    }
+
+    #[test]
+    fn test_loopback_and_raw() {
+        let mut frame = AF_INET.to_le_bytes().to_vec();
+        frame.extend_from_slice(&IPV4);
+        let (eth, offset) = decode(LinkType::Null, &frame.into()).unwrap();
+        assert_eq!(eth.eth_type, EtherTypes::Ipv4);
+        assert_eq!(offset, 4);
+
+        let mut frame = AF_INET6_DARWIN.to_be_bytes().to_vec();
+        frame.extend_from_slice(&[0x60, 0x00]);
+        let (eth, _) = decode(LinkType::Loop, &frame.into()).unwrap();
+        assert_eq!(eth.eth_type, EtherTypes::Ipv6);
+
+        let (eth, offset) = decode(LinkType::Raw, &Bytes::from_static(&IPV4)).unwrap();
+        assert_eq!(eth.eth_type, EtherTypes::Ipv4);
+        assert_eq!(offset, 0);
+        assert!(decode(LinkType::Raw, &Bytes::from_static(&[0x10])).is_none());
+    }
+
+    #[test]
+    fn test_from_arphrd() {
+        assert_eq!(
+            LinkType::from_arphrd(ARPHRD_ETHER),
+            Some(LinkType::Ethernet)
+        );
+        assert_eq!(
+            LinkType::from_arphrd(ARPHRD_LOOPBACK),
+            Some(LinkType::Ethernet)
+        );
+        // tun0, ipip, sit and ppp0
+        for hatype in [ARPHRD_NONE, ARPHRD_TUNNEL, ARPHRD_SIT, ARPHRD_PPP] {
+            assert_eq!(LinkType::from_arphrd(hatype), Some(LinkType::Raw));
+        }
+        // 802.11 radiotap
+        assert_eq!(LinkType::from_arphrd(803), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! Just enough of MySQL's lexical structure to split a multi-statement
+//! COM_QUERY into its statements: strings, quoted identifiers, comments and
+//! the BEGIN ... END bodies of stored programs.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Semicolon,
+    // strings, quoted identifiers, operators and anything else
+    Other,
+}
+
+/// Tokens of `sql` with their byte ranges, comments and whitespace left out.
+struct Lexer<'a> {
+    sql: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(sql: &'a str) -> Self {
+        Lexer { sql, pos: 0 }
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.sql.as_bytes().get(self.pos + offset).copied()
+    }
+
+    // up to and past `end`, or to the end of the input
+    fn skip_past(&mut self, end: &str) {
+        self.pos = match self.sql[self.pos..].find(end) {
+            Some(i) => self.pos + i + end.len(),
+            None => self.sql.len(),
+        };
+    }
+
+    // the rest of a string or quoted identifier opened by `quote`, a quote
+    // is escaped by doubling it or, in strings, by a backslash
+    fn skip_quoted(&mut self, quote: u8) {
+        while let Some(c) = self.peek(0) {
+            self.pos += 1;
+            if c == b'\\' && quote != b'`' {
+                self.pos = (self.pos + 1).min(self.sql.len());
+            } else if c == quote {
+                if self.peek(0) != Some(quote) {
+                    return;
+                }
+                self.pos += 1;
+            }
+        }
+    }
+}
+
+fn word_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$'
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = (Token<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let c = self.peek(0)?;
+            let start = self.pos;
+            self.pos += 1;
+            let token = match c {
+                c if c.is_ascii_whitespace() => continue,
+                b'#' => {
+                    self.skip_past("\n");
+                    continue;
+                }
+                // `--` only starts a comment before whitespace
+                b'-' if self.peek(0) == Some(b'-')
+                    && self.peek(1).is_none_or(|c| c.is_ascii_whitespace()) =>
+                {
+                    self.skip_past("\n");
+                    continue;
+                }
+                b'/' if self.peek(0) == Some(b'*') => {
+                    self.pos += 1;
+                    self.skip_past("*/");
+                    continue;
+                }
+                b';' => Token::Semicolon,
+                b'\'' | b'"' | b'`' => {
+                    self.skip_quoted(c);
+                    Token::Other
+                }
+                c if word_byte(c) => {
+                    while self.peek(0).is_some_and(word_byte) {
+                        self.pos += 1;
+                    }
+                    Token::Word(&self.sql[start..self.pos])
+                }
+                _ => Token::Other,
+            };
+            return Some((token, start..self.pos));
+        }
+    }
+}
+
+/// The statements of `sql`, split at the semicolons outside of strings,
+/// comments and the bodies of CREATE PROCEDURE and the like. Statements are
+/// trimmed, empty ones left out.
+pub(crate) fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    // the first word of the statement, and how deep in BEGIN ... END it is
+    let mut first = None;
+    let mut depth = 0u32;
+    let mut after_end = false;
+    for (token, range) in Lexer::new(sql) {
+        let word = match token {
+            Token::Word(word) => Some(word.to_ascii_uppercase()),
+            _ => None,
+        };
+        if after_end {
+            after_end = false;
+            // END IF and the like close blocks that were never counted
+            match word.as_deref() {
+                Some("IF" | "LOOP" | "WHILE" | "REPEAT") => continue,
+                Some("CASE") => {
+                    depth = depth.saturating_sub(1);
+                    continue;
+                }
+                _ => depth = depth.saturating_sub(1),
+            }
+        }
+        match (token, word) {
+            (Token::Semicolon, _) if depth == 0 => {
+                let statement = sql[start..range.start].trim();
+                if !statement.is_empty() {
+                    statements.push(statement);
+                }
+                start = range.end;
+                first = None;
+            }
+            (_, Some(word)) if first.is_none() => first = Some(word),
+            (_, Some(word)) if first.as_deref() == Some("CREATE") => match word.as_str() {
+                "BEGIN" | "CASE" => depth += 1,
+                "END" => after_end = true,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    let statement = sql[start..].trim();
+    if !statement.is_empty() {
+        statements.push(statement);
+    }
+    statements
+}
+
+/// The first keyword of a statement, upper case, comments skipped.
+pub(crate) fn keyword(statement: &str) -> Option<String> {
+    match Lexer::new(statement).next()? {
+        (Token::Word(word), _) => Some(word.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+/// Whether a statement is a LOAD DATA or LOAD XML with LOCAL, the only
+/// statements a server may answer with a LOCAL INFILE request.
+pub(crate) fn load_local(statement: &str) -> bool {
+    let mut words = Lexer::new(statement).map_while(|(token, _)| match token {
+        Token::Word(word) => Some(word.to_ascii_uppercase()),
+        _ => None,
+    });
+    words.next().as_deref() == Some("LOAD")
+        && matches!(words.next().as_deref(), Some("DATA" | "XML"))
+        && words
+            .take_while(|word| word != "INFILE")
+            .any(|word| word == "LOCAL")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("select 1; select 2;\n  update t set a = 1 ;"),
+            ["select 1", "select 2", "update t set a = 1"]
+        );
+        assert_eq!(
+            split_statements(
+                "select ';', \"a\\\";\", `x;``y`; -- not; here\nselect 2 # nor; here\n; /* ; */ select 3"
+            ),
+            [
+                "select ';', \"a\\\";\", `x;``y`",
+                "-- not; here\nselect 2 # nor; here",
+                "/* ; */ select 3"
+            ]
+        );
+        // a minus sign and not a comment
+        assert_eq!(
+            split_statements("select 1--1; select 2"),
+            ["select 1--1", "select 2"]
+        );
+        assert_eq!(split_statements(" ; ;"), Vec::<&str>::new());
+        assert_eq!(
+            split_statements("select 'open; select 2"),
+            ["select 'open; select 2"]
+        );
+
+        let create = "create procedure p() begin \
+            if 1 then select case when 1 then 2 end; end if; \
+            case 1 when 1 then select 3; end case; \
+            begin select 4; end; end";
+        assert_eq!(
+            split_statements(&format!("{}; call p()", create)),
+            [create, "call p()"]
+        );
+    }
+
+    #[test]
+    fn test_keyword() {
+        assert_eq!(keyword("/* hint */ Call p()").as_deref(), Some("CALL"));
+        assert_eq!(keyword("-- x\nselect 1").as_deref(), Some("SELECT"));
+        assert_eq!(keyword("(select 1)"), None);
+    }
+
+    #[test]
+    fn test_load_local() {
+        assert!(load_local(
+            "LOAD DATA LOW_PRIORITY LOCAL INFILE '/tmp/a.csv' INTO TABLE t"
+        ));
+        assert!(load_local(
+            "/* x */ load xml local infile 'a.xml' into table t"
+        ));
+        assert!(!load_local("load data infile '/tmp/a.csv' into table t"));
+        assert!(!load_local("select 'load data local infile'"));
+        assert!(!load_local("load data infile 'local' into table t"));
+    }
+}
@@ -0,0 +1,29 @@
+//! Frames of a MySQL connection for the unit tests, benchmarks and fuzz
+//! targets.
+
+/// Client end of the connection built by [`tcp_frame`].
+pub const CLIENT: ([u8; 4], u16) = ([10, 0, 0, 1], 50000);
+/// Server end of the connection built by [`tcp_frame`].
+pub const SERVER: ([u8; 4], u16) = ([10, 0, 0, 2], 3306);
+
+/// Ethernet, IPv4 and TCP headers around `payload`, sent from [`CLIENT`]
+/// to [`SERVER`] or back with sequence number `seq`.
+pub fn tcp_frame(from_client: bool, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let ((src, sport), (dst, dport)) = if from_client {
+        (CLIENT, SERVER)
+    } else {
+        (SERVER, CLIENT)
+    };
+    let mut data = vec![0, 1, 2, 3, 4, dst[3], 0, 1, 2, 3, 4, src[3], 0x08, 0x00];
+    data.extend_from_slice(&[0x45, 0]);
+    data.extend_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+    data.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+    data.extend_from_slice(&src);
+    data.extend_from_slice(&dst);
+    data.extend_from_slice(&sport.to_be_bytes());
+    data.extend_from_slice(&dport.to_be_bytes());
+    data.extend_from_slice(&seq.to_be_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+    data.extend_from_slice(payload);
+    data
+}
@@ -0,0 +1,267 @@
+use crate::ip::IpLayer;
+use crate::link::LinkType;
+use config::TunnelConfig;
+use std::fmt;
+
+pub const IPPROTO_UDP: u8 = 17;
+pub const IPPROTO_GRE: u8 = 47;
+
+// GRE protocol types
+const GRE_TEB: u16 = 0x6558;
+const GRE_IPV4: u16 = 0x0800;
+const GRE_IPV6: u16 = 0x86dd;
+const GRE_ERSPAN_II: u16 = 0x88be;
+const GRE_ERSPAN_III: u16 = 0x22eb;
+
+const GRE_CHECKSUM: u16 = 0x8000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQ: u16 = 0x1000;
+
+const VXLAN_VNI_VALID: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelKind {
+    Vxlan,
+    Geneve,
+    Gre,
+    Erspan,
+}
+
+/// Outermost tunnel a packet was decapsulated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tunnel {
+    pub kind: TunnelKind,
+    // VNI for VXLAN/GENEVE, key for GRE, session id for ERSPAN
+    pub id: Option<u32>,
+}
+
+impl fmt::Display for Tunnel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            TunnelKind::Vxlan => "vxlan",
+            TunnelKind::Geneve => "geneve",
+            TunnelKind::Gre => "gre",
+            TunnelKind::Erspan => "erspan",
+        };
+        match self.id {
+            Some(id) => write!(f, "{}:{}", kind, id),
+            None => write!(f, "{}", kind),
+        }
+    }
+}
+
+/// Peels VXLAN, GENEVE and GRE/ERSPAN encapsulation off mirrored traffic.
+pub struct Decapsulator {
+    conf: TunnelConfig,
+}
+
+impl Decapsulator {
+    pub fn new(conf: &TunnelConfig) -> Decapsulator {
+        Decapsulator { conf: conf.clone() }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        if self.conf.enabled {
+            self.conf.max_depth
+        } else {
+            0
+        }
+    }
+
+    /// The tunnel carried by `ip` and the inner frame, `None` if it is not
+    /// tunnelled traffic.
+    pub fn decap<'a>(&self, ip: &'a IpLayer) -> Option<(Tunnel, LinkType, &'a [u8])> {
+        if !self.conf.enabled {
+            return None;
+        }
+        match ip.protocol {
+            IPPROTO_UDP => self.decap_udp(&ip.payload),
+            IPPROTO_GRE if self.conf.gre => decap_gre(&ip.payload),
+            _ => None,
+        }
+    }
+
+    fn decap_udp<'a>(&self, data: &'a [u8]) -> Option<(Tunnel, LinkType, &'a [u8])> {
+        let udp = data.get(..8)?;
+        let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+        let data = &data[8..];
+        if self.conf.vxlan_ports.contains(&dst_port) {
+            decap_vxlan(data)
+        } else if self.conf.geneve_ports.contains(&dst_port) {
+            decap_geneve(data)
+        } else {
+            None
+        }
+    }
+}
+
+fn decap_vxlan(data: &[u8]) -> Option<(Tunnel, LinkType, &[u8])> {
+    let header = data.get(..8)?;
+    let id = if header[0] & VXLAN_VNI_VALID != 0 {
+        Some(u32::from_be_bytes([0, header[4], header[5], header[6]]))
+    } else {
+        None
+    };
+    let tunnel = Tunnel {
+        kind: TunnelKind::Vxlan,
+        id,
+    };
+    Some((tunnel, LinkType::Ethernet, &data[8..]))
+}
+
+fn decap_geneve(data: &[u8]) -> Option<(Tunnel, LinkType, &[u8])> {
+    let header = data.get(..8)?;
+    // only version 0 is defined
+    if header[0] >> 6 != 0 {
+        return None;
+    }
+    let len = 8 + (header[0] & 0x3f) as usize * 4;
+    let link_type = match u16::from_be_bytes([header[2], header[3]]) {
+        GRE_TEB => LinkType::Ethernet,
+        GRE_IPV4 | GRE_IPV6 => LinkType::Raw,
+        _ => return None,
+    };
+    let tunnel = Tunnel {
+        kind: TunnelKind::Geneve,
+        id: Some(u32::from_be_bytes([0, header[4], header[5], header[6]])),
+    };
+    Some((tunnel, link_type, data.get(len..)?))
+}
+
+fn decap_gre(data: &[u8]) -> Option<(Tunnel, LinkType, &[u8])> {
+    let header = data.get(..4)?;
+    let flags = u16::from_be_bytes([header[0], header[1]]);
+    // version 1 is PPTP, never a mirror
+    if flags & 0x7 != 0 {
+        return None;
+    }
+    let protocol = u16::from_be_bytes([header[2], header[3]]);
+    let mut pos = 4;
+    if flags & GRE_CHECKSUM != 0 {
+        pos += 4;
+    }
+    let mut key = None;
+    if flags & GRE_KEY != 0 {
+        let k = data.get(pos..pos + 4)?;
+        key = Some(u32::from_be_bytes([k[0], k[1], k[2], k[3]]));
+        pos += 4;
+    }
+    let seq = flags & GRE_SEQ != 0;
+    if seq {
+        pos += 4;
+    }
+    let data = data.get(pos..)?;
+
+    let gre = Tunnel {
+        kind: TunnelKind::Gre,
+        id: key,
+    };
+    match protocol {
+        GRE_TEB => Some((gre, LinkType::Ethernet, data)),
+        GRE_IPV4 | GRE_IPV6 => Some((gre, LinkType::Raw, data)),
+        // type I has no ERSPAN header and no sequence number
+        GRE_ERSPAN_II if !seq => Some((
+            Tunnel {
+                kind: TunnelKind::Erspan,
+                id: None,
+            },
+            LinkType::Ethernet,
+            data,
+        )),
+        GRE_ERSPAN_II => {
+            let header = data.get(..8)?;
+            Some((erspan(header), LinkType::Ethernet, &data[8..]))
+        }
+        GRE_ERSPAN_III => {
+            let header = data.get(..12)?;
+            // optional platform specific subheader
+            let len = if header[11] & 0x1 != 0 { 20 } else { 12 };
+            Some((erspan(header), LinkType::Ethernet, data.get(len..)?))
+        }
+        _ => None,
+    }
+}
+
+fn erspan(header: &[u8]) -> Tunnel {
+    Tunnel {
+        kind: TunnelKind::Erspan,
+        id: Some(u16::from_be_bytes([header[2], header[3]]) as u32 & 0x3ff),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn ip(protocol: u8, payload: Vec<u8>) -> IpLayer {
+        IpLayer {
+            src_ip: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+            dst_ip: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)),
+            protocol,
+            reassembled: false,
+            payload: payload.into(),
+        }
+    }
+
+    fn enabled() -> Decapsulator {
+        Decapsulator::new(&TunnelConfig {
+            enabled: true,
+            ..TunnelConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_vxlan_geneve() {
+        let inner = [0xee; 14];
+        let mut vxlan = vec![0xc0, 0x00, 0x12, 0xb5, 0, 30, 0, 0];
+        vxlan.extend_from_slice(&[0x08, 0, 0, 0, 0x00, 0x01, 0x02, 0]);
+        vxlan.extend_from_slice(&inner);
+        let pkt = ip(IPPROTO_UDP, vxlan);
+        let (tunnel, link_type, data) = enabled().decap(&pkt).unwrap();
+        assert_eq!(tunnel.to_string(), "vxlan:258");
+        assert_eq!(link_type, LinkType::Ethernet);
+        assert_eq!(data, inner);
+        assert!(Decapsulator::new(&TunnelConfig::default())
+            .decap(&pkt)
+            .is_none());
+
+        // one 4 byte option, inner IPv4
+        let mut geneve = vec![0xc0, 0x00, 0x17, 0xc1, 0, 30, 0, 0];
+        geneve.extend_from_slice(&[0x01, 0, 0x08, 0x00, 0, 0, 7, 0]);
+        geneve.extend_from_slice(&[1, 2, 3, 4]);
+        geneve.extend_from_slice(&inner);
+        let pkt = ip(IPPROTO_UDP, geneve);
+        let (tunnel, link_type, data) = enabled().decap(&pkt).unwrap();
+        assert_eq!(tunnel.kind, TunnelKind::Geneve);
+        assert_eq!(tunnel.id, Some(7));
+        assert_eq!(link_type, LinkType::Raw);
+        assert_eq!(data, inner);
+    }
+
+    #[test]
+    fn test_gre_erspan() {
+        let inner = [0xee; 14];
+        // GRE with key
+        let mut gre = vec![0x20, 0x00, 0x65, 0x58, 0, 0, 0, 42];
+        gre.extend_from_slice(&inner);
+        let pkt = ip(IPPROTO_GRE, gre);
+        let (tunnel, _, data) = enabled().decap(&pkt).unwrap();
+        assert_eq!(tunnel.to_string(), "gre:42");
+        assert_eq!(data, inner);
+
+        // ERSPAN type II, session id 100
+        let mut erspan = vec![0x10, 0x00, 0x88, 0xbe, 0, 0, 0, 1];
+        erspan.extend_from_slice(&[0x10, 0x00, 0x00, 100, 0, 0, 0, 0]);
+        erspan.extend_from_slice(&inner);
+        let pkt = ip(IPPROTO_GRE, erspan);
+        let (tunnel, link_type, data) = enabled().decap(&pkt).unwrap();
+        assert_eq!(tunnel.to_string(), "erspan:100");
+        assert_eq!(link_type, LinkType::Ethernet);
+        assert_eq!(data, inner);
+
+        // truncated ERSPAN type III header
+        let erspan = vec![0x00, 0x00, 0x22, 0xeb, 0x20, 0x00];
+        assert!(enabled().decap(&ip(IPPROTO_GRE, erspan)).is_none());
+    }
+}
@@ -1,48 +1,443 @@
-use config::Config;
-use log::{debug, error};
+use crate::queue::QueueSender;
+use bytes::Bytes;
+use config::filter::Filter;
+use config::{CaptureBackend, Config, ANY_DEVICE};
+use log::{debug, error, info, warn};
+use metrics::Registry;
+use pcap::PcapReader;
+use session::{CapturedFrame, LinkType, Timestamp};
 
 use pnet::datalink::Channel::Ethernet;
-use tokio::sync::mpsc;
+use pnet::datalink::{DataLinkReceiver, NetworkInterface};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+#[cfg(target_os = "linux")]
+use std::os::fd::IntoRawFd;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+#[cfg(target_os = "linux")]
+mod bpf;
+#[cfg(target_os = "linux")]
+mod ring;
+
 pub struct Capture {
     config: Config,
-    raw_pkt_tx: mpsc::UnboundedSender<Vec<u8>>,
+    raw_pkt_tx: QueueSender<CapturedFrame>,
+    registry: Registry,
 }
 
 impl Capture {
-    pub fn new(config: Config, raw_pkt_tx: mpsc::UnboundedSender<Vec<u8>>) -> Capture {
-        Capture { config, raw_pkt_tx }
+    pub fn new(
+        config: Config,
+        raw_pkt_tx: QueueSender<CapturedFrame>,
+        registry: Registry,
+    ) -> Capture {
+        Capture {
+            config,
+            raw_pkt_tx,
+            registry,
+        }
     }
 
-    pub async fn active(&mut self) {
-        let conf = &self.config;
-        let ifaces = pnet::datalink::interfaces();
-        let cap_iface = ifaces
-            .iter()
-            .find(|iface| iface.name == conf.device)
-            .unwrap();
+    /// Capture on every configured interface until the consumer hangs up.
+    /// All sockets are opened first so a bad interface fails at startup,
+    /// then each is read on its own thread.
+    pub fn active(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let devices = &self.config.capture.devices;
+        let any = devices.iter().any(|d| d == ANY_DEVICE);
+        let mut sources = Vec::new();
+        let mut names = Vec::new();
+        for iface in resolve_devices(devices, pnet::datalink::interfaces())? {
+            let link_type = match link_type(&iface) {
+                Ok(link_type) => link_type,
+                // `any` takes what it can decode
+                Err(e) if any => {
+                    warn!("Skipping {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            sources.extend(self.open(&iface, link_type)?);
+            names.push(iface.name);
+        }
+        if sources.is_empty() {
+            return Err("no interface with a supported link type".into());
+        }
+        info!(
+            "Capturing on {} with {} reader thread(s)",
+            names.join(", "),
+            sources.len()
+        );
 
-        let (_, mut rx) = match pnet::datalink::channel(&cap_iface, Default::default()) {
-            Ok(Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unhandled channel type"),
-            Err(e) => {
-                error!("Error happened: {}", e);
-                return;
+        let snaplen = self.config.capture.snaplen as usize;
+        thread::scope(|scope| {
+            let workers = sources
+                .into_iter()
+                .enumerate()
+                .map(|(i, (interface, link_type, source))| {
+                    let tx = self.raw_pkt_tx.clone();
+                    thread::Builder::new()
+                        .name(format!("capture-{}", i))
+                        .spawn_scoped(scope, move || {
+                            source.read(interface, link_type, tx, snaplen)
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            // one failing interface does not stop the others
+            let mut result = Ok(());
+            for worker in workers {
+                match worker.join() {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        error!("{}", e);
+                        result = Err(e);
+                    }
+                    Err(_) => result = Err("capture thread panicked".into()),
+                }
             }
-        };
+            result
+        })
+    }
 
-        loop {
-            match rx.next() {
-                Ok(packet) => match self.raw_pkt_tx.send(packet.to_vec()) {
-                    Ok(_) => {
-                        debug!("Send packet to executor, payload len: {}", packet.len());
+    /// Open the sockets of one interface, more than one only for a ring
+    /// backend with fanout.
+    fn open(
+        &self,
+        iface: &NetworkInterface,
+        link_type: LinkType,
+    ) -> Result<Sources, Box<dyn Error + Send + Sync>> {
+        let conf = &self.config.capture;
+        let interface: Arc<str> = iface.name.as_str().into();
+        match conf.backend {
+            CaptureBackend::Pnet => {
+                // pnet binds the filtered socket it is handed
+                #[cfg(target_os = "linux")]
+                let socket_fd = match self.filter(link_type)? {
+                    Some(filter) => Some(
+                        bpf::packet_socket(&filter)
+                            .map_err(|e| format!("failed to filter {}: {}", iface.name, e))?
+                            .into_raw_fd(),
+                    ),
+                    None => None,
+                };
+                #[cfg(not(target_os = "linux"))]
+                let socket_fd = {
+                    if Filter::parse(&conf.bpf)?.is_some() {
+                        warn!(
+                            "Ignoring capture.bpf on {}, filters are only attached on Linux",
+                            iface.name
+                        );
+                    }
+                    None
+                };
+                let channel_config = pnet::datalink::Config {
+                    promiscuous: conf.promisc,
+                    socket_fd,
+                    ..Default::default()
+                };
+                match pnet::datalink::channel(iface, channel_config) {
+                    Ok(Ethernet(_, rx)) => Ok(vec![(interface, link_type, Source::Pnet(rx))]),
+                    Ok(_) => Err(format!("{}: unhandled channel type", iface.name).into()),
+                    Err(e) => Err(format!("failed to open {}: {}", iface.name, e).into()),
+                }
+            }
+            CaptureBackend::Ring => self.open_ring(iface, interface, link_type),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn open_ring(
+        &self,
+        _iface: &NetworkInterface,
+        _interface: Arc<str>,
+        _link_type: LinkType,
+    ) -> Result<Sources, Box<dyn Error + Send + Sync>> {
+        Err("the ring capture backend is only available on Linux".into())
+    }
+
+    /// `capture.fanout_threads` TPACKET_V3 rings on one interface. With more
+    /// than one the rings join a fanout group so the kernel spreads flows
+    /// across them.
+    #[cfg(target_os = "linux")]
+    fn open_ring(
+        &self,
+        iface: &NetworkInterface,
+        interface: Arc<str>,
+        link_type: LinkType,
+    ) -> Result<Sources, Box<dyn Error + Send + Sync>> {
+        let conf = &self.config.capture;
+        let filter = self.filter(link_type)?;
+        let mut rings = Vec::with_capacity(conf.fanout_threads);
+        for thread in 0..conf.fanout_threads {
+            let ring = ring::Ring::open(
+                &iface.name,
+                conf.ring_block_size,
+                conf.ring_block_count,
+                conf.ring_block_timeout_ms,
+                conf.promisc,
+                filter.as_ref(),
+            )
+            .map_err(|e| format!("failed to open ring on {}: {}", iface.name, e))?;
+            if conf.fanout_threads > 1 {
+                // groups are per network namespace and bound to one
+                // interface, keep ours apart
+                let group = std::process::id().wrapping_add(iface.index) as u16;
+                ring.join_fanout(group)
+                    .map_err(|e| format!("failed to join fanout group on {}: {}", iface.name, e))?;
+            }
+            let stats = RingStats::new(&self.registry, &iface.name, thread);
+            rings.push((interface.clone(), link_type, Source::Ring(ring, stats)));
+        }
+        Ok(rings)
+    }
+
+    /// `capture.bpf` compiled for frames of `link_type`, None when it lets
+    /// every frame through.
+    #[cfg(target_os = "linux")]
+    fn filter(
+        &self,
+        link_type: LinkType,
+    ) -> Result<Option<bpf::Program>, Box<dyn Error + Send + Sync>> {
+        let expr = &self.config.capture.bpf;
+        match Filter::parse(expr)? {
+            Some(filter) => bpf::compile(&filter, link_type)
+                .map(Some)
+                .map_err(|e| format!("cannot apply filter `{}`: {}", expr, e).into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Replay pcap files in order, returns the number of frames read.
+    pub fn read_files(&mut self, files: &[PathBuf]) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let snaplen = self.config.capture.snaplen as usize;
+        let mut frames = 0;
+        for path in files {
+            let file = File::open(path)
+                .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+            let reader = PcapReader::new(BufReader::new(file))
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            let link_type = match LinkType::from_pcap(reader.link_type()) {
+                Some(link_type) => link_type,
+                None => {
+                    return Err(format!(
+                        "{}: unsupported link type {}",
+                        path.display(),
+                        reader.link_type()
+                    )
+                    .into())
+                }
+            };
+            info!("Reading {}, link type {:?}", path.display(), link_type);
+            for record in reader {
+                let mut record =
+                    record.map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                record.data.truncate(snaplen);
+                let frame = CapturedFrame {
+                    timestamp: Timestamp::new(record.ts_sec as u64, record.ts_nsec),
+                    interface: None,
+                    link_type,
+                    orig_len: record.orig_len,
+                    data: record.data.into(),
+                };
+                if self.raw_pkt_tx.send_blocking(frame).is_err() {
+                    return Err("executor channel closed".into());
+                }
+                frames += 1;
+            }
+        }
+        Ok(frames)
+    }
+}
+
+/// Interfaces to capture on, `any` meaning every interface that is up.
+fn resolve_devices(
+    devices: &[String],
+    available: Vec<NetworkInterface>,
+) -> Result<Vec<NetworkInterface>, Box<dyn Error + Send + Sync>> {
+    let names = || {
+        available
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if devices.iter().any(|d| d == ANY_DEVICE) {
+        let up: Vec<NetworkInterface> = available.iter().filter(|i| i.is_up()).cloned().collect();
+        if up.is_empty() {
+            return Err(format!("no interface is up, available: {}", names()).into());
+        }
+        return Ok(up);
+    }
+    devices
+        .iter()
+        .map(
+            |device| match available.iter().find(|i| &i.name == device) {
+                Some(iface) => Ok(iface.clone()),
+                None => {
+                    Err(format!("interface not found: {}, available: {}", device, names()).into())
+                }
+            },
+        )
+        .collect()
+}
+
+/// Link type of the frames captured on `iface`, from the hardware type the
+/// kernel reports for it.
+#[cfg(target_os = "linux")]
+fn link_type(iface: &NetworkInterface) -> Result<LinkType, Box<dyn Error + Send + Sync>> {
+    let path = format!("/sys/class/net/{}/type", iface.name);
+    let hatype =
+        std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let hatype: u16 = hatype
+        .trim()
+        .parse()
+        .map_err(|_| format!("{}: bad hardware type {:?}", iface.name, hatype.trim()))?;
+    LinkType::from_arphrd(hatype)
+        .ok_or_else(|| format!("{}: unsupported hardware type {}", iface.name, hatype).into())
+}
+
+// pnet hands out Ethernet frames elsewhere
+#[cfg(not(target_os = "linux"))]
+fn link_type(_iface: &NetworkInterface) -> Result<LinkType, Box<dyn Error + Send + Sync>> {
+    Ok(LinkType::Ethernet)
+}
+
+/// Opened sockets, the interface each one reads and the link type of its
+/// frames.
+type Sources = Vec<(Arc<str>, LinkType, Source)>;
+
+/// An opened capture socket.
+enum Source {
+    Pnet(Box<dyn DataLinkReceiver>),
+    #[cfg(target_os = "linux")]
+    Ring(ring::Ring, RingStats),
+}
+
+impl Source {
+    /// Forward frames tagged with `interface` until the consumer hangs up.
+    fn read(
+        self,
+        interface: Arc<str>,
+        link_type: LinkType,
+        tx: QueueSender<CapturedFrame>,
+        snaplen: usize,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // the only copy, out of the interface buffer or the ring
+        let frame = |packet: &[u8], orig_len: u32, timestamp: Timestamp| CapturedFrame {
+            timestamp,
+            interface: Some(interface.clone()),
+            link_type,
+            orig_len,
+            data: Bytes::copy_from_slice(&packet[..packet.len().min(snaplen)]),
+        };
+        match self {
+            Source::Pnet(mut rx) => loop {
+                match rx.next() {
+                    // pnet does not expose the kernel timestamp
+                    Ok(packet) => {
+                        match tx.send_blocking(frame(packet, packet.len() as u32, Timestamp::now()))
+                        {
+                            Ok(_) => {
+                                debug!("Send packet to executor, payload len: {}", packet.len());
+                            }
+                            Err(e) => {
+                                error!("Error happened: {}", e);
+                                return Ok(());
+                            }
+                        }
                     }
                     Err(e) => {
-                        error!("Error happened: {}", e);
+                        error!("Error happened on {}: {}", interface, e);
                     }
-                },
-                Err(e) => {
-                    error!("Error happened: {}", e);
                 }
+            },
+            #[cfg(target_os = "linux")]
+            Source::Ring(ring, stats) => {
+                read_ring(ring, &interface, stats, |packet, orig_len, timestamp| {
+                    tx.send_blocking(frame(packet, orig_len, timestamp)).is_ok()
+                })
+            }
+        }
+    }
+}
+
+/// Kernel side counters of one ring, `thread` is the fanout member.
+#[cfg(target_os = "linux")]
+struct RingStats {
+    packets: metrics::Counter,
+    drops: metrics::Counter,
+    freezes: metrics::Counter,
+}
+
+#[cfg(target_os = "linux")]
+impl RingStats {
+    fn new(registry: &Registry, interface: &str, thread: usize) -> RingStats {
+        let thread = thread.to_string();
+        let labels = [("interface", interface), ("thread", thread.as_str())];
+        RingStats {
+            packets: registry.counter(
+                "rshark_capture_kernel_packets_total",
+                "Packets the kernel passed to the capture ring",
+                &labels,
+            ),
+            drops: registry.counter(
+                "rshark_capture_kernel_drops_total",
+                "Packets the kernel dropped because the capture ring was full",
+                &labels,
+            ),
+            freezes: registry.counter(
+                "rshark_capture_kernel_freezes_total",
+                "Times the kernel froze the capture ring queue",
+                &labels,
+            ),
+        }
+    }
+
+    // the kernel resets its counters on every read
+    fn update(&self, ring: &ring::Ring) {
+        match ring.stats() {
+            Ok((packets, drops, freezes)) => {
+                self.packets.add(packets as u64);
+                self.drops.add(drops as u64);
+                self.freezes.add(freezes as u64);
+            }
+            Err(e) => error!("Failed to read ring statistics: {}", e),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_ring(
+    mut ring: ring::Ring,
+    interface: &str,
+    stats: RingStats,
+    mut forward: impl FnMut(&[u8], u32, Timestamp) -> bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    use std::time::{Duration, Instant};
+
+    let mut last_stats = Instant::now();
+    loop {
+        let more = ring.read_block(100, &mut forward);
+        if last_stats.elapsed() >= Duration::from_secs(1) {
+            stats.update(&ring);
+            last_stats = Instant::now();
+        }
+        match more {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!(
+                    "Executor channel closed, stopping ring capture on {}",
+                    interface
+                );
+                stats.update(&ring);
+                return Ok(());
+            }
+            Err(e) => {
+                stats.update(&ring);
+                return Err(format!("ring capture on {} failed: {}", interface, e).into());
             }
         }
     }
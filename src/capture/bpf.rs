@@ -0,0 +1,535 @@
+use config::filter::{Dir, Filter, IPPROTO_SCTP, IPPROTO_TCP, IPPROTO_UDP};
+use config::IpNetwork;
+use libc::{c_int, c_void, sock_filter, socklen_t};
+use session::LinkType;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+// asm-generic value, only alpha, mips, parisc and sparc differ
+const SO_ATTACH_FILTER: c_int = 26;
+
+// offsets into the IPv4 and IPv6 headers
+const IP4_FRAG: u32 = 6;
+const IP4_PROTO: u32 = 9;
+const IP4_SRC: u32 = 12;
+const IP4_DST: u32 = 16;
+const IP6_NEXT: u32 = 6;
+const IP6_SRC: u32 = 8;
+const IP6_DST: u32 = 24;
+const IP6_HEADER_LEN: u32 = 40;
+
+/// A classic BPF program for the kernel to run on every frame of a
+/// capture socket.
+pub struct Program(Vec<sock_filter>);
+
+type Label = usize;
+
+enum Insn {
+    Stmt(u32, u32),
+    Jump(u32, u32, Label, Label),
+}
+
+/// Compile `filter` for frames of `link_type`. Conditions jump to labels
+/// that are placed later on, so every jump is a forward one as BPF wants.
+pub fn compile(filter: &Filter, link_type: LinkType) -> Result<Program, String> {
+    let mut compiler = Compiler {
+        link_type,
+        insns: Vec::new(),
+        labels: Vec::new(),
+    };
+    let (matched, dropped) = (compiler.label(), compiler.label());
+    compiler.cond(filter, matched, dropped)?;
+    compiler.place(matched);
+    // keep the whole frame, `capture.snaplen` truncates it later
+    compiler.stmt(libc::BPF_RET | libc::BPF_K, u32::MAX);
+    compiler.place(dropped);
+    compiler.stmt(libc::BPF_RET | libc::BPF_K, 0);
+    compiler.finish()
+}
+
+struct Compiler {
+    link_type: LinkType,
+    insns: Vec<Insn>,
+    // instruction each label points at once placed
+    labels: Vec<Option<usize>>,
+}
+
+impl Compiler {
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: Label) {
+        self.labels[label] = Some(self.insns.len());
+    }
+
+    fn stmt(&mut self, code: u32, k: u32) {
+        self.insns.push(Insn::Stmt(code, k));
+    }
+
+    /// Test the accumulator against `k`, `op` is `BPF_JEQ` or `BPF_JSET`.
+    fn jump(&mut self, op: u32, k: u32, jt: Label, jf: Label) {
+        self.insns
+            .push(Insn::Jump(libc::BPF_JMP | op | libc::BPF_K, k, jt, jf));
+    }
+
+    /// Emit a jump to `t` when `filter` matches and to `f` otherwise.
+    fn cond(&mut self, filter: &Filter, t: Label, f: Label) -> Result<(), String> {
+        match filter {
+            Filter::And(a, b) => {
+                let next = self.label();
+                self.cond(a, next, f)?;
+                self.place(next);
+                self.cond(b, t, f)
+            }
+            Filter::Or(a, b) => {
+                let next = self.label();
+                self.cond(a, t, next)?;
+                self.place(next);
+                self.cond(b, t, f)
+            }
+            Filter::Not(a) => self.cond(a, f, t),
+            Filter::Ip4 => self.ip4(t, f),
+            Filter::Ip6 => self.ip6(t, f),
+            Filter::Proto(proto) => self.either_ip(
+                |c, t, f| c.proto(IP4_PROTO, &[*proto], t, f),
+                |c, t, f| c.proto(IP6_NEXT, &[*proto], t, f),
+                t,
+                f,
+            ),
+            Filter::Port { proto, dir, port } => {
+                let protos = match proto {
+                    Some(proto) => vec![*proto],
+                    None => vec![IPPROTO_TCP, IPPROTO_UDP, IPPROTO_SCTP],
+                };
+                let l3 = self.l3()?;
+                self.either_ip(
+                    |c, t, f| {
+                        let (first, unfragmented) = (c.label(), c.label());
+                        c.proto(IP4_PROTO, &protos, first, f)?;
+                        // ports are only in the first fragment
+                        c.place(first);
+                        c.stmt(libc::BPF_LD | libc::BPF_H | libc::BPF_ABS, l3 + IP4_FRAG);
+                        c.jump(libc::BPF_JSET, 0x1fff, f, unfragmented);
+                        c.place(unfragmented);
+                        c.stmt(libc::BPF_LDX | libc::BPF_B | libc::BPF_MSH, l3);
+                        c.ports(libc::BPF_IND, l3, *dir, *port, t, f);
+                        Ok(())
+                    },
+                    |c, t, f| {
+                        let next = c.label();
+                        c.proto(IP6_NEXT, &protos, next, f)?;
+                        c.place(next);
+                        c.ports(libc::BPF_ABS, l3 + IP6_HEADER_LEN, *dir, *port, t, f);
+                        Ok(())
+                    },
+                    t,
+                    f,
+                )
+            }
+            Filter::Host { dir, addr } => {
+                let net = IpNetwork::from(*addr);
+                self.net(net, *dir, t, f)
+            }
+            Filter::Net { dir, net } => self.net(*net, *dir, t, f),
+        }
+    }
+
+    /// Offset of the network header.
+    fn l3(&self) -> Result<u32, String> {
+        match self.link_type {
+            LinkType::Ethernet => Ok(14),
+            LinkType::Raw => Ok(0),
+            other => Err(format!("cannot filter frames of link type {:?}", other)),
+        }
+    }
+
+    fn version(&mut self, ethertype: u32, version: u32, t: Label, f: Label) -> Result<(), String> {
+        match self.link_type {
+            LinkType::Ethernet => {
+                self.stmt(libc::BPF_LD | libc::BPF_H | libc::BPF_ABS, 12);
+                self.jump(libc::BPF_JEQ, ethertype, t, f);
+            }
+            LinkType::Raw => {
+                self.stmt(libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, 0);
+                self.stmt(libc::BPF_ALU | libc::BPF_AND | libc::BPF_K, 0xf0);
+                self.jump(libc::BPF_JEQ, version << 4, t, f);
+            }
+            other => return Err(format!("cannot filter frames of link type {:?}", other)),
+        }
+        Ok(())
+    }
+
+    fn ip4(&mut self, t: Label, f: Label) -> Result<(), String> {
+        self.version(0x0800, 4, t, f)
+    }
+
+    fn ip6(&mut self, t: Label, f: Label) -> Result<(), String> {
+        self.version(0x86dd, 6, t, f)
+    }
+
+    /// `v4` on IPv4 packets, `v6` on IPv6 ones, anything else fails.
+    fn either_ip(
+        &mut self,
+        v4: impl FnOnce(&mut Self, Label, Label) -> Result<(), String>,
+        v6: impl FnOnce(&mut Self, Label, Label) -> Result<(), String>,
+        t: Label,
+        f: Label,
+    ) -> Result<(), String> {
+        let (is_v4, not_v4, is_v6) = (self.label(), self.label(), self.label());
+        self.ip4(is_v4, not_v4)?;
+        self.place(is_v4);
+        v4(self, t, f)?;
+        self.place(not_v4);
+        self.ip6(is_v6, f)?;
+        self.place(is_v6);
+        v6(self, t, f)
+    }
+
+    /// The protocol byte at `offset` of the network header is one of
+    /// `protos`.
+    fn proto(&mut self, offset: u32, protos: &[u8], t: Label, f: Label) -> Result<(), String> {
+        let l3 = self.l3()?;
+        self.stmt(libc::BPF_LD | libc::BPF_B | libc::BPF_ABS, l3 + offset);
+        for (i, proto) in protos.iter().enumerate() {
+            if i + 1 == protos.len() {
+                self.jump(libc::BPF_JEQ, *proto as u32, t, f);
+            } else {
+                let next = self.label();
+                self.jump(libc::BPF_JEQ, *proto as u32, t, next);
+                self.place(next);
+            }
+        }
+        Ok(())
+    }
+
+    /// Source and destination ports of the transport header at `offset`,
+    /// plus the X register for `BPF_IND`.
+    fn ports(&mut self, mode: u32, offset: u32, dir: Dir, port: u16, t: Label, f: Label) {
+        let load = libc::BPF_LD | libc::BPF_H | mode;
+        match dir {
+            Dir::Src => {
+                self.stmt(load, offset);
+                self.jump(libc::BPF_JEQ, port as u32, t, f);
+            }
+            Dir::Dst => {
+                self.stmt(load, offset + 2);
+                self.jump(libc::BPF_JEQ, port as u32, t, f);
+            }
+            Dir::Either => {
+                let dst = self.label();
+                self.stmt(load, offset);
+                self.jump(libc::BPF_JEQ, port as u32, t, dst);
+                self.place(dst);
+                self.stmt(load, offset + 2);
+                self.jump(libc::BPF_JEQ, port as u32, t, f);
+            }
+        }
+    }
+
+    fn net(&mut self, net: IpNetwork, dir: Dir, t: Label, f: Label) -> Result<(), String> {
+        let l3 = self.l3()?;
+        let is_ip = self.label();
+        let (src, dst, words) = match net {
+            IpNetwork::V4(net) => {
+                self.ip4(is_ip, f)?;
+                let word = (u32::from(net.ip()), u32::from(net.mask()));
+                (l3 + IP4_SRC, l3 + IP4_DST, vec![word])
+            }
+            IpNetwork::V6(net) => {
+                self.ip6(is_ip, f)?;
+                let (ip, mask) = (net.ip().octets(), net.mask().octets());
+                let word = |b: &[u8; 16], i: usize| {
+                    u32::from_be_bytes([b[i * 4], b[i * 4 + 1], b[i * 4 + 2], b[i * 4 + 3]])
+                };
+                let words = (0..4).map(|i| (word(&ip, i), word(&mask, i))).collect();
+                (l3 + IP6_SRC, l3 + IP6_DST, words)
+            }
+        };
+        self.place(is_ip);
+        match dir {
+            Dir::Src => self.addr(src, &words, t, f),
+            Dir::Dst => self.addr(dst, &words, t, f),
+            Dir::Either => {
+                let other = self.label();
+                self.addr(src, &words, t, other);
+                self.place(other);
+                self.addr(dst, &words, t, f);
+            }
+        }
+        Ok(())
+    }
+
+    /// The address at `offset` matches every (value, mask) word.
+    fn addr(&mut self, offset: u32, words: &[(u32, u32)], t: Label, f: Label) {
+        let words: Vec<_> = words
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, mask))| *mask != 0)
+            .collect();
+        if words.is_empty() {
+            // a /0 network holds every address
+            self.jump(libc::BPF_JEQ, 0, t, t);
+            return;
+        }
+        for (n, (i, (value, mask))) in words.iter().enumerate() {
+            self.stmt(
+                libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                offset + *i as u32 * 4,
+            );
+            if *mask != u32::MAX {
+                self.stmt(libc::BPF_ALU | libc::BPF_AND | libc::BPF_K, *mask);
+            }
+            if n + 1 == words.len() {
+                self.jump(libc::BPF_JEQ, *value, t, f);
+            } else {
+                let next = self.label();
+                self.jump(libc::BPF_JEQ, *value, next, f);
+                self.place(next);
+            }
+        }
+    }
+
+    /// Resolve the labels into relative jump offsets.
+    fn finish(self) -> Result<Program, String> {
+        if self.insns.len() > libc::BPF_MAXINSNS as usize {
+            return Err(format!(
+                "filter needs {} instructions, at most {} fit",
+                self.insns.len(),
+                libc::BPF_MAXINSNS
+            ));
+        }
+        let offset = |pos: usize, label: Label| {
+            let target = self.labels[label].expect("jump to a label never placed");
+            u8::try_from(target - pos - 1).map_err(|_| "filter is too long".to_string())
+        };
+        let mut program = Vec::with_capacity(self.insns.len());
+        for (pos, insn) in self.insns.iter().enumerate() {
+            program.push(match *insn {
+                Insn::Stmt(code, k) => sock_filter {
+                    code: code as u16,
+                    jt: 0,
+                    jf: 0,
+                    k,
+                },
+                Insn::Jump(code, k, jt, jf) => sock_filter {
+                    code: code as u16,
+                    jt: offset(pos, jt)?,
+                    jf: offset(pos, jf)?,
+                    k,
+                },
+            });
+        }
+        Ok(Program(program))
+    }
+}
+
+/// Attach `program` to the socket `fd`, frames it rejects never leave the
+/// kernel.
+pub fn attach(fd: RawFd, program: &Program) -> io::Result<()> {
+    let fprog = libc::sock_fprog {
+        len: program.0.len() as u16,
+        filter: program.0.as_ptr() as *mut sock_filter,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            SO_ATTACH_FILTER,
+            &fprog as *const libc::sock_fprog as *const c_void,
+            mem::size_of::<libc::sock_fprog>() as socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A raw AF_PACKET socket with `program` attached, for pnet to bind.
+pub fn packet_socket(program: &Program) -> io::Result<OwnedFd> {
+    let protocol = (libc::ETH_P_ALL as u16).to_be();
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            protocol as c_int,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    attach(fd.as_raw_fd(), program)?;
+    Ok(fd)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use session::testing::tcp_frame;
+
+    /// Run `program` over `frame` the way the kernel does, returns the
+    /// number of bytes to keep.
+    fn run(program: &Program, frame: &[u8]) -> u32 {
+        let load = |offset: u32, size: usize| -> Option<u32> {
+            let bytes = frame.get(offset as usize..offset as usize + size)?;
+            Some(bytes.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+        };
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0);
+        loop {
+            let insn = &program.0[pc];
+            let code = insn.code as u32;
+            pc += 1;
+            let size = match code & 0x18 {
+                libc::BPF_W => 4,
+                libc::BPF_H => 2,
+                _ => 1,
+            };
+            match code & 0x07 {
+                libc::BPF_LD => {
+                    let offset = match code & 0xe0 {
+                        libc::BPF_ABS => insn.k,
+                        libc::BPF_IND => x + insn.k,
+                        mode => panic!("unexpected load mode {:#x}", mode),
+                    };
+                    // an out of bounds load drops the frame
+                    match load(offset, size) {
+                        Some(value) => a = value,
+                        None => return 0,
+                    }
+                }
+                libc::BPF_LDX => match load(insn.k, 1) {
+                    Some(value) => x = (value & 0xf) * 4,
+                    None => return 0,
+                },
+                libc::BPF_ALU => a &= insn.k,
+                libc::BPF_JMP => {
+                    let taken = match code & 0xf0 {
+                        libc::BPF_JEQ => a == insn.k,
+                        libc::BPF_JSET => a & insn.k != 0,
+                        op => panic!("unexpected jump {:#x}", op),
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+                libc::BPF_RET => return insn.k,
+                class => panic!("unexpected class {:#x}", class),
+            }
+        }
+    }
+
+    fn matches(filter: &str, link_type: LinkType, frame: &[u8]) -> bool {
+        let filter = Filter::parse(filter).unwrap().unwrap();
+        run(&compile(&filter, link_type).unwrap(), frame) != 0
+    }
+
+    /// Ethernet, IPv6 and UDP headers from fd00::1 port 5353 to fd00::2
+    /// port 4789.
+    fn udp6_frame() -> Vec<u8> {
+        let mut data = vec![0; 12];
+        data.extend_from_slice(&[0x86, 0xdd, 0x60, 0, 0, 0, 0, 8, 17, 64]);
+        data.extend_from_slice(&"fd00::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&"fd00::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&[0x14, 0xe9, 0x12, 0xb5, 0, 8, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_filter_ipv4() {
+        let frame = tcp_frame(true, 1, b"select 1");
+        let raw = &frame[14..];
+        for link_type in [LinkType::Ethernet, LinkType::Raw] {
+            let frame = if link_type == LinkType::Raw {
+                raw
+            } else {
+                &frame[..]
+            };
+            for (filter, expected) in [
+                ("tcp", true),
+                ("udp", false),
+                ("ip", true),
+                ("ip6", false),
+                ("tcp and port 3306", true),
+                ("tcp and port 3307", false),
+                ("src port 50000 and dst port 3306", true),
+                ("src port 3306", false),
+                ("udp port 3306", false),
+                ("host 10.0.0.2", true),
+                ("src host 10.0.0.2", false),
+                ("net 10.0.0.0/24 and not net 10.0.1.0/24", true),
+                ("dst net 192.168.0.0/16", false),
+                ("net 0.0.0.0/0", true),
+                ("host fd00::2", false),
+                ("tcp or udp port 4789 or proto gre", true),
+                ("not (tcp and port 3306)", false),
+            ] {
+                assert_eq!(
+                    matches(filter, link_type, frame),
+                    expected,
+                    "{} on {:?}",
+                    filter,
+                    link_type
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter_ipv4_fragment() {
+        let mut frame = tcp_frame(true, 1, b"select 1");
+        // a later fragment, the bytes after the IP header are no ports
+        frame[20] = 0x20;
+        frame[21] = 0x01;
+        assert!(matches("tcp", LinkType::Ethernet, &frame));
+        assert!(!matches("port 3306", LinkType::Ethernet, &frame));
+    }
+
+    #[test]
+    fn test_filter_ipv6() {
+        let frame = udp6_frame();
+        for (filter, expected) in [
+            ("ip6", true),
+            ("tcp", false),
+            ("udp port 4789", true),
+            ("tcp or udp port 4789 or proto gre", true),
+            ("dst port 5353", false),
+            ("host fd00::1", true),
+            ("dst host fd00::1", false),
+            ("net fd00::/64 and dst net fd00::2/128", true),
+            ("net fd01::/16", false),
+            ("host 10.0.0.1", false),
+        ] {
+            assert_eq!(
+                matches(filter, LinkType::Ethernet, &frame),
+                expected,
+                "{}",
+                filter
+            );
+        }
+        assert!(matches("ip6 and udp", LinkType::Raw, &frame[14..]));
+    }
+
+    #[test]
+    fn test_filter_truncated_frame() {
+        let frame = tcp_frame(true, 1, b"");
+        assert!(!matches("port 3306", LinkType::Ethernet, &frame[..30]));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let filter = Filter::parse("tcp").unwrap().unwrap();
+        assert!(compile(&filter, LinkType::LinuxSll).is_err());
+
+        // a jump over more than 255 instructions does not fit
+        let long = vec!["host fd00::1"; 40].join(" or ");
+        let filter = Filter::parse(&format!("({}) and tcp", long))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            compile(&filter, LinkType::Ethernet).err().unwrap(),
+            "filter is too long"
+        );
+    }
+}
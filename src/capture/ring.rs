@@ -0,0 +1,310 @@
+use super::bpf;
+use libc::{c_int, c_void, socklen_t};
+use session::Timestamp;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{fence, Ordering};
+
+const TPACKET_V3: c_int = 2;
+
+// slot size the kernel accounts frames in, V3 packs frames tighter
+const FRAME_SIZE: u32 = 2048;
+
+/// Memory-mapped AF_PACKET TPACKET_V3 receive ring bound to one interface.
+pub struct Ring {
+    fd: OwnedFd,
+    map: *mut u8,
+    block_size: usize,
+    block_count: usize,
+    current: usize,
+}
+
+// the mapping is private to the ring and only touched through &mut self
+unsafe impl Send for Ring {}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn setsockopt<T>(fd: &OwnedFd, name: c_int, value: &T) -> io::Result<()> {
+    check(unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_PACKET,
+            name,
+            value as *const T as *const c_void,
+            mem::size_of::<T>() as socklen_t,
+        )
+    })
+    .map(|_| ())
+}
+
+impl Ring {
+    pub fn open(
+        device: &str,
+        block_size: u32,
+        block_count: u32,
+        block_timeout_ms: u32,
+        promisc: bool,
+        filter: Option<&bpf::Program>,
+    ) -> io::Result<Ring> {
+        let name = CString::new(device)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = check(unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol as c_int,
+            )
+        })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // before the bind so no unfiltered frame gets queued
+        if let Some(filter) = filter {
+            bpf::attach(fd.as_raw_fd(), filter)?;
+        }
+        setsockopt(&fd, libc::PACKET_VERSION, &TPACKET_V3)?;
+
+        let req = libc::tpacket_req3 {
+            tp_block_size: block_size,
+            tp_block_nr: block_count,
+            tp_frame_size: FRAME_SIZE,
+            tp_frame_nr: block_size / FRAME_SIZE * block_count,
+            tp_retire_blk_tov: block_timeout_ms,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        setsockopt(&fd, libc::PACKET_RX_RING, &req)?;
+
+        let map_len = block_size as usize * block_count as usize;
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // unmapped by Drop from here on
+        let ring = Ring {
+            fd,
+            map: map as *mut u8,
+            block_size: block_size as usize,
+            block_count: block_count as usize,
+            current: 0,
+        };
+
+        let addr = libc::sockaddr_ll {
+            sll_family: libc::AF_PACKET as u16,
+            sll_protocol: protocol,
+            sll_ifindex: ifindex as c_int,
+            sll_hatype: 0,
+            sll_pkttype: 0,
+            sll_halen: 0,
+            sll_addr: [0; 8],
+        };
+        check(unsafe {
+            libc::bind(
+                ring.fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as socklen_t,
+            )
+        })?;
+
+        if promisc {
+            let mreq = libc::packet_mreq {
+                mr_ifindex: ifindex as c_int,
+                mr_type: libc::PACKET_MR_PROMISC as u16,
+                mr_alen: 0,
+                mr_address: [0; 8],
+            };
+            setsockopt(&ring.fd, libc::PACKET_ADD_MEMBERSHIP, &mreq)?;
+        }
+        Ok(ring)
+    }
+
+    /// Join fanout `group`. The kernel hashes each flow, reassembling IP
+    /// fragments first, to a single member so per-flow order is kept.
+    pub fn join_fanout(&self, group: u16) -> io::Result<()> {
+        let mode = libc::PACKET_FANOUT_HASH | libc::PACKET_FANOUT_FLAG_DEFRAG;
+        let arg = (group as u32 | (mode << 16)) as c_int;
+        setsockopt(&self.fd, libc::PACKET_FANOUT, &arg)
+    }
+
+    /// Kernel (packets, drops, queue freezes) since the previous call.
+    pub fn stats(&self) -> io::Result<(u32, u32, u32)> {
+        let mut stats: libc::tpacket_stats_v3 = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::tpacket_stats_v3>() as socklen_t;
+        check(unsafe {
+            libc::getsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_PACKET,
+                libc::PACKET_STATISTICS,
+                &mut stats as *mut libc::tpacket_stats_v3 as *mut c_void,
+                &mut len,
+            )
+        })?;
+        Ok((stats.tp_packets, stats.tp_drops, stats.tp_freeze_q_cnt))
+    }
+
+    /// Hand every frame of the next filled block to `f` along with its
+    /// length on the wire and kernel timestamp, waiting up to `timeout_ms`
+    /// for the kernel to retire one. Returns false as soon as
+    /// `f` does, the rest of that block is skipped.
+    pub fn read_block(
+        &mut self,
+        timeout_ms: c_int,
+        mut f: impl FnMut(&[u8], u32, Timestamp) -> bool,
+    ) -> io::Result<bool> {
+        let block = unsafe { self.map.add(self.current * self.block_size) };
+        let desc = block as *mut libc::tpacket_block_desc;
+        let status = unsafe { ptr::addr_of_mut!((*desc).hdr.bh1.block_status) };
+
+        if unsafe { ptr::read_volatile(status) } & libc::TP_STATUS_USER == 0 {
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pfd, 1, timeout_ms) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(true);
+                }
+                return Err(err);
+            }
+            if unsafe { ptr::read_volatile(status) } & libc::TP_STATUS_USER == 0 {
+                return Ok(true);
+            }
+        }
+        fence(Ordering::Acquire);
+
+        let (num_pkts, first) = unsafe {
+            let hdr = &(*desc).hdr.bh1;
+            (hdr.num_pkts, hdr.offset_to_first_pkt as usize)
+        };
+        let mut offset = first;
+        let mut more = true;
+        for _ in 0..num_pkts {
+            if offset + mem::size_of::<libc::tpacket3_hdr>() > self.block_size {
+                break;
+            }
+            let hdr = unsafe { &*(block.add(offset) as *const libc::tpacket3_hdr) };
+            let start = offset + hdr.tp_mac as usize;
+            let end = start + hdr.tp_snaplen as usize;
+            if end > self.block_size {
+                break;
+            }
+            let data = unsafe { slice::from_raw_parts(block.add(start), end - start) };
+            let timestamp = Timestamp::new(hdr.tp_sec as u64, hdr.tp_nsec);
+            if !f(data, hdr.tp_len, timestamp) {
+                more = false;
+                break;
+            }
+            if hdr.tp_next_offset == 0 {
+                break;
+            }
+            offset += hdr.tp_next_offset as usize;
+        }
+
+        // give the block back to the kernel
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(status, libc::TP_STATUS_KERNEL) };
+        self.current = (self.current + 1) % self.block_count;
+        Ok(more)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.map as *mut c_void, self.block_size * self.block_count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_ring_on_loopback() {
+        // needs CAP_NET_RAW
+        let mut ring = match Ring::open("lo", 1 << 16, 4, 10, false, None) {
+            Ok(ring) => ring,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("failed to open ring on lo: {}", e),
+        };
+        let marker = b"rshark-ring-test";
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = socket.local_addr().unwrap();
+
+        let started = Instant::now();
+        let mut seen = false;
+        while !seen && started.elapsed() < Duration::from_secs(5) {
+            socket.send_to(marker, target).unwrap();
+            ring.read_block(100, |frame, _, _| {
+                seen |= frame.windows(marker.len()).any(|w| w == marker);
+                true
+            })
+            .unwrap();
+        }
+        assert!(seen);
+        let (packets, _, _) = ring.stats().unwrap();
+        assert!(packets > 0);
+    }
+
+    #[test]
+    fn test_ring_filter_on_loopback() {
+        let target = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let expr = format!("udp and dst port {}", target.local_addr().unwrap().port());
+        let filter = config::filter::Filter::parse(&expr).unwrap().unwrap();
+        let program = bpf::compile(&filter, session::LinkType::Ethernet).unwrap();
+        let mut ring = match Ring::open("lo", 1 << 16, 4, 10, false, Some(&program)) {
+            Ok(ring) => ring,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("failed to open ring on lo: {}", e),
+        };
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let started = Instant::now();
+        let (mut kept, mut filtered) = (false, false);
+        while !kept && started.elapsed() < Duration::from_secs(5) {
+            socket
+                .send_to(b"rshark-filtered", other.local_addr().unwrap())
+                .unwrap();
+            socket
+                .send_to(b"rshark-kept", target.local_addr().unwrap())
+                .unwrap();
+            ring.read_block(100, |frame, _, _| {
+                kept |= frame.ends_with(b"rshark-kept");
+                filtered |= frame.ends_with(b"rshark-filtered");
+                true
+            })
+            .unwrap();
+        }
+        assert!(kept);
+        assert!(!filtered);
+    }
+}
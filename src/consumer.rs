@@ -1,49 +1,121 @@
+use crate::queue::{self, QueueSender};
 use config::Config;
-use log::error;
-use session::SessionManager;
+use log::{error, info};
+use metrics::{Counter, Gauge, Registry};
+use session::{
+    CapturedFrame, Decoder, EventSink, FlowKey, ServerDiscovered, SessionManager, Totals,
+};
 use session::SessionPacket;
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// One session manager task and the queue feeding it.
+struct Shard {
+    db_pkt_tx: QueueSender<SessionPacket>,
+    depth: Gauge,
+    sm_handle: JoinHandle<SessionManager>,
+}
 
 pub struct Consumer {
     config: Config,
-    raw_pkt_rx: mpsc::UnboundedReceiver<Vec<u8>>,
-    db_pkt_tx: mpsc::UnboundedSender<SessionPacket>,
+    raw_pkt_rx: mpsc::Receiver<CapturedFrame>,
+    shards: Vec<Shard>,
+    decoder: Decoder,
+    discovered: Vec<ServerDiscovered>,
+    packets: u64,
+    duplicates: Counter,
 }
 
 impl Consumer {
-    pub fn new(
+    pub fn new<S: EventSink + Clone + 'static>(
         conf: Config,
         runtime: &Runtime,
-        raw_pkt_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+        raw_pkt_rx: mpsc::Receiver<CapturedFrame>,
+        registry: &Registry,
+        events: Option<S>,
     ) -> Consumer {
-        let (db_pkt_tx, db_pkt_rx) = mpsc::unbounded_channel::<SessionPacket>();
-        let mut sm = SessionManager::new(conf.clone(), db_pkt_rx);
-        if !sm.is_running() {
-            runtime.spawn(async move {
-                sm.run().await;
-            });
-        }
+        let totals = Arc::new(Totals::default());
+        let shards = (0..conf.pipeline.shards)
+            .map(|i| {
+                let (db_pkt_tx, db_pkt_rx) = queue::channel::<SessionPacket>(
+                    conf.pipeline.packet_queue,
+                    conf.pipeline.overflow,
+                    registry,
+                    "session",
+                );
+                let depth = registry.gauge(
+                    "rshark_shard_queue_depth",
+                    "Packets waiting for a session manager shard",
+                    &[("shard", &i.to_string())],
+                );
+                let mut sm = SessionManager::new(conf.clone(), db_pkt_rx, totals.clone());
+                if let Some(events) = &events {
+                    sm.set_sink(Box::new(events.clone()));
+                }
+                let sm_handle = runtime.spawn(async move {
+                    sm.run().await;
+                    sm
+                });
+                Shard {
+                    db_pkt_tx,
+                    depth,
+                    sm_handle,
+                }
+            })
+            .collect();
         Consumer {
+            decoder: Decoder::new(&conf),
+            discovered: Vec::new(),
             config: conf.clone(),
             raw_pkt_rx,
-            db_pkt_tx,
+            shards,
+            packets: 0,
+            duplicates: registry.counter(
+                "rshark_capture_duplicates_total",
+                "Frames dropped because they were already seen on another interface",
+                &[],
+            ),
         }
     }
 
+    /// Dissect frames until every capture source has hung up.
     pub async fn run(&mut self) {
         loop {
             match self.raw_pkt_rx.recv().await {
                 None => {
-                    error!("Executor channel closed");
+                    info!("Executor channel closed");
+                    break;
                 }
                 Some(raw_pkt) => {
                     // parse packet
                     let conf = &self.config;
-                    match SessionPacket::new(conf, raw_pkt) {
+                    let duplicates = self.decoder.dedup.duplicates();
+                    let sp = SessionPacket::new(conf, &mut self.decoder, raw_pkt);
+                    self.duplicates
+                        .add(self.decoder.dedup.duplicates() - duplicates);
+                    for event in self.decoder.discovery.take_events() {
+                        info!(
+                            "New {} server {}:{} ({}) at {}",
+                            event.db_type,
+                            event.ip,
+                            event.port,
+                            event.server_version,
+                            event.timestamp
+                        );
+                        self.discovered.push(event);
+                    }
+                    match sp {
                         Some(pkt) => {
-                            match self.db_pkt_tx.send(pkt) {
+                            self.packets += 1;
+                            let shard = &self.shards[shard_of(&pkt.flow, self.shards.len())];
+                            let sent = shard.db_pkt_tx.send(pkt).await;
+                            shard.depth.set(shard.db_pkt_tx.queued() as i64);
+                            match sent {
                                 Ok(_) => {
                                     // debug!("Send packet to session manager, payload len: {}", pkt.len());
                                 }
@@ -60,4 +132,65 @@ impl Consumer {
             }
         }
     }
+
+    /// Frames that were recognized as database traffic.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Servers registered by greeting detection.
+    pub fn discovered(&self) -> &[ServerDiscovered] {
+        &self.discovered
+    }
+
+    /// Close the session manager inputs and wait for every shard to drain.
+    pub async fn shutdown(self) -> Option<Vec<SessionManager>> {
+        let mut managers = Vec::with_capacity(self.shards.len());
+        let mut failed = false;
+        for shard in self.shards {
+            drop(shard.db_pkt_tx);
+            shard.depth.set(0);
+            match shard.sm_handle.await {
+                Ok(sm) => managers.push(sm),
+                Err(e) => {
+                    error!("Session manager failed: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            None
+        } else {
+            Some(managers)
+        }
+    }
+}
+
+/// Shard owning `flow`, both directions of a connection share the key so
+/// every packet of a session is handled in order by the same shard.
+fn shard_of(flow: &FlowKey, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    flow.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use session::IPPROTO_TCP;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_shard_of() {
+        let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let server = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        for port in 50000..50064 {
+            let request = FlowKey::new(client, port, server, 3306, IPPROTO_TCP, true);
+            let response = FlowKey::new(server, 3306, client, port, IPPROTO_TCP, false);
+            let shard = shard_of(&request, 4);
+            assert!(shard < 4);
+            assert_eq!(shard, shard_of(&response, 4));
+            assert_eq!(shard_of(&request, 1), 0);
+        }
+    }
 }
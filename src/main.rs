@@ -1,45 +1,355 @@
 mod capture;
 mod consumer;
+mod output;
+mod queue;
+mod report;
 
-use config::Config;
-use log::info;
 use capture::Capture;
+use clap::{Parser, Subcommand};
+use config::{Config, OutputFormat};
 use consumer::Consumer;
+use log::info;
+use metrics::Registry;
+use report::Summary;
+
+use session::{CapturedFrame, SessionEvent, SessionManager};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use tokio::runtime::Runtime;
+
+// exit codes, 2 is also what clap uses for usage errors
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+#[derive(Parser, Debug)]
+#[command(name = "rshark", version, about = "MySQL protocol dissector")]
+struct Cli {
+    /// TOML configuration file
+    #[arg(short, long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
 
-use std::collections::HashMap;
-use tokio::sync::mpsc;
-fn main() {
-    env_logger::init();
+    /// Output format of reports and live events: text or json, overrides
+    /// the format of every `[[outputs]]` entry
+    #[arg(short, long, global = true)]
+    format: Option<OutputFormat>,
 
-    let mut db = HashMap::new();
-    db.insert("3309".to_string(), "mysql".to_string());
+    /// Log filter, e.g. `info` or `session=debug`; defaults to RUST_LOG
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<String>,
 
-    let conf = Config {
-        // storage: &config::STORAGE,
-        bpf: "tcp and port 3309".to_string(),
-        device: "en0".to_string(),
-        support_db: db,
+    /// Override any configuration key, e.g. `--set session.idle_timeout_secs=60`
+    #[arg(short, long = "set", global = true, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Capture on a network interface until interrupted
+    Live {
+        /// Capture interface, repeat or separate with commas for several,
+        /// `any` for all; overrides `capture.devices`
+        #[arg(short = 'i', long = "device", value_delimiter = ',')]
+        devices: Vec<String>,
+
+        /// BPF filter, overrides `capture.bpf`
+        #[arg(long)]
+        bpf: Option<String>,
+    },
+    /// Process pcap files and print a summary
+    Read {
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// List the interfaces available for live capture
+    ListInterfaces,
+    /// Print the sessions reconstructed from pcap files
+    Sessions {
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Print a client/server fingerprint summary of pcap files
+    Stats {
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Print the events of every session in pcap files, in capture order
+    Events {
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Build the configuration from the file (or defaults), then the `RSHARK_*`
+/// environment variables, then the command line, in increasing precedence.
+fn load_config(cli: &Cli) -> Result<Config, Box<dyn Error>> {
+    let mut conf = match &cli.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
     };
+    conf.apply_env(std::env::vars())?;
+
+    match &cli.command {
+        Command::Live { devices, bpf } => {
+            if !devices.is_empty() {
+                conf.capture.devices = devices.clone();
+            }
+            if let Some(bpf) = bpf {
+                conf.capture.bpf = bpf.clone();
+            }
+            conf.capture.file = None;
+        }
+        Command::Read { files }
+        | Command::Sessions { files }
+        | Command::Stats { files }
+        | Command::Events { files } => {
+            conf.capture.file = files.first().cloned();
+            conf.capture.devices.clear();
+        }
+        Command::ListInterfaces => {}
+    }
+    for kv in &cli.set {
+        conf.apply_arg(kv)?;
+    }
+    if let Some(format) = cli.format {
+        for output in conf.outputs.iter_mut() {
+            output.format = format;
+        }
+    }
 
-    let (tx, rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    conf.validate()?;
+    Ok(conf)
+}
 
-    let runtime = tokio::runtime::Builder::new_multi_thread()
+fn build_runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
         .worker_threads(4)
         .enable_all()
         .build()
-        .unwrap();
+        .unwrap()
+}
+
+/// Serve the metrics endpoint if enabled, the registry is shared by every
+/// pipeline stage.
+fn start_metrics(conf: &Config) -> Result<Registry, Box<dyn Error>> {
+    let registry = Registry::new();
+    if conf.metrics.enabled {
+        let addr = conf.metrics.listen.parse()?;
+        metrics::serve(addr, registry.clone())
+            .map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+        info!("Serving metrics on {}", addr);
+    }
+    Ok(registry)
+}
+
+fn live(conf: Config) -> Result<(), Box<dyn Error>> {
+    let registry = start_metrics(&conf)?;
+    let (tx, rx) = queue::channel::<CapturedFrame>(
+        conf.pipeline.frame_queue,
+        conf.pipeline.overflow,
+        &registry,
+        "decode",
+    );
+    let runtime = build_runtime();
+
+    let outputs = conf
+        .outputs
+        .iter()
+        .map(output::Output::open)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (events_tx, events_rx) = queue::sync_channel::<SessionEvent>(
+        conf.pipeline.event_queue,
+        conf.pipeline.overflow,
+        &registry,
+        "output",
+    );
+    let writer = output::spawn(outputs, events_rx)?;
 
     let conf_capture = conf.clone();
-    runtime.spawn(async {
-        info!("Capture started with config: {:?}", conf_capture.clone());
-        let mut capture = Capture::new(conf_capture, tx);
-        capture.active().await;
-    });
+    let registry_capture = registry.clone();
+    let capture = thread::Builder::new()
+        .name("capture".to_string())
+        .spawn(move || {
+            info!("Capture started with config: {:?}", conf_capture);
+            let mut capture = Capture::new(conf_capture, tx, registry_capture);
+            capture.active()
+        })?;
 
     let conf_executor = conf.clone();
     runtime.block_on(async {
         info!("Executor started with config: {:?}", conf_executor);
-        let mut consumer = Consumer::new(conf_executor, &runtime, rx);
+        let mut consumer = Consumer::new(conf_executor, &runtime, rx, &registry, Some(events_tx));
         consumer.run().await;
+        consumer.shutdown().await;
     });
+    // every sender went away with the shards, the writer drains and exits
+    writer.join().map_err(|_| "output thread panicked")?;
+    capture
+        .join()
+        .map_err(|_| "capture thread panicked")?
+        .map_err(|e| e as Box<dyn Error>)
+}
+
+/// Run the whole pipeline over pcap files and hand back the drained
+/// session manager shards. Session events go to `events` if given.
+fn analyze(
+    conf: &Config,
+    files: &[PathBuf],
+    events: Option<mpsc::Sender<SessionEvent>>,
+) -> Result<(Summary, Vec<SessionManager>), Box<dyn Error>> {
+    let started = Instant::now();
+    let registry = start_metrics(conf)?;
+    let (tx, rx) = queue::channel::<CapturedFrame>(
+        conf.pipeline.frame_queue,
+        conf.pipeline.overflow,
+        &registry,
+        "decode",
+    );
+    let runtime = build_runtime();
+
+    let mut capture = Capture::new(conf.clone(), tx, registry.clone());
+    let paths = files.to_vec();
+    let reader = thread::Builder::new()
+        .name("capture".to_string())
+        .spawn(move || capture.read_files(&paths))?;
+
+    runtime.block_on(async {
+        let mut consumer = Consumer::new(conf.clone(), &runtime, rx, &registry, events);
+        consumer.run().await;
+        let frames = reader
+            .join()
+            .map_err(|_| "capture thread panicked")?
+            .map_err(|e| e as Box<dyn Error>)?;
+
+        let packets = consumer.packets();
+        let dropped = queue::dropped(&registry, "decode") + queue::dropped(&registry, "session");
+        let discovered = consumer
+            .discovered()
+            .iter()
+            .map(|d| format!("{}:{}", d.ip, d.port))
+            .collect();
+        let shards = consumer
+            .shutdown()
+            .await
+            .ok_or("session manager stopped unexpectedly")?;
+        let sessions = || shards.iter().flat_map(|sm| sm.sessions());
+        let summary = Summary {
+            files: files.len(),
+            frames,
+            packets,
+            dropped,
+            sessions: sessions().count(),
+            expired: shards.iter().map(|sm| sm.expired()).sum(),
+            handshakes: sessions()
+                .filter(|(_, s)| !s.ctx().server_version.is_empty())
+                .count(),
+            discovered,
+            exported: shards.iter().map(|sm| sm.exported()).sum(),
+            elapsed_ms: started.elapsed().as_millis(),
+        };
+        Ok((summary, shards))
+    })
+}
+
+fn list_interfaces(format: OutputFormat) {
+    let ifaces = pnet::datalink::interfaces();
+    match format {
+        OutputFormat::Json => {
+            let names: Vec<serde_json::Value> = ifaces
+                .iter()
+                .map(|i| {
+                    serde_json::json!({
+                        "name": i.name,
+                        "up": i.is_up(),
+                        "mac": i.mac.map(|m| m.to_string()),
+                        "ips": i.ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::Value::Array(names));
+        }
+        OutputFormat::Text => {
+            for iface in ifaces {
+                let ips: Vec<String> = iface.ips.iter().map(|ip| ip.to_string()).collect();
+                println!(
+                    "{:16} {:4} {}",
+                    iface.name,
+                    if iface.is_up() { "up" } else { "down" },
+                    ips.join(", ")
+                );
+            }
+        }
+    }
+}
+
+fn run(cli: Cli, conf: Config) -> Result<(), Box<dyn Error>> {
+    let format = cli.format.unwrap_or_default();
+    match &cli.command {
+        Command::Live { .. } => live(conf),
+        Command::Read { files } => {
+            let (summary, _) = analyze(&conf, files, None)?;
+            report::print_summary(&summary, format);
+            Ok(())
+        }
+        Command::ListInterfaces => Ok(()),
+        Command::Sessions { files } => {
+            let (_, shards) = analyze(&conf, files, None)?;
+            report::print_sessions(&report::session_rows(&shards), format);
+            Ok(())
+        }
+        Command::Stats { files } => {
+            let (_, shards) = analyze(&conf, files, None)?;
+            let rows = report::session_rows(&shards);
+            report::print_fingerprints(&report::fingerprint_rows(&rows), format);
+            Ok(())
+        }
+        Command::Events { files } => {
+            let (tx, rx) = mpsc::channel();
+            analyze(&conf, files, Some(tx))?;
+            let mut events: Vec<SessionEvent> = rx.try_iter().collect();
+            // shards interleave sessions at random, each session is in order
+            // and the sort is stable
+            events.sort_by_key(|e| (e.timestamp, e.client, e.server));
+            report::print_events(&events, format);
+            Ok(())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = &cli.log_level {
+        logger.parse_filters(level);
+    }
+    logger.init();
+
+    // the only command that does not need a capture source
+    if let Command::ListInterfaces = cli.command {
+        list_interfaces(cli.format.unwrap_or_default());
+        return ExitCode::SUCCESS;
+    }
+
+    let conf = match load_config(&cli) {
+        Ok(conf) => conf,
+        Err(e) => {
+            eprintln!("rshark: {}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match run(cli, conf) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("rshark: {}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use config::{OutputConfig, OutputFormat, OutputKind};
+use log::error;
+use session::SessionEvent;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+/// One `[[outputs]]` entry opened for writing. Live events are streamed,
+/// one line per event, in the order the shards emit them.
+pub struct Output {
+    format: OutputFormat,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Output {
+    pub fn open(conf: &OutputConfig) -> io::Result<Output> {
+        let writer: Box<dyn Write + Send> = match conf.kind {
+            OutputKind::Stdout => Box::new(BufWriter::new(io::stdout())),
+            OutputKind::File => {
+                // validate() makes sure a file output has a path
+                let path = conf.path.clone().unwrap_or_default();
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+                Box::new(BufWriter::new(file))
+            }
+        };
+        Ok(Output {
+            format: conf.format,
+            writer,
+        })
+    }
+
+    fn write(&mut self, event: &SessionEvent) -> io::Result<()> {
+        writeln!(self.writer, "{}", line(event, self.format))
+    }
+}
+
+fn line(event: &SessionEvent, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => serde_json::to_string(event).unwrap_or_default(),
+        OutputFormat::Text => format!(
+            "{}  {}  {}  {}  {}",
+            event.timestamp,
+            event.client,
+            event.server,
+            event.kind.name(),
+            event.kind
+        ),
+    }
+}
+
+/// Drain `rx` into every output until all senders are dropped. The outputs
+/// are flushed whenever the channel runs empty.
+pub fn spawn(
+    mut outputs: Vec<Output>,
+    rx: mpsc::Receiver<SessionEvent>,
+) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("output".to_string())
+        .spawn(move || {
+            while let Ok(event) = rx.recv() {
+                for event in std::iter::once(event).chain(rx.try_iter()) {
+                    for output in outputs.iter_mut() {
+                        if let Err(e) = output.write(&event) {
+                            error!("Failed to write event: {}", e);
+                        }
+                    }
+                }
+                for output in outputs.iter_mut() {
+                    if let Err(e) = output.writer.flush() {
+                        error!("Failed to flush output: {}", e);
+                    }
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use session::{EventKind, FlowKey, Timestamp, IPPROTO_TCP};
+
+    #[test]
+    fn test_line() {
+        let flow = FlowKey::new(
+            "10.0.0.1".parse().unwrap(),
+            40000,
+            "10.0.0.2".parse().unwrap(),
+            3306,
+            IPPROTO_TCP,
+            true,
+        );
+        let event = SessionEvent::new(Timestamp::new(1, 500), &flow, EventKind::TlsRequest);
+        let text = line(&event, OutputFormat::Text);
+        assert!(
+            text.contains("10.0.0.1:40000  10.0.0.2:3306  tls_request"),
+            "{}",
+            text
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&line(&event, OutputFormat::Json)).unwrap();
+        assert_eq!(json["event"], "tls_request");
+        assert_eq!(json["server"], "10.0.0.2:3306");
+    }
+}
@@ -0,0 +1,201 @@
+use config::OverflowPolicy;
+use metrics::{Counter, Registry};
+use session::{EventSink, SessionEvent};
+use std::fmt;
+use std::sync::mpsc as std_mpsc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// The receiving stage is gone.
+#[derive(Debug)]
+pub struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "queue closed")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+/// Sending half of a bounded queue between two pipeline stages, applying
+/// the configured overflow policy.
+pub struct QueueSender<T> {
+    tx: mpsc::Sender<T>,
+    policy: OverflowPolicy,
+    dropped: Counter,
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        QueueSender {
+            tx: self.tx.clone(),
+            policy: self.policy,
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+/// Bounded queue feeding `stage`, drops are counted in
+/// `rshark_queue_dropped_total{stage}`.
+pub fn channel<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+    registry: &Registry,
+    stage: &str,
+) -> (QueueSender<T>, mpsc::Receiver<T>) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let dropped = dropped_counter(registry, stage);
+    (
+        QueueSender {
+            tx,
+            policy,
+            dropped,
+        },
+        rx,
+    )
+}
+
+/// Sending half of a bounded queue to a stage running on a plain thread,
+/// the output writer, applying the configured overflow policy.
+pub struct SyncQueueSender<T> {
+    tx: std_mpsc::SyncSender<T>,
+    policy: OverflowPolicy,
+    dropped: Counter,
+}
+
+impl<T> Clone for SyncQueueSender<T> {
+    fn clone(&self) -> Self {
+        SyncQueueSender {
+            tx: self.tx.clone(),
+            policy: self.policy,
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+/// Like `channel`, for a receiver that blocks its own thread.
+pub fn sync_channel<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+    registry: &Registry,
+    stage: &str,
+) -> (SyncQueueSender<T>, std_mpsc::Receiver<T>) {
+    let (tx, rx) = std_mpsc::sync_channel(capacity);
+    let dropped = dropped_counter(registry, stage);
+    (
+        SyncQueueSender {
+            tx,
+            policy,
+            dropped,
+        },
+        rx,
+    )
+}
+
+impl<T> SyncQueueSender<T> {
+    /// With the block policy this holds up the calling thread, a runtime
+    /// worker included, until the receiver catches up.
+    pub fn send(&self, item: T) -> Result<(), Closed> {
+        match self.policy {
+            OverflowPolicy::Block => self.tx.send(item).map_err(|_| Closed),
+            OverflowPolicy::Drop => match self.tx.try_send(item) {
+                Ok(_) => Ok(()),
+                Err(std_mpsc::TrySendError::Full(_)) => {
+                    self.dropped.inc();
+                    Ok(())
+                }
+                Err(std_mpsc::TrySendError::Disconnected(_)) => Err(Closed),
+            },
+        }
+    }
+}
+
+impl EventSink for SyncQueueSender<SessionEvent> {
+    fn emit(&mut self, event: SessionEvent) {
+        // the receiving end only goes away on shutdown
+        let _ = self.send(event);
+    }
+}
+
+fn dropped_counter(registry: &Registry, stage: &str) -> Counter {
+    registry.counter(
+        "rshark_queue_dropped_total",
+        "Items dropped because the queue to the next stage was full",
+        &[("stage", stage)],
+    )
+}
+
+/// Items dropped so far on the way to `stage`.
+pub fn dropped(registry: &Registry, stage: &str) -> u64 {
+    dropped_counter(registry, stage).get()
+}
+
+impl<T> QueueSender<T> {
+    /// Send from a plain thread, never call this from inside the runtime.
+    pub fn send_blocking(&self, item: T) -> Result<(), Closed> {
+        match self.policy {
+            OverflowPolicy::Block => self.tx.blocking_send(item).map_err(|_| Closed),
+            OverflowPolicy::Drop => self.try_send(item),
+        }
+    }
+
+    pub async fn send(&self, item: T) -> Result<(), Closed> {
+        match self.policy {
+            OverflowPolicy::Block => self.tx.send(item).await.map_err(|_| Closed),
+            OverflowPolicy::Drop => self.try_send(item),
+        }
+    }
+
+    /// Items waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    fn try_send(&self, item: T) -> Result<(), Closed> {
+        match self.tx.try_send(item) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.inc();
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(Closed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drop_policy() {
+        let registry = Registry::new();
+        let (tx, mut rx) = channel(2, OverflowPolicy::Drop, &registry, "decode");
+        for i in 0..5 {
+            tx.send_blocking(i).unwrap();
+        }
+        assert_eq!(dropped(&registry, "decode"), 3);
+        assert_eq!(rx.try_recv().unwrap(), 0);
+        assert_eq!(rx.try_recv().unwrap(), 1);
+        assert!(registry
+            .render()
+            .contains("rshark_queue_dropped_total{stage=\"decode\"} 3"));
+
+        drop(rx);
+        assert!(tx.send_blocking(5).is_err());
+    }
+
+    #[test]
+    fn test_sync_drop_policy() {
+        let registry = Registry::new();
+        let (tx, rx) = sync_channel(2, OverflowPolicy::Drop, &registry, "output");
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(dropped(&registry, "output"), 3);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1]);
+        drop(rx);
+        assert!(tx.send(5).is_err());
+    }
+}
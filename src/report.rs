@@ -0,0 +1,253 @@
+use config::OutputFormat;
+use serde::Serialize;
+use session::{Session, SessionEvent, SessionManager};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub files: usize,
+    pub frames: u64,
+    pub packets: u64,
+    // lost to full queues, only with `pipeline.overflow = "drop"`
+    pub dropped: u64,
+    pub sessions: usize,
+    // dropped by `[session]` timeouts and limits before the end
+    pub expired: u64,
+    pub handshakes: usize,
+    pub discovered: Vec<String>,
+    // sessions written to pcap files by `[export]`
+    pub exported: usize,
+    pub elapsed_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionRow {
+    pub client: String,
+    pub server: String,
+    // e.g. `vxlan:100` for mirrored traffic, empty otherwise
+    pub tunnel: String,
+    // capture interfaces the session was seen on, comma separated
+    pub interfaces: String,
+    // capture time, seconds since the epoch with nanoseconds
+    pub first_seen: String,
+    pub last_seen: String,
+    pub state: String,
+    pub server_version: String,
+    pub user: String,
+    pub database: String,
+    pub client_name: String,
+    pub client_version: String,
+    pub auth_plugin: String,
+    pub packets: u64,
+    // pcap file of an exported session, empty otherwise
+    pub export: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FingerprintRow {
+    pub server_version: String,
+    pub client_name: String,
+    pub client_version: String,
+    pub auth_plugin: String,
+    pub sessions: usize,
+}
+
+impl SessionRow {
+    fn new(session: &Session) -> SessionRow {
+        let ctx = session.ctx();
+        SessionRow {
+            client: ctx.flow.client().to_string(),
+            server: ctx.flow.server().to_string(),
+            tunnel: ctx.tunnel.map(|t| t.to_string()).unwrap_or_default(),
+            interfaces: ctx.interfaces.join(","),
+            first_seen: ctx.first_seen.to_string(),
+            last_seen: ctx.last_seen.to_string(),
+            state: format!("{:?}", ctx.state),
+            server_version: ctx.server_version.clone(),
+            user: ctx.username.clone().unwrap_or_default(),
+            database: ctx.database.clone().unwrap_or_default(),
+            client_name: ctx
+                .client_attrs
+                .get("_client_name")
+                .cloned()
+                .unwrap_or_default(),
+            client_version: ctx.client_version.clone(),
+            auth_plugin: ctx.auth_plugin_name.clone(),
+            packets: session.packets(),
+            export: session
+                .export_path()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub fn session_rows(shards: &[SessionManager]) -> Vec<SessionRow> {
+    let mut rows: Vec<SessionRow> = shards
+        .iter()
+        .flat_map(|sm| sm.sessions())
+        .map(|(_, session)| SessionRow::new(session))
+        .collect();
+    rows.sort_by(|a, b| (&a.server, &a.client).cmp(&(&b.server, &b.client)));
+    rows
+}
+
+pub fn fingerprint_rows(rows: &[SessionRow]) -> Vec<FingerprintRow> {
+    let mut groups: BTreeMap<(&str, &str, &str, &str), usize> = BTreeMap::new();
+    for row in rows {
+        let key = (
+            row.server_version.as_str(),
+            row.client_name.as_str(),
+            row.client_version.as_str(),
+            row.auth_plugin.as_str(),
+        );
+        *groups.entry(key).or_default() += 1;
+    }
+    let mut fingerprints: Vec<FingerprintRow> = groups
+        .into_iter()
+        .map(
+            |((server_version, client_name, client_version, auth_plugin), sessions)| {
+                FingerprintRow {
+                    server_version: server_version.to_string(),
+                    client_name: client_name.to_string(),
+                    client_version: client_version.to_string(),
+                    auth_plugin: auth_plugin.to_string(),
+                    sessions,
+                }
+            },
+        )
+        .collect();
+    fingerprints.sort_by_key(|f| std::cmp::Reverse(f.sessions));
+    fingerprints
+}
+
+pub fn print_summary(summary: &Summary, format: OutputFormat) {
+    match format {
+        OutputFormat::Json => print_json(summary),
+        OutputFormat::Text => {
+            println!("files:      {}", summary.files);
+            println!("frames:     {}", summary.frames);
+            println!("packets:    {}", summary.packets);
+            println!("dropped:    {}", summary.dropped);
+            println!("sessions:   {}", summary.sessions);
+            println!("expired:    {}", summary.expired);
+            println!("handshakes: {}", summary.handshakes);
+            println!("discovered: {}", summary.discovered.join(", "));
+            println!("exported:   {}", summary.exported);
+            println!("elapsed:    {} ms", summary.elapsed_ms);
+        }
+    }
+}
+
+pub fn print_sessions(rows: &[SessionRow], format: OutputFormat) {
+    match format {
+        OutputFormat::Json => print_json(&rows),
+        OutputFormat::Text => print_table(
+            &[
+                "CLIENT",
+                "SERVER",
+                "TUNNEL",
+                "INTERFACES",
+                "STATE",
+                "VERSION",
+                "USER",
+                "DATABASE",
+                "CLIENT NAME",
+                "PACKETS",
+            ],
+            rows.iter()
+                .map(|r| {
+                    vec![
+                        r.client.clone(),
+                        r.server.clone(),
+                        r.tunnel.clone(),
+                        r.interfaces.clone(),
+                        r.state.clone(),
+                        r.server_version.clone(),
+                        r.user.clone(),
+                        r.database.clone(),
+                        r.client_name.clone(),
+                        r.packets.to_string(),
+                    ]
+                })
+                .collect(),
+        ),
+    }
+}
+
+pub fn print_fingerprints(rows: &[FingerprintRow], format: OutputFormat) {
+    match format {
+        OutputFormat::Json => print_json(&rows),
+        OutputFormat::Text => print_table(
+            &[
+                "SERVER VERSION",
+                "CLIENT NAME",
+                "CLIENT VERSION",
+                "AUTH PLUGIN",
+                "SESSIONS",
+            ],
+            rows.iter()
+                .map(|r| {
+                    vec![
+                        r.server_version.clone(),
+                        r.client_name.clone(),
+                        r.client_version.clone(),
+                        r.auth_plugin.clone(),
+                        r.sessions.to_string(),
+                    ]
+                })
+                .collect(),
+        ),
+    }
+}
+
+pub fn print_events(events: &[SessionEvent], format: OutputFormat) {
+    match format {
+        OutputFormat::Json => print_json(&events),
+        OutputFormat::Text => print_table(
+            &["TIMESTAMP", "CLIENT", "SERVER", "EVENT", "DETAIL"],
+            events
+                .iter()
+                .map(|e| {
+                    vec![
+                        e.timestamp.to_string(),
+                        e.client.to_string(),
+                        e.server.to_string(),
+                        e.kind.name().to_string(),
+                        e.kind.to_string(),
+                    ]
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(s) => println!("{}", s),
+        Err(e) => eprintln!("rshark: failed to serialize output: {}", e),
+    }
+}
+
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{:width$}", c, width = widths[i]))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", line(header.to_vec()));
+    for row in &rows {
+        println!("{}", line(row.iter().map(|c| c.as_str()).collect()));
+    }
+}
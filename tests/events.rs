@@ -0,0 +1,149 @@
+//! Runs the whole pipeline over the pcap fixtures with `rshark events` and
+//! compares the events to the golden JSON files next to them. Set
+//! `RSHARK_BLESS=1` to rewrite the golden files instead, and review the
+//! diff before committing it. The captures of real servers in
+//! `tests/fixtures/captured` come from `tests/fixtures/capture.sh`, the
+//! synthetic edge cases next to it from `tests/fixtures/generate.py`.
+
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FIXTURES: [&str; 4] = ["mysql57", "mysql80", "mysql84", "mariadb"];
+
+fn fixture(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(file)
+}
+
+/// JSON output of `rshark events` over `pcaps`.
+fn events(pcaps: &[PathBuf], args: &[&str]) -> String {
+    // RSHARK_* variables would change the configuration
+    let output = Command::new(env!("CARGO_BIN_EXE_rshark"))
+        .env_clear()
+        .args(["--format", "json"])
+        .args(args)
+        .arg("events")
+        .args(pcaps)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "rshark failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn parse(json: &str) -> Vec<Value> {
+    match serde_json::from_str(json) {
+        Ok(Value::Array(events)) => events,
+        other => panic!("expected an array of events, got {:?}", other),
+    }
+}
+
+/// Fail on the first event that differs, whole outputs are too long to read.
+fn assert_events_eq(actual: &[Value], expected: &[Value], what: &str) {
+    for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+        assert_eq!(a, e, "{}: event {} differs", what, i);
+    }
+    assert_eq!(
+        actual.len(),
+        expected.len(),
+        "{}: number of events differs",
+        what
+    );
+}
+
+fn check(name: &str) {
+    check_pcap(&fixture(&format!("{}.pcap", name)));
+}
+
+/// Compare the events of `pcap` to the JSON file of the same name.
+fn check_pcap(pcap: &Path) {
+    let name = pcap.display().to_string();
+    let actual = events(&[pcap.to_path_buf()], &[]);
+    let golden = pcap.with_extension("json");
+    if std::env::var_os("RSHARK_BLESS").is_some() {
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", golden.display(), e));
+    assert_events_eq(
+        &parse(&actual),
+        &parse(&expected),
+        &format!("{} (RSHARK_BLESS=1 updates the golden file)", name),
+    );
+}
+
+#[test]
+fn test_mysql57() {
+    check("mysql57");
+}
+
+#[test]
+fn test_mysql80() {
+    check("mysql80");
+}
+
+#[test]
+fn test_mysql84() {
+    check("mysql84");
+}
+
+#[test]
+fn test_mariadb() {
+    check("mariadb");
+}
+
+/// Every capture recorded by `tests/fixtures/capture.sh`, none at all is a
+/// failure rather than a silent pass.
+#[test]
+fn test_captured() {
+    let dir = fixture("captured");
+    let entries = fs::read_dir(&dir).unwrap_or_else(|e| {
+        panic!(
+            "failed to read {}: {}, record it with tests/fixtures/capture.sh",
+            dir.display(),
+            e
+        )
+    });
+    let mut pcaps: Vec<PathBuf> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pcap"))
+        .collect();
+    assert!(
+        !pcaps.is_empty(),
+        "no captures in {}, record them with tests/fixtures/capture.sh",
+        dir.display()
+    );
+    pcaps.sort();
+    for pcap in pcaps {
+        check_pcap(&pcap);
+    }
+}
+
+#[test]
+fn test_deterministic_across_shards() {
+    let pcaps: Vec<PathBuf> = FIXTURES
+        .iter()
+        .map(|name| fixture(&format!("{}.pcap", name)))
+        .collect();
+    let sharded = parse(&events(&pcaps, &["--set", "pipeline.shards=8"]));
+    let single = parse(&events(&pcaps, &["--set", "pipeline.shards=1"]));
+    assert_events_eq(&sharded, &single, "8 shards against 1");
+
+    // the fixtures do not overlap in time
+    if std::env::var_os("RSHARK_BLESS").is_none() {
+        let goldens: Vec<Value> = FIXTURES
+            .iter()
+            .flat_map(|name| {
+                parse(&fs::read_to_string(fixture(&format!("{}.json", name))).unwrap())
+            })
+            .collect();
+        assert_events_eq(&sharded, &goldens, "all fixtures at once");
+    }
+}
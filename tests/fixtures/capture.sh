@@ -0,0 +1,81 @@
+#!/bin/sh
+# Records the real captures of the event tests, one session per server
+# version against a throwaway container, seen on the loopback interface.
+# Needs docker and tcpdump, and root for the capture:
+#
+#   sudo tests/fixtures/capture.sh            # every version
+#   sudo tests/fixtures/capture.sh mysql84    # only some
+#
+# Then write the golden files with `RSHARK_BLESS=1 cargo test --test events`
+# and review them before committing the pcaps and the JSON next to them.
+set -eu
+
+HERE=$(cd "$(dirname "$0")" && pwd)
+OUT=$HERE/captured
+PORT=3306
+PASSWORD=rshark
+
+# the same statements on every server: DDL and DML, result sets, a SELECT
+# inside a transaction, a server-side prepared statement and an error
+SQL="
+create database shop;
+use shop;
+create table users (id bigint primary key, name varchar(32));
+insert into users values (1, 'ada'), (2, 'grace');
+select id, name from users;
+begin;
+select name from users where id = 1;
+update users set name = 'Ada' where id = 1;
+rollback;
+prepare stmt from 'select name from users where id = ?';
+set @id = 2;
+execute stmt using @id;
+deallocate prepare stmt;
+insert into users values (1, 'dup');
+"
+
+record() {
+    name=$1
+    # TLS is turned off in the client, encrypted sessions cannot be decoded
+    case $name in
+    mysql57) image=mysql:5.7 client="mysql --ssl-mode=DISABLED" ;;
+    mysql80) image=mysql:8.0 client="mysql --ssl-mode=DISABLED --get-server-public-key" ;;
+    mysql84) image=mysql:8.4 client="mysql --ssl-mode=DISABLED --get-server-public-key" ;;
+    mariadb) image=mariadb:10.11 client="mysql --skip-ssl" ;;
+    *)
+        echo "unknown server $name, expected mysql57, mysql80, mysql84 or mariadb" >&2
+        exit 2
+        ;;
+    esac
+
+    container=rshark-capture-$name
+    docker run -d --rm --name "$container" --network host \
+        -e MYSQL_ROOT_PASSWORD=$PASSWORD "$image" >/dev/null
+    trap 'docker stop "$container" >/dev/null 2>&1 || true' EXIT
+    # over TCP, the server the entrypoint starts first does not listen
+    until docker exec "$container" mysqladmin ping -h127.0.0.1 -P$PORT \
+        -uroot -p$PASSWORD --silent >/dev/null 2>&1; do
+        sleep 1
+    done
+
+    tcpdump -i lo -U -w "$OUT/$name.pcap" tcp port $PORT &
+    tcpdump=$!
+    sleep 1
+    docker run --rm --network host "$image" $client -h127.0.0.1 -P$PORT \
+        -uroot -p$PASSWORD --force -e "$SQL" || true
+    sleep 1
+    kill "$tcpdump"
+    wait "$tcpdump" || true
+
+    docker stop "$container" >/dev/null
+    trap - EXIT
+    echo "wrote $OUT/$name.pcap"
+}
+
+mkdir -p "$OUT"
+if [ $# -eq 0 ]; then
+    set -- mysql57 mysql80 mysql84 mariadb
+fi
+for name in "$@"; do
+    record "$name"
+done
@@ -0,0 +1,620 @@
+#!/usr/bin/env python3
+"""Writes the synthetic pcap fixtures of the event tests, for the edge cases
+the real captures of `capture.sh` rarely show.
+
+The conversations follow what MySQL 5.7, 8.0, 8.4 and MariaDB 10.11 servers
+and their usual clients send, byte for byte where it matters to a passive
+decoder. The output is deterministic: rerun after changing this file and
+commit the pcaps, then regenerate the golden files with
+`RSHARK_BLESS=1 cargo test --test events`.
+"""
+
+import os
+import struct
+import zlib
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+# client capabilities
+LONG_PASSWORD = 1
+LONG_FLAG = 4
+CONNECT_WITH_DB = 8
+LOCAL_FILES = 0x80
+PROTOCOL_41 = 0x200
+SSL = 0x800
+TRANSACTIONS = 0x2000
+SECURE_CONNECTION = 0x8000
+MULTI_STATEMENTS = 1 << 16
+MULTI_RESULTS = 1 << 17
+PS_MULTI_RESULTS = 1 << 18
+PLUGIN_AUTH = 1 << 19
+CONNECT_ATTRS = 1 << 20
+PLUGIN_AUTH_LENENC = 1 << 21
+SESSION_TRACK = 1 << 23
+DEPRECATE_EOF = 1 << 24
+QUERY_ATTRIBUTES = 1 << 27
+
+CLIENT_CAPS = (LONG_PASSWORD | LONG_FLAG | CONNECT_WITH_DB | LOCAL_FILES | PROTOCOL_41
+               | TRANSACTIONS | SECURE_CONNECTION | MULTI_STATEMENTS | MULTI_RESULTS
+               | PS_MULTI_RESULTS | PLUGIN_AUTH | CONNECT_ATTRS | PLUGIN_AUTH_LENENC)
+SERVER_CAPS = 0xffffffff & ~(1 << 26)
+
+STATUS_IN_TRANS = 1
+STATUS_AUTOCOMMIT = 2
+MORE_RESULTS = 8
+SESSION_STATE_CHANGED = 1 << 14
+
+# session state entry types
+TRACK_SYSTEM_VARIABLES = 0
+TRACK_SCHEMA = 1
+TRACK_TRANSACTION_CHARACTERISTICS = 4
+TRACK_TRANSACTION_STATE = 5
+
+# column types
+LONG = 0x03
+LONGLONG = 0x08
+VARCHAR = 0x0f
+DATETIME2 = 0x12
+VAR_STRING = 0xfd
+
+# binlog event types
+QUERY_EVENT = 2
+ROTATE_EVENT = 4
+FORMAT_DESCRIPTION_EVENT = 15
+XID_EVENT = 16
+TABLE_MAP_EVENT = 19
+WRITE_ROWS_EVENT = 30
+UPDATE_ROWS_EVENT = 31
+GTID_EVENT = 33
+STMT_END_F = 1
+
+UTF8MB4 = 45
+SCRAMBLE = b"abcdefghijklmnopqrst"
+
+
+def lenenc_int(n):
+    if n < 0xfb:
+        return bytes([n])
+    if n < 1 << 16:
+        return b"\xfc" + struct.pack("<H", n)
+    if n < 1 << 24:
+        return b"\xfd" + struct.pack("<I", n)[:3]
+    return b"\xfe" + struct.pack("<Q", n)
+
+
+def lenenc_str(s):
+    if isinstance(s, str):
+        s = s.encode()
+    return lenenc_int(len(s)) + s
+
+
+def greeting(version, connection_id, plugin, mariadb_caps=0, caps=SERVER_CAPS):
+    filler = b"\0" * 6 + struct.pack("<I", mariadb_caps)
+    return (b"\x0a" + version.encode() + b"\0" + struct.pack("<I", connection_id)
+            + SCRAMBLE[:8] + b"\0" + struct.pack("<H", caps & 0xffff)
+            + bytes([UTF8MB4]) + struct.pack("<H", STATUS_AUTOCOMMIT)
+            + struct.pack("<H", caps >> 16) + bytes([21]) + filler
+            + SCRAMBLE[8:] + b"\0" + plugin.encode() + b"\0")
+
+
+def login(caps, user, plugin, attrs, database=None, auth=b"\x11" * 20):
+    if database is not None:
+        caps |= CONNECT_WITH_DB
+    else:
+        caps &= ~CONNECT_WITH_DB
+    out = struct.pack("<IIB", caps, 1 << 24, UTF8MB4) + b"\0" * 23
+    out += user.encode() + b"\0" + lenenc_str(auth)
+    if database is not None:
+        out += database.encode() + b"\0"
+    out += plugin.encode() + b"\0"
+    kv = b"".join(lenenc_str(k) + lenenc_str(v) for k, v in attrs)
+    return out + lenenc_str(kv)
+
+
+def ssl_request(caps):
+    return struct.pack("<IIB", caps | SSL, 1 << 24, UTF8MB4) + b"\0" * 23
+
+
+def ok(affected=0, insert_id=0, status=STATUS_AUTOCOMMIT, warnings=0, header=0x00,
+       session_state=b""):
+    out = (bytes([header]) + lenenc_int(affected) + lenenc_int(insert_id)
+           + struct.pack("<HH", status, warnings))
+    if session_state:
+        out += lenenc_str(b"") + lenenc_str(session_state)
+    return out
+
+
+def tracked(kind, *values):
+    """A session state entry of an OK packet, its values length-encoded."""
+    return bytes([kind]) + lenenc_str(b"".join(lenenc_str(v) for v in values))
+
+
+def eof(status=STATUS_AUTOCOMMIT, warnings=0):
+    return b"\xfe" + struct.pack("<HH", warnings, status)
+
+
+def err(code, state, message):
+    return b"\xff" + struct.pack("<H", code) + b"#" + state.encode() + message.encode()
+
+
+def coldef(name, col_type, table="", schema="", length=255, flags=0):
+    return (lenenc_str("def") + lenenc_str(schema) + lenenc_str(table) + lenenc_str(table)
+            + lenenc_str(name) + lenenc_str(name) + b"\x0c"
+            + struct.pack("<HIBHB", UTF8MB4, length, col_type, flags, 0) + b"\0\0")
+
+
+def text_row(*values):
+    return b"".join(b"\xfb" if v is None else lenenc_str(str(v)) for v in values)
+
+
+def binary_row(columns, *values):
+    bitmap = bytearray((len(columns) + 7 + 2) // 8)
+    out = b""
+    for i, (v, t) in enumerate(zip(values, columns)):
+        if v is None:
+            bitmap[(i + 2) // 8] |= 1 << ((i + 2) % 8)
+        elif t == LONGLONG:
+            out += struct.pack("<q", v)
+        else:
+            out += lenenc_str(v)
+    return b"\0" + bytes(bitmap) + out
+
+
+def result_set(columns, rows, deprecate_eof, binary=False, status=STATUS_AUTOCOMMIT,
+               session_state=b""):
+    """Packets of a result set, `columns` are (name, type) pairs, only the OK
+    ending the rows with `deprecate_eof` tracks `session_state`."""
+    out = [lenenc_int(len(columns))]
+    out += [coldef(name, t, table="t") for name, t in columns]
+    if not deprecate_eof:
+        out.append(eof(status))
+    types = [t for _, t in columns]
+    out += [binary_row(types, *r) if binary else text_row(*r) for r in rows]
+    out.append(ok(header=0xfe, status=status, session_state=session_state) if deprecate_eof
+               else eof(status))
+    return out
+
+
+def prepare_ok(statement_id, columns, params, deprecate_eof):
+    out = [b"\0" + struct.pack("<IHHBH", statement_id, len(columns), len(params), 0, 0)]
+    out += [coldef("?", t) for t in params]
+    if params and not deprecate_eof:
+        out.append(eof())
+    out += [coldef(name, t, table="t") for name, t in columns]
+    if columns and not deprecate_eof:
+        out.append(eof())
+    return out
+
+
+def execute(statement_id, params=()):
+    out = b"\x17" + struct.pack("<IBI", statement_id, 0, 1)
+    if params:
+        out += b"\0" * ((len(params) + 7) // 8) + b"\x01"
+        out += b"".join(struct.pack("<H", LONGLONG) for _ in params)
+        out += b"".join(struct.pack("<q", p) for p in params)
+    return out
+
+
+class Capture:
+    """Frames of several TCP connections, in the order they are added."""
+
+    def __init__(self, start):
+        self.usecs = start * 1000000
+        self.frames = []
+
+    def add(self, frame):
+        self.usecs += 250
+        self.frames.append((self.usecs, frame))
+
+    def write(self, name):
+        out = struct.pack("<IHHiIII", 0xa1b2c3d4, 2, 4, 0, 0, 65535, 1)
+        for usecs, frame in self.frames:
+            out += struct.pack("<IIII", usecs // 1000000, usecs % 1000000, len(frame), len(frame))
+            out += frame
+        with open(os.path.join(HERE, name), "wb") as f:
+            f.write(out)
+
+
+class Connection:
+    SYN, ACK, PSH_ACK, FIN_ACK = 0x02, 0x10, 0x18, 0x11
+
+    def __init__(self, capture, client, server, port):
+        self.capture = capture
+        self.client = (bytes(map(int, client.split("."))), port)
+        self.server = (bytes(map(int, server.split("."))), 3306)
+        self.seq = {True: 1000, False: 5000}
+        self.packet_seq = 0
+        self.segment(True, b"", self.SYN)
+        self.segment(False, b"", self.SYN | self.ACK)
+        self.segment(True, b"", self.ACK)
+
+    def segment(self, from_client, payload, flags=PSH_ACK):
+        (src, sport), (dst, dport) = ((self.client, self.server) if from_client
+                                      else (self.server, self.client))
+        ack = self.seq[not from_client]
+        tcp = struct.pack(">HHIIBBHHH", sport, dport, self.seq[from_client], ack, 0x50,
+                          flags, 65535, 0, 0) + payload
+        self.seq[from_client] += len(payload) + (1 if flags & (self.SYN | 0x01) else 0)
+        ip = struct.pack(">BBHHHBBH4s4s", 0x45, 0, 20 + len(tcp), 0, 0x4000, 64, 6, 0,
+                         src, dst) + tcp
+        eth = bytes([2, 0, 0, 0, 0, dst[3], 2, 0, 0, 0, 0, src[3]]) + b"\x08\x00"
+        self.capture.add(eth + ip)
+
+    def packets(self, bodies):
+        out = b""
+        for body in bodies:
+            out += struct.pack("<I", len(body))[:3] + bytes([self.packet_seq & 0xff]) + body
+            self.packet_seq += 1
+        return out
+
+    def client_sends(self, *bodies, seq=None):
+        if seq is not None:
+            self.packet_seq = seq
+        self.segment(True, self.packets(bodies))
+
+    def server_sends(self, *bodies, split=()):
+        """Send `bodies` as one stream, cut into segments at the `split` offsets."""
+        data = self.packets(bodies)
+        cuts = [0, *split, len(data)]
+        for start, end in zip(cuts, cuts[1:]):
+            self.segment(False, data[start:end])
+
+    def command(self, body, *response, split=()):
+        self.client_sends(body, seq=0)
+        if response:
+            self.server_sends(*response, split=split)
+
+    def raw(self, from_client, payload):
+        self.segment(from_client, payload)
+
+    def close(self):
+        self.segment(True, b"", self.FIN_ACK)
+        self.segment(False, b"", self.FIN_ACK)
+        self.segment(True, b"", self.ACK)
+
+
+def query(sql, attrs=None):
+    """COM_QUERY, `attrs` are string query attributes, a list when
+    CLIENT_QUERY_ATTRIBUTES is on."""
+    out = b"\x03"
+    if attrs is not None:
+        out += lenenc_int(len(attrs)) + b"\x01"
+        if attrs:
+            out += b"\0" * ((len(attrs) + 7) // 8) + b"\x01"
+            for name, _ in attrs:
+                out += bytes([VAR_STRING, 0]) + lenenc_str(name)
+            for _, value in attrs:
+                out += lenenc_str(value)
+    return out + sql.encode()
+
+
+def prepare(sql):
+    return b"\x16" + sql.encode()
+
+
+def close_statement(statement_id):
+    return b"\x19" + struct.pack("<I", statement_id)
+
+
+class Binlog:
+    """Events of a binlog file as a source sends them to a replica, each in
+    a packet of its own behind an OK header, with CRC32 checksums."""
+
+    def __init__(self, server_id, position):
+        self.server_id = server_id
+        self.position = position
+
+    def event(self, event_type, body, timestamp, flags=0, log_pos=None):
+        size = 19 + len(body) + 4
+        if log_pos is None:
+            self.position += size
+            log_pos = self.position
+        out = struct.pack("<IBIIIH", timestamp, event_type, self.server_id, size, log_pos,
+                          flags) + body
+        return b"\0" + out + struct.pack("<I", zlib.crc32(out))
+
+    def start(self, file, version, created):
+        """The rotate and format description events a dump starts with,
+        made up for the replica."""
+        rotate = self.event(ROTATE_EVENT, struct.pack("<Q", self.position) + file.encode(),
+                            0, flags=0x20, log_pos=0)
+        # the lengths of the post-headers, and the checksum algorithm
+        post_headers = bytes([56, 13, 0, 8, 0, 18, 0, 4, 4, 4, 4, 18, 0, 0, 95, 0, 4, 26,
+                              8, 0, 0, 0, 8, 8, 8, 2, 0, 0, 0, 10, 10, 10, 42, 42, 0, 18,
+                              52, 0, 10, 40, 0])
+        body = struct.pack("<H50sIB", 4, version.encode(), created, 19) + post_headers
+        fde = self.event(FORMAT_DESCRIPTION_EVENT, body + b"\x01", created, log_pos=0)
+        return [rotate, fde]
+
+    def gtid(self, sid, gno, timestamp):
+        body = struct.pack("<B16sqBqq", 1, bytes.fromhex(sid.replace("-", "")), gno, 2,
+                           gno - 1, gno)
+        return self.event(GTID_EVENT, body, timestamp)
+
+    def query(self, schema, sql, timestamp, thread_id=30):
+        # Q_FLAGS2_CODE, the only status variable
+        status = b"\x00" + struct.pack("<I", 0)
+        body = (struct.pack("<IIBHH", thread_id, 0, len(schema), 0, len(status)) + status
+                + schema.encode() + b"\0" + sql.encode())
+        return self.event(QUERY_EVENT, body, timestamp)
+
+    def table_map(self, table_id, schema, table, columns, timestamp):
+        """`columns` are the type, metadata and nullability of each column."""
+        body = struct.pack("<Q", table_id)[:6] + struct.pack("<H", 1)
+        for name in (schema, table):
+            body += bytes([len(name)]) + name.encode() + b"\0"
+        body += lenenc_int(len(columns)) + bytes(c[0] for c in columns)
+        body += lenenc_str(b"".join(c[1] for c in columns))
+        body += bitmap([c[2] for c in columns])
+        return self.event(TABLE_MAP_EVENT, body, timestamp)
+
+    def rows(self, event_type, table_id, images, timestamp):
+        """`images` are rows of values already encoded, None for NULL, two
+        images a row for an update."""
+        columns = len(images[0])
+        body = (struct.pack("<Q", table_id)[:6] + struct.pack("<HH", STMT_END_F, 2)
+                + lenenc_int(columns) + bitmap([True] * columns))
+        if event_type == UPDATE_ROWS_EVENT:
+            body += bitmap([True] * columns)
+        for image in images:
+            body += bitmap([v is None for v in image]) + b"".join(v or b"" for v in image)
+        return self.event(event_type, body, timestamp)
+
+    def xid(self, xid, timestamp):
+        return self.event(XID_EVENT, struct.pack("<Q", xid), timestamp)
+
+
+def bitmap(bits):
+    out = bytearray((len(bits) + 7) // 8)
+    for i, b in enumerate(bits):
+        if b:
+            out[i // 8] |= 1 << (i % 8)
+    return bytes(out)
+
+
+def datetime2(year, month, day, hour, minute, second):
+    ymd = ((year * 13 + month) << 5) | day
+    hms = (hour << 12) | (minute << 6) | second
+    return struct.pack(">Q", (1 << 39) + ((ymd << 17) | hms))[3:]
+
+
+ORDERS = [("id", LONGLONG), ("item", VAR_STRING)]
+
+
+def mysql57():
+    cap = Capture(1700000000)
+    attrs = [("_os", "Linux"), ("_client_name", "libmysql"), ("_pid", "4242"),
+             ("_client_version", "5.7.44"), ("_platform", "x86_64")]
+    c = Connection(cap, "10.0.0.10", "10.0.0.57", 40001)
+    c.server_sends(greeting("5.7.44-log", 11, "mysql_native_password"))
+    c.client_sends(login(CLIENT_CAPS, "app", "mysql_native_password", attrs, "shop"))
+    c.server_sends(ok())
+    c.command(query("select @@version_comment limit 1"),
+              *result_set([("@@version_comment", VAR_STRING)],
+                          [["MySQL Community Server (GPL)"]], False))
+    # affected rows and insert id need more than one byte
+    c.command(query("insert into orders (item) select item from cart"),
+              ok(affected=300, insert_id=70000))
+    c.command(query("selec 1"),
+              err(1064, "42000", "You have an error in your SQL syntax; check the manual"
+                                 " that corresponds to your MySQL server version for the"
+                                 " right syntax to use near 'selec 1' at line 1"))
+    # the whole prepare response in one segment
+    c.command(prepare("select id, item from orders where id > ?"),
+              *prepare_ok(1, ORDERS, [LONGLONG], False))
+    c.command(execute(1, [10]),
+              *result_set(ORDERS, [[11, "book"], [12, None]], False, binary=True))
+    c.command(close_statement(1))
+    c.command(query("select * from missing"),
+              err(1146, "42S02", "Table 'shop.missing' doesn't exist"))
+    # the server stops at the first statement that fails
+    c.command(query("select 1; select * from missing; select 2"),
+              *result_set([("1", LONGLONG)], [[1]], False,
+                          status=STATUS_AUTOCOMMIT | MORE_RESULTS),
+              err(1146, "42S02", "Table 'shop.missing' doesn't exist"))
+    c.command(b"\x01")
+    c.close()
+
+    c = Connection(cap, "10.0.0.11", "10.0.0.57", 40002)
+    c.server_sends(greeting("5.7.44-log", 12, "mysql_native_password"))
+    c.client_sends(login(CLIENT_CAPS, "intruder", "mysql_native_password", attrs))
+    c.server_sends(err(1045, "28000", "Access denied for user 'intruder'@'10.0.0.11'"
+                                      " (using password: YES)"))
+    c.close()
+
+    # a rogue server reads a file off a client that allows LOCAL INFILE
+    c = Connection(cap, "10.0.0.12", "10.0.0.57", 40003)
+    c.server_sends(greeting("5.7.44-log", 13, "mysql_native_password"))
+    c.client_sends(login(CLIENT_CAPS, "app", "mysql_native_password", attrs, "shop"))
+    c.server_sends(ok())
+    c.command(query("select @@version_comment limit 1"), b"\xfb/etc/passwd")
+    c.client_sends(b"root:x:0:0:root:/root:/bin/bash\n", b"")
+    c.server_sends(ok())
+    c.command(b"\x01")
+    c.close()
+    cap.write("mysql57.pcap")
+
+
+def mysql80():
+    cap = Capture(1710000000)
+    caps = CLIENT_CAPS | SESSION_TRACK | DEPRECATE_EOF
+    attrs = [("_pid", "777"), ("_platform", "x86_64"), ("_os", "Linux"),
+             ("_client_name", "libmysql"), ("os_user", "deploy"),
+             ("_client_version", "8.0.36"), ("program_name", "mysql")]
+    c = Connection(cap, "10.0.1.10", "10.0.0.80", 41001)
+    c.server_sends(greeting("8.0.36", 21, "caching_sha2_password"))
+    c.client_sends(login(caps, "app", "caching_sha2_password", attrs, "shop", b"\x22" * 32))
+    # fast authentication from the server's cache
+    c.server_sends(b"\x01\x03", ok())
+    changed = STATUS_AUTOCOMMIT | SESSION_STATE_CHANGED
+    c.command(b"\x02billing",
+              ok(status=changed, session_state=tracked(TRACK_SCHEMA, "billing")))
+    # the server tracks the character set and isolation level variables
+    names = b"".join(tracked(TRACK_SYSTEM_VARIABLES, "character_set_" + name, "latin1")
+                     for name in ("client", "connection", "results"))
+    c.command(query("set names latin1"), ok(status=changed, session_state=names))
+    isolation = tracked(TRACK_SYSTEM_VARIABLES, "transaction_isolation", "READ-COMMITTED")
+    c.command(query("set session transaction isolation level read committed"),
+              ok(status=changed, session_state=isolation))
+    # column definitions and rows spread over segments, cut inside a row
+    users = [("id", LONGLONG), ("name", VAR_STRING)]
+    rows = [[1, "ada"], [2, "grace"], [3, "linus"]]
+    c.command(query("select id, name from users"),
+              *result_set(users, rows, True), split=(40, 130))
+    c.command(query("set transaction isolation level serializable"),
+              ok(status=changed,
+                 session_state=tracked(TRACK_TRANSACTION_CHARACTERISTICS,
+                                       "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;")))
+    c.command(query("begin"),
+              ok(status=changed | STATUS_IN_TRANS,
+                 session_state=tracked(TRACK_TRANSACTION_STATE, "T_______")))
+    # the OK ending the rows reports what the transaction did so far
+    c.command(query("select id, name from users where id = 1"),
+              *result_set(users, rows[:1], True, status=changed | STATUS_IN_TRANS,
+                          session_state=tracked(TRACK_TRANSACTION_STATE, "T_R___S_")))
+    c.command(query("update users set name = 'Ada' where id = 1"),
+              ok(affected=1, status=STATUS_AUTOCOMMIT | STATUS_IN_TRANS))
+    c.command(prepare("insert into users (id, name) values (?, 'x')"),
+              *prepare_ok(2, [], [LONGLONG], True))
+    c.command(execute(2, [1]),
+              err(1062, "23000", "Duplicate entry '1' for key 'users.PRIMARY'"))
+    c.command(query("rollback"),
+              ok(status=changed, session_state=tracked(TRACK_TRANSACTION_STATE, "________")))
+    # a CALL answers with its result sets and an OK of its own
+    more = STATUS_AUTOCOMMIT | MORE_RESULTS
+    c.command(query("delete from sessions; call report(1); select 'done'"),
+              ok(affected=3, status=more),
+              *result_set([("total", LONGLONG)], [[42]], True, status=more),
+              *result_set([("orders", LONGLONG)], [[7]], True, status=more),
+              ok(status=more),
+              *result_set([("done", VAR_STRING)], [["done"]], True))
+    # the file follows the server's request, in packets up to an empty one
+    c.command(query("load data local infile '/tmp/users.csv' into table users"),
+              b"\xfb/tmp/users.csv")
+    c.client_sends(b"4,alan\n5,barbara\n", b"6,edsger\n", b"")
+    c.server_sends(ok(affected=3))
+    c.command(b"\x0e", ok())
+    c.command(b"\x01")
+    c.close()
+
+    # a replica following the binlog, two transactions behind the source
+    c = Connection(cap, "10.0.1.20", "10.0.0.80", 41003)
+    c.server_sends(greeting("8.0.36", 23, "caching_sha2_password"))
+    replica = [("_os", "Linux"), ("_client_name", "libmysql"), ("_pid", "1"),
+               ("program_name", "mysqld"), ("_platform", "x86_64"),
+               ("_client_version", "8.0.36")]
+    c.client_sends(login(caps, "repl", "caching_sha2_password", replica, None, b"\x55" * 32))
+    c.server_sends(b"\x01\x03", ok())
+    c.command(query("SET @master_binlog_checksum = @@global.binlog_checksum"), ok())
+    host = b"replica1"
+    c.command(b"\x15" + struct.pack("<IB", 2, len(host)) + host + b"\0\0"
+              + struct.pack("<HII", 3306, 0, 0), ok())
+    binlog = Binlog(1, 1234)
+    users = [(LONG, b"", False), (VARCHAR, struct.pack("<H", 256), False),
+             (DATETIME2, b"\x00", True)]
+    varchar = lambda s: struct.pack("<H", len(s)) + s
+    sid = "3e11fa47-71ca-11e1-9e33-c80aa9429562"
+    stream = binlog.start("binlog.000003", "8.0.36", 1709990000) + [
+        binlog.gtid(sid, 41, 1709999997),
+        binlog.query("shop", "BEGIN", 1709999997),
+        binlog.table_map(108, "shop", "users", users, 1709999997),
+        binlog.rows(WRITE_ROWS_EVENT, 108, [
+            [struct.pack("<I", 7), varchar(b"alan"), datetime2(2024, 3, 9, 15, 59, 57)],
+            [struct.pack("<I", 8), varchar(b"barbara"), None]], 1709999997),
+        binlog.xid(9001, 1709999997),
+        binlog.gtid(sid, 42, 1710000000),
+        binlog.query("shop", "BEGIN", 1710000000),
+        binlog.table_map(108, "shop", "users", users, 1710000000),
+        binlog.rows(UPDATE_ROWS_EVENT, 108, [
+            [struct.pack("<I", 8), varchar(b"barbara"), None],
+            [struct.pack("<I", 8), varchar(b"Barbara"), None]], 1710000000),
+        binlog.xid(9002, 1710000000),
+    ]
+    c.command(b"\x12" + struct.pack("<IHI", 1234, 0, 2) + b"binlog.000003", *stream,
+              split=(150, 420))
+    c.close()
+
+    # TLS, nothing is readable after the SSL request
+    c = Connection(cap, "10.0.1.11", "10.0.0.80", 41002)
+    c.server_sends(greeting("8.0.36", 22, "caching_sha2_password"))
+    c.client_sends(ssl_request(caps))
+    c.raw(True, b"\x16\x03\x01\x00\x30" + bytes(range(48)))
+    c.raw(False, b"\x16\x03\x03\x00\x30" + bytes(range(48, 96)))
+    c.raw(True, b"\x17\x03\x03\x00\x20" + b"\x03" * 32)
+    c.close()
+    cap.write("mysql80.pcap")
+
+
+def mysql84():
+    cap = Capture(1720000000)
+    caps = CLIENT_CAPS | SESSION_TRACK | DEPRECATE_EOF | QUERY_ATTRIBUTES
+    attrs = [("_client_name", "libmariadb"), ("_client_version", "3.3.8"),
+             ("_os", "Linux"), ("_pid", "31337"), ("_server_host", "db84")]
+    c = Connection(cap, "10.0.2.10", "10.0.0.84", 42001)
+    c.server_sends(greeting("8.4.3", 31, "caching_sha2_password"))
+    # the client starts with mysql_native_password, disabled in 8.4
+    c.client_sends(login(caps, "report", "mysql_native_password", attrs, "shop"))
+    c.server_sends(b"\xfecaching_sha2_password\0" + SCRAMBLE + b"\0")
+    c.client_sends(b"\x33" * 32)
+    # full authentication with the server's RSA key
+    c.server_sends(b"\x01\x04")
+    c.client_sends(b"\x02")
+    c.server_sends(b"\x01-----BEGIN PUBLIC KEY-----\nMIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8A\n"
+                   b"-----END PUBLIC KEY-----\n")
+    c.client_sends(b"\x44" * 256)
+    c.server_sends(ok())
+    c.command(query("show databases", []),
+              *result_set([("Database", VAR_STRING)],
+                          [["information_schema"], ["mysql"], ["performance_schema"],
+                           ["shop"], ["sys"]], True))
+    c.command(b"\x09")
+    c.server_sends(b"Uptime: 4242  Threads: 2  Questions: 17  Slow queries: 0")
+    trace = [("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")]
+    c.command(query("select count(*) from orders where created > now() - interval 1 day", trace),
+              *result_set([("count(*)", LONGLONG)], [[1200]], True))
+    c.command(prepare("select id, item from orders where id = ?"),
+              *prepare_ok(1, ORDERS, [LONGLONG], True))
+    c.command(execute(1, [11]),
+              *result_set(ORDERS, [[11, "book"]], True, binary=True))
+    c.command(execute(1, [99]), *result_set(ORDERS, [], True, binary=True))
+    c.command(close_statement(1))
+    c.command(query("select sleep(1) from missing", []),
+              err(1146, "42S02", "Table 'shop.missing' doesn't exist"))
+    c.command(b"\x01")
+    c.close()
+    cap.write("mysql84.pcap")
+
+
+def mariadb():
+    cap = Capture(1730000000)
+    # MariaDB clears CLIENT_MYSQL and announces its own capabilities
+    server_caps = SERVER_CAPS & ~LONG_PASSWORD & ~DEPRECATE_EOF
+    attrs = [("_client_name", "MariaDB connector/J"), ("_client_version", "3.4.1"),
+             ("_os", "Linux"), ("_java_version", "21.0.2")]
+    c = Connection(cap, "10.0.3.10", "10.0.0.90", 43001)
+    c.server_sends(greeting("5.5.5-10.11.6-MariaDB-log", 41, "mysql_native_password",
+                            mariadb_caps=0x1d, caps=server_caps))
+    c.client_sends(login(CLIENT_CAPS & ~LONG_PASSWORD, "shop", "mysql_native_password",
+                         attrs, "shop"))
+    c.server_sends(ok())
+    c.command(query("set names utf8mb4"), ok())
+    c.command(query("select id, item, note from orders order by id limit 2"),
+              *result_set(ORDERS + [("note", VAR_STRING)],
+                          [[11, "book", None], [12, "pen", "gift"]], False),
+              split=(60,))
+    c.command(query("select nope from orders"),
+              err(1054, "42S22", "Unknown column 'nope' in 'field list'"))
+    c.command(prepare("delete from orders where id = ?"),
+              *prepare_ok(7, [], [LONGLONG], False))
+    c.command(execute(7, [12]), ok(affected=1))
+    c.command(close_statement(7))
+    c.command(b"\x01")
+    c.close()
+    cap.write("mariadb.pcap")
+
+
+if __name__ == "__main__":
+    mysql57()
+    mysql80()
+    mysql84()
+    mariadb()